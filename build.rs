@@ -0,0 +1,146 @@
+// Generates the zlang parse table from zlang.cfg and compiles it into static
+// tables, so ZOBOS does not need to find a grammar or a table in the working
+// directory at runtime. The table is built by the crate's own generator,
+// included below, the same way `Parser::from_grammar` builds an SLR one.
+
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+/// The parts of `crate::error` the generator uses.
+mod error {
+    use std::fmt;
+    use std::io;
+    use std::path::PathBuf;
+
+    #[derive(Debug)]
+    pub enum Error {
+        Io { path: PathBuf, source: io::Error },
+        BadGrammar(String),
+    }
+
+    impl Error {
+        pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+            Error::Io {
+                path: path.into(),
+                source,
+            }
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+                Error::BadGrammar(message) => write!(f, "bad grammar: {}", message),
+            }
+        }
+    }
+
+    pub type Result<T> = std::result::Result<T, Error>;
+}
+
+/// The table generator from `src/parser`. Only part of it is used here, and
+/// clippy holds items that are private here but public in the library to
+/// stricter rules.
+#[allow(dead_code, clippy::enum_variant_names)]
+#[path = "src/parser"]
+mod parser {
+    pub mod grammar;
+    pub mod items;
+    pub mod production;
+    pub mod symbol;
+    pub mod table;
+
+    /// `crate::parser::Action`
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum Action {
+        Shift(usize),
+        Reduce(usize),
+        ReduceTerminate(usize),
+    }
+}
+
+use parser::grammar::Grammar;
+use parser::symbol::Symbol;
+use parser::table;
+use parser::Action;
+
+fn symbol_index(symbols: &mut Vec<Symbol>, symbol: &Symbol) -> usize {
+    match symbols.iter().position(|s| s == symbol) {
+        Some(i) => i,
+        None => {
+            symbols.push(symbol.clone());
+            symbols.len() - 1
+        }
+    }
+}
+
+fn raw_symbol(symbol: &Symbol) -> String {
+    match symbol {
+        Symbol::Terminal(t) => format!("RawSymbol::Terminal({:?})", t.terminal()),
+        Symbol::NonTerminal(nt) => format!("RawSymbol::NonTerminal({:?})", nt.non_terminal()),
+        Symbol::Lambda => "RawSymbol::Lambda".to_string(),
+    }
+}
+
+fn raw_action(action: Action) -> String {
+    match action {
+        Action::Shift(n) => format!("Action::Shift({})", n),
+        Action::Reduce(n) => format!("Action::Reduce({})", n),
+        Action::ReduceTerminate(n) => format!("Action::ReduceTerminate({})", n),
+    }
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=zlang.cfg");
+    for module in ["grammar", "items", "production", "symbol", "table"].iter() {
+        println!("cargo:rerun-if-changed=src/parser/{}.rs", module);
+    }
+
+    let grammar = Grammar::from_file("zlang.cfg").unwrap_or_else(|e| panic!("{}", e));
+    let table = table::slr_table(&grammar);
+
+    let mut symbols: Vec<Symbol> = Vec::new();
+    let mut out = String::new();
+
+    writeln!(out, "pub static RULES: &[(usize, &[usize])] = &[").unwrap();
+    for (lhs, production) in grammar.rules.iter() {
+        let lhs = symbol_index(&mut symbols, &Symbol::NonTerminal(lhs.clone()));
+        let rhs: Vec<String> = production
+            .symbols()
+            .iter()
+            .map(|s| symbol_index(&mut symbols, s).to_string())
+            .collect();
+
+        writeln!(out, "    ({}, &[{}]),", lhs, rhs.join(", ")).unwrap();
+    }
+    writeln!(out, "];").unwrap();
+
+    writeln!(out, "pub static TABLE: &[&[(usize, Action)]] = &[").unwrap();
+    for row in table.iter() {
+        let cells: Vec<String> = row
+            .iter()
+            .map(|(symbol, action)| {
+                format!(
+                    "({}, {})",
+                    symbol_index(&mut symbols, symbol),
+                    raw_action(*action)
+                )
+            })
+            .collect();
+
+        writeln!(out, "    &[{}],", cells.join(", ")).unwrap();
+    }
+    writeln!(out, "];").unwrap();
+
+    writeln!(out, "pub static SYMBOLS: &[RawSymbol] = &[").unwrap();
+    for symbol in symbols.iter() {
+        writeln!(out, "    {},", raw_symbol(symbol)).unwrap();
+    }
+    writeln!(out, "];").unwrap();
+
+    let dest = Path::new(&env::var("OUT_DIR").unwrap()).join("tables.rs");
+    fs::write(dest, out).expect("could not write tables.rs");
+}
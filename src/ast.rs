@@ -7,6 +7,9 @@ use std::io::Write;
 use std::ops::Index;
 use std::path::Path;
 
+use crate::error::{Error, Result};
+use crate::span::Span;
+
 // #[derive(Debug, Clone, PartialEq)]
 // pub enum AstKind {
 //     Program,
@@ -100,7 +103,7 @@ impl fmt::Display for AstKind {
 pub struct AstNode {
     pub kind: AstKind,
     pub data: String,
-    pub span: (usize, usize),
+    pub span: Span,
     pub children: Vec<AstNode>,
 }
 
@@ -116,19 +119,17 @@ impl AstNode {
     pub fn new(kind: AstKind) -> Self {
         Self {
             kind,
-            span: (0, 0),
+            span: Span::default(),
             data: String::new(),
             children: Vec::new(),
         }
     }
 
     // Export a graph to something that Graphvis can us
-    pub fn export_graph(&self, file_path: impl AsRef<Path>) {
+    pub fn export_graph(&self, file_path: impl AsRef<Path>) -> Result<()> {
         let graph = self.create_pet_graph();
-        let mut f = File::create(file_path).unwrap_or_else(|e| std::process::exit(1));
         let output = format!("{}", Dot::with_config(&graph, &[Config::EdgeNoLabel]));
-        f.write_all(&output.as_bytes())
-            .expect("could not write file");
+        write_file(file_path.as_ref(), &output)
     }
 
     fn create_pet_graph(&self) -> Graph<String, usize> {
@@ -150,7 +151,7 @@ impl AstNode {
         parent: petgraph::graph::NodeIndex,
     ) -> Graph<String, usize> {
         for child in node.children.iter() {
-            if child.data.len() != 0 {
+            if !child.data.is_empty() {
                 let cnode = graph.add_node(child.data.clone());
                 graph.add_edge(parent, cnode, 0);
                 graph = self.create_pet_graph_rec(graph, child, cnode);
@@ -216,14 +217,13 @@ impl AstNode {
         if decl_ids.children.len() == 1 {
             // DECLIDS -> DECLID
             let id = self.simplify_dec_id(&decl_ids.children[0]);
-            let ids = vec![id];
-            return ids;
+            vec![id]
         } else {
             // DECLIDS -> DECLIDS comma DECLID
             let mut ids = self.simplify_dec_ids(&decl_ids.children[0]);
             let id = self.simplify_dec_id(&decl_ids.children[2]);
             ids.push(id);
-            return ids;
+            ids
         }
     }
 
@@ -248,7 +248,7 @@ impl AstNode {
         if atype.children.len() > 1 {
             let mut new_node = AstNode::new(AstKind::DeclType);
             new_node.data = atype[0].data.to_owned();
-            new_node.data.push_str(" ");
+            new_node.data.push(' ');
             new_node.data.push_str(&atype[1].data.to_owned());
             return new_node;
         }
@@ -256,13 +256,12 @@ impl AstNode {
     }
 
     fn simplify_assign(&self, assign: &AstNode) -> AstNode {
-        // TODO add fisher opt
         let mut retval = AstNode::new(AstKind::Eq);
-        retval.span = assign[1].span.clone();
+        retval.span = assign.span;
         if assign.children[2].kind == AstKind::Expr {
             // ASSIGN -> id assign EXPR
             let mut equals = AstNode::new(AstKind::Eq);
-            equals.span = assign[1].span;
+            equals.span = assign[0].span.to(assign[1].span);
             equals.children.push(assign[0].clone());
             retval.children.push(equals);
             retval
@@ -271,7 +270,7 @@ impl AstNode {
         } else {
             //ASSIGN -> id assign Assign
             let mut equals = AstNode::new(AstKind::Eq);
-            equals.span = assign[1].span;
+            equals.span = assign[0].span.to(assign[1].span);
             equals.children.push(assign[0].clone());
             retval.children.push(equals);
             retval
@@ -282,18 +281,17 @@ impl AstNode {
     }
 
     fn simplify_assign_rec(&self, assign: &AstNode) -> Vec<AstNode> {
-        // TODO add fisher opt
         let mut retval: Vec<AstNode> = Vec::new();
         if assign.children[2].kind == AstKind::Expr {
             // ASSIGN -> id assign EXPR
             let mut equals = AstNode::new(AstKind::Eq);
-            equals.span = assign[1].span;
+            equals.span = assign[0].span.to(assign[1].span);
             equals.children.push(assign[0].clone());
             retval.push(equals);
             retval.push(self.simplify_expr(&assign.children[2]));
         } else {
             let mut equals = AstNode::new(AstKind::Eq);
-            equals.span = assign[1].span;
+            equals.span = assign[0].span.to(assign[1].span);
             equals.children.push(assign[0].clone());
             //ASSIGN -> id assign Assign
             retval.push(equals);
@@ -318,30 +316,30 @@ impl AstNode {
     fn simplify_sum(&self, sum: &AstNode) -> AstNode {
         if sum.children.len() == 1 {
             //SUM -> PRODUCT
-            return self.simplify_prod(&sum.children[0]);
+            self.simplify_prod(&sum.children[0])
         } else {
             //SUM -> SUM PLUS PRODUCT
             let mut plus = AstNode::new(AstKind::Plus);
             plus.data = sum[1][0].data.clone();
-            plus.span = sum[1][0].span;
+            plus.span = sum[0].span.to(sum[2].span);
             plus.children.push(self.simplify_sum(&sum.children[0]));
             plus.children.push(self.simplify_prod(&sum.children[2]));
-            return plus;
+            plus
         }
     }
 
     fn simplify_prod(&self, prod: &AstNode) -> AstNode {
         if prod.children.len() == 1 {
             // PRODUCT -> VAlUE
-            return self.simplify_value(&prod.children[0]);
+            self.simplify_value(&prod.children[0])
         } else {
             // PRODUCT -> PRODUCT TIMES VALUE
             let mut times = AstNode::new(AstKind::Times);
             times.data = prod[1][0].data.clone();
-            times.span = prod[1][0].span;
+            times.span = prod[0].span.to(prod[2].span);
             times.children.push(self.simplify_prod(&prod.children[0]));
             times.children.push(self.simplify_value(&prod.children[2]));
-            return times;
+            times
         }
     }
 
@@ -463,7 +461,7 @@ impl AstNode {
         new_node
     }
 
-    fn simplify_eoi(&self, node: &AstNode) -> AstNode {
+    fn simplify_eoi(&self, _node: &AstNode) -> AstNode {
         AstNode::new(AstKind::EOI)
     }
 }
@@ -475,10 +473,14 @@ pub struct AstGraph {
 
 impl AstGraph {
     //This writes out a .dot file to a path
-    pub fn export_graph(&self, file_path: impl AsRef<Path>) {
-        let mut f = File::create(file_path).unwrap_or_else(|e| std::process::exit(1));
+    pub fn export_graph(&self, file_path: impl AsRef<Path>) -> Result<()> {
         let output = format!("{}", Dot::with_config(&self.graph, &[Config::EdgeNoLabel]));
-        f.write_all(&output.as_bytes())
-            .expect("could not write file");
+        write_file(file_path.as_ref(), &output)
     }
 }
+
+fn write_file(path: &Path, contents: &str) -> Result<()> {
+    let mut f = File::create(path).map_err(|e| Error::io(path, e))?;
+    f.write_all(contents.as_bytes())
+        .map_err(|e| Error::io(path, e))
+}
@@ -1,44 +1,456 @@
-#![allow(non_snake_case)]
-
-use std::path::PathBuf;
-use structopt::StructOpt;
-use zobos::parser::Parser;
-use zobos::symbol_table::*;
-
-#[derive(Debug, Clone, StructOpt)]
-pub struct Args {
-    pub token_input: PathBuf,
-    pub ast_output: PathBuf,
-    pub table_output: PathBuf,
-}
-
-fn main() {
-    let args = Args::from_args();
-
-    let mut dot_out = args.token_input.clone();
-    dot_out.set_extension("dot");
-
-    let mut parser = Parser::new();
-
-    let tree = parser
-        .parse(args.token_input)
-        .unwrap_or_else(|| std::process::exit(1));
-    let ast = tree.create_ast();
-    ast.export_graph(&args.ast_output);
-
-    let mut sv = SymbolVisitor::new(args.table_output);
-    sv.program(&ast);
-
-    sv.report_unused(0);
-
-    if sv.errored {
-        std::process::exit(1);
-    }
-
-    // TODO do check to see if 'emit symtable' is anywhere in table
-    // sv.write_table_to_file(&args.table_output);
-
-    // output symbol table at end
-
-    //println!("{:#?}", tree);
-}
+#![allow(non_snake_case)]
+
+use std::collections::{BTreeSet, HashSet};
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use structopt::StructOpt;
+use zobos::ast::{AstKind, AstNode};
+use zobos::bytecode::{self, Vm};
+use zobos::codegen;
+use zobos::diagnostics::{DiagnosticSink, Diagnostics, Format};
+use zobos::error::{Error, Result};
+use zobos::hazards::{ErrorId, Hazard, HazardType, WarnId};
+use zobos::interp;
+use zobos::ir::{self, opt::Pass, opt::PassManager};
+use zobos::lint::{Level, Lint, Lints};
+use zobos::parser::grammar::Grammar;
+use zobos::parser::table::{Construction, Resolution};
+use zobos::parser::token;
+use zobos::parser::Parser;
+use zobos::semantics::{fold, typeck};
+use zobos::symbol_table::*;
+use zobos::syntax::Program;
+use zobos::typed;
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct Args {
+    /// A zlang source file, or a token file of `id data row col` lines
+    pub token_input: PathBuf,
+    pub ast_output: PathBuf,
+    pub table_output: PathBuf,
+    #[structopt(flatten)]
+    pub parser: ParserArgs,
+}
+
+/// `ZOBOS run`: type checks a program and runs it, printing what it emits.
+#[derive(Debug, Clone, StructOpt)]
+#[structopt(name = "ZOBOS run")]
+pub struct RunArgs {
+    /// A zlang source file, or a token file of `id data row col` lines
+    pub input: PathBuf,
+    /// Compile to bytecode and run that instead of walking the tree
+    #[structopt(long)]
+    pub vm: bool,
+    #[structopt(flatten)]
+    pub parser: ParserArgs,
+}
+
+/// `ZOBOS compile`: compiles a program to bytecode and prints the
+/// disassembly, or generates source for another toolchain.
+#[derive(Debug, Clone, StructOpt)]
+#[structopt(name = "ZOBOS compile")]
+pub struct CompileArgs {
+    /// A zlang source file, or a token file of `id data row col` lines
+    pub input: PathBuf,
+    /// Write the output here instead of to stdout
+    #[structopt(short, long)]
+    pub output: Option<PathBuf>,
+    /// What to compile to: `bytecode`, `c`, `x86-64`, `wat` or `ir`
+    #[structopt(long, default_value = "bytecode")]
+    pub target: Target,
+    /// Also write the control flow graph of the IR as a .dot file
+    #[structopt(long)]
+    pub cfg: Option<PathBuf>,
+    /// How much to optimize the IR: 0 for not at all, 1 for constant and
+    /// copy propagation and dead code elimination, 2 to also eliminate
+    /// common subexpressions and hoist loop invariant code
+    #[structopt(short = "O", default_value = "0")]
+    pub opt_level: u8,
+    /// Also run this IR pass: `const-prop`, `copy-prop`, `cse`, `licm` or
+    /// `dce`
+    #[structopt(long = "pass", number_of_values = 1)]
+    pub passes: Vec<Pass>,
+    /// Skip this IR pass
+    #[structopt(long = "no-pass", number_of_values = 1)]
+    pub skip_passes: Vec<Pass>,
+    /// Print the IR before and after each pass to stderr
+    #[structopt(long)]
+    pub dump_passes: bool,
+    #[structopt(flatten)]
+    pub parser: ParserArgs,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Target {
+    Bytecode,
+    C,
+    X86_64,
+    Wat,
+    Ir,
+}
+
+impl CompileArgs {
+    /// Lowers `program` to the IR and runs the passes picked.
+    fn lower(&self, program: &typed::Program) -> ir::Function {
+        let mut manager = PassManager::new(self.opt_level);
+        self.passes.iter().for_each(|p| manager.enable(*p));
+        self.skip_passes.iter().for_each(|p| manager.disable(*p));
+
+        let mut func = ir::lower(program);
+        manager.run_with(&mut func, |pass, before, after| {
+            if self.dump_passes {
+                eprintln!("--- before {} ---\n{}", pass, before);
+                eprintln!("--- after {} ---\n{}", pass, after);
+            }
+        });
+        func
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "bytecode" => Ok(Target::Bytecode),
+            "c" => Ok(Target::C),
+            "x86-64" => Ok(Target::X86_64),
+            "wat" => Ok(Target::Wat),
+            "ir" => Ok(Target::Ir),
+            _ => Err(format!("unknown target {:?}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct ParserArgs {
+    /// Generate the parse table from this grammar instead of the built-in one
+    #[structopt(long, conflicts_with = "table")]
+    pub grammar: Option<PathBuf>,
+    /// The table to generate from `--grammar`: `slr`, `lalr` or `lr1`
+    #[structopt(long, requires = "grammar")]
+    pub table_kind: Option<Construction>,
+    /// Settle the conflicts in the table generated from `--grammar` with the
+    /// grammar's `%left`, `%right` and `%nonassoc` declarations instead of
+    /// always shifting
+    #[structopt(long, requires = "grammar")]
+    pub precedence: bool,
+    /// Load a `zlang.lr` style parse table instead of the built-in one
+    #[structopt(long, requires = "rules")]
+    pub table: Option<PathBuf>,
+    /// The rule list that goes with `--table`
+    #[structopt(long, requires = "table")]
+    pub rules: Option<PathBuf>,
+    /// How to print hazards: `human`, `text` for the bare `OUTPUT` lines
+    /// the autograder reads, `json` or `sarif`
+    #[structopt(long, default_value = "human")]
+    pub format: Format,
+    /// Drop the hazards of this lint. Lints are the hazard ids in lower
+    /// case, like `unused`, or `warnings` for every warning. Errors that
+    /// are allowed or only warned about still stop `run` and `compile`
+    #[structopt(short = "A", long = "allow", number_of_values = 1)]
+    pub allow: Vec<Lint>,
+    /// Report this lint as a warning
+    #[structopt(short = "W", long = "warn", number_of_values = 1)]
+    pub warn: Vec<Lint>,
+    /// Report this lint as an error, failing the check
+    #[structopt(short = "D", long = "deny", number_of_values = 1)]
+    pub deny: Vec<Lint>,
+    /// Deny this lint, overriding every other level given for it here or in `zobos.toml`
+    #[structopt(short = "F", long = "forbid", number_of_values = 1)]
+    pub forbid: Vec<Lint>,
+    /// Fail on any warning, the same as `-D warnings`
+    #[structopt(long)]
+    pub deny_warnings: bool,
+    /// Read the `[lints]` table of this file instead of the `zobos.toml`
+    /// next to the input or in a directory above it
+    #[structopt(long)]
+    pub config: Option<PathBuf>,
+}
+
+impl ParserArgs {
+    /// The parser the options pick. The conflicts in a table generated from
+    /// `--grammar` are reported to `sink`.
+    fn parser(&self, sink: impl DiagnosticSink) -> Result<Parser> {
+        Ok(if let Some(grammar) = &self.grammar {
+            let resolution = if self.precedence {
+                Resolution::Precedence
+            } else {
+                Resolution::PreferShift
+            };
+            Parser::from_grammar(
+                &Grammar::from_file(grammar)?,
+                self.table_kind.unwrap_or(Construction::Slr),
+                resolution,
+                sink,
+            )
+        } else if let (Some(table), Some(rules)) = (&self.table, &self.rules) {
+            Parser::from_files(rules, table)?
+        } else {
+            Parser::new()
+        })
+    }
+
+    fn parse(&self, input: &Path) -> Result<AstNode> {
+        // Conflicts are warnings, which only the classic check reports
+        self.parser(Vec::new())?.parse(input)
+    }
+
+    /// The lint levels from the config file, then from the command line in
+    /// the order `-A`, `-W`, `-D`, `-F` and `--deny-warnings`.
+    fn lints(&self, input: &Path) -> Result<Lints> {
+        let mut lints = Lints::new();
+
+        let config = self.config.clone().or_else(|| {
+            let input = input.canonicalize().unwrap_or_else(|_| input.to_path_buf());
+            input
+                .ancestors()
+                .skip(1)
+                .map(|dir| dir.join("zobos.toml"))
+                .find(|path| path.is_file())
+        });
+        if let Some(path) = config {
+            let text = std::fs::read_to_string(&path).map_err(|e| Error::io(&path, e))?;
+            lints
+                .read_config(&text)
+                .map_err(|e| Error::Lint(format!("{}: {}", path.display(), e)))?;
+        }
+
+        let flags = [
+            (&self.allow, Level::Allow),
+            (&self.warn, Level::Warn),
+            (&self.deny, Level::Deny),
+            (&self.forbid, Level::Forbid),
+        ];
+        for (names, level) in flags {
+            for lint in names {
+                lints.set(*lint, level).map_err(Error::Lint)?;
+            }
+        }
+        if self.deny_warnings {
+            lints
+                .set(Lint::Warnings, Level::Deny)
+                .map_err(Error::Lint)?;
+        }
+
+        Ok(lints)
+    }
+
+    /// Parses, type checks and folds `input`.
+    fn check(&self, input: &Path) -> Result<typed::Program> {
+        // A bad config fails even if there is nothing to report
+        self.lints(input)?;
+        let tree = self.parse(input)?;
+        let mut program = typeck::check(&Program::from_cst(&tree)?).map_err(Error::Semantic)?;
+
+        // Warnings are only reported by the classic check
+        fold::program(&mut program);
+        Ok(program)
+    }
+}
+
+/// Prints `hazards`, found in `input`, in `format`.
+fn print_hazards(hazards: &[Hazard], format: Format, input: &Path) {
+    // Token files have no source lines to show
+    let source = match format {
+        Format::Human => std::fs::read_to_string(input)
+            .ok()
+            .filter(|text| token::is_source(input, text)),
+        _ => None,
+    };
+
+    print!("{}", format.render(hazards, input, source.as_deref()));
+}
+
+/// Prints an error found checking `input` and picks the exit code. Hazards
+/// are printed at their lint levels in the format `args` picked, which
+/// fails if the lint levels cannot be read.
+fn report(error: &Error, args: &ParserArgs, input: &Path) -> Result<i32> {
+    let hazards = match error {
+        Error::Syntax(hazards) | Error::Semantic(hazards) => hazards.clone(),
+        Error::Lex(e) => vec![Hazard::new_one_loc(HazardType::Syntax, e.span.0, e.span.1)
+            .with_description(e.message.clone())],
+        // The legacy exit code for token files the course tools reject
+        Error::MalformedToken { .. } => {
+            eprintln!("{}", error);
+            return Ok(42);
+        }
+        Error::Reported => return Ok(1),
+        _ => {
+            eprintln!("{}", error);
+            return Ok(1);
+        }
+    };
+
+    let mut diagnostics = Diagnostics::with_lints(args.lints(input)?);
+    hazards.into_iter().for_each(|h| diagnostics.report(h));
+    print_hazards(&diagnostics.into_vec(), args.format, input);
+    Ok(1)
+}
+
+/// Subcommands are picked by the first argument, anything else is the
+/// classic `ZOBOS input ast_output table_output` invocation.
+fn main() {
+    let mut argv: Vec<OsString> = std::env::args_os().collect();
+
+    let (parser, input, result) = match argv.get(1).and_then(|a| a.to_str()) {
+        Some("run") => {
+            argv.remove(1);
+            let args = RunArgs::from_iter(argv);
+            (args.parser.clone(), args.input.clone(), run(args))
+        }
+        Some("compile") => {
+            argv.remove(1);
+            let args = CompileArgs::from_iter(argv);
+            (args.parser.clone(), args.input.clone(), compile(args))
+        }
+        _ => {
+            let args = Args::from_iter(argv);
+            (args.parser.clone(), args.token_input.clone(), check(args))
+        }
+    };
+
+    if let Err(e) = result {
+        let code = report(&e, &parser, &input).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            1
+        });
+        std::process::exit(code);
+    }
+}
+
+fn run(args: RunArgs) -> Result<()> {
+    let program = args.parser.check(&args.input)?;
+
+    if args.vm {
+        let chunk = bytecode::compile(&program);
+        Vm::new(&chunk, io::stdout().lock()).run()
+    } else {
+        interp::run(&program, io::stdout().lock())
+    }
+}
+
+fn compile(args: CompileArgs) -> Result<()> {
+    let program = args.parser.check(&args.input)?;
+    let func = if args.target == Target::Ir || args.cfg.is_some() {
+        Some(args.lower(&program))
+    } else {
+        None
+    };
+
+    let output = match (args.target, &func) {
+        (Target::Bytecode, _) => bytecode::compile(&program).disassemble(),
+        (Target::C, _) => codegen::c::generate(&program),
+        (Target::X86_64, _) => codegen::x86::generate(&program),
+        (Target::Wat, _) => codegen::wat::generate(&program),
+        (Target::Ir, func) => func
+            .as_ref()
+            .expect("lowered for the ir target")
+            .to_string(),
+    };
+
+    if let (Some(path), Some(func)) = (&args.cfg, &func) {
+        func.export_cfg(path)?;
+    }
+
+    match &args.output {
+        Some(path) => std::fs::write(path, output).map_err(|e| Error::io(path, e)),
+        None => {
+            print!("{}", output);
+            Ok(())
+        }
+    }
+}
+
+/// Passes on every hazard but the ones about a variable that a statement
+/// the parser skipped also names. The skipped statement may have declared,
+/// assigned or read it, so whether it is undeclared, unused or uninitialized
+/// is not known.
+struct SkipRecovered<S> {
+    /// Where the variables with a skipped name appear in the tree
+    skipped: HashSet<(usize, usize)>,
+    sink: S,
+}
+
+impl<S> SkipRecovered<S> {
+    fn new(tree: &AstNode, names: &BTreeSet<String>, sink: S) -> Self {
+        fn find(node: &AstNode, names: &BTreeSet<String>, out: &mut HashSet<(usize, usize)>) {
+            if node.kind == AstKind::Identifier && names.contains(&node.data) {
+                out.insert(node.span.start);
+            }
+            node.children.iter().for_each(|c| find(c, names, out));
+        }
+
+        let mut skipped = HashSet::new();
+        find(tree, names, &mut skipped);
+        Self { skipped, sink }
+    }
+}
+
+impl<S: DiagnosticSink> DiagnosticSink for SkipRecovered<S> {
+    fn report(&mut self, hazard: Hazard) {
+        let about_variable = matches!(
+            hazard.hazard_type,
+            HazardType::ErrorT(ErrorId::NoVar)
+                | HazardType::Warn(WarnId::Unused)
+                | HazardType::Warn(WarnId::Uninit)
+                | HazardType::Warn(WarnId::MaybeUninit)
+        );
+        let skipped = hazard
+            .locations
+            .first()
+            .is_some_and(|l| self.skipped.contains(&(l.row, l.col)));
+
+        if !(about_variable && skipped) {
+            self.sink.report(hazard);
+        }
+    }
+}
+
+fn check(args: Args) -> Result<()> {
+    let lints = args.parser.lints(&args.token_input)?;
+    let mut conflicts = Vec::new();
+    let mut parser = args.parser.parser(&mut conflicts)?;
+    let tree = parser.parse_partial(&args.token_input)?;
+    let mut syntax_errors = parser.syntax_errors.clone();
+
+    // The statements that did not parse are left out of the recovered tree,
+    // the rest of it is checked as usual
+    let tree = tree.ok_or_else(|| Error::Syntax(syntax_errors.clone()))?;
+    if syntax_errors.is_empty() {
+        tree.create_ast().export_graph(&args.ast_output)?;
+    }
+
+    let program = Program::from_cst(&tree).map_err(|e| match e {
+        Error::Syntax(mut literals) => {
+            syntax_errors.append(&mut literals);
+            Error::Syntax(syntax_errors.clone())
+        }
+        e => e,
+    })?;
+
+    let mut diagnostics = Diagnostics::with_lints(lints);
+    syntax_errors
+        .into_iter()
+        .for_each(|h| diagnostics.report(h));
+    let sink = SkipRecovered::new(&tree, &parser.skipped_names, diagnostics);
+    let mut sv = SymbolVisitor::with_sink(args.table_output, sink);
+    let result = sv.program(&program);
+
+    // Hazards the checks share are only printed once
+    let mut diagnostics = sv.into_sink().sink;
+    diagnostics.dedup();
+    // Conflicts have no location to tell them apart, so they skip the dedup
+    conflicts.into_iter().for_each(|h| diagnostics.report(h));
+    print_hazards(diagnostics.hazards(), args.parser.format, &args.token_input);
+    result?;
+
+    // Only denied hazards fail the check
+    if diagnostics.has_errors() {
+        return Err(Error::Reported);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,235 @@
+use super::{Chunk, Local, Op};
+use crate::interp::Value;
+use crate::syntax::{Ident, Span, UnOp};
+use crate::typed::{Assign, Block, Decl, Declarator, Emit, Expr, ExprKind, Program, Stmt, VarId};
+use crate::types::{QualType, Type};
+
+/// Compiles a type checked program.
+pub fn compile(program: &Program) -> Chunk {
+    let mut compiler = Compiler {
+        program,
+        chunk: Chunk::default(),
+        next_slot: 0,
+    };
+    compiler.chunk.locals = program
+        .vars
+        .iter()
+        .map(|v| Local {
+            name: v.name.clone(),
+            ty: v.ty,
+            scope: v.depth,
+            slot: 0,
+        })
+        .collect();
+
+    for stmt in &program.stmts {
+        compiler.stmt(stmt);
+    }
+    compiler.chunk
+}
+
+struct Compiler<'p> {
+    program: &'p Program,
+    chunk: Chunk,
+    /// The first slot not taken by a variable of an open block
+    next_slot: u32,
+}
+
+impl Compiler<'_> {
+    fn emit(&mut self, op: Op, span: Span) -> usize {
+        self.chunk.code.push(op);
+        self.chunk.spans.push(span);
+        self.chunk.code.len() - 1
+    }
+
+    /// Points the jump at `at` to the next instruction.
+    fn patch(&mut self, at: usize) {
+        let target = self.chunk.code.len() as u32;
+
+        match &mut self.chunk.code[at] {
+            Op::Jump(t) | Op::JumpIfFalse(t) => *t = target,
+            op => unreachable!("patching {}", op),
+        }
+    }
+
+    fn constant(&mut self, value: Value, span: Span) {
+        let index = match self.chunk.constants.iter().position(|c| *c == value) {
+            Some(i) => i,
+            None => {
+                self.chunk.constants.push(value);
+                self.chunk.constants.len() - 1
+            }
+        };
+        self.emit(Op::Const(index as u32), span);
+    }
+
+    fn cast(&mut self, from: Type, to: Type, span: Span) {
+        if from != to {
+            self.emit(Op::Cast(to), span);
+        }
+    }
+
+    /// The slot and type of `var`. The declaration that introduces it
+    /// takes the next free slot, `new` is set for that one.
+    fn declare(&mut self, ident: &Ident, var: VarId) -> (u32, QualType, bool) {
+        let new = self.program.var(var).declared_by(ident);
+        let local = &mut self.chunk.locals[var.0];
+
+        if new {
+            local.slot = self.next_slot;
+            self.next_slot += 1;
+            self.chunk.slots = self.chunk.slots.max(self.next_slot as usize);
+        }
+        (local.slot, local.ty, new)
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Decl(decl) => self.decl(decl),
+            Stmt::Assign(assign) => {
+                self.expr(&assign.value);
+                self.store(assign);
+            }
+            Stmt::If(if_) => {
+                self.expr(&if_.cond);
+                let skip = self.emit(Op::JumpIfFalse(0), if_.cond.span);
+                self.body(&if_.then);
+                self.patch(skip);
+            }
+            Stmt::IfElse(if_else) => {
+                self.expr(&if_else.cond);
+                let otherwise = self.emit(Op::JumpIfFalse(0), if_else.cond.span);
+                self.block(&if_else.then);
+                let end = self.emit(Op::Jump(0), if_else.cond.span);
+                self.patch(otherwise);
+                self.body(&if_else.otherwise);
+                self.patch(end);
+            }
+            Stmt::While(while_) => {
+                let start = self.chunk.code.len() as u32;
+                self.expr(&while_.cond);
+                let end = self.emit(Op::JumpIfFalse(0), while_.cond.span);
+                self.body(&while_.body);
+                self.emit(Op::Jump(start), while_.cond.span);
+                self.patch(end);
+            }
+            Stmt::Emit(Emit::Value { var, first, second }) => {
+                self.expr(var);
+                self.expr(first);
+                self.expr(second);
+                self.emit(Op::Emit, var.span);
+            }
+            Stmt::Emit(Emit::Symtable { span, vars }) => {
+                let live = vars.iter().map(|v| v.0).collect();
+                self.chunk.symtables.push(live);
+                self.emit(Op::Symtable(self.chunk.symtables.len() as u32 - 1), *span);
+            }
+            Stmt::Block(block) => self.block(block),
+        }
+    }
+
+    /// The body of an `if`, `else` or `while`. A declaration on its own
+    /// gives its slot back after the statement, like a block.
+    fn body(&mut self, stmt: &Stmt) {
+        let next_slot = self.next_slot;
+        self.stmt(stmt);
+        self.next_slot = next_slot;
+    }
+
+    /// The slots of the block's variables are free again after it.
+    fn block(&mut self, block: &Block) {
+        let next_slot = self.next_slot;
+        for stmt in &block.stmts {
+            self.stmt(stmt);
+        }
+        self.next_slot = next_slot;
+    }
+
+    /// Every new variable is stored to, so a slot reused by a later block
+    /// or a loop iteration starts out as the zero value again.
+    fn decl(&mut self, decl: &Decl) {
+        for var in &decl.vars {
+            match var {
+                Declarator::Ident(ident, var) => {
+                    let (slot, ty, new) = self.declare(ident, *var);
+                    if new {
+                        self.constant(Value::zero(ty.ty), ident.span);
+                        self.emit(Op::Store(slot), ident.span);
+                    }
+                }
+                Declarator::Init(assign) => {
+                    self.expr(&assign.value);
+
+                    let last = assign.targets.len() - 1;
+                    for (i, target) in assign.targets.iter().enumerate() {
+                        let (slot, ty, _) = self.declare(&target.ident, target.var);
+                        if i < last {
+                            self.emit(Op::Dup, target.span);
+                        }
+                        self.cast(assign.value.ty, ty.ty, target.span);
+                        self.emit(Op::Store(slot), target.span);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Stores the value on the stack in every target. Stores to a `const`
+    /// are dropped, it keeps its initial value.
+    fn store(&mut self, assign: &Assign) {
+        for target in &assign.targets {
+            let local = &self.chunk.locals[target.var.0];
+            let (slot, ty) = (local.slot, local.ty);
+
+            if !ty.constant {
+                self.emit(Op::Dup, target.span);
+                self.cast(assign.value.ty, ty.ty, target.span);
+                self.emit(Op::Store(slot), target.span);
+            }
+        }
+        self.emit(Op::Pop, assign.value.span);
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Literal(literal) => self.constant(Value::from(literal), expr.span),
+            ExprKind::Var(_, var) => {
+                let slot = self.chunk.locals[var.0].slot;
+                self.emit(Op::Load(slot), expr.span);
+            }
+            ExprKind::Unary { op, operand } => {
+                self.expr(operand);
+                match op {
+                    UnOp::Plus => {}
+                    UnOp::Neg => {
+                        self.emit(Op::Neg, expr.span);
+                    }
+                    UnOp::Not => {
+                        self.emit(Op::Not, expr.span);
+                    }
+                    UnOp::Compl => {
+                        self.emit(Op::Compl, expr.span);
+                    }
+                }
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                // Mixed int and float operands are both made floats
+                let operands = if lhs.ty.is_numeric() && rhs.ty.is_numeric() && lhs.ty != rhs.ty {
+                    Type::Float
+                } else {
+                    lhs.ty
+                };
+
+                self.expr(lhs);
+                self.cast(lhs.ty, operands, lhs.span);
+                self.expr(rhs);
+                self.cast(rhs.ty, operands, rhs.span);
+                self.emit(Op::binary(*op), expr.span);
+            }
+            ExprKind::Cast(inner) => {
+                self.expr(inner);
+                self.cast(inner.ty, expr.ty, expr.span);
+            }
+        }
+    }
+}
@@ -0,0 +1,232 @@
+//! A compact bytecode for zlang and the stack machine that runs it.
+//!
+//! `compile` turns a type checked program into a `Chunk`. Every variable is
+//! resolved to a numbered slot at compile time: a block's variables take the
+//! slots after the ones already in use and give them back at its closing
+//! brace, so the frame is only as large as the deepest nesting needs.
+//! Implicit conversions are made explicit with `Cast`, so the arithmetic
+//! instructions always see operands of the same type.
+
+pub mod compile;
+pub mod vm;
+
+use std::fmt;
+
+use crate::interp::Value;
+use crate::syntax::{BinOp, Span, UnOp};
+use crate::types::{QualType, Type};
+
+pub use compile::compile;
+pub use vm::Vm;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Op {
+    /// Push `constants[i]`
+    Const(u32),
+    /// Push the value in a slot
+    Load(u32),
+    /// Pop into a slot
+    Store(u32),
+    Dup,
+    Pop,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Lt,
+    Leq,
+    Eq,
+    Geq,
+    Gt,
+    Neg,
+    Not,
+    Compl,
+    /// Convert the top of the stack, fails for strings that do not parse
+    Cast(Type),
+    Jump(u32),
+    /// Pop a bool and jump if it is false
+    JumpIfFalse(u32),
+    /// Pop three values and print them on one line
+    Emit,
+    /// Print the variables in `symtables[i]` with their values
+    Symtable(u32),
+}
+
+impl Op {
+    pub fn binary(op: BinOp) -> Self {
+        match op {
+            BinOp::Add => Op::Add,
+            BinOp::Sub => Op::Sub,
+            BinOp::Mul => Op::Mul,
+            BinOp::Div => Op::Div,
+            BinOp::Mod => Op::Mod,
+            BinOp::Lt => Op::Lt,
+            BinOp::Leq => Op::Leq,
+            BinOp::Eq => Op::Eq,
+            BinOp::Geq => Op::Geq,
+            BinOp::Gt => Op::Gt,
+        }
+    }
+
+    /// The operator a binary instruction applies.
+    pub fn bin_op(&self) -> Option<BinOp> {
+        Some(match self {
+            Op::Add => BinOp::Add,
+            Op::Sub => BinOp::Sub,
+            Op::Mul => BinOp::Mul,
+            Op::Div => BinOp::Div,
+            Op::Mod => BinOp::Mod,
+            Op::Lt => BinOp::Lt,
+            Op::Leq => BinOp::Leq,
+            Op::Eq => BinOp::Eq,
+            Op::Geq => BinOp::Geq,
+            Op::Gt => BinOp::Gt,
+            _ => return None,
+        })
+    }
+
+    /// The operator a unary instruction applies.
+    pub fn un_op(&self) -> Option<UnOp> {
+        Some(match self {
+            Op::Neg => UnOp::Neg,
+            Op::Not => UnOp::Not,
+            Op::Compl => UnOp::Compl,
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Op::Const(i) => write!(f, "const {}", i),
+            Op::Load(slot) => write!(f, "load {}", slot),
+            Op::Store(slot) => write!(f, "store {}", slot),
+            Op::Cast(ty) => write!(f, "cast {}", ty),
+            Op::Jump(target) => write!(f, "jump {:04}", target),
+            Op::JumpIfFalse(target) => write!(f, "jump_if_false {:04}", target),
+            Op::Symtable(i) => write!(f, "symtable {}", i),
+            op => write!(f, "{}", format!("{:?}", op).to_lowercase()),
+        }
+    }
+}
+
+/// A declared variable and the slot it lives in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Local {
+    pub name: String,
+    pub ty: QualType,
+    /// The block nesting depth, 0 for the top level
+    pub scope: usize,
+    pub slot: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    /// The source span each instruction was compiled from
+    pub spans: Vec<Span>,
+    pub constants: Vec<Value>,
+    /// Every variable the program declares, indexed by `VarId`
+    pub locals: Vec<Local>,
+    /// The live variables at each `emit symtable`, as indices into `locals`
+    pub symtables: Vec<Vec<usize>>,
+    /// The number of slots the program needs
+    pub slots: usize,
+}
+
+impl Chunk {
+    /// A listing of the constants, the variables and the code, with the
+    /// value of every constant an instruction refers to.
+    pub fn disassemble(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("constants:\n");
+        for (i, value) in self.constants.iter().enumerate() {
+            out.push_str(&format!("  {:4}  {} {}\n", i, value.ty(), literal(value)));
+        }
+
+        out.push_str(&format!("locals ({} slots):\n", self.slots));
+        for local in &self.locals {
+            out.push_str(&format!(
+                "  {:4}  {} {} (scope {})\n",
+                local.slot, local.ty, local.name, local.scope
+            ));
+        }
+
+        out.push_str("code:\n");
+        for (pc, (op, span)) in self.code.iter().zip(&self.spans).enumerate() {
+            let line = format!("  {:04}  {:>4}:{:<3}  {}", pc, span.row(), span.col(), op);
+            match op {
+                Op::Const(i) => {
+                    let value = &self.constants[*i as usize];
+                    out.push_str(&format!("{:36}; {}\n", line, literal(value)))
+                }
+                _ => {
+                    out.push_str(&line);
+                    out.push('\n');
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Strings are quoted so their whitespace shows.
+fn literal(value: &Value) -> String {
+    match value {
+        Value::String(s) => format!("{:?}", s),
+        v => v.to_string(),
+    }
+}
+
+impl fmt::Display for Chunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.disassemble())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::semantics::typeck;
+    use crate::syntax;
+
+    #[test]
+    fn disassembly() {
+        let tree = Parser::new()
+            .parse_str("float f = 1;\nwhile (f < 2) f = f + 0.5;\n")
+            .unwrap();
+        let program = typeck::check(&syntax::Program::from_cst(&tree).unwrap()).unwrap();
+        let listing = compile(&program).disassemble();
+
+        let code: Vec<_> = listing
+            .lines()
+            .skip_while(|l| *l != "code:")
+            .skip(1)
+            .map(|l| l[18..].trim_end())
+            .collect();
+        assert_eq!(
+            code,
+            vec![
+                "const 0           ; 1",
+                "cast float",
+                "store 0",
+                "load 0",
+                "const 1           ; 2",
+                "cast float",
+                "lt",
+                "jump_if_false 0015",
+                "load 0",
+                "const 2           ; 0.5",
+                "add",
+                "dup",
+                "store 0",
+                "pop",
+                "jump 0003",
+            ]
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use std::io::Write;
+
+use super::{Chunk, Op};
+use crate::error::{Error, Result};
+use crate::interp::{self, Value};
+
+/// The stack machine. It runs a `Chunk` to the end of its code, writing
+/// what the program emits to `out`.
+pub struct Vm<'a, W> {
+    chunk: &'a Chunk,
+    out: W,
+    stack: Vec<Value>,
+    slots: Vec<Value>,
+}
+
+impl<'a, W: Write> Vm<'a, W> {
+    pub fn new(chunk: &'a Chunk, out: W) -> Self {
+        Self {
+            chunk,
+            out,
+            stack: Vec::new(),
+            // Every slot is stored to before it is read
+            slots: vec![Value::Int(0); chunk.slots],
+        }
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("stack underflow")
+    }
+
+    fn write(&mut self, text: &str) -> Result<()> {
+        self.out
+            .write_all(text.as_bytes())
+            .map_err(|e| Error::io("<output>", e))
+    }
+
+    pub fn run(&mut self) -> Result<()> {
+        let code = &self.chunk.code;
+        let mut pc = 0;
+
+        while let Some(&op) = code.get(pc) {
+            let span = self.chunk.spans[pc];
+            pc += 1;
+
+            match op {
+                Op::Const(i) => self.stack.push(self.chunk.constants[i as usize].clone()),
+                Op::Load(slot) => self.stack.push(self.slots[slot as usize].clone()),
+                Op::Store(slot) => self.slots[slot as usize] = self.pop(),
+                Op::Dup => self
+                    .stack
+                    .push(self.stack.last().expect("stack underflow").clone()),
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::Add
+                | Op::Sub
+                | Op::Mul
+                | Op::Div
+                | Op::Mod
+                | Op::Lt
+                | Op::Leq
+                | Op::Eq
+                | Op::Geq
+                | Op::Gt => {
+                    let rhs = self.pop();
+                    let lhs = self.pop();
+                    let ty = lhs.ty();
+                    let value = interp::binary(op.bin_op().unwrap(), lhs, rhs, ty, span)?;
+                    self.stack.push(value);
+                }
+                Op::Neg | Op::Not | Op::Compl => {
+                    let value = self.pop();
+                    self.stack.push(interp::unary(op.un_op().unwrap(), value));
+                }
+                Op::Cast(ty) => {
+                    let value = self.pop();
+                    self.stack.push(interp::convert(value, ty, span)?);
+                }
+                Op::Jump(target) => pc = target as usize,
+                Op::JumpIfFalse(target) => {
+                    if self.pop() == Value::Bool(false) {
+                        pc = target as usize;
+                    }
+                }
+                Op::Emit => {
+                    let second = self.pop();
+                    let first = self.pop();
+                    let var = self.pop();
+                    self.write(&format!("{} {} {}\n", var, first, second))?;
+                }
+                Op::Symtable(i) => {
+                    let mut out = String::new();
+                    for &local in &self.chunk.symtables[i as usize] {
+                        let local = &self.chunk.locals[local];
+                        let constant = if local.ty.constant { "const" } else { "" };
+                        out.push_str(&format!(
+                            "{},{}{},{},{}\n",
+                            local.scope,
+                            constant,
+                            local.ty.ty,
+                            local.name,
+                            self.slots[local.slot as usize]
+                        ));
+                    }
+                    self.write(&out)?;
+                }
+            }
+        }
+
+        self.out.flush().map_err(|e| Error::io("<output>", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::compile;
+    use crate::parser::Parser;
+    use crate::semantics::typeck;
+    use crate::syntax;
+
+    fn both(src: &str) -> (String, String) {
+        let tree = Parser::new().parse_str(src).unwrap();
+        let program = typeck::check(&syntax::Program::from_cst(&tree).unwrap()).unwrap();
+
+        let mut interpreted = Vec::new();
+        interp::run(&program, &mut interpreted).unwrap();
+
+        let chunk = compile(&program);
+        let mut compiled = Vec::new();
+        Vm::new(&chunk, &mut compiled).run().unwrap();
+
+        (
+            String::from_utf8(interpreted).unwrap(),
+            String::from_utf8(compiled).unwrap(),
+        )
+    }
+
+    #[test]
+    fn matches_the_interpreter() {
+        let (interpreted, compiled) = both(
+            "int n = 10, a = 0, b = 1, i;\n\
+             while (i < n) { int t = a + b; a = b; b = t; i = i + 1; }\n\
+             float f = a / 2.0;\n\
+             const int c = 3;\n\
+             c = a = b = 7;\n\
+             if (f > 1) { int i; emit i f c; emit symtable; } else emit a a a;\n\
+             { int x; emit x 0 0; x = 2; }\n\
+             { int y; emit y 0 0; }\n\
+             emit a b int(\"42\");\n",
+        );
+
+        assert_eq!(
+            interpreted,
+            "0 27.5 3\n0,int,n,10\n0,int,a,7\n0,int,b,7\n0,int,i,10\n\
+             0,float,f,27.5\n0,constint,c,3\n1,int,i,0\n0 0 0\n0 0 0\n7 7 42\n"
+        );
+        assert_eq!(compiled, interpreted);
+    }
+
+    #[test]
+    fn unbraced_declarations_are_scoped() {
+        let (interpreted, compiled) = both(
+            "int z = 5, i;\nif (z > 1) int z = 1;\n\
+             while (i < 2) { i = i + 1; if (i > 1) int z = i; int w = z; emit z i w; emit symtable; }\n\
+             emit z i 0;\n",
+        );
+
+        assert_eq!(
+            interpreted,
+            "5 1 5\n0,int,z,5\n0,int,i,1\n1,int,w,5\n\
+             5 2 5\n0,int,z,5\n0,int,i,2\n1,int,w,5\n5 2 0\n"
+        );
+        assert_eq!(compiled, interpreted);
+    }
+
+    #[test]
+    fn slots_are_reused_by_sibling_blocks() {
+        let tree = Parser::new()
+            .parse_str("int a;\n{ int b, c; }\n{ int d; { int e, f; } }\n")
+            .unwrap();
+        let program = typeck::check(&syntax::Program::from_cst(&tree).unwrap()).unwrap();
+        let chunk = compile(&program);
+
+        let slots: Vec<_> = chunk
+            .locals
+            .iter()
+            .map(|l| (l.name.as_str(), l.slot))
+            .collect();
+        assert_eq!(
+            slots,
+            vec![("a", 0), ("b", 1), ("c", 2), ("d", 1), ("e", 2), ("f", 3)]
+        );
+        assert_eq!(chunk.slots, 4);
+    }
+}
@@ -0,0 +1,650 @@
+//! Lowers a type checked program to a single C99 file.
+//!
+//! `int` is `long long`, `float` is `double`, `bool` is `bool` from
+//! `stdbool.h` and `string` is `const char *`. zlang blocks become C blocks
+//! and every variable is prefixed with `z_`, so no name can clash with C.
+//! Integer arithmetic wraps and a zero divisor stops the program with the
+//! same runtime error the interpreter reports. `emit` is lowered to `printf`,
+//! with floats formatted the way the interpreter prints them.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use crate::syntax::{BinOp, Literal, Span, UnOp};
+use crate::typed::{Assign, Block, Decl, Declarator, Emit, Expr, ExprKind, Program, Stmt, VarId};
+use crate::types::Type;
+
+/// Helpers the generated code calls. They are `static inline` so the
+/// unused ones do not warn.
+const PRELUDE: &str = r#"#include <math.h>
+#include <stdbool.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+static inline void zl_error(int row, int col, const char *message, const char *value) {
+    fprintf(stderr, "runtime error at %d:%d: ", row, col);
+    fprintf(stderr, message, value);
+    fputc('\n', stderr);
+    exit(1);
+}
+
+static inline long long zl_add(long long a, long long b) {
+    return (long long)((unsigned long long)a + (unsigned long long)b);
+}
+
+static inline long long zl_sub(long long a, long long b) {
+    return (long long)((unsigned long long)a - (unsigned long long)b);
+}
+
+static inline long long zl_mul(long long a, long long b) {
+    return (long long)((unsigned long long)a * (unsigned long long)b);
+}
+
+static inline long long zl_neg(long long a) {
+    return (long long)(0ULL - (unsigned long long)a);
+}
+
+static inline long long zl_div(long long a, long long b, int row, int col) {
+    if (b == 0) zl_error(row, col, "division by zero%s", "");
+    if (b == -1) return zl_neg(a);
+    return a / b;
+}
+
+static inline long long zl_mod(long long a, long long b, int row, int col) {
+    if (b == 0) zl_error(row, col, "division by zero%s", "");
+    if (b == -1) return 0;
+    return a % b;
+}
+
+/* Truncates like a Rust `as` cast, saturating out of range values */
+static inline long long zl_ftoi(double f) {
+    if (isnan(f)) return 0;
+    if (f >= 9223372036854775807.0) return 9223372036854775807LL;
+    if (f <= -9223372036854775807.0 - 1.0) return -9223372036854775807LL - 1;
+    return (long long)f;
+}
+
+static inline const char *zl_trim(const char *s, char *buf, size_t size) {
+    size_t n;
+    while (*s == ' ' || *s == '\t' || *s == '\n' || *s == '\r') s++;
+    n = strlen(s);
+    while (n > 0 && (s[n - 1] == ' ' || s[n - 1] == '\t' || s[n - 1] == '\n' || s[n - 1] == '\r')) n--;
+    if (n >= size) n = size - 1;
+    memcpy(buf, s, n);
+    buf[n] = '\0';
+    return buf;
+}
+
+static inline long long zl_stoi(const char *s, int row, int col) {
+    char buf[64], *end;
+    const char *t = zl_trim(s, buf, sizeof buf);
+    long long i = strtoll(t, &end, 10);
+    if (*t == '\0' || *end != '\0') zl_error(row, col, "cannot convert \"%s\" to int", s);
+    return i;
+}
+
+static inline double zl_stof(const char *s, int row, int col) {
+    char buf[64], *end;
+    const char *t = zl_trim(s, buf, sizeof buf);
+    double f = strtod(t, &end);
+    if (*t == '\0' || *end != '\0') zl_error(row, col, "cannot convert \"%s\" to float", s);
+    return f;
+}
+
+static inline bool zl_stob(const char *s, int row, int col) {
+    char buf[64];
+    const char *t = zl_trim(s, buf, sizeof buf);
+    if (strcmp(t, "true") == 0) return true;
+    if (strcmp(t, "false") != 0) zl_error(row, col, "cannot convert \"%s\" to bool", s);
+    return false;
+}
+
+/* Formats a float like Rust's `{:?}`: the shortest digits that read back as
+   the same value, with an exponent below 1e-4 and from 1e16 on */
+static inline const char *zl_float(double x, char *out) {
+    char buf[40], digits[24], *s, *o = out;
+    int p, n = 0, exp, i;
+
+    if (isnan(x)) return "NaN";
+    if (isinf(x)) return x < 0 ? "-inf" : "inf";
+    if (x == 0) return signbit(x) ? "-0.0" : "0.0";
+
+    for (p = 0; p < 17; p++) {
+        snprintf(buf, sizeof buf, "%.*e", p, x);
+        if (strtod(buf, NULL) == x) break;
+    }
+
+    s = buf;
+    if (*s == '-') *o++ = *s++;
+    for (; *s != 'e'; s++)
+        if (*s != '.') digits[n++] = *s;
+    exp = atoi(s + 1);
+
+    if (fabs(x) < 1e-4 || fabs(x) >= 1e16) {
+        *o++ = digits[0];
+        if (n > 1) {
+            *o++ = '.';
+            memcpy(o, digits + 1, n - 1);
+            o += n - 1;
+        }
+        sprintf(o, "e%d", exp);
+    } else if (exp < 0) {
+        *o++ = '0';
+        *o++ = '.';
+        for (i = -1; i > exp; i--) *o++ = '0';
+        memcpy(o, digits, n);
+        o[n] = '\0';
+    } else {
+        for (i = 0; i <= exp; i++) *o++ = i < n ? digits[i] : '0';
+        *o++ = '.';
+        if (n > exp + 1) {
+            memcpy(o, digits + exp + 1, n - exp - 1);
+            o += n - exp - 1;
+        } else {
+            *o++ = '0';
+        }
+        *o = '\0';
+    }
+    return out;
+}
+
+static char zl_bufs[3][40];
+"#;
+
+/// Generates the C source for `program`.
+pub fn generate(program: &Program) -> String {
+    let mut gen = Generator {
+        program,
+        out: String::new(),
+        indent: 1,
+        c_names: HashMap::new(),
+        redeclared: HashSet::new(),
+        names: HashMap::new(),
+        temps: 0,
+    };
+
+    gen.out
+        .push_str("/* Generated by ZOBOS from a zlang program */\n");
+    gen.out.push_str(PRELUDE);
+    gen.out.push_str("\nint main(void) {\n");
+    gen.scoped(&program.stmts);
+    gen.line("return 0;");
+    gen.out.push_str("}\n");
+    gen.out
+}
+
+struct Generator<'p> {
+    program: &'p Program,
+    out: String,
+    indent: usize,
+    /// The C variable that holds each zlang one
+    c_names: HashMap<VarId, String>,
+    /// Declared more than once in their block, so stored to after their
+    /// declaration even if they are `const`
+    redeclared: HashSet<VarId>,
+    /// How many C variables each name has had, so a variable that shadows
+    /// another gets a name of its own and `emit symtable` can read both
+    names: HashMap<String, usize>,
+    temps: usize,
+}
+
+fn c_type(ty: Type) -> &'static str {
+    match ty {
+        Type::Bool => "bool",
+        Type::Int => "long long",
+        Type::Float => "double",
+        Type::String => "const char *",
+    }
+}
+
+/// A C string literal. Everything but printable ASCII is an octal escape.
+fn c_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for b in s.bytes() {
+        match b {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            // Keep `??` from starting a trigraph
+            b'?' => out.push_str("\\?"),
+            0x20..=0x7e => out.push(b as char),
+            _ => write!(out, "\\{:03o}", b).unwrap(),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Converts `expr`, of type `from`, for a store or a cast to `to`.
+fn convert(expr: String, from: Type, to: Type, span: Span) -> String {
+    let (row, col) = span.start;
+
+    match (from, to) {
+        (from, to) if from == to => expr,
+        (Type::Bool, Type::Int) => format!("((long long){})", expr),
+        (Type::Bool, Type::Float) | (Type::Int, Type::Float) => format!("((double){})", expr),
+        (Type::Int, Type::Bool) => format!("({} != 0)", expr),
+        (Type::Float, Type::Bool) => format!("({} != 0.0)", expr),
+        (Type::Float, Type::Int) => format!("zl_ftoi({})", expr),
+        (Type::String, Type::Bool) => format!("zl_stob({}, {}, {})", expr, row, col),
+        (Type::String, Type::Int) => format!("zl_stoi({}, {}, {})", expr, row, col),
+        (Type::String, Type::Float) => format!("zl_stof({}, {}, {})", expr, row, col),
+        // The grammar has no casts to string
+        (from, to) => unreachable!("no conversion from {} to {}", from, to),
+    }
+}
+
+/// The `printf` conversion and argument that print a value of type `ty`.
+/// `buf` picks one of the buffers for formatting floats.
+fn printf_arg(expr: String, ty: Type, buf: usize) -> (&'static str, String) {
+    match ty {
+        Type::Bool => ("%s", format!("({} ? \"true\" : \"false\")", expr)),
+        Type::Int => ("%lld", expr),
+        Type::Float => ("%s", format!("zl_float({}, zl_bufs[{}])", expr, buf)),
+        Type::String => ("%s", expr),
+    }
+}
+
+impl Generator<'_> {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str("    ");
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn temp(&mut self) -> String {
+        self.temps += 1;
+        format!("zt_{}", self.temps)
+    }
+
+    /// Generates the statements of a block. The caller writes the braces.
+    fn scoped(&mut self, stmts: &[Stmt]) {
+        let program = self.program;
+        for stmt in stmts {
+            if let Stmt::Decl(decl) = stmt {
+                let redeclared = decl
+                    .vars
+                    .iter()
+                    .flat_map(|v| v.vars())
+                    .filter(|&(ident, var)| !program.var(var).declared_by(ident))
+                    .map(|(_, var)| var);
+                self.redeclared.extend(redeclared);
+            }
+        }
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn c_name(&self, var: VarId) -> &str {
+        &self.c_names[&var]
+    }
+
+    /// Whether stores to `var` are kept. Stores to a `const` are dropped,
+    /// like in the interpreter.
+    fn writable(&self, var: VarId) -> bool {
+        !self.program.var(var).ty.constant
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.line("{");
+        self.indent += 1;
+        self.scoped(&block.stmts);
+        self.indent -= 1;
+        self.line("}");
+    }
+
+    /// The body of an `if` or a `while`. A bare declaration is wrapped in
+    /// braces, C does not allow one there, which also give it the scope of
+    /// its own it has in zlang.
+    fn body(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Block(block) => self.block(block),
+            Stmt::Decl(_) => {
+                self.line("{");
+                self.indent += 1;
+                self.scoped(std::slice::from_ref(stmt));
+                self.indent -= 1;
+                self.line("}");
+            }
+            stmt => {
+                self.indent += 1;
+                self.stmt(stmt);
+                self.indent -= 1;
+            }
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Decl(decl) => self.decl(decl),
+            Stmt::Assign(assign) => self.assign(assign),
+            Stmt::If(if_) => {
+                let cond = self.expr(&if_.cond);
+                self.line(&format!("if ({})", cond));
+                self.body(&if_.then);
+            }
+            Stmt::IfElse(if_else) => {
+                let cond = self.expr(&if_else.cond);
+                self.line(&format!("if ({})", cond));
+                self.block(&if_else.then);
+                self.line("else");
+                self.body(&if_else.otherwise);
+            }
+            Stmt::While(while_) => {
+                let cond = self.expr(&while_.cond);
+                self.line(&format!("while ({})", cond));
+                self.body(&while_.body);
+            }
+            Stmt::Emit(Emit::Value { var, first, second }) => {
+                let mut format = Vec::new();
+                let mut args = Vec::new();
+
+                for (i, expr) in [var, first, second].iter().enumerate() {
+                    let (spec, arg) = printf_arg(self.expr(expr), expr.ty, i);
+                    format.push(spec);
+                    args.push(arg);
+                }
+                self.line(&format!(
+                    "printf(\"{}\\n\", {});",
+                    format.join(" "),
+                    args.join(", ")
+                ));
+            }
+            Stmt::Emit(Emit::Symtable { vars, .. }) => self.symtable(vars),
+            Stmt::Block(block) => self.block(block),
+        }
+    }
+
+    /// One `printf` per live variable, in the symbol table dump format with
+    /// the value appended.
+    fn symtable(&mut self, live: &[VarId]) {
+        let mut lines = Vec::new();
+
+        for &id in live {
+            let var = self.program.var(id);
+            let constant = if var.ty.constant { "const" } else { "" };
+            let (spec, arg) = printf_arg(self.c_name(id).to_string(), var.ty.ty, 0);
+            lines.push(format!(
+                "printf(\"{},{}{},{},{}\\n\", {});",
+                var.depth, constant, var.ty.ty, var.name, spec, arg
+            ));
+        }
+
+        for line in lines {
+            self.line(&line);
+        }
+    }
+
+    fn decl(&mut self, decl: &Decl) {
+        for var in &decl.vars {
+            match var {
+                Declarator::Ident(ident, var) => {
+                    if self.program.var(*var).declared_by(ident) {
+                        let zero = match decl.ty.ty {
+                            Type::Bool => "false",
+                            Type::Int => "0",
+                            Type::Float => "0.0",
+                            Type::String => "\"\"",
+                        };
+                        self.declare(*var, zero.to_string());
+                    }
+                }
+                Declarator::Init(assign) => {
+                    let value = self.expr(&assign.value);
+                    let value = if assign.targets.len() > 1 {
+                        let temp = self.temp();
+                        self.line(&format!(
+                            "{} {} = {};",
+                            c_type(assign.value.ty),
+                            temp,
+                            value
+                        ));
+                        temp
+                    } else {
+                        value
+                    };
+
+                    for target in &assign.targets {
+                        let ty = self.program.var(target.var).ty.ty;
+                        let value = convert(value.clone(), assign.value.ty, ty, target.span);
+
+                        if self.program.var(target.var).declared_by(&target.ident) {
+                            self.declare(target.var, value);
+                        } else {
+                            let store = format!("{} = {};", self.c_name(target.var), value);
+                            self.line(&store);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Declares `ident` as the C variable `z_name`, or `zN_name` if it shadows
+    /// another; no `z_` name or temporary starts with `zN_`, so no C name can
+    /// belong to two variables
+    fn declare(&mut self, id: VarId, value: String) {
+        let var = self.program.var(id);
+        let count = self.names.entry(var.name.clone()).or_insert(0);
+        let c_name = match *count {
+            0 => format!("z_{}", var.name),
+            n => format!("z{}_{}", n, var.name),
+        };
+        *count += 1;
+
+        let constant = var.ty.constant && !self.redeclared.contains(&id);
+        let qualifier = if constant { "const " } else { "" };
+        let line = format!("{}{} {} = {};", qualifier, c_type(var.ty.ty), c_name, value);
+        self.c_names.insert(id, c_name);
+        self.line(&line);
+    }
+
+    fn assign(&mut self, assign: &Assign) {
+        let value = self.expr(&assign.value);
+        let targets: Vec<_> = assign
+            .targets
+            .iter()
+            .filter(|t| self.writable(t.var))
+            .collect();
+
+        match targets.as_slice() {
+            [] => self.line(&format!("(void){};", value)),
+            [target] => {
+                let ty = self.program.var(target.var).ty.ty;
+                let value = convert(value, assign.value.ty, ty, target.span);
+                let store = format!("{} = {};", self.c_name(target.var), value);
+                self.line(&store);
+            }
+            targets => {
+                let temp = self.temp();
+                let mut stores = vec![format!("{} {} = {};", c_type(assign.value.ty), temp, value)];
+                for target in targets {
+                    let ty = self.program.var(target.var).ty.ty;
+                    let value = convert(temp.clone(), assign.value.ty, ty, target.span);
+                    stores.push(format!("{} = {};", self.c_name(target.var), value));
+                }
+
+                self.line("{");
+                self.indent += 1;
+                for store in stores {
+                    self.line(&store);
+                }
+                self.indent -= 1;
+                self.line("}");
+            }
+        }
+    }
+
+    fn expr(&self, expr: &Expr) -> String {
+        let (row, col) = expr.span.start;
+
+        match &expr.kind {
+            // C has no literal for the smallest `long long`, only its negation
+            ExprKind::Literal(Literal::Int(i64::MIN)) => format!("({}LL - 1)", i64::MIN + 1),
+            ExprKind::Literal(Literal::Int(i)) => format!("{}LL", i),
+            ExprKind::Literal(Literal::Float(f)) => format!("{:?}", f),
+            ExprKind::Literal(Literal::String(s)) => c_string(s),
+            ExprKind::Var(_, var) => self.c_name(*var).to_string(),
+            ExprKind::Unary { op, operand } => {
+                let value = self.expr(operand);
+
+                match (op, operand.ty) {
+                    (UnOp::Plus, _) => value,
+                    (UnOp::Neg, Type::Int) => format!("zl_neg({})", value),
+                    (UnOp::Neg, _) => format!("(-{})", value),
+                    (UnOp::Not, _) => format!("(!{})", value),
+                    (UnOp::Compl, _) => format!("(~{} != 0)", value),
+                }
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                // Mixed int and float operands are both made doubles
+                let ty = if lhs.ty != rhs.ty && lhs.ty.is_numeric() && rhs.ty.is_numeric() {
+                    Type::Float
+                } else {
+                    lhs.ty
+                };
+                let l = convert(self.expr(lhs), lhs.ty, ty, lhs.span);
+                let r = convert(self.expr(rhs), rhs.ty, ty, rhs.span);
+
+                match (op, ty) {
+                    (BinOp::Eq, Type::String) => format!("(strcmp({}, {}) == 0)", l, r),
+                    (BinOp::Add, Type::Int) => format!("zl_add({}, {})", l, r),
+                    (BinOp::Sub, Type::Int) => format!("zl_sub({}, {})", l, r),
+                    (BinOp::Mul, Type::Int) => format!("zl_mul({}, {})", l, r),
+                    (BinOp::Div, Type::Int) => format!("zl_div({}, {}, {}, {})", l, r, row, col),
+                    (BinOp::Mod, Type::Int) => format!("zl_mod({}, {}, {}, {})", l, r, row, col),
+                    (op, _) => format!("({} {} {})", l, op.symbol(), r),
+                }
+            }
+            ExprKind::Cast(inner) => convert(self.expr(inner), inner.ty, expr.ty, expr.span),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interp;
+    use crate::parser::Parser;
+    use crate::semantics::typeck;
+    use crate::syntax;
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    fn check(src: &str) -> Program {
+        let tree = Parser::new().parse_str(src).unwrap();
+        typeck::check(&syntax::Program::from_cst(&tree).unwrap()).unwrap()
+    }
+
+    /// Compiles the generated C with `cc` and runs it, `None` if there is no
+    /// C compiler to test with.
+    fn compile_and_run(name: &str, program: &Program) -> Option<(String, String, i32)> {
+        let dir = std::env::temp_dir().join(format!("zobos-c-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join(format!("{}.c", name));
+        let binary: PathBuf = dir.join(name);
+        std::fs::write(&source, generate(program)).unwrap();
+
+        let cc = Command::new("cc")
+            .args([
+                "-std=c99",
+                "-pedantic",
+                "-Wall",
+                "-Wno-unused",
+                "-Werror",
+                "-o",
+            ])
+            .arg(&binary)
+            .arg(&source)
+            .arg("-lm")
+            .output();
+        let cc = match cc {
+            Ok(cc) => cc,
+            Err(_) => {
+                eprintln!("no cc, skipping {}", name);
+                return None;
+            }
+        };
+        assert!(
+            cc.status.success(),
+            "{}",
+            String::from_utf8_lossy(&cc.stderr)
+        );
+
+        let run = Command::new(&binary).output().unwrap();
+        Some((
+            String::from_utf8(run.stdout).unwrap(),
+            String::from_utf8(run.stderr).unwrap(),
+            run.status.code().unwrap(),
+        ))
+    }
+
+    /// The compiled program prints what the interpreter prints.
+    fn agrees(name: &str, src: &str) {
+        let program = check(src);
+        let mut expected = Vec::new();
+        interp::run(&program, &mut expected).unwrap();
+
+        if let Some((out, err, code)) = compile_and_run(name, &program) {
+            assert_eq!(out, String::from_utf8(expected).unwrap());
+            assert_eq!((err.as_str(), code), ("", 0));
+        }
+    }
+
+    #[test]
+    fn arithmetic_and_conversions() {
+        agrees(
+            "arith",
+            "int i = 7, j = -3;\nfloat f = i;\nbool b = bool(j);\n\
+             emit i i / j i % j;\nemit f f / 4 i * 1.5;\nemit b (i < f) (2 == 2.0);\n\
+             float big = 1.0e20, small = 0.00001, third = 1.0 / 3;\n\
+             emit big small third;\nemit big 100.0 int(\"  12 \") + int(2.9);\n\
+             emit i -i + +j ~i;\n",
+        );
+    }
+
+    #[test]
+    fn control_flow_and_scopes() {
+        agrees(
+            "control",
+            "int n = 10, a, b = 1, i;\n\
+             while (i < n) { int t = a + b; a = b; b = t; i = i + 1; }\n\
+             const int c = 3;\nc = a = b = 7;\n\
+             if (a == 7) { int a = 1; emit a b c; emit symtable; } else emit a a a;\n\
+             int z = 5;\nif (a < 8) int z = 1;\nwhile (z < 5) float z;\nemit z 0 0;\n\
+             string s = \"tab\\there \\\"q\\\"\", t = s;\n\
+             if (s == t) emit s t b;\n\
+             int x = 1;\nint x = 2;\nemit symtable;\n",
+        );
+    }
+
+    #[test]
+    fn shadowing_names_do_not_clash() {
+        agrees(
+            "shadow",
+            "int a_1 = 5;\nint a = 1;\n{ int a = 2; emit a_1 a a; }\n",
+        );
+    }
+
+    #[test]
+    fn const_declarations() {
+        let c = generate(&check("const float pi = 3;\nint x = 1;\n"));
+
+        assert!(c.contains("    const double z_pi = ((double)3LL);\n"));
+        assert!(c.contains("    long long z_x = 1LL;\n"));
+    }
+
+    #[test]
+    fn runtime_errors() {
+        let program = check("int z = 0;\nemit z z 1 / z;\n");
+
+        if let Some((out, err, code)) = compile_and_run("div", &program) {
+            assert_eq!(out, "");
+            assert_eq!(err, "runtime error at 2:12: division by zero\n");
+            assert_eq!(code, 1);
+        }
+    }
+}
@@ -0,0 +1,7 @@
+//! Backends that turn a type checked program into source for another
+//! toolchain. Each one produces the same output as `interp` when the
+//! generated program runs.
+
+pub mod c;
+pub mod wat;
+pub mod x86;
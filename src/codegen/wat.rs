@@ -0,0 +1,723 @@
+//! Lowers a type checked program to a WebAssembly text module.
+//!
+//! `int` is `i64`, `float` is `f64` and `bool` is `i32`. Every variable is a
+//! local of `main`, named after the variable and numbered when it shadows
+//! another one. Strings only ever come from literals, so each distinct one
+//! is stored once in the exported memory, as a 4 byte length followed by
+//! its bytes, and a string is the `i32` address of that record. Two strings
+//! are then equal exactly when their addresses are.
+//!
+//! The module imports everything that talks to the outside world from
+//! `zlang`:
+//!
+//! - `print_int`, `print_float`, `print_bool` and `print_str` print one
+//!   value, floats formatted the way the interpreter prints them. `emit`
+//!   prints its separators with `print_str`.
+//! - `parse_int`, `parse_float` and `parse_bool` convert a string, and
+//!   `error` reports a runtime error. Both take the row and column of the
+//!   error and should trap to stop the program.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::syntax::{BinOp, Literal, Span, UnOp};
+use crate::typed::{Assign, Block, Decl, Declarator, Emit, Expr, ExprKind, Program, Stmt, VarId};
+use crate::types::Type;
+
+/// The imports and the helpers for integer division, which traps where
+/// zlang reports an error or wraps.
+const PRELUDE: &str = r#"  (import "zlang" "print_int" (func $print_int (param i64)))
+  (import "zlang" "print_float" (func $print_float (param f64)))
+  (import "zlang" "print_bool" (func $print_bool (param i32)))
+  (import "zlang" "print_str" (func $print_str (param i32)))
+  (import "zlang" "parse_int" (func $parse_int (param i32 i32 i32) (result i64)))
+  (import "zlang" "parse_float" (func $parse_float (param i32 i32 i32) (result f64)))
+  (import "zlang" "parse_bool" (func $parse_bool (param i32 i32 i32) (result i32)))
+  (import "zlang" "error" (func $error (param i32 i32 i32)))
+  (func $div (param $a i64) (param $b i64) (param $row i32) (param $col i32) (result i64)
+    local.get $b
+    i64.eqz
+    if
+      local.get $row
+      local.get $col
+      global.get $div_zero
+      call $error
+      unreachable
+    end
+    local.get $b
+    i64.const -1
+    i64.eq
+    if (result i64)
+      i64.const 0
+      local.get $a
+      i64.sub
+    else
+      local.get $a
+      local.get $b
+      i64.div_s
+    end)
+  (func $rem (param $a i64) (param $b i64) (param $row i32) (param $col i32) (result i64)
+    local.get $b
+    i64.eqz
+    if
+      local.get $row
+      local.get $col
+      global.get $div_zero
+      call $error
+      unreachable
+    end
+    local.get $b
+    i64.const -1
+    i64.eq
+    if (result i64)
+      i64.const 0
+    else
+      local.get $a
+      local.get $b
+      i64.rem_s
+    end)
+"#;
+
+/// Generates the module for `program`.
+pub fn generate(program: &Program) -> String {
+    let mut gen = Generator {
+        program,
+        out: String::new(),
+        indent: 2,
+        vars: HashMap::new(),
+        names: HashMap::new(),
+        locals: Vec::new(),
+        strings: Strings::default(),
+        labels: 0,
+    };
+    // The message is a string like any other
+    let div_zero = gen.strings.get("division by zero");
+
+    gen.stmts(&program.stmts);
+
+    let mut module = String::from(";; Generated by ZOBOS from a zlang program\n(module\n");
+    module.push_str(PRELUDE);
+    writeln!(
+        module,
+        "  (memory (export \"memory\") {})",
+        gen.strings.data.len() / 65536 + 1
+    )
+    .unwrap();
+    writeln!(
+        module,
+        "  (data (i32.const 0) {})",
+        wat_string(&gen.strings.data)
+    )
+    .unwrap();
+    writeln!(module, "  (global $div_zero i32 (i32.const {}))", div_zero).unwrap();
+    module.push_str("  (func $main (export \"main\")\n");
+    for (name, ty) in &gen.locals {
+        writeln!(module, "    (local ${} {})", name, ty).unwrap();
+    }
+    module.push_str(&gen.out);
+    module.push_str("  )\n)\n");
+    module
+}
+
+/// The string literals, laid out in memory.
+#[derive(Default)]
+struct Strings {
+    data: Vec<u8>,
+    addresses: HashMap<String, usize>,
+}
+
+impl Strings {
+    /// The address of `s`, adding it if it is new.
+    fn get(&mut self, s: &str) -> usize {
+        if let Some(&address) = self.addresses.get(s) {
+            return address;
+        }
+
+        // Keep the lengths aligned
+        while !self.data.len().is_multiple_of(4) {
+            self.data.push(0);
+        }
+        let address = self.data.len();
+        self.data.extend_from_slice(&(s.len() as u32).to_le_bytes());
+        self.data.extend_from_slice(s.as_bytes());
+        self.addresses.insert(s.to_string(), address);
+        address
+    }
+}
+
+/// A WAT string. Everything but printable ASCII is a hex escape.
+fn wat_string(bytes: &[u8]) -> String {
+    let mut out = String::from("\"");
+    for &b in bytes {
+        match b {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            0x20..=0x7e => out.push(b as char),
+            _ => write!(out, "\\{:02x}", b).unwrap(),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn wasm_type(ty: Type) -> &'static str {
+    match ty {
+        Type::Bool | Type::String => "i32",
+        Type::Int => "i64",
+        Type::Float => "f64",
+    }
+}
+
+struct Generator<'p> {
+    program: &'p Program,
+    out: String,
+    indent: usize,
+    /// The local that holds each variable
+    vars: HashMap<VarId, String>,
+    /// How many locals each name has had
+    names: HashMap<String, usize>,
+    /// `(name, type)` for every local, declared at the top of `main`
+    locals: Vec<(String, &'static str)>,
+    strings: Strings,
+    labels: usize,
+}
+
+impl Generator<'_> {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str("  ");
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn local(&mut self, name: &str, ty: Type) -> String {
+        let count = self.names.entry(name.to_string()).or_insert(0);
+        let local = match *count {
+            0 => name.to_string(),
+            // zlang identifiers cannot contain a `.`
+            n => format!("{}.{}", name, n),
+        };
+        *count += 1;
+        self.locals.push((local.clone(), wasm_type(ty)));
+        local
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    /// The body of an `if` or a `while`.
+    fn body(&mut self, stmt: &Stmt) {
+        self.indent += 1;
+        self.stmt(stmt);
+        self.indent -= 1;
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Decl(decl) => self.decl(decl),
+            Stmt::Assign(assign) => self.assign(assign),
+            Stmt::If(if_) => {
+                self.expr(&if_.cond);
+                self.line("if");
+                self.body(&if_.then);
+                self.line("end");
+            }
+            Stmt::IfElse(if_else) => {
+                self.expr(&if_else.cond);
+                self.line("if");
+                self.indent += 1;
+                self.stmts(&if_else.then.stmts);
+                self.indent -= 1;
+                self.line("else");
+                self.body(&if_else.otherwise);
+                self.line("end");
+            }
+            Stmt::While(while_) => {
+                let n = self.labels;
+                self.labels += 1;
+
+                self.line(&format!("block $break{}", n));
+                self.indent += 1;
+                self.line(&format!("loop $continue{}", n));
+                self.indent += 1;
+                self.expr(&while_.cond);
+                self.line("i32.eqz");
+                self.line(&format!("br_if $break{}", n));
+                self.indent -= 1;
+                self.body(&while_.body);
+                self.indent += 1;
+                self.line(&format!("br $continue{}", n));
+                self.indent -= 1;
+                self.line("end");
+                self.indent -= 1;
+                self.line("end");
+            }
+            Stmt::Emit(Emit::Value { var, first, second }) => {
+                for (i, expr) in [var, first, second].iter().enumerate() {
+                    self.expr(expr);
+                    self.print(expr.ty);
+                    self.print_str(if i < 2 { " " } else { "\n" });
+                }
+            }
+            Stmt::Emit(Emit::Symtable { vars, .. }) => self.symtable(vars),
+            Stmt::Block(block) => self.block(block),
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.line("block");
+        self.indent += 1;
+        self.stmts(&block.stmts);
+        self.indent -= 1;
+        self.line("end");
+    }
+
+    /// Prints the value of type `ty` on top of the stack.
+    fn print(&mut self, ty: Type) {
+        let func = match ty {
+            Type::Bool => "$print_bool",
+            Type::Int => "$print_int",
+            Type::Float => "$print_float",
+            Type::String => "$print_str",
+        };
+        self.line(&format!("call {}", func));
+    }
+
+    fn print_str(&mut self, s: &str) {
+        let address = self.strings.get(s);
+        self.line(&format!("i32.const {}", address));
+        self.line("call $print_str");
+    }
+
+    /// Every live variable, in the symbol table dump format with the value
+    /// appended.
+    fn symtable(&mut self, live: &[VarId]) {
+        let mut vars = Vec::new();
+
+        for &id in live {
+            let var = self.program.var(id);
+            let constant = if var.ty.constant { "const" } else { "" };
+            let prefix = format!("{},{}{},{},", var.depth, constant, var.ty.ty, var.name);
+            vars.push((prefix, self.vars[&id].clone(), var.ty.ty));
+        }
+
+        for (prefix, local, ty) in vars {
+            self.print_str(&prefix);
+            self.line(&format!("local.get ${}", local));
+            self.print(ty);
+            self.print_str("\n");
+        }
+    }
+
+    fn decl(&mut self, decl: &Decl) {
+        for var in &decl.vars {
+            match var {
+                Declarator::Ident(ident, var) => {
+                    if self.program.var(*var).declared_by(ident) {
+                        // Locals start out zero, but a declaration in a loop
+                        // runs more than once
+                        match decl.ty.ty {
+                            Type::Bool => self.line("i32.const 0"),
+                            Type::Int => self.line("i64.const 0"),
+                            Type::Float => self.line("f64.const 0"),
+                            Type::String => {
+                                let address = self.strings.get("");
+                                self.line(&format!("i32.const {}", address));
+                            }
+                        }
+                        self.declare(*var);
+                    }
+                }
+                Declarator::Init(assign) => {
+                    self.expr(&assign.value);
+                    let value = self.spread(assign.value.ty, assign.targets.len());
+
+                    for target in &assign.targets {
+                        if let Some(temp) = &value {
+                            self.line(&format!("local.get ${}", temp));
+                        }
+
+                        let var = self.program.var(target.var);
+                        let new = var.declared_by(&target.ident);
+                        self.convert(assign.value.ty, var.ty.ty, target.span);
+
+                        if new {
+                            self.declare(target.var);
+                        } else {
+                            let local = &self.vars[&target.var];
+                            self.line(&format!("local.set ${}", local));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Stores the value on top of the stack to a temporary if there is more
+    /// than one target, which then reads it back for each.
+    fn spread(&mut self, ty: Type, targets: usize) -> Option<String> {
+        if targets > 1 {
+            let temp = self.local(".tmp", ty);
+            self.line(&format!("local.set ${}", temp));
+            Some(temp)
+        } else {
+            None
+        }
+    }
+
+    /// Pops the value on top of the stack into a new variable.
+    fn declare(&mut self, id: VarId) {
+        let var = self.program.var(id);
+        let local = self.local(&var.name, var.ty.ty);
+        self.line(&format!("local.set ${}", local));
+        self.vars.insert(id, local);
+    }
+
+    /// Stores to a `const` are dropped, like in the interpreter, but the
+    /// value is still computed.
+    fn assign(&mut self, assign: &Assign) {
+        self.expr(&assign.value);
+        let targets: Vec<_> = assign
+            .targets
+            .iter()
+            .filter(|t| !self.program.var(t.var).ty.constant)
+            .map(|t| {
+                let ty = self.program.var(t.var).ty.ty;
+                (self.vars[&t.var].clone(), ty, t.span)
+            })
+            .collect();
+
+        if targets.is_empty() {
+            self.line("drop");
+            return;
+        }
+
+        let temp = self.spread(assign.value.ty, targets.len());
+        for (local, ty, span) in targets {
+            if let Some(temp) = &temp {
+                self.line(&format!("local.get ${}", temp));
+            }
+            self.convert(assign.value.ty, ty, span);
+            self.line(&format!("local.set ${}", local));
+        }
+    }
+
+    /// Converts the value on top of the stack, of type `from`, for a store
+    /// or a cast to `to`.
+    fn convert(&mut self, from: Type, to: Type, span: Span) {
+        let (row, col) = span.start;
+        let parse = |func: &str| {
+            vec![
+                format!("i32.const {}", row),
+                format!("i32.const {}", col),
+                format!("call {}", func),
+            ]
+        };
+
+        let lines = match (from, to) {
+            (from, to) if from == to => vec![],
+            (Type::Bool, Type::Int) => vec!["i64.extend_i32_u".to_string()],
+            (Type::Bool, Type::Float) => vec!["f64.convert_i32_u".to_string()],
+            (Type::Int, Type::Float) => vec!["f64.convert_i64_s".to_string()],
+            (Type::Int, Type::Bool) => vec!["i64.const 0".to_string(), "i64.ne".to_string()],
+            (Type::Float, Type::Bool) => vec!["f64.const 0".to_string(), "f64.ne".to_string()],
+            // Saturates and makes NaN 0, like a Rust `as` cast
+            (Type::Float, Type::Int) => vec!["i64.trunc_sat_f64_s".to_string()],
+            (Type::String, Type::Bool) => parse("$parse_bool"),
+            (Type::String, Type::Int) => parse("$parse_int"),
+            (Type::String, Type::Float) => parse("$parse_float"),
+            // The grammar has no casts to string
+            (from, to) => unreachable!("no conversion from {} to {}", from, to),
+        };
+
+        for line in lines {
+            self.line(&line);
+        }
+    }
+
+    /// Pushes the value of `expr`.
+    fn expr(&mut self, expr: &Expr) {
+        let (row, col) = expr.span.start;
+
+        match &expr.kind {
+            ExprKind::Literal(Literal::Int(i)) => self.line(&format!("i64.const {}", i)),
+            ExprKind::Literal(Literal::Float(f)) => self.line(&format!("f64.const {:?}", f)),
+            ExprKind::Literal(Literal::String(s)) => {
+                let address = self.strings.get(s);
+                self.line(&format!("i32.const {}", address));
+            }
+            ExprKind::Var(_, var) => {
+                let local = self.vars[var].clone();
+                self.line(&format!("local.get ${}", local));
+            }
+            ExprKind::Unary { op, operand } => match (op, operand.ty) {
+                (UnOp::Plus, _) => self.expr(operand),
+                (UnOp::Neg, Type::Int) => {
+                    self.line("i64.const 0");
+                    self.expr(operand);
+                    self.line("i64.sub");
+                }
+                (UnOp::Neg, _) => {
+                    self.expr(operand);
+                    self.line("f64.neg");
+                }
+                (UnOp::Not, _) => {
+                    self.expr(operand);
+                    self.line("i32.eqz");
+                }
+                (UnOp::Compl, _) => {
+                    self.expr(operand);
+                    self.line("i64.const -1");
+                    self.line("i64.ne");
+                }
+            },
+            ExprKind::Binary { op, lhs, rhs } => {
+                // Mixed int and float operands are both made floats
+                let ty = if lhs.ty != rhs.ty && lhs.ty.is_numeric() && rhs.ty.is_numeric() {
+                    Type::Float
+                } else {
+                    lhs.ty
+                };
+                self.expr(lhs);
+                self.convert(lhs.ty, ty, lhs.span);
+                self.expr(rhs);
+                self.convert(rhs.ty, ty, rhs.span);
+
+                let prefix = wasm_type(ty);
+                let inst = match (op, ty) {
+                    (BinOp::Div, Type::Int) | (BinOp::Mod, Type::Int) => {
+                        self.line(&format!("i32.const {}", row));
+                        self.line(&format!("i32.const {}", col));
+                        let func = if *op == BinOp::Div { "$div" } else { "$rem" };
+                        self.line(&format!("call {}", func));
+                        return;
+                    }
+                    (BinOp::Add, _) => "add",
+                    (BinOp::Sub, _) => "sub",
+                    (BinOp::Mul, _) => "mul",
+                    (BinOp::Div, _) => "div",
+                    (BinOp::Eq, _) => "eq",
+                    (BinOp::Lt, Type::Float) => "lt",
+                    (BinOp::Leq, Type::Float) => "le",
+                    (BinOp::Geq, Type::Float) => "ge",
+                    (BinOp::Gt, Type::Float) => "gt",
+                    (BinOp::Lt, _) => "lt_s",
+                    (BinOp::Leq, _) => "le_s",
+                    (BinOp::Geq, _) => "ge_s",
+                    (BinOp::Gt, _) => "gt_s",
+                    (BinOp::Mod, ty) => unreachable!("% on {} was not type checked", ty),
+                };
+                self.line(&format!("{}.{}", prefix, inst));
+            }
+            ExprKind::Cast(inner) => {
+                self.expr(inner);
+                self.convert(inner.ty, expr.ty, expr.span);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interp::{self, Value};
+    use crate::parser::Parser;
+    use crate::semantics::typeck;
+    use crate::syntax;
+    use wasmi::{Caller, Engine, Linker, Module, Store};
+
+    fn check(src: &str) -> Program {
+        let tree = Parser::new().parse_str(src).unwrap();
+        typeck::check(&syntax::Program::from_cst(&tree).unwrap()).unwrap()
+    }
+
+    fn read_str(caller: &Caller<'_, String>, address: i32) -> String {
+        let memory = caller.get_export("memory").unwrap().into_memory().unwrap();
+        let data = memory.data(caller);
+        let address = address as usize;
+        let mut len = [0; 4];
+        len.copy_from_slice(&data[address..address + 4]);
+        let len = u32::from_le_bytes(len) as usize;
+        String::from_utf8(data[address + 4..address + 4 + len].to_vec()).unwrap()
+    }
+
+    /// Parses `s` the way the interpreter does, trapping with its error.
+    fn parse(
+        caller: &Caller<'_, String>,
+        s: i32,
+        to: Type,
+        span: Span,
+    ) -> Result<Value, wasmi::Error> {
+        let s = read_str(caller, s);
+        interp::convert(Value::String(s), to, span).map_err(|e| wasmi::Error::new(e.to_string()))
+    }
+
+    /// Validates the module and runs it, returning what it printed or the
+    /// runtime error it stopped with.
+    fn run(program: &Program) -> Result<String, String> {
+        let wasm = wat::parse_str(generate(program)).unwrap();
+        wasmparser::Validator::new().validate_all(&wasm).unwrap();
+
+        let engine = Engine::default();
+        let module = Module::new(&engine, &wasm[..]).unwrap();
+        let mut store = Store::new(&engine, String::new());
+        let mut linker = <Linker<String>>::new(&engine);
+        linker
+            .func_wrap("zlang", "print_int", |mut c: Caller<'_, String>, i: i64| {
+                c.data_mut().push_str(&i.to_string())
+            })
+            .unwrap()
+            .func_wrap(
+                "zlang",
+                "print_float",
+                |mut c: Caller<'_, String>, f: f64| {
+                    c.data_mut().push_str(&Value::Float(f).to_string())
+                },
+            )
+            .unwrap()
+            .func_wrap(
+                "zlang",
+                "print_bool",
+                |mut c: Caller<'_, String>, b: i32| c.data_mut().push_str(&(b != 0).to_string()),
+            )
+            .unwrap()
+            .func_wrap("zlang", "print_str", |mut c: Caller<'_, String>, s: i32| {
+                let s = read_str(&c, s);
+                c.data_mut().push_str(&s)
+            })
+            .unwrap()
+            .func_wrap(
+                "zlang",
+                "parse_int",
+                |c: Caller<'_, String>, s: i32, row: i32, col: i32| match parse(
+                    &c,
+                    s,
+                    Type::Int,
+                    Span::point(row as usize, col as usize),
+                )? {
+                    Value::Int(i) => Ok(i),
+                    v => unreachable!("{:?}", v),
+                },
+            )
+            .unwrap()
+            .func_wrap(
+                "zlang",
+                "parse_float",
+                |c: Caller<'_, String>, s: i32, row: i32, col: i32| match parse(
+                    &c,
+                    s,
+                    Type::Float,
+                    Span::point(row as usize, col as usize),
+                )? {
+                    Value::Float(f) => Ok(f),
+                    v => unreachable!("{:?}", v),
+                },
+            )
+            .unwrap()
+            .func_wrap(
+                "zlang",
+                "parse_bool",
+                |c: Caller<'_, String>, s: i32, row: i32, col: i32| match parse(
+                    &c,
+                    s,
+                    Type::Bool,
+                    Span::point(row as usize, col as usize),
+                )? {
+                    Value::Bool(b) => Ok(b as i32),
+                    v => unreachable!("{:?}", v),
+                },
+            )
+            .unwrap()
+            .func_wrap(
+                "zlang",
+                "error",
+                |c: Caller<'_, String>,
+                 row: i32,
+                 col: i32,
+                 message: i32|
+                 -> Result<(), wasmi::Error> {
+                    let message = read_str(&c, message);
+                    Err(wasmi::Error::new(format!(
+                        "runtime error at {}:{}: {}",
+                        row, col, message
+                    )))
+                },
+            )
+            .unwrap();
+
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .unwrap()
+            .start(&mut store)
+            .unwrap();
+        let main = instance.get_typed_func::<(), ()>(&store, "main").unwrap();
+
+        match main.call(&mut store, ()) {
+            Ok(()) => Ok(store.into_data()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// The module prints what the interpreter prints.
+    fn agrees(src: &str) {
+        let program = check(src);
+        let mut expected = Vec::new();
+        interp::run(&program, &mut expected).unwrap();
+
+        assert_eq!(run(&program).unwrap(), String::from_utf8(expected).unwrap());
+    }
+
+    #[test]
+    fn arithmetic_and_conversions() {
+        agrees(
+            "int i = 7, j = -3;\nfloat f = i;\nbool b = bool(j);\n\
+             emit i i / j i % j;\nemit f f / 4 i * 1.5;\nemit b (i < f) (2 == 2.0);\n\
+             float big = 1.0e20, small = 0.00001, third = 1.0 / 3;\n\
+             emit big small third;\nemit big 100.0 int(\"  12 \") + int(2.9);\n\
+             emit i -i + +j ~i;\nfloat g = -f;\nemit g (f <= 7) (f > 7.5);\n\
+             float h = float(\" 2.5\");\nemit h bool(\"false\") bool(0.0);\n\
+             int min = -9223372036854775807 - 1, q = min / -1;\n\
+             emit q min % -1 int(-1.0e30);\nbool nb = !b;\nemit nb 0 0;\n",
+        );
+    }
+
+    #[test]
+    fn control_flow_and_scopes() {
+        agrees(
+            "int n = 10, a, b = 1, i;\n\
+             while (i < n) { int t = a + b; a = b; b = t; i = i + 1; }\n\
+             const int c = 3;\nc = a = b = 7;\n\
+             if (a == 7) { int a = 1; emit a b c; emit symtable; } else emit a a a;\n\
+             int z = 5;\nif (a < 8) int z = 1;\nwhile (z < 5) float z;\nemit z 0 0;\n\
+             string s = \"tab\\there \\\"q\\\"\", t = s;\n\
+             if (s == t) emit s t b;\n\
+             bool e = s == \"tab\\there \\\"q\\\"\";\nemit e 0 0;\n\
+             int x = 1;\nint x = 2;\nemit symtable;\n",
+        );
+    }
+
+    #[test]
+    fn locals() {
+        let wat = generate(&check("int x = 1;\n{ float x = 2.0; }\nint y = x = 3;\n"));
+
+        assert!(wat.contains(
+            "    (local $x i64)\n    (local $x.1 f64)\n    (local $.tmp i64)\n    (local $y i64)\n"
+        ));
+    }
+
+    #[test]
+    fn runtime_errors() {
+        assert_eq!(
+            run(&check("int z = 0;\nemit z z 1 / z;\n")),
+            Err("runtime error at 2:12: division by zero".to_string())
+        );
+        assert_eq!(
+            run(&check("string s = \"1x\";\nint i = int(s);\n")),
+            Err("runtime error at 2:9: cannot convert \"1x\" to int".to_string())
+        );
+    }
+}
@@ -0,0 +1,610 @@
+//! The low level IR the x86-64 backend allocates registers for.
+//!
+//! A program is one flat list of instructions over an unlimited supply of
+//! virtual registers, with labels and jumps for control flow. Every zlang
+//! variable gets a virtual register of its own that is assigned like any
+//! other, so the allocator decides which variables live in registers.
+//! Implicit conversions are made explicit, the operands of an instruction
+//! always have the types it expects.
+
+use std::collections::HashMap;
+
+use crate::syntax::{BinOp, Literal, Span, UnOp};
+use crate::typed::{Assign, Block, Decl, Declarator, Emit, Expr, ExprKind, Program, Stmt, VarId};
+use crate::types::Type;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct VReg(pub u32);
+
+/// The register file a virtual register is allocated from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Class {
+    /// Ints, bools and string pointers
+    Gpr,
+    /// Floats
+    Xmm,
+}
+
+impl Class {
+    pub fn of(ty: Type) -> Self {
+        match ty {
+            Type::Float => Class::Xmm,
+            _ => Class::Gpr,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Label(pub u32);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IntOp {
+    Add,
+    Sub,
+    Mul,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FloatOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Cond {
+    Lt,
+    Leq,
+    Eq,
+    Geq,
+    Gt,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UnaryOp {
+    /// Wrapping integer negation
+    Neg,
+    FNeg,
+    /// Flips a bool
+    Not,
+    /// Whether any bit of the complement is set
+    Compl,
+    IntToFloat,
+    IntToBool,
+    FloatToBool,
+    /// The address of `"true"` or `"false"`, for printing a bool
+    BoolName,
+}
+
+/// An argument passed to a runtime or C library function.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Arg {
+    Reg(VReg),
+    Imm(i64),
+    /// The address of `strings[i]`
+    Str(usize),
+    /// The address of one of the buffers floats are formatted into
+    Buf(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inst {
+    Const {
+        dst: VReg,
+        value: i64,
+    },
+    /// `dst = floats[index]`
+    Float {
+        dst: VReg,
+        index: usize,
+    },
+    /// `dst` = the address of `strings[index]`
+    Str {
+        dst: VReg,
+        index: usize,
+    },
+    Move {
+        dst: VReg,
+        src: VReg,
+    },
+    Int {
+        op: IntOp,
+        dst: VReg,
+        lhs: VReg,
+        rhs: VReg,
+    },
+    /// Integer `/` or `%`, stopping the program on a zero divisor
+    Div {
+        dst: VReg,
+        lhs: VReg,
+        rhs: VReg,
+        rem: bool,
+        span: Span,
+    },
+    FloatOp {
+        op: FloatOp,
+        dst: VReg,
+        lhs: VReg,
+        rhs: VReg,
+    },
+    /// Compares two ints or two bools
+    Cmp {
+        cond: Cond,
+        dst: VReg,
+        lhs: VReg,
+        rhs: VReg,
+    },
+    /// Compares two floats, false if either is NaN
+    FCmp {
+        cond: Cond,
+        dst: VReg,
+        lhs: VReg,
+        rhs: VReg,
+    },
+    Unary {
+        op: UnaryOp,
+        dst: VReg,
+        src: VReg,
+    },
+    /// Clobbers every register calls do not preserve
+    Call {
+        func: &'static str,
+        args: Vec<Arg>,
+        ret: Option<VReg>,
+    },
+    Jump(Label),
+    JumpIfFalse {
+        cond: VReg,
+        target: Label,
+    },
+    Label(Label),
+}
+
+impl Inst {
+    /// The virtual registers the instruction reads.
+    pub fn uses(&self) -> Vec<VReg> {
+        match self {
+            Inst::Const { .. } | Inst::Float { .. } | Inst::Str { .. } => vec![],
+            Inst::Move { src, .. } | Inst::Unary { src, .. } => vec![*src],
+            Inst::Int { lhs, rhs, .. }
+            | Inst::Div { lhs, rhs, .. }
+            | Inst::FloatOp { lhs, rhs, .. }
+            | Inst::Cmp { lhs, rhs, .. }
+            | Inst::FCmp { lhs, rhs, .. } => vec![*lhs, *rhs],
+            Inst::Call { args, .. } => args
+                .iter()
+                .filter_map(|a| match a {
+                    Arg::Reg(r) => Some(*r),
+                    _ => None,
+                })
+                .collect(),
+            Inst::JumpIfFalse { cond, .. } => vec![*cond],
+            Inst::Jump(_) | Inst::Label(_) => vec![],
+        }
+    }
+
+    /// The virtual register the instruction writes.
+    pub fn def(&self) -> Option<VReg> {
+        match self {
+            Inst::Const { dst, .. }
+            | Inst::Float { dst, .. }
+            | Inst::Str { dst, .. }
+            | Inst::Move { dst, .. }
+            | Inst::Int { dst, .. }
+            | Inst::Div { dst, .. }
+            | Inst::FloatOp { dst, .. }
+            | Inst::Cmp { dst, .. }
+            | Inst::FCmp { dst, .. }
+            | Inst::Unary { dst, .. } => Some(*dst),
+            Inst::Call { ret, .. } => *ret,
+            Inst::Jump(_) | Inst::JumpIfFalse { .. } | Inst::Label(_) => None,
+        }
+    }
+}
+
+/// A lowered program, the body of `main`.
+#[derive(Debug, Clone, Default)]
+pub struct Function {
+    pub insts: Vec<Inst>,
+    /// Indexed by `VReg`
+    pub classes: Vec<Class>,
+    pub strings: Vec<String>,
+    pub floats: Vec<f64>,
+    pub labels: u32,
+}
+
+impl Function {
+    pub fn class(&self, vreg: VReg) -> Class {
+        self.classes[vreg.0 as usize]
+    }
+}
+
+/// Lowers `program` to the body of `main`.
+pub fn lower(program: &Program) -> Function {
+    let mut lower = Lower {
+        program,
+        func: Function::default(),
+        vregs: HashMap::new(),
+    };
+    lower.stmts(&program.stmts);
+    lower.func
+}
+
+struct Lower<'p> {
+    program: &'p Program,
+    func: Function,
+    /// The virtual register that holds each variable
+    vregs: HashMap<VarId, VReg>,
+}
+
+impl Lower<'_> {
+    fn vreg(&mut self, class: Class) -> VReg {
+        self.func.classes.push(class);
+        VReg(self.func.classes.len() as u32 - 1)
+    }
+
+    fn label(&mut self) -> Label {
+        self.func.labels += 1;
+        Label(self.func.labels - 1)
+    }
+
+    fn push(&mut self, inst: Inst) {
+        self.func.insts.push(inst);
+    }
+
+    fn string(&mut self, s: &str) -> usize {
+        match self.func.strings.iter().position(|t| t == s) {
+            Some(i) => i,
+            None => {
+                self.func.strings.push(s.to_string());
+                self.func.strings.len() - 1
+            }
+        }
+    }
+
+    fn int(&mut self, value: i64) -> VReg {
+        let dst = self.vreg(Class::Gpr);
+        self.push(Inst::Const { dst, value });
+        dst
+    }
+
+    fn call(&mut self, func: &'static str, args: Vec<Arg>, ret: Option<Class>) -> Option<VReg> {
+        let ret = ret.map(|class| self.vreg(class));
+        self.push(Inst::Call { func, args, ret });
+        ret
+    }
+
+    fn unary(&mut self, op: UnaryOp, src: VReg, class: Class) -> VReg {
+        let dst = self.vreg(class);
+        self.push(Inst::Unary { op, dst, src });
+        dst
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.stmts(&block.stmts);
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Decl(decl) => self.decl(decl),
+            Stmt::Assign(assign) => self.assign(assign),
+            Stmt::If(if_) => {
+                let end = self.label();
+                let cond = self.expr(&if_.cond);
+                self.push(Inst::JumpIfFalse { cond, target: end });
+                self.stmt(&if_.then);
+                self.push(Inst::Label(end));
+            }
+            Stmt::IfElse(if_else) => {
+                let otherwise = self.label();
+                let end = self.label();
+                let cond = self.expr(&if_else.cond);
+                self.push(Inst::JumpIfFalse {
+                    cond,
+                    target: otherwise,
+                });
+                self.block(&if_else.then);
+                self.push(Inst::Jump(end));
+                self.push(Inst::Label(otherwise));
+                self.stmt(&if_else.otherwise);
+                self.push(Inst::Label(end));
+            }
+            Stmt::While(while_) => {
+                let top = self.label();
+                let end = self.label();
+                self.push(Inst::Label(top));
+                let cond = self.expr(&while_.cond);
+                self.push(Inst::JumpIfFalse { cond, target: end });
+                self.stmt(&while_.body);
+                self.push(Inst::Jump(top));
+                self.push(Inst::Label(end));
+            }
+            Stmt::Emit(Emit::Value { var, first, second }) => {
+                let mut format = Vec::new();
+                let mut args = Vec::new();
+
+                for (i, expr) in [var, first, second].iter().enumerate() {
+                    let value = self.expr(expr);
+                    format.push(if expr.ty == Type::Int { "%lld" } else { "%s" });
+                    args.push(Arg::Reg(self.printable(value, expr.ty, i)));
+                }
+
+                let format = self.string(&format!("{}\n", format.join(" ")));
+                args.insert(0, Arg::Str(format));
+                self.call("printf", args, None);
+            }
+            Stmt::Emit(Emit::Symtable { vars, .. }) => self.symtable(vars),
+            Stmt::Block(block) => self.block(block),
+        }
+    }
+
+    /// A value of type `ty` as a `printf` argument: ints are printed with
+    /// `%lld`, everything else becomes a string. `buf` picks the buffer a
+    /// float is formatted into.
+    fn printable(&mut self, value: VReg, ty: Type, buf: usize) -> VReg {
+        match ty {
+            Type::Bool => self.unary(UnaryOp::BoolName, value, Class::Gpr),
+            Type::Float => self
+                .call(
+                    "zl_float",
+                    vec![Arg::Reg(value), Arg::Buf(buf)],
+                    Some(Class::Gpr),
+                )
+                .unwrap(),
+            Type::Int | Type::String => value,
+        }
+    }
+
+    /// One `printf` per live variable, in the symbol table dump format with
+    /// the value appended.
+    fn symtable(&mut self, live: &[VarId]) {
+        let mut lines = Vec::new();
+
+        for &id in live {
+            let var = self.program.var(id);
+            let constant = if var.ty.constant { "const" } else { "" };
+            let spec = if var.ty.ty == Type::Int { "%lld" } else { "%s" };
+            let format = format!(
+                "{},{}{},{},{}\n",
+                var.depth, constant, var.ty.ty, var.name, spec
+            );
+            lines.push((format, self.vregs[&id], var.ty.ty));
+        }
+
+        for (format, vreg, ty) in lines {
+            let value = self.printable(vreg, ty, 0);
+            let format = self.string(&format);
+            self.call("printf", vec![Arg::Str(format), Arg::Reg(value)], None);
+        }
+    }
+
+    fn decl(&mut self, decl: &Decl) {
+        for var in &decl.vars {
+            match var {
+                Declarator::Ident(ident, var) => {
+                    if self.program.var(*var).declared_by(ident) {
+                        let zero = match decl.ty.ty {
+                            Type::Bool | Type::Int => self.int(0),
+                            Type::Float => self.float(0.0),
+                            Type::String => self.str(""),
+                        };
+                        self.declare(*var, zero);
+                    }
+                }
+                Declarator::Init(assign) => {
+                    let value = self.expr(&assign.value);
+
+                    for target in &assign.targets {
+                        let var = self.program.var(target.var);
+                        let (ty, new) = (var.ty.ty, var.declared_by(&target.ident));
+                        let src = self.convert(value, assign.value.ty, ty, target.span);
+
+                        if new {
+                            self.declare(target.var, src);
+                        } else {
+                            let dst = self.vregs[&target.var];
+                            self.push(Inst::Move { dst, src });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn declare(&mut self, var: VarId, value: VReg) {
+        let vreg = self.vreg(Class::of(self.program.var(var).ty.ty));
+        self.push(Inst::Move {
+            dst: vreg,
+            src: value,
+        });
+        self.vregs.insert(var, vreg);
+    }
+
+    /// Stores to a `const` are dropped, like in the interpreter, but the
+    /// value is still computed.
+    fn assign(&mut self, assign: &Assign) {
+        let value = self.expr(&assign.value);
+
+        for target in &assign.targets {
+            let var = self.program.var(target.var);
+            if var.ty.constant {
+                continue;
+            }
+
+            let (dst, ty) = (self.vregs[&target.var], var.ty.ty);
+            let src = self.convert(value, assign.value.ty, ty, target.span);
+            self.push(Inst::Move { dst, src });
+        }
+    }
+
+    fn float(&mut self, value: f64) -> VReg {
+        self.func.floats.push(value);
+        let dst = self.vreg(Class::Xmm);
+        self.push(Inst::Float {
+            dst,
+            index: self.func.floats.len() - 1,
+        });
+        dst
+    }
+
+    fn str(&mut self, s: &str) -> VReg {
+        let index = self.string(s);
+        let dst = self.vreg(Class::Gpr);
+        self.push(Inst::Str { dst, index });
+        dst
+    }
+
+    /// Converts `value`, of type `from`, for a store or a cast to `to`.
+    fn convert(&mut self, value: VReg, from: Type, to: Type, span: Span) -> VReg {
+        let (row, col) = (span.row() as i64, span.col() as i64);
+        let parse = |value| vec![Arg::Reg(value), Arg::Imm(row), Arg::Imm(col)];
+
+        match (from, to) {
+            (from, to) if from == to => value,
+            // Bools are already 0 or 1
+            (Type::Bool, Type::Int) => value,
+            (Type::Bool, Type::Float) | (Type::Int, Type::Float) => {
+                self.unary(UnaryOp::IntToFloat, value, Class::Xmm)
+            }
+            (Type::Int, Type::Bool) => self.unary(UnaryOp::IntToBool, value, Class::Gpr),
+            (Type::Float, Type::Bool) => self.unary(UnaryOp::FloatToBool, value, Class::Gpr),
+            (Type::Float, Type::Int) => self
+                .call("zl_ftoi", vec![Arg::Reg(value)], Some(Class::Gpr))
+                .unwrap(),
+            (Type::String, Type::Bool) => self
+                .call("zl_stob", parse(value), Some(Class::Gpr))
+                .unwrap(),
+            (Type::String, Type::Int) => self
+                .call("zl_stoi", parse(value), Some(Class::Gpr))
+                .unwrap(),
+            (Type::String, Type::Float) => self
+                .call("zl_stof", parse(value), Some(Class::Xmm))
+                .unwrap(),
+            // The grammar has no casts to string
+            (from, to) => unreachable!("no conversion from {} to {}", from, to),
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> VReg {
+        match &expr.kind {
+            ExprKind::Literal(Literal::Int(i)) => self.int(*i),
+            ExprKind::Literal(Literal::Float(f)) => self.float(*f),
+            ExprKind::Literal(Literal::String(s)) => self.str(s),
+            ExprKind::Var(_, var) => self.vregs[var],
+            ExprKind::Unary { op, operand } => {
+                let value = self.expr(operand);
+
+                match (op, operand.ty) {
+                    (UnOp::Plus, _) => value,
+                    (UnOp::Neg, Type::Float) => self.unary(UnaryOp::FNeg, value, Class::Xmm),
+                    (UnOp::Neg, _) => self.unary(UnaryOp::Neg, value, Class::Gpr),
+                    (UnOp::Not, _) => self.unary(UnaryOp::Not, value, Class::Gpr),
+                    (UnOp::Compl, _) => self.unary(UnaryOp::Compl, value, Class::Gpr),
+                }
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                // Mixed int and float operands are both made floats
+                let ty = if lhs.ty != rhs.ty && lhs.ty.is_numeric() && rhs.ty.is_numeric() {
+                    Type::Float
+                } else {
+                    lhs.ty
+                };
+                let l = self.expr(lhs);
+                let l = self.convert(l, lhs.ty, ty, lhs.span);
+                let r = self.expr(rhs);
+                let r = self.convert(r, rhs.ty, ty, rhs.span);
+
+                self.binary(*op, ty, l, r, expr.span)
+            }
+            ExprKind::Cast(inner) => {
+                let value = self.expr(inner);
+                self.convert(value, inner.ty, expr.ty, expr.span)
+            }
+        }
+    }
+
+    /// `lhs op rhs` on two operands of type `ty`.
+    fn binary(&mut self, op: BinOp, ty: Type, lhs: VReg, rhs: VReg, span: Span) -> VReg {
+        let cond = match op {
+            BinOp::Lt => Some(Cond::Lt),
+            BinOp::Leq => Some(Cond::Leq),
+            BinOp::Eq => Some(Cond::Eq),
+            BinOp::Geq => Some(Cond::Geq),
+            BinOp::Gt => Some(Cond::Gt),
+            _ => None,
+        };
+
+        if let Some(cond) = cond {
+            if ty == Type::String {
+                let args = vec![Arg::Reg(lhs), Arg::Reg(rhs)];
+                return self.call("zl_streq", args, Some(Class::Gpr)).unwrap();
+            }
+
+            let dst = self.vreg(Class::Gpr);
+            self.push(match ty {
+                Type::Float => Inst::FCmp {
+                    cond,
+                    dst,
+                    lhs,
+                    rhs,
+                },
+                _ => Inst::Cmp {
+                    cond,
+                    dst,
+                    lhs,
+                    rhs,
+                },
+            });
+            return dst;
+        }
+
+        let dst = self.vreg(Class::of(ty));
+        let inst = match (op, ty) {
+            (BinOp::Add, Type::Int) => Inst::Int {
+                op: IntOp::Add,
+                dst,
+                lhs,
+                rhs,
+            },
+            (BinOp::Sub, Type::Int) => Inst::Int {
+                op: IntOp::Sub,
+                dst,
+                lhs,
+                rhs,
+            },
+            (BinOp::Mul, Type::Int) => Inst::Int {
+                op: IntOp::Mul,
+                dst,
+                lhs,
+                rhs,
+            },
+            (BinOp::Div, Type::Int) | (BinOp::Mod, Type::Int) => Inst::Div {
+                dst,
+                lhs,
+                rhs,
+                rem: op == BinOp::Mod,
+                span,
+            },
+            (op, Type::Float) => {
+                let op = match op {
+                    BinOp::Add => FloatOp::Add,
+                    BinOp::Sub => FloatOp::Sub,
+                    BinOp::Mul => FloatOp::Mul,
+                    BinOp::Div => FloatOp::Div,
+                    op => unreachable!("{} on floats was not type checked", op),
+                };
+                Inst::FloatOp { op, dst, lhs, rhs }
+            }
+            (op, ty) => unreachable!("{} on {} was not type checked", op, ty),
+        };
+        self.push(inst);
+        dst
+    }
+}
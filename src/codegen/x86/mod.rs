@@ -0,0 +1,933 @@
+//! Lowers a type checked program to x86-64 assembly for the GNU assembler.
+//!
+//! The program is lowered to `lir`, registers are allocated with a linear
+//! scan in `regalloc` and the result becomes the body of `main`, to be
+//! linked against the C library with `cc program.s -o program`. Ints, bools
+//! and strings live in general purpose registers, floats in SSE registers.
+//! The small runtime the generated code calls is written out after `main`:
+//! it stops the program on runtime errors with the messages the
+//! interpreter uses, parses strings and formats floats like Rust does.
+
+pub mod lir;
+pub mod regalloc;
+
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+use lir::{Arg, Class, Cond, FloatOp, Function, Inst, IntOp, Label, UnaryOp, VReg};
+use regalloc::{Allocation, Location};
+
+use crate::typed::Program;
+
+/// The C library functions the generated code calls. Everything else is
+/// in the runtime.
+const LIBC: &[&str] = &["printf"];
+
+/// Registers the System V ABI passes integer arguments in.
+const ARGS: &[&str] = &["%rdi", "%rsi", "%rdx", "%rcx"];
+
+/// The runtime, in the same order as `codegen::c::PRELUDE`.
+const RUNTIME: &str = r#"
+# void zl_error(int row, int col, const char *message, const char *value)
+zl_error:
+    pushq %rbx
+    pushq %r12
+    pushq %r13
+    movq %rdx, %r12
+    movq %rcx, %r13
+    movl %esi, %ecx
+    movl %edi, %edx
+    movq stderr@GOTPCREL(%rip), %rbx
+    movq (%rbx), %rdi
+    leaq .Lzl_at(%rip), %rsi
+    xorl %eax, %eax
+    call fprintf@PLT
+    movq (%rbx), %rdi
+    movq %r12, %rsi
+    movq %r13, %rdx
+    xorl %eax, %eax
+    call fprintf@PLT
+    movl $10, %edi
+    movq (%rbx), %rsi
+    call fputc@PLT
+    movl $1, %edi
+    call exit@PLT
+
+# long long zl_ftoi(double f), truncating like a Rust `as` cast and
+# saturating out of range values
+zl_ftoi:
+    # `cvttsd2si` already gives the minimum int for anything below it
+    ucomisd %xmm0, %xmm0
+    jp .Lzl_ftoi_nan
+    ucomisd .Lzl_two63(%rip), %xmm0
+    jae .Lzl_ftoi_max
+    cvttsd2si %xmm0, %rax
+    ret
+.Lzl_ftoi_nan:
+    xorl %eax, %eax
+    ret
+.Lzl_ftoi_max:
+    movabsq $9223372036854775807, %rax
+    ret
+
+# const char *zl_skip_ws(const char *s), past any leading whitespace
+zl_skip_ws:
+    movq %rdi, %rax
+.Lzl_skip_ws_loop:
+    movzbl (%rax), %ecx
+    cmpb $32, %cl
+    je .Lzl_skip_ws_next
+    cmpb $9, %cl
+    je .Lzl_skip_ws_next
+    cmpb $10, %cl
+    je .Lzl_skip_ws_next
+    cmpb $13, %cl
+    je .Lzl_skip_ws_next
+    ret
+.Lzl_skip_ws_next:
+    incq %rax
+    jmp .Lzl_skip_ws_loop
+
+# long long zl_stoi(const char *s, int row, int col)
+zl_stoi:
+    pushq %rbx
+    pushq %r12
+    pushq %r13
+    pushq %r14
+    subq $24, %rsp
+    movq %rdi, %rbx
+    movl %esi, %r12d
+    movl %edx, %r13d
+    call zl_skip_ws
+    movq %rax, %r14
+    cmpb $0, (%r14)
+    je .Lzl_stoi_error
+    movq %r14, %rdi
+    leaq 8(%rsp), %rsi
+    movl $10, %edx
+    call strtoll@PLT
+    movq %rax, (%rsp)
+    movq 8(%rsp), %rdi
+    cmpq %rdi, %r14
+    je .Lzl_stoi_error
+    call zl_skip_ws
+    cmpb $0, (%rax)
+    jne .Lzl_stoi_error
+    movq (%rsp), %rax
+    addq $24, %rsp
+    popq %r14
+    popq %r13
+    popq %r12
+    popq %rbx
+    ret
+.Lzl_stoi_error:
+    movl %r12d, %edi
+    movl %r13d, %esi
+    leaq .Lzl_to_int(%rip), %rdx
+    movq %rbx, %rcx
+    call zl_error
+
+# double zl_stof(const char *s, int row, int col)
+zl_stof:
+    pushq %rbx
+    pushq %r12
+    pushq %r13
+    pushq %r14
+    subq $24, %rsp
+    movq %rdi, %rbx
+    movl %esi, %r12d
+    movl %edx, %r13d
+    call zl_skip_ws
+    movq %rax, %r14
+    cmpb $0, (%r14)
+    je .Lzl_stof_error
+    movq %r14, %rdi
+    leaq 8(%rsp), %rsi
+    call strtod@PLT
+    movsd %xmm0, (%rsp)
+    movq 8(%rsp), %rdi
+    cmpq %rdi, %r14
+    je .Lzl_stof_error
+    call zl_skip_ws
+    cmpb $0, (%rax)
+    jne .Lzl_stof_error
+    movsd (%rsp), %xmm0
+    addq $24, %rsp
+    popq %r14
+    popq %r13
+    popq %r12
+    popq %rbx
+    ret
+.Lzl_stof_error:
+    movl %r12d, %edi
+    movl %r13d, %esi
+    leaq .Lzl_to_float(%rip), %rdx
+    movq %rbx, %rcx
+    call zl_error
+
+# bool zl_stob(const char *s, int row, int col)
+zl_stob:
+    pushq %rbx
+    pushq %r12
+    pushq %r13
+    pushq %r14
+    subq $8, %rsp
+    movq %rdi, %rbx
+    movl %esi, %r12d
+    movl %edx, %r13d
+    call zl_skip_ws
+    movq %rax, %r14
+    movq %r14, %rdi
+    leaq .Lzl_true(%rip), %rsi
+    movl $4, %edx
+    call strncmp@PLT
+    testl %eax, %eax
+    jne .Lzl_stob_false
+    leaq 4(%r14), %rdi
+    call zl_skip_ws
+    cmpb $0, (%rax)
+    jne .Lzl_stob_error
+    movl $1, %eax
+    jmp .Lzl_stob_done
+.Lzl_stob_false:
+    movq %r14, %rdi
+    leaq .Lzl_false(%rip), %rsi
+    movl $5, %edx
+    call strncmp@PLT
+    testl %eax, %eax
+    jne .Lzl_stob_error
+    leaq 5(%r14), %rdi
+    call zl_skip_ws
+    cmpb $0, (%rax)
+    jne .Lzl_stob_error
+    xorl %eax, %eax
+.Lzl_stob_done:
+    addq $8, %rsp
+    popq %r14
+    popq %r13
+    popq %r12
+    popq %rbx
+    ret
+.Lzl_stob_error:
+    movl %r12d, %edi
+    movl %r13d, %esi
+    leaq .Lzl_to_bool(%rip), %rdx
+    movq %rbx, %rcx
+    call zl_error
+
+# bool zl_streq(const char *a, const char *b)
+zl_streq:
+    subq $8, %rsp
+    call strcmp@PLT
+    xorl %ecx, %ecx
+    testl %eax, %eax
+    sete %cl
+    movq %rcx, %rax
+    addq $8, %rsp
+    ret
+
+# const char *zl_float(double x, char *out) formats a float like Rust's
+# `{:?}`: the shortest digits that read back as the same value, with an
+# exponent below 1e-4 and from 1e16 on
+zl_float:
+    pushq %rbx
+    pushq %r12
+    subq $72, %rsp
+    movq %rdi, %rbx
+    movsd %xmm0, 64(%rsp)
+    ucomisd %xmm0, %xmm0
+    jp .Lzl_float_nan
+    movq %xmm0, %rax
+    btrq $63, %rax
+    movabsq $0x7ff0000000000000, %rcx
+    cmpq %rcx, %rax
+    je .Lzl_float_inf
+    testq %rax, %rax
+    je .Lzl_float_zero
+    xorl %r12d, %r12d
+.Lzl_float_digits:
+    movq %rsp, %rdi
+    movl $40, %esi
+    leaq .Lzl_fmt_e(%rip), %rdx
+    movl %r12d, %ecx
+    movsd 64(%rsp), %xmm0
+    movl $1, %eax
+    call snprintf@PLT
+    movq %rsp, %rdi
+    xorl %esi, %esi
+    call strtod@PLT
+    incl %r12d
+    ucomisd 64(%rsp), %xmm0
+    jp .Lzl_float_digits
+    jne .Lzl_float_digits
+    decl %r12d
+    movq %rsp, %rdi
+    movl $101, %esi
+    call strchr@PLT
+    movb $0, (%rax)
+    leaq 1(%rax), %rdi
+    call atoi@PLT
+    movl %eax, 48(%rsp)
+    movq 64(%rsp), %rax
+    btrq $63, %rax
+    movq %rax, %xmm0
+    ucomisd .Lzl_1em4(%rip), %xmm0
+    jb .Lzl_float_exp
+    ucomisd .Lzl_1e16(%rip), %xmm0
+    jae .Lzl_float_exp
+    # As many decimals as the digits need, at least one
+    movl %r12d, %ecx
+    subl 48(%rsp), %ecx
+    movl $1, %eax
+    cmpl %eax, %ecx
+    cmovl %eax, %ecx
+    movq %rbx, %rdi
+    movl $40, %esi
+    leaq .Lzl_fmt_f(%rip), %rdx
+    movsd 64(%rsp), %xmm0
+    movl $1, %eax
+    call snprintf@PLT
+    movq %rbx, %rax
+    jmp .Lzl_float_done
+.Lzl_float_exp:
+    movq %rbx, %rdi
+    movl $40, %esi
+    leaq .Lzl_fmt_exp(%rip), %rdx
+    movq %rsp, %rcx
+    movl 48(%rsp), %r8d
+    xorl %eax, %eax
+    call snprintf@PLT
+    movq %rbx, %rax
+    jmp .Lzl_float_done
+.Lzl_float_nan:
+    leaq .Lzl_nan(%rip), %rax
+    jmp .Lzl_float_done
+.Lzl_float_inf:
+    leaq .Lzl_inf(%rip), %rax
+    leaq .Lzl_neg_inf(%rip), %rcx
+    jmp .Lzl_float_sign
+.Lzl_float_zero:
+    leaq .Lzl_zero(%rip), %rax
+    leaq .Lzl_neg_zero(%rip), %rcx
+.Lzl_float_sign:
+    cmpq $0, 64(%rsp)
+    cmovl %rcx, %rax
+.Lzl_float_done:
+    addq $72, %rsp
+    popq %r12
+    popq %rbx
+    ret
+
+    .section .rodata
+    .p2align 3
+.Lzl_two63:
+    .double 9223372036854775808.0
+.Lzl_1em4:
+    .double 1.0e-4
+.Lzl_1e16:
+    .double 1.0e16
+.Lzl_at:
+    .string "runtime error at %d:%d: "
+.Lzl_div_zero:
+    .string "division by zero"
+.Lzl_to_int:
+    .string "cannot convert \"%s\" to int"
+.Lzl_to_float:
+    .string "cannot convert \"%s\" to float"
+.Lzl_to_bool:
+    .string "cannot convert \"%s\" to bool"
+.Lzl_true:
+    .string "true"
+.Lzl_false:
+    .string "false"
+.Lzl_nan:
+    .string "NaN"
+.Lzl_inf:
+    .string "inf"
+.Lzl_neg_inf:
+    .string "-inf"
+.Lzl_zero:
+    .string "0.0"
+.Lzl_neg_zero:
+    .string "-0.0"
+.Lzl_empty:
+    .string ""
+.Lzl_fmt_e:
+    .string "%.*e"
+.Lzl_fmt_f:
+    .string "%.*f"
+.Lzl_fmt_exp:
+    .string "%se%d"
+
+    .bss
+    .p2align 4
+zl_bufs:
+    .zero 120
+"#;
+
+/// Generates the assembly for `program`.
+pub fn generate(program: &Program) -> String {
+    let func = lir::lower(program);
+    let alloc = regalloc::allocate(&func);
+    emit(&func, &alloc)
+}
+
+/// Writes out `func` with the registers in `alloc`.
+pub fn emit(func: &Function, alloc: &Allocation) -> String {
+    let mut gen = Generator {
+        out: String::new(),
+        func,
+        alloc,
+        labels: 0,
+    };
+
+    gen.out
+        .push_str("# Generated by ZOBOS from a zlang program\n");
+    gen.out.push_str("    .text\n    .globl main\nmain:\n");
+    gen.prologue();
+    for inst in &func.insts {
+        gen.inst(inst);
+    }
+    gen.line("xorl %eax, %eax");
+    gen.epilogue();
+    gen.out.push_str(RUNTIME);
+    gen.constants();
+    gen.out
+        .push_str("\n    .section .note.GNU-stack,\"\",@progbits\n");
+    gen.out
+}
+
+/// A string for `.string`. Everything but printable ASCII is an octal
+/// escape.
+fn asm_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for b in s.bytes() {
+        match b {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            0x20..=0x7e => out.push(b as char),
+            _ => write!(out, "\\{:03o}", b).unwrap(),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn label(label: Label) -> String {
+    format!(".Lz{}", label.0)
+}
+
+/// The `setcc` suffix that is true when `lhs cond rhs` after
+/// `cmp rhs, lhs` on signed values.
+fn signed(cond: Cond) -> &'static str {
+    match cond {
+        Cond::Lt => "l",
+        Cond::Leq => "le",
+        Cond::Eq => "e",
+        Cond::Geq => "ge",
+        Cond::Gt => "g",
+    }
+}
+
+struct Generator<'a> {
+    out: String,
+    func: &'a Function,
+    alloc: &'a Allocation,
+    /// Labels for the checks in `Div`, after the ones `lir` used
+    labels: u32,
+}
+
+impl Generator<'_> {
+    fn line(&mut self, text: &str) {
+        self.out.push_str("    ");
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn label(&mut self) -> String {
+        self.labels += 1;
+        label(Label(self.func.labels + self.labels - 1))
+    }
+
+    /// How far below the saved registers the stack pointer goes. The
+    /// pushes and this keep it 16 byte aligned for calls.
+    fn frame_size(&self) -> usize {
+        let used = 8 * (self.alloc.saved.len() + self.alloc.slots);
+        used.div_ceil(16) * 16 - 8 * self.alloc.saved.len()
+    }
+
+    fn prologue(&mut self) {
+        self.line("pushq %rbp");
+        self.line("movq %rsp, %rbp");
+        for reg in &self.alloc.saved {
+            self.line(&format!("pushq {}", reg.name));
+        }
+        let size = self.frame_size();
+        if size > 0 {
+            self.line(&format!("subq ${}, %rsp", size));
+        }
+    }
+
+    fn epilogue(&mut self) {
+        let saved = self.alloc.saved.len();
+        self.line(&format!("leaq -{}(%rbp), %rsp", 8 * saved));
+        for reg in self.alloc.saved.iter().rev() {
+            self.line(&format!("popq {}", reg.name));
+        }
+        self.line("popq %rbp");
+        self.line("ret");
+    }
+
+    /// The operand that names where `vreg` lives.
+    fn loc(&self, vreg: VReg) -> String {
+        match self.alloc.location(vreg) {
+            Location::Reg(reg) => reg.name.to_string(),
+            Location::Stack(slot) => {
+                let offset = 8 * (self.alloc.saved.len() + slot + 1);
+                format!("-{}(%rbp)", offset)
+            }
+        }
+    }
+
+    fn in_reg(&self, vreg: VReg) -> bool {
+        matches!(self.alloc.location(vreg), Location::Reg(_))
+    }
+
+    /// `mov` for a class, going through a scratch register when both
+    /// operands are in memory.
+    fn mov(&mut self, class: Class, src: &str, dst: &str) {
+        if src == dst {
+            return;
+        }
+        let (mov, scratch) = match class {
+            Class::Gpr => ("movq", "%rax"),
+            Class::Xmm => ("movsd", "%xmm0"),
+        };
+
+        if src.starts_with('%') || dst.starts_with('%') {
+            self.line(&format!("{} {}, {}", mov, src, dst));
+        } else {
+            self.line(&format!("{} {}, {}", mov, src, scratch));
+            self.line(&format!("{} {}, {}", mov, scratch, dst));
+        }
+    }
+
+    /// `dst = lhs op rhs` for a two operand instruction, computed in `dst`
+    /// when it is a register and in a scratch register otherwise.
+    fn two_address(&mut self, class: Class, op: &str, dst: VReg, lhs: VReg, rhs: VReg) {
+        let (d, l, r) = (self.loc(dst), self.loc(lhs), self.loc(rhs));
+
+        if self.in_reg(dst) && d != r {
+            self.mov(class, &l, &d);
+            self.line(&format!("{} {}, {}", op, r, d));
+        } else {
+            let scratch = match class {
+                Class::Gpr => "%rax",
+                Class::Xmm => "%xmm0",
+            };
+            self.mov(class, &l, scratch);
+            self.line(&format!("{} {}, {}", op, r, scratch));
+            self.mov(class, scratch, &d);
+        }
+    }
+
+    /// Stores the flag `set` leaves in `%al` to `dst` as a 0 or 1.
+    fn set(&mut self, set: &[&str], dst: VReg) {
+        for line in set {
+            self.line(line);
+        }
+        self.line("movzbl %al, %eax");
+        let d = self.loc(dst);
+        self.mov(Class::Gpr, "%rax", &d);
+    }
+
+    fn inst(&mut self, inst: &Inst) {
+        match inst {
+            Inst::Const { dst, value } => {
+                let d = self.loc(*dst);
+                if i32::try_from(*value).is_ok() {
+                    self.line(&format!("movq ${}, {}", value, d));
+                } else {
+                    self.line(&format!("movabsq ${}, %rax", value));
+                    self.mov(Class::Gpr, "%rax", &d);
+                }
+            }
+            Inst::Float { dst, index } => {
+                let d = self.loc(*dst);
+                self.mov(Class::Xmm, &format!(".Lzf{}(%rip)", index), &d);
+            }
+            Inst::Str { dst, index } => {
+                let d = self.loc(*dst);
+                if self.in_reg(*dst) {
+                    self.line(&format!("leaq .Lzs{}(%rip), {}", index, d));
+                } else {
+                    self.line(&format!("leaq .Lzs{}(%rip), %rax", index));
+                    self.mov(Class::Gpr, "%rax", &d);
+                }
+            }
+            Inst::Move { dst, src } => {
+                let (d, s) = (self.loc(*dst), self.loc(*src));
+                self.mov(self.func.class(*dst), &s, &d);
+            }
+            Inst::Int { op, dst, lhs, rhs } => {
+                let op = match op {
+                    IntOp::Add => "addq",
+                    IntOp::Sub => "subq",
+                    IntOp::Mul => "imulq",
+                };
+                self.two_address(Class::Gpr, op, *dst, *lhs, *rhs);
+            }
+            Inst::Div {
+                dst,
+                lhs,
+                rhs,
+                rem,
+                span,
+            } => self.div(*dst, *lhs, *rhs, *rem, span.row(), span.col()),
+            Inst::FloatOp { op, dst, lhs, rhs } => {
+                let op = match op {
+                    FloatOp::Add => "addsd",
+                    FloatOp::Sub => "subsd",
+                    FloatOp::Mul => "mulsd",
+                    FloatOp::Div => "divsd",
+                };
+                self.two_address(Class::Xmm, op, *dst, *lhs, *rhs);
+            }
+            Inst::Cmp {
+                cond,
+                dst,
+                lhs,
+                rhs,
+            } => {
+                let (l, r) = (self.loc(*lhs), self.loc(*rhs));
+                self.mov(Class::Gpr, &l, "%rax");
+                self.line(&format!("cmpq {}, %rax", r));
+                self.set(&[&format!("set{} %al", signed(*cond))], *dst);
+            }
+            Inst::FCmp {
+                cond,
+                dst,
+                lhs,
+                rhs,
+            } => {
+                // `ucomisd` sets the flags of an unsigned compare, and the
+                // parity flag when either side is NaN. `a` and `ae` are
+                // false for NaN, so `<` and `<=` swap the operands.
+                let (l, r) = (self.loc(*lhs), self.loc(*rhs));
+                let (a, b, set): (_, _, &[&str]) = match cond {
+                    Cond::Lt => (r, l, &["seta %al"]),
+                    Cond::Leq => (r, l, &["setae %al"]),
+                    Cond::Gt => (l, r, &["seta %al"]),
+                    Cond::Geq => (l, r, &["setae %al"]),
+                    Cond::Eq => (l, r, &["sete %al", "setnp %cl", "andb %cl, %al"]),
+                };
+                self.mov(Class::Xmm, &a, "%xmm0");
+                self.line(&format!("ucomisd {}, %xmm0", b));
+                self.set(set, *dst);
+            }
+            Inst::Unary { op, dst, src } => self.unary(*op, *dst, *src),
+            Inst::Call { func, args, ret } => self.call(func, args, *ret),
+            Inst::Jump(target) => self.line(&format!("jmp {}", label(*target))),
+            Inst::JumpIfFalse { cond, target } => {
+                let c = self.loc(*cond);
+                self.line(&format!("cmpq $0, {}", c));
+                self.line(&format!("je {}", label(*target)));
+            }
+            Inst::Label(l) => {
+                self.out.push_str(&label(*l));
+                self.out.push_str(":\n");
+            }
+        }
+    }
+
+    /// `idiv` traps on a zero divisor and on the minimum int divided by
+    /// -1, so both are checked first. The interpreter wraps the second.
+    fn div(&mut self, dst: VReg, lhs: VReg, rhs: VReg, rem: bool, row: usize, col: usize) {
+        let (d, l, r) = (self.loc(dst), self.loc(lhs), self.loc(rhs));
+        let (nonzero, general, done) = (self.label(), self.label(), self.label());
+
+        self.mov(Class::Gpr, &l, "%rax");
+        self.mov(Class::Gpr, &r, "%rcx");
+        self.line("testq %rcx, %rcx");
+        self.line(&format!("jne {}", nonzero));
+        self.line(&format!("movl ${}, %edi", row));
+        self.line(&format!("movl ${}, %esi", col));
+        self.line("leaq .Lzl_div_zero(%rip), %rdx");
+        self.line("leaq .Lzl_empty(%rip), %rcx");
+        self.line("call zl_error");
+        self.out.push_str(&format!("{}:\n", nonzero));
+        self.line("cmpq $-1, %rcx");
+        self.line(&format!("jne {}", general));
+        if rem {
+            self.line("xorl %eax, %eax");
+        } else {
+            self.line("negq %rax");
+        }
+        self.line(&format!("jmp {}", done));
+        self.out.push_str(&format!("{}:\n", general));
+        self.line("cqto");
+        self.line("idivq %rcx");
+        if rem {
+            self.line("movq %rdx, %rax");
+        }
+        self.out.push_str(&format!("{}:\n", done));
+        self.mov(Class::Gpr, "%rax", &d);
+    }
+
+    fn unary(&mut self, op: UnaryOp, dst: VReg, src: VReg) {
+        let (d, s) = (self.loc(dst), self.loc(src));
+
+        match op {
+            UnaryOp::Neg => {
+                self.mov(Class::Gpr, &s, &d);
+                self.line(&format!("negq {}", d));
+            }
+            UnaryOp::Not => {
+                self.mov(Class::Gpr, &s, &d);
+                self.line(&format!("xorq $1, {}", d));
+            }
+            UnaryOp::Compl => {
+                self.line(&format!("cmpq $-1, {}", s));
+                self.set(&["setne %al"], dst);
+            }
+            UnaryOp::IntToBool => {
+                self.line(&format!("cmpq $0, {}", s));
+                self.set(&["setne %al"], dst);
+            }
+            UnaryOp::FNeg => {
+                self.mov(Class::Xmm, &s, "%xmm0");
+                self.line("movabsq $0x8000000000000000, %rax");
+                self.line("movq %rax, %xmm1");
+                self.line("xorpd %xmm1, %xmm0");
+                self.mov(Class::Xmm, "%xmm0", &d);
+            }
+            UnaryOp::IntToFloat => {
+                self.line(&format!("cvtsi2sdq {}, %xmm0", s));
+                self.mov(Class::Xmm, "%xmm0", &d);
+            }
+            UnaryOp::FloatToBool => {
+                // NaN is not zero, so it is true
+                self.mov(Class::Xmm, &s, "%xmm0");
+                self.line("xorpd %xmm1, %xmm1");
+                self.line("ucomisd %xmm1, %xmm0");
+                self.set(&["setne %al", "setp %cl", "orb %cl, %al"], dst);
+            }
+            UnaryOp::BoolName => {
+                self.line("leaq .Lzl_true(%rip), %rax");
+                self.line("leaq .Lzl_false(%rip), %rcx");
+                self.line(&format!("cmpq $0, {}", s));
+                self.line("cmove %rcx, %rax");
+                self.mov(Class::Gpr, "%rax", &d);
+            }
+        }
+    }
+
+    fn call(&mut self, func: &str, args: &[Arg], ret: Option<VReg>) {
+        let mut gprs = ARGS.iter();
+        let mut xmms = 0;
+
+        for arg in args {
+            match *arg {
+                Arg::Reg(vreg) if self.func.class(vreg) == Class::Xmm => {
+                    let s = self.loc(vreg);
+                    self.mov(Class::Xmm, &s, &format!("%xmm{}", xmms));
+                    xmms += 1;
+                }
+                arg => {
+                    let reg = gprs.next().expect("at most four integer arguments");
+                    match arg {
+                        Arg::Reg(vreg) => {
+                            let s = self.loc(vreg);
+                            self.mov(Class::Gpr, &s, reg);
+                        }
+                        Arg::Imm(i) => self.line(&format!("movq ${}, {}", i, reg)),
+                        Arg::Str(i) => self.line(&format!("leaq .Lzs{}(%rip), {}", i, reg)),
+                        Arg::Buf(i) => {
+                            self.line(&format!("leaq zl_bufs+{}(%rip), {}", 40 * i, reg))
+                        }
+                    }
+                }
+            }
+        }
+
+        // Variadic functions take the number of vector registers in `%al`
+        self.line(&format!("movl ${}, %eax", xmms));
+        if LIBC.contains(&func) {
+            self.line(&format!("call {}@PLT", func));
+        } else {
+            self.line(&format!("call {}", func));
+        }
+
+        if let Some(ret) = ret {
+            let d = self.loc(ret);
+            match self.func.class(ret) {
+                Class::Gpr => self.mov(Class::Gpr, "%rax", &d),
+                Class::Xmm => self.mov(Class::Xmm, "%xmm0", &d),
+            }
+        }
+    }
+
+    fn constants(&mut self) {
+        self.out
+            .push_str("\n    .section .rodata\n    .p2align 3\n");
+        for (i, f) in self.func.floats.iter().enumerate() {
+            self.out.push_str(&format!(".Lzf{}:\n", i));
+            self.line(&format!(".quad {:#x}", f.to_bits()));
+        }
+        for (i, s) in self.func.strings.iter().enumerate() {
+            self.out.push_str(&format!(".Lzs{}:\n", i));
+            self.line(&format!(".string {}", asm_string(s)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interp;
+    use crate::parser::Parser;
+    use crate::semantics::typeck;
+    use crate::syntax;
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    fn check(src: &str) -> Program {
+        let tree = Parser::new().parse_str(src).unwrap();
+        typeck::check(&syntax::Program::from_cst(&tree).unwrap()).unwrap()
+    }
+
+    /// Assembles and links the generated code with `cc` and runs it, `None`
+    /// if there is no toolchain to test with.
+    fn assemble_and_run(name: &str, program: &Program) -> Option<(String, String, i32)> {
+        let dir = std::env::temp_dir().join(format!("zobos-x86-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join(format!("{}.s", name));
+        let binary: PathBuf = dir.join(name);
+        std::fs::write(&source, generate(program)).unwrap();
+
+        let cc = Command::new("cc")
+            .arg("-o")
+            .arg(&binary)
+            .arg(&source)
+            .output();
+        let cc = match cc {
+            Ok(cc) => cc,
+            Err(_) => {
+                eprintln!("no cc, skipping {}", name);
+                return None;
+            }
+        };
+        assert!(
+            cc.status.success(),
+            "{}",
+            String::from_utf8_lossy(&cc.stderr)
+        );
+
+        let run = Command::new(&binary).output().unwrap();
+        Some((
+            String::from_utf8(run.stdout).unwrap(),
+            String::from_utf8(run.stderr).unwrap(),
+            run.status.code().unwrap(),
+        ))
+    }
+
+    /// The assembled program prints what the interpreter prints.
+    fn agrees(name: &str, src: &str) {
+        let program = check(src);
+        let mut expected = Vec::new();
+        interp::run(&program, &mut expected).unwrap();
+
+        if let Some((out, err, code)) = assemble_and_run(name, &program) {
+            assert_eq!(out, String::from_utf8(expected).unwrap());
+            assert_eq!((err.as_str(), code), ("", 0));
+        }
+    }
+
+    #[test]
+    fn arithmetic_and_conversions() {
+        agrees(
+            "arith",
+            "int i = 7, j = -3;\nfloat f = i;\nbool b = bool(j);\n\
+             emit i i / j i % j;\nemit f f / 4 i * 1.5;\nemit b (i < f) (2 == 2.0);\n\
+             float big = 1.0e20, small = 0.00001, third = 1.0 / 3;\n\
+             emit big small third;\nemit big 100.0 int(\"  12 \") + int(2.9);\n\
+             emit i -i + +j ~i;\nfloat g = -f;\nemit g (f <= 7) (f > 7.5);\n\
+             float h = float(\" 2.5\");\nemit h bool(\"false\") bool(0.0);\n\
+             int min = -9223372036854775807 - 1, q = min / -1;\n\
+             emit q min % -1 int(-1.0e30);\nbool nb = !b;\nemit nb 0 0;\n",
+        );
+    }
+
+    #[test]
+    fn control_flow_and_scopes() {
+        agrees(
+            "control",
+            "int n = 10, a, b = 1, i;\n\
+             while (i < n) { int t = a + b; a = b; b = t; i = i + 1; }\n\
+             const int c = 3;\nc = a = b = 7;\n\
+             if (a == 7) { int a = 1; emit a b c; emit symtable; } else emit a a a;\n\
+             if (a > 7) int z = 1;\n\
+             string s = \"tab\\there \\\"q\\\" 100%\", t = s;\n\
+             if (s == t) emit s t b;\n\
+             bool e = s == \"\";\nif (e == (1 < 0)) emit t t t;\n\
+             int x = 1;\nint x = 2;\nemit symtable;\n",
+        );
+    }
+
+    #[test]
+    fn unbraced_declarations_are_scoped() {
+        // `z` is gone after the `if`, so this does not check
+        let tree = Parser::new()
+            .parse_str("int a = 0;\nif (a > 7) int z = 1;\nemit z 0 0;\n")
+            .unwrap();
+        assert!(typeck::check(&syntax::Program::from_cst(&tree).unwrap()).is_err());
+
+        agrees(
+            "unbraced",
+            "int a = 0, z = 5;\nif (a < 7) int z = 1;\nwhile (a > 7) float z;\n\
+             if (z == 5) { } else int z;\nemit z 0 0;\nemit symtable;\n",
+        );
+    }
+
+    #[test]
+    fn register_pressure() {
+        // More values live around the loop than there are registers, and
+        // floats live across calls
+        let mut src = String::new();
+        for i in 0..20 {
+            src.push_str(&format!("int v{} = {};\nfloat f{} = {}.5;\n", i, i, i, i));
+        }
+        src.push_str("int k = 0;\nwhile (k < 3) {\n");
+        for i in 0..20 {
+            src.push_str(&format!(
+                "v{} = v{} * 2 + v{};\nf{} = f{} + f{};\n",
+                i,
+                i,
+                (i + 1) % 20,
+                i,
+                i,
+                (i + 7) % 20
+            ));
+        }
+        src.push_str("k = k + 1;\n}\nemit symtable;\n");
+
+        agrees("pressure", &src);
+    }
+
+    #[test]
+    fn runtime_errors() {
+        let program = check("int z = 0;\nemit z z 1 / z;\n");
+
+        if let Some((out, err, code)) = assemble_and_run("div", &program) {
+            assert_eq!(out, "");
+            assert_eq!(err, "runtime error at 2:12: division by zero\n");
+            assert_eq!(code, 1);
+        }
+
+        let program = check("string s = \"1x\";\nint i = int(s);\n");
+        if let Some((_, err, code)) = assemble_and_run("parse", &program) {
+            assert_eq!(err, "runtime error at 2:9: cannot convert \"1x\" to int\n");
+            assert_eq!(code, 1);
+        }
+    }
+}
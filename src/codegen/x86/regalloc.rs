@@ -0,0 +1,391 @@
+//! Linear scan register allocation (Poletto and Sarkar) over `lir`.
+//!
+//! Liveness is solved backwards over the instruction list, following jumps,
+//! and each virtual register gets one interval from the first to the last
+//! instruction it is live at. Intervals are visited by start; when no
+//! register is free the interval that ends last is spilled to the stack.
+//! Calls clobber the registers the System V ABI does not preserve, so an
+//! interval live across a call may only take a callee-saved one. Every XMM
+//! register is caller-saved, floats live across a call are always spilled.
+
+use std::collections::{HashMap, HashSet};
+
+use super::lir::{Class, Function, Inst, VReg};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Reg {
+    pub name: &'static str,
+    pub class: Class,
+    pub callee_saved: bool,
+}
+
+const fn gpr(name: &'static str, callee_saved: bool) -> Reg {
+    Reg {
+        name,
+        class: Class::Gpr,
+        callee_saved,
+    }
+}
+
+const fn xmm(name: &'static str) -> Reg {
+    Reg {
+        name,
+        class: Class::Xmm,
+        callee_saved: false,
+    }
+}
+
+/// The registers handed out to virtual registers. `rax`, `rcx`, `rdx`,
+/// `rsi`, `rdi`, `r11`, `xmm0` and `xmm1` are left free for the code
+/// generator to pass arguments and hold operands in.
+pub const REGISTERS: &[Reg] = &[
+    gpr("%rbx", true),
+    gpr("%r12", true),
+    gpr("%r13", true),
+    gpr("%r14", true),
+    gpr("%r15", true),
+    gpr("%r8", false),
+    gpr("%r9", false),
+    gpr("%r10", false),
+    xmm("%xmm2"),
+    xmm("%xmm3"),
+    xmm("%xmm4"),
+    xmm("%xmm5"),
+    xmm("%xmm6"),
+    xmm("%xmm7"),
+    xmm("%xmm8"),
+    xmm("%xmm9"),
+    xmm("%xmm10"),
+    xmm("%xmm11"),
+    xmm("%xmm12"),
+    xmm("%xmm13"),
+    xmm("%xmm14"),
+    xmm("%xmm15"),
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Location {
+    Reg(Reg),
+    /// A numbered 8 byte slot in the stack frame
+    Stack(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Allocation {
+    /// Indexed by `VReg`
+    pub locations: Vec<Location>,
+    pub slots: usize,
+    /// The callee-saved registers in use, which `main` has to restore
+    pub saved: Vec<Reg>,
+}
+
+impl Allocation {
+    pub fn location(&self, vreg: VReg) -> Location {
+        self.locations[vreg.0 as usize]
+    }
+}
+
+/// The instructions a virtual register is live at, inclusive.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Interval {
+    pub vreg: VReg,
+    pub start: usize,
+    pub end: usize,
+    /// Whether a call happens while it is live
+    pub crosses_call: bool,
+}
+
+/// The virtual registers live on entry to each instruction.
+fn liveness(func: &Function) -> Vec<HashSet<VReg>> {
+    let insts = &func.insts;
+    let labels: HashMap<_, _> = insts
+        .iter()
+        .enumerate()
+        .filter_map(|(i, inst)| match inst {
+            Inst::Label(label) => Some((*label, i)),
+            _ => None,
+        })
+        .collect();
+    let successors: Vec<Vec<usize>> = insts
+        .iter()
+        .enumerate()
+        .map(|(i, inst)| match inst {
+            Inst::Jump(label) => vec![labels[label]],
+            Inst::JumpIfFalse { target, .. } => vec![i + 1, labels[target]],
+            _ => vec![i + 1],
+        })
+        .map(|s| s.into_iter().filter(|&j| j < insts.len()).collect())
+        .collect();
+
+    let mut live_in = vec![HashSet::new(); insts.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+
+        for i in (0..insts.len()).rev() {
+            let mut live: HashSet<VReg> = successors[i]
+                .iter()
+                .flat_map(|&j| live_in[j].iter().copied())
+                .collect();
+            if let Some(def) = insts[i].def() {
+                live.remove(&def);
+            }
+            live.extend(insts[i].uses());
+
+            if live != live_in[i] {
+                live_in[i] = live;
+                changed = true;
+            }
+        }
+    }
+
+    live_in
+}
+
+/// The live interval of every virtual register that is used, by start.
+pub fn intervals(func: &Function) -> Vec<Interval> {
+    let live_in = liveness(func);
+    let mut ranges: HashMap<VReg, (usize, usize)> = HashMap::new();
+
+    for (i, inst) in func.insts.iter().enumerate() {
+        for &vreg in live_in[i].iter().chain(inst.def().iter()) {
+            let range = ranges.entry(vreg).or_insert((i, i));
+            range.0 = range.0.min(i);
+            range.1 = range.1.max(i);
+        }
+    }
+
+    let calls: Vec<usize> = func
+        .insts
+        .iter()
+        .enumerate()
+        .filter(|(_, inst)| matches!(inst, Inst::Call { .. }))
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut intervals: Vec<_> = ranges
+        .into_iter()
+        .map(|(vreg, (start, end))| Interval {
+            vreg,
+            start,
+            end,
+            crosses_call: calls.iter().any(|&c| start < c && c < end),
+        })
+        .collect();
+    intervals.sort_by_key(|i| (i.start, i.vreg));
+    intervals
+}
+
+/// Allocates `REGISTERS` to the virtual registers of `func`.
+pub fn allocate(func: &Function) -> Allocation {
+    allocate_with(func, REGISTERS)
+}
+
+pub fn allocate_with(func: &Function, registers: &[Reg]) -> Allocation {
+    let mut locations = vec![None; func.classes.len()];
+    let mut slots = 0;
+    // Sorted by end
+    let mut active: Vec<(Interval, Reg)> = Vec::new();
+
+    for interval in intervals(func) {
+        let class = func.class(interval.vreg);
+        active.retain(|(i, _)| i.end >= interval.start);
+
+        let allowed =
+            |reg: &Reg| reg.class == class && (reg.callee_saved || !interval.crosses_call);
+        // Caller-saved registers first, to keep the others for the
+        // intervals that need them
+        let free = registers
+            .iter()
+            .filter(|r| allowed(r) && !r.callee_saved)
+            .chain(registers.iter().filter(|r| allowed(r) && r.callee_saved))
+            .find(|r| active.iter().all(|(_, used)| used != *r))
+            .copied();
+
+        let reg = match free {
+            Some(reg) => Some(reg),
+            None => {
+                let victim = active
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (i, reg))| allowed(reg) && i.end > interval.end)
+                    .max_by_key(|(_, (i, _))| i.end)
+                    .map(|(n, _)| n);
+
+                victim.map(|n| {
+                    let (spilled, reg) = active.remove(n);
+                    locations[spilled.vreg.0 as usize] = Some(Location::Stack(slots));
+                    slots += 1;
+                    reg
+                })
+            }
+        };
+
+        match reg {
+            Some(reg) => {
+                locations[interval.vreg.0 as usize] = Some(Location::Reg(reg));
+                let at = active
+                    .iter()
+                    .position(|(i, _)| i.end > interval.end)
+                    .unwrap_or(active.len());
+                active.insert(at, (interval, reg));
+            }
+            None => {
+                locations[interval.vreg.0 as usize] = Some(Location::Stack(slots));
+                slots += 1;
+            }
+        }
+    }
+
+    let mut saved = Vec::new();
+    for location in &locations {
+        if let Some(Location::Reg(reg)) = location {
+            if reg.callee_saved && !saved.contains(reg) {
+                saved.push(*reg);
+            }
+        }
+    }
+    // Keep the order stable for the prologue
+    saved.sort_by_key(|r| registers.iter().position(|s| s == r));
+
+    // A virtual register that is never live still needs somewhere to go
+    let locations = locations
+        .into_iter()
+        .map(|l| {
+            l.unwrap_or_else(|| {
+                slots += 1;
+                Location::Stack(slots - 1)
+            })
+        })
+        .collect();
+
+    Allocation {
+        locations,
+        slots,
+        saved,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::x86::lir::{self, IntOp};
+    use crate::parser::Parser;
+    use crate::semantics::typeck;
+    use crate::syntax;
+
+    fn lower(src: &str) -> Function {
+        let tree = Parser::new().parse_str(src).unwrap();
+        lir::lower(&typeck::check(&syntax::Program::from_cst(&tree).unwrap()).unwrap())
+    }
+
+    /// No two virtual registers that are live at the same time share a
+    /// register.
+    fn assert_valid(func: &Function, alloc: &Allocation) {
+        let intervals = intervals(func);
+
+        for a in &intervals {
+            for b in &intervals {
+                if a.vreg != b.vreg && a.start <= b.end && b.start <= a.end {
+                    if let Location::Reg(reg) = alloc.location(a.vreg) {
+                        assert_ne!(
+                            Location::Reg(reg),
+                            alloc.location(b.vreg),
+                            "{:?} {:?}",
+                            a,
+                            b
+                        );
+                    }
+                }
+            }
+            if let Location::Reg(reg) = alloc.location(a.vreg) {
+                assert!(
+                    reg.callee_saved || !a.crosses_call,
+                    "{:?} in {}",
+                    a,
+                    reg.name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn loops_keep_variables_live() {
+        let func = lower("int i = 0, n = 10;\nwhile (i < n) i = i + 1;\nemit i n 0;\n");
+        let n = func
+            .insts
+            .iter()
+            .filter_map(|inst| match inst {
+                Inst::Move { dst, .. } => Some(*dst),
+                _ => None,
+            })
+            .nth(1)
+            .unwrap();
+        let top = func
+            .insts
+            .iter()
+            .position(|inst| matches!(inst, Inst::Label(_)))
+            .unwrap();
+        let back = func
+            .insts
+            .iter()
+            .rposition(|inst| matches!(inst, Inst::Jump(_)))
+            .unwrap();
+
+        // `n` is last read in the condition, but has to survive the back
+        // edge for the next iteration
+        let n = intervals(&func).into_iter().find(|i| i.vreg == n).unwrap();
+        assert!(n.start < top && n.end >= back, "{:?}", n);
+
+        let alloc = allocate(&func);
+        assert_valid(&func, &alloc);
+    }
+
+    #[test]
+    fn spills_under_pressure() {
+        let mut func = Function::default();
+        let regs: Vec<_> = (0..6)
+            .map(|i| {
+                func.classes.push(Class::Gpr);
+                let dst = VReg(i);
+                func.insts.push(Inst::Const {
+                    dst,
+                    value: i as i64,
+                });
+                dst
+            })
+            .collect();
+        for pair in regs.windows(2) {
+            func.insts.push(Inst::Int {
+                op: IntOp::Add,
+                dst: pair[1],
+                lhs: pair[0],
+                rhs: pair[1],
+            });
+        }
+
+        let alloc = allocate_with(&func, &REGISTERS[..3]);
+        assert_valid(&func, &alloc);
+        assert_eq!(alloc.slots, 3);
+        assert_eq!(alloc.saved.len(), 3);
+    }
+
+    #[test]
+    fn calls_clobber_caller_saved() {
+        let func =
+            lower("int a = 1;\nfloat f = 2.5;\nemit a f 0;\nint b = int(\"3\");\nemit a b f;\n");
+        let alloc = allocate(&func);
+        assert_valid(&func, &alloc);
+
+        // `f` is live across `printf` and there are no callee-saved XMMs
+        let f = func
+            .insts
+            .iter()
+            .find_map(|inst| match inst {
+                Inst::Move { dst, .. } if func.class(*dst) == Class::Xmm => Some(*dst),
+                _ => None,
+            })
+            .unwrap();
+        assert!(matches!(alloc.location(f), Location::Stack(_)));
+    }
+}
@@ -0,0 +1,594 @@
+//! Collecting and rendering hazards. The analysis reports every hazard it
+//! finds to a `DiagnosticSink` and never prints, what to do with them is up
+//! to the caller.
+//!
+//! `Format::Human` renders hazards like rustc, with the source lines they
+//! point at. `Format::Text` is the bare `OUTPUT` lines the course tools
+//! read, `Format::Json` an array with an object for every hazard, and
+//! `Format::Sarif` a SARIF 2.1.0 log that code scanning tools ingest
+//! directly.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::hazards::{Hazard, HazardType, Location};
+use crate::lint::{Level, Lints};
+
+/// Where hazards go as they are found.
+pub trait DiagnosticSink {
+    fn report(&mut self, hazard: Hazard);
+}
+
+impl DiagnosticSink for Vec<Hazard> {
+    fn report(&mut self, hazard: Hazard) {
+        self.push(hazard);
+    }
+}
+
+impl<S: DiagnosticSink + ?Sized> DiagnosticSink for &mut S {
+    fn report(&mut self, hazard: Hazard) {
+        (**self).report(hazard);
+    }
+}
+
+/// Collects hazards in the order they are found, at the level `lints`
+/// gives them. Allowed hazards are dropped.
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    hazards: Vec<Hazard>,
+    lints: Lints,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_lints(lints: Lints) -> Self {
+        Self {
+            hazards: Vec::new(),
+            lints,
+        }
+    }
+
+    pub fn hazards(&self) -> &[Hazard] {
+        &self.hazards
+    }
+
+    pub fn into_vec(self) -> Vec<Hazard> {
+        self.hazards
+    }
+
+    /// Whether any hazard is denied, which fails the check.
+    pub fn has_errors(&self) -> bool {
+        self.hazards.iter().any(|h| h.level.is_error())
+    }
+
+    /// Drops every hazard reported before with the same id at the same
+    /// locations.
+    pub fn dedup(&mut self) {
+        let mut seen = HashSet::new();
+        self.hazards
+            .retain(|h| seen.insert((h.hazard_type.display_id(), h.locations.clone())));
+    }
+
+    /// Sorts the hazards by their first location. Hazards at the same
+    /// location keep the order they were found in.
+    pub fn sort(&mut self) {
+        self.hazards.sort_by_key(|h| h.locations.first().cloned());
+    }
+}
+
+impl DiagnosticSink for Diagnostics {
+    fn report(&mut self, mut hazard: Hazard) {
+        hazard.level = self.lints.level(&hazard.hazard_type);
+        if hazard.level != Level::Allow {
+            self.hazards.push(hazard);
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Human,
+    Text,
+    Json,
+    Sarif,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Format::Human),
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "sarif" => Ok(Format::Sarif),
+            _ => Err(format!("unknown format {:?}", s)),
+        }
+    }
+}
+
+impl Format {
+    /// Renders `hazards`, found in the file `artifact`, as one document.
+    /// `source` is the text of `artifact` if it is zlang source, only
+    /// `Format::Human` shows it.
+    pub fn render(self, hazards: &[Hazard], artifact: &Path, source: Option<&str>) -> String {
+        match self {
+            Format::Human => human(hazards, artifact, source),
+            Format::Text => hazards.iter().map(|h| h.show_output() + "\n").collect(),
+            Format::Json => json(hazards),
+            Format::Sarif => sarif(hazards, artifact),
+        }
+    }
+}
+
+fn message(hazard: &Hazard) -> String {
+    hazard
+        .message()
+        .unwrap_or_else(|| hazard.hazard_type.summary().to_string())
+}
+
+/// `s` as a JSON string, quotes included.
+fn string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// An array of `{"type", "id", "locations", "message", "labels"}` objects,
+/// one line each. Every location has the `"row"` and `"col"` it starts at
+/// and the `"end"` just past it.
+pub fn json(hazards: &[Hazard]) -> String {
+    let mut out = String::from("[");
+
+    for (i, hazard) in hazards.iter().enumerate() {
+        out.push_str(if i == 0 { "\n  " } else { ",\n  " });
+
+        let locations: Vec<String> = hazard
+            .locations
+            .iter()
+            .map(|l| format!("{{{}}}", position(l)))
+            .collect();
+        let labels: Vec<String> = hazard
+            .labels
+            .iter()
+            .map(|l| {
+                format!(
+                    "{{{}, \"message\": {}}}",
+                    position(&l.location),
+                    string(&l.message)
+                )
+            })
+            .collect();
+        write!(
+            out,
+            "{{\"type\": {}, \"id\": {}, \"locations\": [{}], \"message\": {}, \"labels\": [{}]}}",
+            string(hazard.display_type()),
+            string(hazard.hazard_type.display_id()),
+            locations.join(", "),
+            string(&message(hazard)),
+            labels.join(", "),
+        )
+        .unwrap();
+    }
+
+    if !hazards.is_empty() {
+        out.push('\n');
+    }
+    out.push_str("]\n");
+    out
+}
+
+/// The members of a JSON location object for `location`.
+fn position(location: &Location) -> String {
+    format!(
+        "\"row\": {}, \"col\": {}, \"end\": {{\"row\": {}, \"col\": {}}}",
+        location.row, location.col, location.end.0, location.end.1
+    )
+}
+
+/// rustc style: a header with the level, the id and the message, where the
+/// hazard is, then the lines it points at with a `^` under the hazard and a
+/// `-` under each label. Without `source` only the header and the position
+/// are given.
+pub fn human(hazards: &[Hazard], artifact: &Path, source: Option<&str>) -> String {
+    let lines: Vec<&str> = source.map_or_else(Vec::new, |s| s.lines().collect());
+    let mut out = String::new();
+
+    for hazard in hazards {
+        let primary = hazard.locations.iter().map(|l| (l, '^', ""));
+        let labels = hazard
+            .labels
+            .iter()
+            .map(|l| (&l.location, '-', l.message.as_str()));
+        let mut marks: Vec<(&Location, char, &str)> = primary
+            .chain(labels)
+            .filter(|(l, _, _)| l.row >= 1 && l.row <= lines.len())
+            .collect();
+        marks.sort_by_key(|(l, _, _)| l.row);
+
+        let width = marks
+            .iter()
+            .map(|(l, _, _)| l.row.to_string().len())
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        let pad = " ".repeat(width);
+
+        writeln!(
+            out,
+            "{}[{}]: {}",
+            level(hazard.level),
+            hazard.hazard_type.display_id(),
+            message(hazard)
+        )
+        .unwrap();
+        if let Some(l) = hazard.locations.first() {
+            writeln!(out, "{}--> {}:{}:{}", pad, artifact.display(), l.row, l.col).unwrap();
+        }
+
+        if !marks.is_empty() {
+            writeln!(out, "{} |", pad).unwrap();
+        }
+        let mut shown = 0;
+        for (location, marker, label) in marks {
+            let line = lines[location.row - 1];
+            if location.row != shown {
+                writeln!(out, "{:>width$} | {}", location.row, line, width = width).unwrap();
+                shown = location.row;
+            }
+
+            // Tabs are kept so the marker lines up with the line above
+            let col = location.col.saturating_sub(1);
+            let indent: String = line
+                .chars()
+                .take(col)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            // Up to the end when it is on the same line, otherwise the word
+            let len = match location.end {
+                (row, end) if row == location.row && end > location.col => end - location.col,
+                _ => line
+                    .chars()
+                    .skip(col)
+                    .take_while(|c| c.is_alphanumeric() || *c == '_')
+                    .count()
+                    .max(1),
+            };
+            let marks = marker.to_string().repeat(len);
+            let mark_line = format!("{} | {}{} {}", pad, indent, marks, label);
+            writeln!(out, "{}", mark_line.trim_end()).unwrap();
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// The rustc and SARIF name of `level`.
+fn level(level: Level) -> &'static str {
+    match level {
+        Level::Allow => "none",
+        Level::Warn => "warning",
+        Level::Deny | Level::Forbid => "error",
+    }
+}
+
+/// The `"physicalLocation"` member of a SARIF location at `location` in the
+/// file `uri`, already a JSON string.
+fn physical_location(uri: &str, location: &Location) -> String {
+    // SARIF lines and columns start at 1
+    let mut region = format!(
+        "\"startLine\": {}, \"startColumn\": {}",
+        location.row.max(1),
+        location.col.max(1),
+    );
+    if location.end > (location.row, location.col) {
+        write!(
+            region,
+            ", \"endLine\": {}, \"endColumn\": {}",
+            location.end.0, location.end.1
+        )
+        .unwrap();
+    }
+
+    format!(
+        "\"physicalLocation\": {{\"artifactLocation\": {{\"uri\": {}}}, \"region\": {{{}}}}}",
+        uri, region,
+    )
+}
+
+/// A SARIF 2.1.0 log of a single run, with a rule for every kind of hazard
+/// and a result for every hazard.
+pub fn sarif(hazards: &[Hazard], artifact: &Path) -> String {
+    let uri = string(&artifact.to_string_lossy().replace('\\', "/"));
+
+    let rules: Vec<String> = HazardType::ALL
+        .iter()
+        .map(|rule| {
+            format!(
+                "            {{\"id\": {}, \"shortDescription\": {{\"text\": {}}}, \
+                 \"defaultConfiguration\": {{\"level\": \"{}\"}}}}",
+                string(rule.display_id()),
+                string(rule.summary()),
+                level(rule.default_level()),
+            )
+        })
+        .collect();
+
+    let results: Vec<String> = hazards
+        .iter()
+        .map(|hazard| {
+            let id = hazard.hazard_type.display_id();
+            let index = HazardType::ALL
+                .iter()
+                .position(|r| r.display_id() == id)
+                .expect("every hazard has a rule");
+            let locations: Vec<String> = hazard
+                .locations
+                .iter()
+                .map(|l| format!("{{{}}}", physical_location(&uri, l)))
+                .collect();
+            let related: Vec<String> = hazard
+                .labels
+                .iter()
+                .enumerate()
+                .map(|(i, l)| {
+                    format!(
+                        "{{\"id\": {}, {}, \"message\": {{\"text\": {}}}}}",
+                        i,
+                        physical_location(&uri, &l.location),
+                        string(&l.message)
+                    )
+                })
+                .collect();
+
+            let mut result = format!(
+                "        {{\"ruleId\": {}, \"ruleIndex\": {}, \"level\": \"{}\", \
+                 \"message\": {{\"text\": {}}}, \"locations\": [{}]",
+                string(id),
+                index,
+                level(hazard.level),
+                string(&message(hazard)),
+                locations.join(", "),
+            );
+            if !related.is_empty() {
+                write!(result, ", \"relatedLocations\": [{}]", related.join(", ")).unwrap();
+            }
+            result.push('}');
+            result
+        })
+        .collect();
+
+    let mut out = String::new();
+    out.push_str("{\n");
+    out.push_str("  \"$schema\": \"https://json.schemastore.org/sarif-2.1.0.json\",\n");
+    out.push_str("  \"version\": \"2.1.0\",\n");
+    out.push_str("  \"runs\": [\n    {\n");
+    out.push_str("      \"tool\": {\n        \"driver\": {\n");
+    out.push_str("          \"name\": \"zobos\",\n");
+    writeln!(
+        out,
+        "          \"version\": {},",
+        string(env!("CARGO_PKG_VERSION"))
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "          \"rules\": [\n{}\n          ]",
+        rules.join(",\n")
+    )
+    .unwrap();
+    out.push_str("        }\n      },\n");
+    out.push_str("      \"artifacts\": [{\"location\": {\"uri\": ");
+    out.push_str(&uri);
+    out.push_str("}}],\n");
+    if results.is_empty() {
+        out.push_str("      \"results\": []\n");
+    } else {
+        writeln!(
+            out,
+            "      \"results\": [\n{}\n      ]",
+            results.join(",\n")
+        )
+        .unwrap();
+    }
+    out.push_str("    }\n  ]\n}\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hazards::{ErrorId, WarnId};
+    use crate::lint::Lint;
+    use crate::parser::symbol::Terminal;
+    use crate::parser::token::Token;
+    use crate::span::Span;
+
+    fn hazards() -> Vec<Hazard> {
+        let found = Token {
+            id: "rbrace".to_string(),
+            data: "x7d".to_string(),
+            span: Span::new((3, 4), (3, 5)),
+        };
+        vec![
+            Hazard::new_syntax(found.clone(), vec![Terminal::new("sc")], found.span),
+            Hazard::new(
+                HazardType::Warn(WarnId::Unused),
+                vec![Location::new(1, 5), Location::new(2, 7)],
+            ),
+        ]
+    }
+
+    #[test]
+    fn sink_dedup_and_sort() {
+        let warn = |row, col| Hazard::new_one_loc(HazardType::Warn(WarnId::Unused), row, col);
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.report(warn(3, 1));
+        diagnostics.report(warn(1, 9));
+        diagnostics.report(Hazard::new_one_loc(HazardType::Warn(WarnId::Uninit), 3, 1));
+        diagnostics.report(warn(3, 1));
+        diagnostics.report(warn(1, 2));
+        assert!(!diagnostics.has_errors());
+
+        diagnostics.dedup();
+        diagnostics.sort();
+        let output: Vec<_> = diagnostics
+            .hazards()
+            .iter()
+            .map(Hazard::show_output)
+            .collect();
+        assert_eq!(
+            output,
+            vec![
+                "OUTPUT :WARN: 1 2 :UNUSED:",
+                "OUTPUT :WARN: 1 9 :UNUSED:",
+                "OUTPUT :WARN: 3 1 :UNUSED:",
+                "OUTPUT :WARN: 3 1 :UNINIT:",
+            ]
+        );
+
+        diagnostics.report(Hazard::new_one_loc(
+            HazardType::ErrorT(ErrorId::NoVar),
+            4,
+            1,
+        ));
+        assert!(diagnostics.has_errors());
+        assert_eq!(diagnostics.into_vec().len(), 5);
+    }
+
+    #[test]
+    fn sink_applies_lints() {
+        let mut lints = Lints::new();
+        lints.set("warnings".parse().unwrap(), Level::Deny).unwrap();
+        lints
+            .set(
+                Lint::Hazard(HazardType::Warn(WarnId::RedeclareVar)),
+                Level::Allow,
+            )
+            .unwrap();
+        lints.set("novar".parse().unwrap(), Level::Warn).unwrap();
+
+        let mut diagnostics = Diagnostics::with_lints(lints.clone());
+        diagnostics.report(Hazard::new_one_loc(
+            HazardType::Warn(WarnId::RedeclareVar),
+            1,
+            5,
+        ));
+        diagnostics.report(Hazard::new_one_loc(
+            HazardType::ErrorT(ErrorId::NoVar),
+            2,
+            1,
+        ));
+        assert!(!diagnostics.has_errors());
+
+        diagnostics.report(Hazard::new_one_loc(HazardType::Warn(WarnId::Unused), 1, 5));
+        assert!(diagnostics.has_errors());
+
+        let hazards = diagnostics.into_vec();
+        let output: Vec<String> = hazards.iter().map(Hazard::show_output).collect();
+        assert_eq!(
+            output,
+            vec!["OUTPUT :WARN: 2 1 :NOVAR:", "OUTPUT :ERROR: 1 5 :UNUSED:"]
+        );
+        assert!(human(&hazards, Path::new("a.z"), None).contains("error[UNUSED]"));
+        assert!(sarif(&hazards, Path::new("a.z"))
+            .contains("\"ruleId\": \"NOVAR\", \"ruleIndex\": 1, \"level\": \"warning\""));
+    }
+
+    #[test]
+    fn escapes_strings() {
+        assert_eq!(string("a \"b\"\\\n\u{1}"), "\"a \\\"b\\\"\\\\\\n\\u0001\"");
+    }
+
+    #[test]
+    fn json_output() {
+        assert_eq!(json(&[]), "[]\n");
+        assert_eq!(
+            json(&hazards()),
+            "[\n  {\"type\": \"SYNTAX\", \"id\": \"SYNTAX\", \"locations\": [{\"row\": 3, \"col\": 4, \
+             \"end\": {\"row\": 3, \"col\": 5}}], \
+             \"message\": \"found `rbrace`, expected `sc`\", \"labels\": []},\n  \
+             {\"type\": \"WARN\", \"id\": \"UNUSED\", \"locations\": [{\"row\": 1, \"col\": 5, \
+             \"end\": {\"row\": 1, \"col\": 5}}, {\"row\": 2, \"col\": 7, \
+             \"end\": {\"row\": 2, \"col\": 7}}], \"message\": \"variable is never used\", \"labels\": []}\n]\n"
+        );
+    }
+
+    #[test]
+    fn sarif_output() {
+        let log = sarif(&hazards(), Path::new("tests\\a b.zl"));
+
+        assert!(log.contains("\"version\": \"2.1.0\""));
+        assert_eq!(
+            log.matches("\"shortDescription\"").count(),
+            HazardType::ALL.len()
+        );
+        assert!(log.contains(
+            "{\"ruleId\": \"UNUSED\", \"ruleIndex\": 5, \"level\": \"warning\", \
+             \"message\": {\"text\": \"variable is never used\"}, \"locations\": \
+             [{\"physicalLocation\": {\"artifactLocation\": {\"uri\": \"tests/a b.zl\"}, \
+             \"region\": {\"startLine\": 1, \"startColumn\": 5}}}, "
+        ));
+        assert!(log.contains("\"ruleId\": \"SYNTAX\", \"ruleIndex\": 0, \"level\": \"error\""));
+        assert_eq!(log.matches('{').count(), log.matches('}').count());
+        assert_eq!(log.matches('[').count(), log.matches(']').count());
+
+        assert!(sarif(&[], Path::new("a.zl")).contains("\"results\": []"));
+
+        let revar = Hazard::new_one_loc(HazardType::Warn(WarnId::RedeclareVar), 2, 5)
+            .with_label(Span::new((1, 5), (1, 6)), "first declared here");
+        assert!(sarif(&[revar], Path::new("a.zl")).contains(
+            "\"relatedLocations\": [{\"id\": 0, \"physicalLocation\": {\"artifactLocation\": \
+             {\"uri\": \"a.zl\"}, \"region\": {\"startLine\": 1, \"startColumn\": 5, \
+             \"endLine\": 1, \"endColumn\": 6}}, \"message\": {\"text\": \"first declared here\"}}]}"
+        ));
+    }
+
+    #[test]
+    fn human_output() {
+        let source = "int x;\n{\n\tint x;\n}\nint count;\n";
+        let revar = Hazard::new_one_loc(HazardType::Warn(WarnId::RedeclareVar), 3, 6)
+            .with_description("variable `x` is already declared in this block")
+            .with_label(Span::point(1, 5), "first declared here");
+        let unused = Hazard::new_one_loc(HazardType::Warn(WarnId::Unused), 5, 5);
+        let range = Hazard::at(HazardType::Warn(WarnId::Unused), Span::new((5, 1), (5, 10)));
+        let hazards = [revar, unused, range];
+
+        assert_eq!(
+            human(&hazards, Path::new("a.z"), Some(source)),
+            "warning[REVAR]: variable `x` is already declared in this block\n \
+             --> a.z:3:6\n  |\n\
+             1 | int x;\n  |     - first declared here\n\
+             3 | \tint x;\n  | \t    ^\n\n\
+             warning[UNUSED]: variable is never used\n \
+             --> a.z:5:5\n  |\n\
+             5 | int count;\n  |     ^^^^^\n\n\
+             warning[UNUSED]: variable is never used\n \
+             --> a.z:5:1\n  |\n\
+             5 | int count;\n  | ^^^^^^^^^\n\n"
+        );
+        // Without the source there is nothing to point at
+        assert_eq!(
+            human(&hazards[1..2], Path::new("a.z"), None),
+            "warning[UNUSED]: variable is never used\n --> a.z:5:5\n\n"
+        );
+    }
+}
@@ -0,0 +1,86 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::hazards::Hazard;
+use crate::interp::RuntimeError;
+use crate::parser::lexer::LexError;
+
+/// Everything that can go wrong in the library. Nothing below `main` exits
+/// the process, so callers decide how each of these is reported.
+#[derive(Debug)]
+pub enum Error {
+    /// A file could not be read or written.
+    Io { path: PathBuf, source: io::Error },
+    /// A line of a token file is not `id data row col`.
+    MalformedToken { line: usize, text: String },
+    /// The source could not be scanned into tokens.
+    Lex(LexError),
+    /// A grammar file could not be read as one.
+    BadGrammar(String),
+    /// A parse table or its rule list is malformed.
+    BadTable(String),
+    /// The input did not parse. Holds every syntax hazard found.
+    Syntax(Vec<Hazard>),
+    /// The program parsed but is not well typed. Holds every error hazard.
+    Semantic(Vec<Hazard>),
+    /// Running the program failed.
+    Runtime(RuntimeError),
+    /// The lint levels could not be read, or set a forbidden lint.
+    Lint(String),
+    /// The hazards were printed already, and some of them are errors.
+    Reported,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        Error::Io {
+            path: path.into(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::MalformedToken { line, text } => {
+                write!(f, "line {}: malformed token {:?}", line, text)
+            }
+            Error::Lex(e) => write!(f, "{}", e),
+            Error::BadGrammar(message) => write!(f, "bad grammar: {}", message),
+            Error::BadTable(message) => write!(f, "bad parse table: {}", message),
+            Error::Syntax(hazards) => write!(f, "{} syntax error(s)", hazards.len()),
+            Error::Semantic(hazards) => write!(f, "{} semantic error(s)", hazards.len()),
+            Error::Runtime(e) => write!(f, "runtime error at {}", e),
+            Error::Lint(message) => write!(f, "bad lint levels: {}", message),
+            Error::Reported => write!(f, "the check reported errors"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Lex(e) => Some(e),
+            Error::Runtime(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<LexError> for Error {
+    fn from(e: LexError) -> Self {
+        Error::Lex(e)
+    }
+}
+
+impl From<RuntimeError> for Error {
+    fn from(e: RuntimeError) -> Self {
+        Error::Runtime(e)
+    }
+}
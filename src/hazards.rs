@@ -1,18 +1,86 @@
+use crate::lint::Level;
+use crate::parser::symbol::Terminal;
+use crate::parser::token::Token;
+use crate::span::Span;
+use std::fmt;
+
 #[derive(Debug, Clone)]
 pub struct Hazard {
-    hazard_type: HazardType,
-    locations: Vec<Location>,
+    pub hazard_type: HazardType,
+    pub locations: Vec<Location>,
+    /// Only set for syntax hazards found by the parser
+    pub syntax: Option<SyntaxContext>,
+    /// What went wrong in terms of the program, e.g. naming the variable
+    pub description: Option<String>,
+    /// Other places that explain the hazard
+    pub labels: Vec<Label>,
+    /// The default level of its type until the lint levels are applied
+    pub level: Level,
+}
+
+/// A secondary location of a hazard, like the first declaration of a
+/// redeclared variable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub location: Location,
+    pub message: String,
 }
 
+/// The token a syntax hazard was found at and the terminals the parser
+/// would have accepted in its place.
 #[derive(Debug, Clone)]
+pub struct SyntaxContext {
+    pub found: Token,
+    pub expected: Vec<Terminal>,
+}
+
+impl fmt::Display for SyntaxContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "found `{}`", self.found.id)?;
+
+        match self.expected.as_slice() {
+            [] => Ok(()),
+            [one] => write!(f, ", expected `{}`", one.terminal()),
+            many => {
+                write!(f, ", expected one of ")?;
+                for (i, t) in many.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "`{}`", t.terminal())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Location {
-    row: usize,
-    col: usize,
+    pub row: usize,
+    pub col: usize,
+    /// The row and column just past the end, the same as the start when
+    /// only the start is known
+    pub end: (usize, usize),
 }
 
 impl Location {
     pub fn new(row: usize, col: usize) -> Self {
-        Self { row, col }
+        Self {
+            row,
+            col,
+            end: (row, col),
+        }
+    }
+}
+
+impl From<Span> for Location {
+    fn from(span: Span) -> Self {
+        Self {
+            row: span.start.0,
+            col: span.start.1,
+            end: span.end,
+        }
     }
 }
 
@@ -21,23 +89,65 @@ impl Hazard {
         Self {
             hazard_type,
             locations,
+            syntax: None,
+            description: None,
+            labels: Vec::new(),
+            level: hazard_type.default_level(),
         }
     }
 
+    /// A syntax hazard at `found`, listing the terminals that were valid there
+    pub fn new_syntax(found: Token, expected: Vec<Terminal>, span: Span) -> Self {
+        let mut hazard = Self::at(HazardType::Syntax, span);
+        hazard.syntax = Some(SyntaxContext { found, expected });
+        hazard
+    }
+
     /// useful if you only have one location so you don't have to init a vec
     pub fn new_one_loc(hazard_type: HazardType, row: usize, col: usize) -> Self {
-        let l = Location::new(row, col);
-        let mut locations = Vec::new();
-        locations.push(l);
-        Self {
-            hazard_type,
-            locations,
+        Self::new(hazard_type, vec![Location::new(row, col)])
+    }
+
+    /// A hazard covering `span`
+    pub fn at(hazard_type: HazardType, span: Span) -> Self {
+        Self::new(hazard_type, vec![Location::from(span)])
+    }
+
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            location: Location::from(span),
+            message: message.into(),
+        });
+        self
+    }
+
+    /// A human readable explanation, when there is more to say than the
+    /// hazard id
+    pub fn message(&self) -> Option<String> {
+        self.syntax
+            .as_ref()
+            .map(|s| s.to_string())
+            .or_else(|| self.description.clone())
+    }
+
+    /// `SYNTAX` for syntax errors, otherwise `ERROR` or `WARN` by the level
+    /// the hazard is reported at.
+    pub fn display_type(&self) -> &'static str {
+        match (&self.hazard_type, self.level.is_error()) {
+            (HazardType::Syntax, _) => "SYNTAX",
+            (_, true) => "ERROR",
+            (_, false) => "WARN",
         }
     }
 
     pub fn show_output(&self) -> String {
         let mut out = String::from("OUTPUT :");
-        out.push_str(self.hazard_type.display_type());
+        out.push_str(self.display_type());
         out.push(':');
         out.push(' ');
         for loc in &self.locations {
@@ -53,21 +163,45 @@ impl Hazard {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum HazardType {
     Syntax,          // String should be token
     ErrorT(ErrorId), // `Error` and `Err` where causing match problem
     Warn(WarnId),
 }
 impl HazardType {
-    pub fn display_type(&self) -> &str {
+    /// Every kind of hazard
+    pub const ALL: &'static [HazardType] = &[
+        HazardType::Syntax,
+        HazardType::ErrorT(ErrorId::NoVar),
+        HazardType::ErrorT(ErrorId::Conversion),
+        HazardType::ErrorT(ErrorId::Expr),
+        HazardType::Warn(WarnId::RedeclareVar),
+        HazardType::Warn(WarnId::Unused),
+        HazardType::Warn(WarnId::Uninit),
+        HazardType::Warn(WarnId::MaybeUninit),
+        HazardType::Warn(WarnId::Const),
+        HazardType::Warn(WarnId::DivZero),
+        HazardType::Warn(WarnId::Conflict),
+    ];
+
+    /// Errors are denied and warnings warned about unless the lint levels
+    /// say otherwise
+    pub fn default_level(&self) -> Level {
+        match self {
+            HazardType::Syntax | HazardType::ErrorT(_) => Level::Deny,
+            HazardType::Warn(_) => Level::Warn,
+        }
+    }
+
+    pub fn display_type(&self) -> &'static str {
         match self {
             HazardType::Syntax => "SYNTAX",
             HazardType::ErrorT(_e) => "ERROR",
             HazardType::Warn(_w) => "WARN",
         }
     }
-    pub fn display_id(&self) -> &str {
+    pub fn display_id(&self) -> &'static str {
         match self {
             HazardType::Syntax => "SYNTAX",
             HazardType::ErrorT(e) => match e {
@@ -80,23 +214,50 @@ impl HazardType {
                 WarnId::RedeclareVar => "REVAR",
                 WarnId::Unused => "UNUSED",
                 WarnId::Uninit => "UNINIT",
+                WarnId::MaybeUninit => "MAYBEUNINIT",
+                WarnId::DivZero => "DIVZERO",
+                WarnId::Conflict => "CONFLICT",
+            },
+        }
+    }
+
+    /// What the hazard means, for readers who do not know the ids
+    pub fn summary(&self) -> &'static str {
+        match self {
+            HazardType::Syntax => "syntax error",
+            HazardType::ErrorT(e) => match e {
+                ErrorId::NoVar => "use of an undeclared variable",
+                ErrorId::Conversion => "value cannot be converted to the type it is stored as",
+                ErrorId::Expr => "operand has the wrong type for the operator",
+            },
+            HazardType::Warn(w) => match w {
+                WarnId::Const => "store to a const variable is ignored",
+                WarnId::RedeclareVar => "variable is already declared in this block",
+                WarnId::Unused => "variable is never used",
+                WarnId::Uninit => "variable is read before it is initialized",
+                WarnId::MaybeUninit => "variable may be read before it is initialized",
+                WarnId::DivZero => "division by zero",
+                WarnId::Conflict => "the parse table generated for the grammar has a conflict",
             },
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum ErrorId {
     NoVar,      // undeclared var
     Conversion, // value conversion error
     Expr,       // Expression tree operand error
 }
-#[derive(Debug, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum WarnId {
     RedeclareVar, // Attempting to re-declare a variable
     Unused,       // The variable is not used in an expression or assignment within it's scope
     Uninit,       // Using a variable in an expression before it has been initialized with a value
+    MaybeUninit,  // Using a variable that is only initialized on some of the paths to the use
     Const,        // Attempting to store a value in a variable with the const attribute
+    DivZero,      // Dividing or taking the modulo by a constant zero
+    Conflict,     // Two actions for one cell of a parse table generated with --grammar
 }
 
 #[cfg(test)]
@@ -138,11 +299,32 @@ mod tests {
         let revar = HazardType::Warn(WarnId::RedeclareVar);
         let unused = HazardType::Warn(WarnId::Unused);
         let uninit = HazardType::Warn(WarnId::Uninit);
-        let constT = HazardType::Warn(WarnId::Const);
+        let maybe_uninit = HazardType::Warn(WarnId::MaybeUninit);
+        let const_t = HazardType::Warn(WarnId::Const);
+        let div_zero = HazardType::Warn(WarnId::DivZero);
 
         assert_eq!(revar.display_id(), "REVAR");
         assert_eq!(unused.display_id(), "UNUSED");
         assert_eq!(uninit.display_id(), "UNINIT");
-        assert_eq!(constT.display_id(), "CONST");
+        assert_eq!(maybe_uninit.display_id(), "MAYBEUNINIT");
+        assert_eq!(const_t.display_id(), "CONST");
+        assert_eq!(div_zero.display_id(), "DIVZERO");
+    }
+
+    #[test]
+    fn syntax_message() {
+        let found = Token {
+            id: "rbrace".to_string(),
+            data: "x7d".to_string(),
+            span: Span::new((3, 4), (3, 5)),
+        };
+        let expected = vec![Terminal::new("comma"), Terminal::new("sc")];
+        let hazard = Hazard::new_syntax(found.clone(), expected, found.span);
+
+        assert_eq!(
+            hazard.message().unwrap(),
+            "found `rbrace`, expected one of `comma`, `sc`"
+        );
+        assert_eq!("OUTPUT :SYNTAX: 3 4 :SYNTAX:", hazard.show_output());
     }
 }
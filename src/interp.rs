@@ -0,0 +1,429 @@
+//! A tree-walking interpreter for type checked programs.
+//!
+//! Variables are the ones the type checker resolved, each with a value
+//! indexed by its `VarId`. They start out as the zero value of their type,
+//! and stores are converted to the variable's type the way
+//! `types::can_convert` allows. `emit x a b` prints the three values on one
+//! line and `emit symtable` prints every live variable with its value.
+
+use std::fmt;
+use std::io::Write;
+
+use crate::error::{Error, Result};
+use crate::syntax::{BinOp, Literal, Span, UnOp};
+use crate::typed::{Assign, Decl, Declarator, Emit, Expr, ExprKind, Program, Stmt, VarId};
+use crate::types::Type;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+impl Value {
+    /// The value of a variable that was declared without one.
+    pub fn zero(ty: Type) -> Self {
+        match ty {
+            Type::Bool => Value::Bool(false),
+            Type::Int => Value::Int(0),
+            Type::Float => Value::Float(0.0),
+            Type::String => Value::String(String::new()),
+        }
+    }
+
+    pub fn ty(&self) -> Type {
+        match self {
+            Value::Bool(_) => Type::Bool,
+            Value::Int(_) => Type::Int,
+            Value::Float(_) => Type::Float,
+            Value::String(_) => Type::String,
+        }
+    }
+
+    /// Converts the value for a store or a cast. Floats are truncated
+    /// towards zero, numbers are true when they are not zero and strings
+    /// are parsed. Returns `None` for a string that does not parse.
+    pub fn cast(self, to: Type) -> Option<Self> {
+        Some(match (self, to) {
+            (v, to) if v.ty() == to => v,
+            (Value::Bool(b), Type::Int) => Value::Int(b as i64),
+            (Value::Bool(b), Type::Float) => Value::Float(b as i64 as f64),
+            (Value::Int(i), Type::Bool) => Value::Bool(i != 0),
+            (Value::Int(i), Type::Float) => Value::Float(i as f64),
+            (Value::Float(f), Type::Bool) => Value::Bool(f != 0.0),
+            (Value::Float(f), Type::Int) => Value::Int(f as i64),
+            (Value::String(s), Type::Bool) => Value::Bool(s.trim().parse().ok()?),
+            (Value::String(s), Type::Int) => Value::Int(s.trim().parse().ok()?),
+            (Value::String(s), Type::Float) => Value::Float(s.trim().parse().ok()?),
+            (v, Type::String) => Value::String(v.to_string()),
+            (v, _) => unreachable!("{:?} already has the target type", v),
+        })
+    }
+
+    fn as_float(&self) -> f64 {
+        match self {
+            Value::Int(i) => *i as f64,
+            Value::Float(f) => *f,
+            v => unreachable!("{:?} is not a number", v),
+        }
+    }
+}
+
+impl From<&Literal> for Value {
+    fn from(literal: &Literal) -> Self {
+        match literal {
+            Literal::Int(i) => Value::Int(*i),
+            Literal::Float(f) => Value::Float(*f),
+            Literal::String(s) => Value::String(s.clone()),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(i) => write!(f, "{}", i),
+            // Keep the `.0` so floats can be told apart from ints
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::String(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// An error that stops a running program.
+#[derive(Debug)]
+pub struct RuntimeError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.span, self.message)
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+fn runtime_error(message: impl Into<String>, span: Span) -> Error {
+    Error::Runtime(RuntimeError {
+        message: message.into(),
+        span,
+    })
+}
+
+/// Runs `program`, writing what it emits to `out`.
+pub fn run<W: Write>(program: &Program, out: W) -> Result<()> {
+    Interpreter::new(program, out).run()
+}
+
+pub struct Interpreter<'p, W> {
+    program: &'p Program,
+    out: W,
+    /// The value of every variable, indexed by `VarId`
+    values: Vec<Value>,
+}
+
+impl<'p, W: Write> Interpreter<'p, W> {
+    pub fn new(program: &'p Program, out: W) -> Self {
+        Self {
+            program,
+            out,
+            values: program.vars.iter().map(|v| Value::zero(v.ty.ty)).collect(),
+        }
+    }
+
+    pub fn run(&mut self) -> Result<()> {
+        let program = self.program;
+        self.stmts(&program.stmts)?;
+        self.out.flush().map_err(|e| Error::io("<output>", e))
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) -> Result<()> {
+        stmts.iter().try_for_each(|s| self.stmt(s))
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Result<()> {
+        match stmt {
+            Stmt::Decl(decl) => self.decl(decl),
+            Stmt::Assign(assign) => {
+                let value = self.expr(&assign.value)?;
+                self.store(assign, value)
+            }
+            Stmt::If(if_) => {
+                if self.condition(&if_.cond)? {
+                    self.stmt(&if_.then)?;
+                }
+                Ok(())
+            }
+            Stmt::IfElse(if_else) => {
+                if self.condition(&if_else.cond)? {
+                    self.stmts(&if_else.then.stmts)
+                } else {
+                    self.stmt(&if_else.otherwise)
+                }
+            }
+            Stmt::While(while_) => {
+                while self.condition(&while_.cond)? {
+                    self.stmt(&while_.body)?;
+                }
+                Ok(())
+            }
+            Stmt::Emit(emit) => self.emit(emit),
+            Stmt::Block(block) => self.stmts(&block.stmts),
+        }
+    }
+
+    /// Running a declaration again, in a loop, starts its variable over. A
+    /// redeclaration in the same block keeps the first variable, like the
+    /// symbol table, but still takes the new initial value.
+    fn decl(&mut self, decl: &Decl) -> Result<()> {
+        for declarator in &decl.vars {
+            match declarator {
+                Declarator::Ident(ident, var) => {
+                    if self.program.var(*var).declared_by(ident) {
+                        self.values[var.0] = Value::zero(decl.ty.ty);
+                    }
+                }
+                Declarator::Init(assign) => {
+                    let value = self.expr(&assign.value)?;
+                    for target in &assign.targets {
+                        let ty = self.program.var(target.var).ty.ty;
+                        self.values[target.var.0] = convert(value.clone(), ty, target.ident.span)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stores to a `const` are checked with a warning, at run time the
+    /// variable keeps its initial value.
+    fn store(&mut self, assign: &Assign, value: Value) -> Result<()> {
+        for target in &assign.targets {
+            let ty = self.program.var(target.var).ty;
+            if !ty.constant {
+                self.values[target.var.0] = convert(value.clone(), ty.ty, target.span)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn condition(&mut self, cond: &Expr) -> Result<bool> {
+        match self.expr(cond)? {
+            Value::Bool(b) => Ok(b),
+            v => unreachable!("condition evaluated to {:?}", v),
+        }
+    }
+
+    fn emit(&mut self, emit: &Emit) -> Result<()> {
+        let out = match emit {
+            Emit::Value { var, first, second } => format!(
+                "{} {} {}\n",
+                self.expr(var)?,
+                self.expr(first)?,
+                self.expr(second)?
+            ),
+            Emit::Symtable { vars, .. } => self.symtable(vars),
+        };
+
+        self.out
+            .write_all(out.as_bytes())
+            .map_err(|e| Error::io("<output>", e))
+    }
+
+    /// The symbol table dump format with the current value appended.
+    pub fn symtable(&self, live: &[VarId]) -> String {
+        let mut out = String::new();
+
+        for &id in live {
+            let var = self.program.var(id);
+            let constant = if var.ty.constant { "const" } else { "" };
+            out.push_str(&format!(
+                "{},{}{},{},{}\n",
+                var.depth, constant, var.ty.ty, var.name, self.values[id.0]
+            ));
+        }
+        out
+    }
+
+    pub fn expr(&mut self, expr: &Expr) -> Result<Value> {
+        match &expr.kind {
+            ExprKind::Literal(literal) => Ok(Value::from(literal)),
+            ExprKind::Var(_, var) => Ok(self.values[var.0].clone()),
+            ExprKind::Unary { op, operand } => {
+                let value = self.expr(operand)?;
+                Ok(unary(*op, value))
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                let lhs = self.expr(lhs)?;
+                let rhs = self.expr(rhs)?;
+
+                binary(*op, lhs, rhs, expr.ty, expr.span)
+            }
+            ExprKind::Cast(inner) => {
+                let value = self.expr(inner)?;
+                convert(value, expr.ty, expr.span)
+            }
+        }
+    }
+}
+
+pub(crate) fn convert(value: Value, to: Type, span: Span) -> Result<Value> {
+    let message = format!("cannot convert {:?} to {}", value.to_string(), to);
+    value.cast(to).ok_or_else(|| runtime_error(message, span))
+}
+
+/// Evaluates `op value`.
+pub(crate) fn unary(op: UnOp, value: Value) -> Value {
+    match (op, value) {
+        (UnOp::Plus, v) => v,
+        (UnOp::Neg, Value::Int(i)) => Value::Int(i.wrapping_neg()),
+        (UnOp::Neg, Value::Float(f)) => Value::Float(-f),
+        (UnOp::Not, Value::Bool(b)) => Value::Bool(!b),
+        // Whether any bit of the complement is set
+        (UnOp::Compl, Value::Int(i)) => Value::Bool(!i != 0),
+        (op, v) => unreachable!("{}{:?} was not type checked", op, v),
+    }
+}
+
+/// Evaluates `lhs op rhs`, where `ty` is the checked type of the result.
+pub(crate) fn binary(op: BinOp, lhs: Value, rhs: Value, ty: Type, span: Span) -> Result<Value> {
+    if op.is_comparison() {
+        return Ok(Value::Bool(compare(op, &lhs, &rhs)));
+    }
+
+    match (ty, lhs, rhs) {
+        (Type::Int, Value::Int(l), Value::Int(r)) => Ok(Value::Int(match op {
+            BinOp::Add => l.wrapping_add(r),
+            BinOp::Sub => l.wrapping_sub(r),
+            BinOp::Mul => l.wrapping_mul(r),
+            BinOp::Div | BinOp::Mod if r == 0 => {
+                return Err(runtime_error("division by zero", span))
+            }
+            BinOp::Div => l.wrapping_div(r),
+            BinOp::Mod => l.wrapping_rem(r),
+            _ => unreachable!(),
+        })),
+        (Type::Float, l, r) => {
+            let (l, r) = (l.as_float(), r.as_float());
+
+            Ok(Value::Float(match op {
+                BinOp::Add => l + r,
+                BinOp::Sub => l - r,
+                BinOp::Mul => l * r,
+                BinOp::Div => l / r,
+                _ => unreachable!("{} on floats was not type checked", op),
+            }))
+        }
+        (ty, l, r) => unreachable!("{:?} {} {:?}: {} was not type checked", l, op, r, ty),
+    }
+}
+
+fn compare(op: BinOp, lhs: &Value, rhs: &Value) -> bool {
+    use std::cmp::Ordering;
+
+    let ordering = match (lhs, rhs) {
+        (Value::Int(l), Value::Int(r)) => l.partial_cmp(r),
+        (Value::Int(_), Value::Float(_))
+        | (Value::Float(_), Value::Int(_))
+        | (Value::Float(_), Value::Float(_)) => lhs.as_float().partial_cmp(&rhs.as_float()),
+        // Only `==` applies to anything else
+        (l, r) => return l == r,
+    };
+
+    match op {
+        BinOp::Lt => ordering == Some(Ordering::Less),
+        BinOp::Leq => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+        BinOp::Eq => ordering == Some(Ordering::Equal),
+        BinOp::Geq => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+        BinOp::Gt => ordering == Some(Ordering::Greater),
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::semantics::typeck;
+    use crate::syntax;
+
+    fn run_str(src: &str) -> Result<String> {
+        let tree = Parser::new().parse_str(src)?;
+        let program = typeck::check(&syntax::Program::from_cst(&tree)?).map_err(Error::Semantic)?;
+
+        let mut out = Vec::new();
+        run(&program, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn loops_and_branches() {
+        let out = run_str(
+            "int i = 0, sum;\n\
+             while (i < 5) { sum = sum + i; i = i + 1; }\n\
+             if (sum == 10) { emit sum i 1; } else emit i i i;\n\
+             if (i > 5) emit i 0 0;\n",
+        )
+        .unwrap();
+
+        assert_eq!(out, "10 5 1\n");
+    }
+
+    #[test]
+    fn conversions_and_casts() {
+        let out = run_str(
+            "float f = 1;\nint i = int(2.9) + 7 / 2;\nbool b = bool(i);\n\
+             emit f i 1.5 / 2;\nemit symtable;\n",
+        )
+        .unwrap();
+
+        assert_eq!(out, "1.0 5 0.75\n0,float,f,1.0\n0,int,i,5\n0,bool,b,true\n");
+    }
+
+    #[test]
+    fn block_scoping() {
+        let out = run_str(
+            "const int x = 1;\nx = 5;\n\
+             { int x = 2; string s = \"in\"; emit s x 0; emit symtable; }\n\
+             emit x 0 0;\nemit symtable;\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            out,
+            "in 2 0\n0,constint,x,1\n1,int,x,2\n1,string,s,in\n1 0 0\n0,constint,x,1\n"
+        );
+    }
+
+    #[test]
+    fn unbraced_declarations_are_scoped() {
+        let out = run_str(
+            "int z = 5;\nif (z > 1) int z = 1;\nif (z < 1) { } else float z = 2;\nemit z 0 0;\n",
+        )
+        .unwrap();
+        assert_eq!(out, "5 0 0\n");
+
+        assert!(matches!(
+            run_str("int a = 0;\nif (a > 7) int z = 1;\nemit z 0 0;\n"),
+            Err(Error::Semantic(_))
+        ));
+    }
+
+    #[test]
+    fn runtime_errors() {
+        match run_str("int z = 0;\nint x = 1 % z;\n") {
+            Err(Error::Runtime(e)) => {
+                assert_eq!(e.span.start, (2, 11));
+                assert_eq!(e.message, "division by zero");
+            }
+            r => panic!("expected a runtime error, found {:?}", r),
+        }
+    }
+}
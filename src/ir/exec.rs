@@ -0,0 +1,147 @@
+//! Runs a `Function` directly, with the semantics of `interp`. It is how
+//! the tests check that lowering and the passes over the IR keep a
+//! program's output.
+
+use std::io::Write;
+
+use crate::error::{Error, Result};
+use crate::interp::{self, Value};
+
+use super::{Function, Inst, Operand, Terminator};
+
+/// Runs `func`, writing what it emits to `out`.
+pub fn run<W: Write>(func: &Function, mut out: W) -> Result<()> {
+    let mut values: Vec<Value> = func.vars.iter().map(|v| Value::zero(v.ty)).collect();
+    let mut block = Function::ENTRY;
+    let mut prev = None;
+
+    let get = |values: &[Value], operand: &Operand| match operand {
+        Operand::Var(v) => values[v.0 as usize].clone(),
+        Operand::Const(c) => c.clone(),
+    };
+
+    loop {
+        let b = func.block(block);
+
+        // Every phi reads the values from before any of them is written
+        let phis: Vec<_> = b
+            .insts
+            .iter()
+            .take_while(|i| i.is_phi())
+            .map(|inst| match inst {
+                Inst::Phi { dst, args } => {
+                    let (_, arg) = args
+                        .iter()
+                        .find(|(from, _)| Some(*from) == prev)
+                        .expect("a phi has an operand for every predecessor");
+                    (*dst, get(&values, arg))
+                }
+                _ => unreachable!(),
+            })
+            .collect();
+        for (dst, value) in phis {
+            values[dst.0 as usize] = value;
+        }
+
+        for inst in b.insts.iter().skip_while(|i| i.is_phi()) {
+            let value = match inst {
+                Inst::Copy { src, .. } => get(&values, src),
+                Inst::Unary { op, src, .. } => interp::unary(*op, get(&values, src)),
+                Inst::Binary {
+                    dst,
+                    op,
+                    lhs,
+                    rhs,
+                    span,
+                } => interp::binary(
+                    *op,
+                    get(&values, lhs),
+                    get(&values, rhs),
+                    func.var(*dst).ty,
+                    *span,
+                )?,
+                Inst::Cast { dst, src, span } => {
+                    interp::convert(get(&values, src), func.var(*dst).ty, *span)?
+                }
+                Inst::Emit(args) => {
+                    let [a, b, c] = args;
+                    let line = format!(
+                        "{} {} {}\n",
+                        get(&values, a),
+                        get(&values, b),
+                        get(&values, c)
+                    );
+                    out.write_all(line.as_bytes())
+                        .map_err(|e| Error::io("<output>", e))?;
+                    continue;
+                }
+                Inst::Symtable(entries) => {
+                    let mut text = String::new();
+                    for entry in entries {
+                        let constant = if entry.ty.constant { "const" } else { "" };
+                        text.push_str(&format!(
+                            "{},{}{},{},{}\n",
+                            entry.depth,
+                            constant,
+                            entry.ty.ty,
+                            entry.name,
+                            get(&values, &entry.value)
+                        ));
+                    }
+                    out.write_all(text.as_bytes())
+                        .map_err(|e| Error::io("<output>", e))?;
+                    continue;
+                }
+                Inst::Phi { .. } => unreachable!("phis come first"),
+            };
+
+            let dst = inst.def().expect("only emits define nothing");
+            values[dst.0 as usize] = value;
+        }
+
+        prev = Some(block);
+        block = match &b.term {
+            Terminator::Jump(next) => *next,
+            Terminator::Branch {
+                cond,
+                then,
+                otherwise,
+            } => match get(&values, cond) {
+                Value::Bool(true) => *then,
+                Value::Bool(false) => *otherwise,
+                v => unreachable!("branch on {:?}", v),
+            },
+            Terminator::Return => break,
+        };
+    }
+
+    out.flush().map_err(|e| Error::io("<output>", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::lower;
+    use crate::parser::Parser;
+    use crate::semantics::typeck;
+    use crate::syntax;
+
+    #[test]
+    fn agrees_with_the_interpreter() {
+        let src = "int n = 10, a, b = 1, i;\n\
+                   while (i < n) { int t = a + b; a = b; b = t; i = i + 1; }\n\
+                   const int c = 3;\nc = a = b = 7;\n\
+                   if (a == 7) { int a = 1; emit a b c; emit symtable; } else emit a a a;\n\
+                   float f = a / 2.0;\nstring s = \"12\";\nemit f int(s) ~a;\n\
+                   int x = 1;\nint x = 2;\nemit symtable;\n";
+        let tree = Parser::new().parse_str(src).unwrap();
+        let program = typeck::check(&syntax::Program::from_cst(&tree).unwrap()).unwrap();
+
+        let mut expected = Vec::new();
+        interp::run(&program, &mut expected).unwrap();
+        let mut out = Vec::new();
+        run(&lower(&program), &mut out).unwrap();
+
+        assert_eq!(String::from_utf8(out), String::from_utf8(expected));
+    }
+}
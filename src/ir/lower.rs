@@ -0,0 +1,327 @@
+//! Lowers a type checked program to three-address code.
+//!
+//! Every variable the type checker resolved becomes a `Var` at its first
+//! declaration, and a redeclaration in the same block stores to it. Literals
+//! become constant operands and every other expression a new temporary.
+
+use crate::interp::Value;
+use std::collections::HashMap;
+
+use crate::syntax::{Span, UnOp};
+use crate::typed::{self, Assign, Decl, Declarator, Emit, Expr, ExprKind, Program, Stmt, VarId};
+use crate::types::Type;
+
+use super::{
+    BasicBlock, BlockId, Function, Inst, Operand, SymtableEntry, Terminator, Var, VarInfo,
+};
+
+/// Lowers `program`.
+pub fn lower(program: &Program) -> Function {
+    let mut lower = Lower {
+        program,
+        func: Function {
+            blocks: Vec::new(),
+            vars: Vec::new(),
+        },
+        current: Vec::new(),
+        block: Function::ENTRY,
+        vars: HashMap::new(),
+    };
+    lower.func.blocks.push(BasicBlock {
+        insts: Vec::new(),
+        term: Terminator::Return,
+    });
+
+    lower.stmts(&program.stmts);
+    lower.finish(Terminator::Return);
+    lower.func
+}
+
+struct Lower<'p> {
+    program: &'p Program,
+    func: Function,
+    /// The instructions of the block being built
+    current: Vec<Inst>,
+    block: BlockId,
+    /// The `Var` of each zlang variable
+    vars: HashMap<VarId, Var>,
+}
+
+impl Lower<'_> {
+    fn var(&mut self, name: Option<&str>, ty: Type) -> Var {
+        self.func.vars.push(VarInfo {
+            name: name.map(str::to_string),
+            ty,
+        });
+        Var(self.func.vars.len() as u32 - 1)
+    }
+
+    fn push(&mut self, inst: Inst) {
+        self.current.push(inst);
+    }
+
+    /// A new, empty block to be filled in later.
+    fn new_block(&mut self) -> BlockId {
+        self.func.blocks.push(BasicBlock {
+            insts: Vec::new(),
+            term: Terminator::Return,
+        });
+        BlockId(self.func.blocks.len() - 1)
+    }
+
+    /// Ends the current block with `term`.
+    fn finish(&mut self, term: Terminator) {
+        let block = &mut self.func.blocks[self.block.0];
+        block.insts = std::mem::take(&mut self.current);
+        block.term = term;
+    }
+
+    /// Ends the current block with `term` and continues in `next`.
+    fn switch(&mut self, term: Terminator, next: BlockId) {
+        self.finish(term);
+        self.block = next;
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Decl(decl) => self.decl(decl),
+            Stmt::Assign(assign) => self.assign(assign),
+            Stmt::If(if_) => {
+                let cond = self.expr(&if_.cond);
+                let (then, end) = (self.new_block(), self.new_block());
+                self.switch(
+                    Terminator::Branch {
+                        cond,
+                        then,
+                        otherwise: end,
+                    },
+                    then,
+                );
+                self.stmt(&if_.then);
+                self.switch(Terminator::Jump(end), end);
+            }
+            Stmt::IfElse(if_else) => {
+                let cond = self.expr(&if_else.cond);
+                let (then, otherwise, end) = (self.new_block(), self.new_block(), self.new_block());
+                self.switch(
+                    Terminator::Branch {
+                        cond,
+                        then,
+                        otherwise,
+                    },
+                    then,
+                );
+                self.stmts(&if_else.then.stmts);
+                self.switch(Terminator::Jump(end), otherwise);
+                self.stmt(&if_else.otherwise);
+                self.switch(Terminator::Jump(end), end);
+            }
+            Stmt::While(while_) => {
+                let (header, body, end) = (self.new_block(), self.new_block(), self.new_block());
+                self.switch(Terminator::Jump(header), header);
+                let cond = self.expr(&while_.cond);
+                self.switch(
+                    Terminator::Branch {
+                        cond,
+                        then: body,
+                        otherwise: end,
+                    },
+                    body,
+                );
+                self.stmt(&while_.body);
+                self.switch(Terminator::Jump(header), end);
+            }
+            Stmt::Emit(Emit::Value { var, first, second }) => {
+                let args = [self.expr(var), self.expr(first), self.expr(second)];
+                self.push(Inst::Emit(args));
+            }
+            Stmt::Emit(Emit::Symtable { vars, .. }) => self.symtable(vars),
+            Stmt::Block(block) => self.stmts(&block.stmts),
+        }
+    }
+
+    fn symtable(&mut self, live: &[VarId]) {
+        let entries = live
+            .iter()
+            .map(|id| {
+                let var = self.program.var(*id);
+                SymtableEntry {
+                    depth: var.depth,
+                    name: var.name.clone(),
+                    ty: var.ty,
+                    value: Operand::Var(self.vars[id]),
+                }
+            })
+            .collect();
+        self.push(Inst::Symtable(entries));
+    }
+
+    fn decl(&mut self, decl: &Decl) {
+        for var in &decl.vars {
+            match var {
+                Declarator::Ident(ident, var) => {
+                    if self.program.var(*var).declared_by(ident) {
+                        let zero = Operand::Const(Value::zero(decl.ty.ty));
+                        self.declare(*var, zero);
+                    }
+                }
+                Declarator::Init(assign) => {
+                    let value = self.expr(&assign.value);
+
+                    for target in &assign.targets {
+                        let var = self.program.var(target.var);
+                        let (ty, new) = (var.ty.ty, var.declared_by(&target.ident));
+                        let src = self.convert(value.clone(), assign.value.ty, ty, target.span);
+
+                        if new {
+                            self.declare(target.var, src);
+                        } else {
+                            let dst = self.vars[&target.var];
+                            self.push(Inst::Copy { dst, src });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn declare(&mut self, id: VarId, value: Operand) {
+        let typed::Var { name, ty, .. } = self.program.var(id);
+        let var = self.var(Some(name), ty.ty);
+        self.push(Inst::Copy {
+            dst: var,
+            src: value,
+        });
+        self.vars.insert(id, var);
+    }
+
+    /// Stores to a `const` are dropped, like in the interpreter, but the
+    /// value is still computed.
+    fn assign(&mut self, assign: &Assign) {
+        let value = self.expr(&assign.value);
+
+        for target in &assign.targets {
+            let var = self.program.var(target.var);
+            if var.ty.constant {
+                continue;
+            }
+
+            let (dst, ty) = (self.vars[&target.var], var.ty.ty);
+            let src = self.convert(value.clone(), assign.value.ty, ty, target.span);
+            self.push(Inst::Copy { dst, src });
+        }
+    }
+
+    /// Converts `value`, of type `from`, for a store or a cast to `to`.
+    fn convert(&mut self, value: Operand, from: Type, to: Type, span: Span) -> Operand {
+        if from == to {
+            return value;
+        }
+
+        let dst = self.var(None, to);
+        self.push(Inst::Cast {
+            dst,
+            src: value,
+            span,
+        });
+        Operand::Var(dst)
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Operand {
+        match &expr.kind {
+            ExprKind::Literal(literal) => Operand::Const(Value::from(literal)),
+            ExprKind::Var(_, var) => Operand::Var(self.vars[var]),
+            ExprKind::Unary {
+                op: UnOp::Plus,
+                operand,
+            } => self.expr(operand),
+            ExprKind::Unary { op, operand } => {
+                let src = self.expr(operand);
+                let dst = self.var(None, expr.ty);
+                self.push(Inst::Unary { dst, op: *op, src });
+                Operand::Var(dst)
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                // Mixed int and float operands are both made floats
+                let ty = if lhs.ty != rhs.ty && lhs.ty.is_numeric() && rhs.ty.is_numeric() {
+                    Type::Float
+                } else {
+                    lhs.ty
+                };
+                let l = self.expr(lhs);
+                let l = self.convert(l, lhs.ty, ty, lhs.span);
+                let r = self.expr(rhs);
+                let r = self.convert(r, rhs.ty, ty, rhs.span);
+
+                let dst = self.var(None, expr.ty);
+                self.push(Inst::Binary {
+                    dst,
+                    op: *op,
+                    lhs: l,
+                    rhs: r,
+                    span: expr.span,
+                });
+                Operand::Var(dst)
+            }
+            ExprKind::Cast(inner) => {
+                let value = self.expr(inner);
+                self.convert(value, inner.ty, expr.ty, expr.span)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::semantics::typeck;
+    use crate::syntax;
+
+    fn lower_str(src: &str) -> Function {
+        let tree = Parser::new().parse_str(src).unwrap();
+        lower(&typeck::check(&syntax::Program::from_cst(&tree).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn dump() {
+        let func = lower_str(
+            "int i = 0;\nfloat f;\nwhile (i < 3) { f = f + i; i = i + 1; }\n\
+             if (f > 2) { int i = 7; emit i f 1; } else emit i i i;\n",
+        );
+
+        assert_eq!(
+            func.to_string(),
+            "int i\nfloat f\nbool %2\nfloat %3\nfloat %4\nint %5\nfloat %6\nbool %7\nint i.1\n\n\
+             b0:\n    i = 0\n    f = 0.0\n    jump b1\n\n\
+             b1:\n    %2 = i < 3\n    branch %2 b2 b3\n\n\
+             b2:\n    %3 = float(i)\n    %4 = f + %3\n    f = %4\n    %5 = i + 1\n    \
+             i = %5\n    jump b1\n\n\
+             b3:\n    %6 = float(2)\n    %7 = f > %6\n    branch %7 b4 b5\n\n\
+             b4:\n    i.1 = 7\n    emit i.1, f, 1\n    jump b6\n\n\
+             b5:\n    emit i, i, i\n    jump b6\n\n\
+             b6:\n    return\n"
+        );
+    }
+
+    #[test]
+    fn cfg() {
+        let func = lower_str("int i;\nwhile (i < 3) i = i + 1;\nemit i i i;\n");
+        let cfg = func.cfg();
+
+        assert_eq!(cfg.node_count(), 4);
+        assert_eq!(cfg.edge_count(), 4);
+        assert!(cfg.contains_edge(BlockId(2).node(), BlockId(1).node()));
+        assert_eq!(func.predecessors()[1], vec![BlockId(0), BlockId(2)]);
+
+        let dot = func.cfg_dot();
+        assert!(dot.starts_with("digraph {"));
+        assert!(dot.contains("label = \"false\""));
+    }
+}
@@ -0,0 +1,484 @@
+//! A three-address code IR for type checked programs.
+//!
+//! `lower` flattens a program into basic blocks of instructions that each
+//! do one thing to at most two operands, ending in a jump, a branch or the
+//! return at the end of the program. Every zlang variable and every
+//! intermediate value is a `Var` with a type; implicit conversions are made
+//! explicit with `Cast`, so both operands of a `Binary` have the same type.
+//! The control flow graph is a petgraph graph with one node per block, the
+//! same numbering as `Function::blocks`.
+//!
+//! `ssa::construct` puts a function in SSA form, after which the passes in
+//! `opt` can run over it.
+
+pub mod exec;
+pub mod lower;
+pub mod opt;
+pub mod ssa;
+
+use std::fmt;
+use std::path::Path;
+
+use petgraph::dot::Dot;
+use petgraph::graph::{DiGraph, NodeIndex};
+use petgraph::Graph;
+
+use crate::error::{Error, Result};
+use crate::interp::Value;
+use crate::syntax::{BinOp, Span, UnOp};
+use crate::types::{QualType, Type};
+
+pub use exec::run;
+pub use lower::lower;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Var(pub u32);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub usize);
+
+impl BlockId {
+    pub fn node(self) -> NodeIndex {
+        NodeIndex::new(self.0)
+    }
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "b{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VarInfo {
+    /// The zlang variable, `None` for a temporary
+    pub name: Option<String>,
+    pub ty: Type,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Var(Var),
+    Const(Value),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymtableEntry {
+    /// The block nesting depth the interpreter reports
+    pub depth: usize,
+    pub name: String,
+    pub ty: QualType,
+    pub value: Operand,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inst {
+    Copy {
+        dst: Var,
+        src: Operand,
+    },
+    Unary {
+        dst: Var,
+        op: UnOp,
+        src: Operand,
+    },
+    /// Both operands have the same type, `dst` has the type of the result
+    Binary {
+        dst: Var,
+        op: BinOp,
+        lhs: Operand,
+        rhs: Operand,
+        span: Span,
+    },
+    /// Converts `src` to the type of `dst`, fails for strings that do not
+    /// parse
+    Cast {
+        dst: Var,
+        src: Operand,
+        span: Span,
+    },
+    Emit([Operand; 3]),
+    Symtable(Vec<SymtableEntry>),
+    /// The operand for the predecessor control came from. Phis only appear
+    /// at the start of a block, and are evaluated together.
+    Phi {
+        dst: Var,
+        args: Vec<(BlockId, Operand)>,
+    },
+}
+
+impl Inst {
+    pub fn def(&self) -> Option<Var> {
+        match self {
+            Inst::Copy { dst, .. }
+            | Inst::Unary { dst, .. }
+            | Inst::Binary { dst, .. }
+            | Inst::Cast { dst, .. }
+            | Inst::Phi { dst, .. } => Some(*dst),
+            Inst::Emit(_) | Inst::Symtable(_) => None,
+        }
+    }
+
+    pub fn operands(&self) -> Vec<&Operand> {
+        match self {
+            Inst::Copy { src, .. } | Inst::Unary { src, .. } | Inst::Cast { src, .. } => {
+                vec![src]
+            }
+            Inst::Binary { lhs, rhs, .. } => vec![lhs, rhs],
+            Inst::Emit(args) => args.iter().collect(),
+            Inst::Symtable(entries) => entries.iter().map(|e| &e.value).collect(),
+            Inst::Phi { args, .. } => args.iter().map(|(_, o)| o).collect(),
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Inst::Copy { src, .. } | Inst::Unary { src, .. } | Inst::Cast { src, .. } => {
+                vec![src]
+            }
+            Inst::Binary { lhs, rhs, .. } => vec![lhs, rhs],
+            Inst::Emit(args) => args.iter_mut().collect(),
+            Inst::Symtable(entries) => entries.iter_mut().map(|e| &mut e.value).collect(),
+            Inst::Phi { args, .. } => args.iter_mut().map(|(_, o)| o).collect(),
+        }
+    }
+
+    /// The variables the instruction reads.
+    pub fn uses(&self) -> Vec<Var> {
+        self.operands()
+            .into_iter()
+            .filter_map(|o| match o {
+                Operand::Var(v) => Some(*v),
+                Operand::Const(_) => None,
+            })
+            .collect()
+    }
+
+    pub fn is_phi(&self) -> bool {
+        matches!(self, Inst::Phi { .. })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Jump(BlockId),
+    Branch {
+        cond: Operand,
+        then: BlockId,
+        otherwise: BlockId,
+    },
+    Return,
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Jump(b) => vec![*b],
+            Terminator::Branch {
+                then, otherwise, ..
+            } => vec![*then, *otherwise],
+            Terminator::Return => vec![],
+        }
+    }
+
+    pub fn cond_mut(&mut self) -> Option<&mut Operand> {
+        match self {
+            Terminator::Branch { cond, .. } => Some(cond),
+            Terminator::Jump(_) | Terminator::Return => None,
+        }
+    }
+
+    /// Sends the edges to `from` to `to` instead.
+    pub fn retarget(&mut self, from: BlockId, to: BlockId) {
+        match self {
+            Terminator::Jump(b) if *b == from => *b = to,
+            Terminator::Branch {
+                then, otherwise, ..
+            } => {
+                for b in [then, otherwise].iter_mut() {
+                    if **b == from {
+                        **b = to;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock {
+    pub insts: Vec<Inst>,
+    pub term: Terminator,
+}
+
+/// The label on a CFG edge.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Edge {
+    Jump,
+    True,
+    False,
+}
+
+impl fmt::Display for Edge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Edge::Jump => Ok(()),
+            Edge::True => write!(f, "true"),
+            Edge::False => write!(f, "false"),
+        }
+    }
+}
+
+pub type Cfg = DiGraph<BlockId, Edge>;
+
+/// A lowered program. The first block is the entry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub blocks: Vec<BasicBlock>,
+    /// Indexed by `Var`
+    pub vars: Vec<VarInfo>,
+}
+
+impl Function {
+    pub const ENTRY: BlockId = BlockId(0);
+
+    pub fn var(&self, var: Var) -> &VarInfo {
+        &self.vars[var.0 as usize]
+    }
+
+    pub fn block(&self, id: BlockId) -> &BasicBlock {
+        &self.blocks[id.0]
+    }
+
+    /// The type of an operand.
+    pub fn ty(&self, operand: &Operand) -> Type {
+        match operand {
+            Operand::Var(v) => self.var(*v).ty,
+            Operand::Const(c) => c.ty(),
+        }
+    }
+
+    /// The control flow graph. Node `i` is `BlockId(i)`.
+    pub fn cfg(&self) -> Cfg {
+        let mut graph = Cfg::new();
+        for i in 0..self.blocks.len() {
+            graph.add_node(BlockId(i));
+        }
+        for (i, block) in self.blocks.iter().enumerate() {
+            let from = BlockId(i).node();
+            match &block.term {
+                Terminator::Jump(to) => {
+                    graph.add_edge(from, to.node(), Edge::Jump);
+                }
+                Terminator::Branch {
+                    then, otherwise, ..
+                } => {
+                    graph.add_edge(from, then.node(), Edge::True);
+                    graph.add_edge(from, otherwise.node(), Edge::False);
+                }
+                Terminator::Return => {}
+            }
+        }
+        graph
+    }
+
+    /// The blocks that jump or branch to each block.
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut preds = vec![Vec::new(); self.blocks.len()];
+        for (i, block) in self.blocks.iter().enumerate() {
+            for succ in block.term.successors() {
+                if !preds[succ.0].contains(&BlockId(i)) {
+                    preds[succ.0].push(BlockId(i));
+                }
+            }
+        }
+        preds
+    }
+
+    /// Drops the blocks control never reaches and renumbers the rest.
+    /// Returns whether any were dropped.
+    pub fn remove_unreachable(&mut self) -> bool {
+        let mut reachable = vec![false; self.blocks.len()];
+        let mut stack = vec![Function::ENTRY];
+        while let Some(b) = stack.pop() {
+            if !std::mem::replace(&mut reachable[b.0], true) {
+                stack.extend(self.block(b).term.successors());
+            }
+        }
+        if reachable.iter().all(|r| *r) {
+            return false;
+        }
+
+        let mut renumber = vec![None; self.blocks.len()];
+        let mut next = 0;
+        for (i, r) in reachable.iter().enumerate() {
+            if *r {
+                renumber[i] = Some(BlockId(next));
+                next += 1;
+            }
+        }
+        let map = |b: &mut BlockId| *b = renumber[b.0].expect("a reachable block");
+
+        let blocks = std::mem::take(&mut self.blocks);
+        for (i, mut block) in blocks.into_iter().enumerate() {
+            if !reachable[i] {
+                continue;
+            }
+            for inst in &mut block.insts {
+                if let Inst::Phi { args, .. } = inst {
+                    args.retain(|(b, _)| reachable[b.0]);
+                    args.iter_mut().for_each(|(b, _)| map(b));
+                }
+            }
+            match &mut block.term {
+                Terminator::Jump(b) => map(b),
+                Terminator::Branch {
+                    then, otherwise, ..
+                } => {
+                    map(then);
+                    map(otherwise);
+                }
+                Terminator::Return => {}
+            }
+            self.blocks.push(block);
+        }
+        true
+    }
+
+    /// How a variable is written in the dump: the zlang name, numbered
+    /// when it shadows another variable, or `%n` for a temporary.
+    pub fn var_name(&self, var: Var) -> String {
+        match &self.var(var).name {
+            Some(name) => {
+                let shadowed = self.vars[..var.0 as usize]
+                    .iter()
+                    .filter(|v| v.name.as_ref() == Some(name))
+                    .count();
+                match shadowed {
+                    0 => name.clone(),
+                    n => format!("{}.{}", name, n),
+                }
+            }
+            None => format!("%{}", var.0),
+        }
+    }
+
+    fn operand(&self, operand: &Operand) -> String {
+        match operand {
+            Operand::Var(v) => self.var_name(*v),
+            Operand::Const(Value::String(s)) => format!("{:?}", s),
+            Operand::Const(c) => c.to_string(),
+        }
+    }
+
+    fn inst(&self, inst: &Inst) -> String {
+        match inst {
+            Inst::Copy { dst, src } => format!("{} = {}", self.var_name(*dst), self.operand(src)),
+            Inst::Unary { dst, op, src } => {
+                format!("{} = {}{}", self.var_name(*dst), op, self.operand(src))
+            }
+            Inst::Binary {
+                dst, op, lhs, rhs, ..
+            } => format!(
+                "{} = {} {} {}",
+                self.var_name(*dst),
+                self.operand(lhs),
+                op,
+                self.operand(rhs)
+            ),
+            Inst::Cast { dst, src, .. } => format!(
+                "{} = {}({})",
+                self.var_name(*dst),
+                self.var(*dst).ty,
+                self.operand(src)
+            ),
+            Inst::Emit(args) => format!(
+                "emit {}, {}, {}",
+                self.operand(&args[0]),
+                self.operand(&args[1]),
+                self.operand(&args[2])
+            ),
+            Inst::Symtable(entries) => {
+                let vars: Vec<_> = entries
+                    .iter()
+                    .map(|e| format!("{}: {}", e.name, self.operand(&e.value)))
+                    .collect();
+                format!("emit symtable [{}]", vars.join(", "))
+            }
+            Inst::Phi { dst, args } => {
+                let args: Vec<_> = args
+                    .iter()
+                    .map(|(b, o)| format!("{}: {}", b, self.operand(o)))
+                    .collect();
+                format!("{} = phi [{}]", self.var_name(*dst), args.join(", "))
+            }
+        }
+    }
+
+    fn term(&self, term: &Terminator) -> String {
+        match term {
+            Terminator::Jump(b) => format!("jump {}", b),
+            Terminator::Branch {
+                cond,
+                then,
+                otherwise,
+            } => format!("branch {} {} {}", self.operand(cond), then, otherwise),
+            Terminator::Return => "return".to_string(),
+        }
+    }
+
+    /// The text of one block, as in the dump.
+    pub fn block_text(&self, id: BlockId) -> String {
+        let block = self.block(id);
+        let mut out = format!("{}:\n", id);
+        for inst in &block.insts {
+            out.push_str(&format!("    {}\n", self.inst(inst)));
+        }
+        out.push_str(&format!("    {}\n", self.term(&block.term)));
+        out
+    }
+
+    /// Writes the CFG as a Graphviz graph, each node showing its block.
+    pub fn export_cfg(&self, file_path: impl AsRef<Path>) -> Result<()> {
+        let path = file_path.as_ref();
+        std::fs::write(path, self.cfg_dot()).map_err(|e| Error::io(path, e))
+    }
+
+    pub fn cfg_dot(&self) -> String {
+        let cfg = self.cfg();
+        let graph: Graph<String, Edge> = cfg.map(|_, id| self.block_text(*id), |_, edge| *edge);
+        format!("{}", Dot::with_config(&graph, &[]))
+    }
+}
+
+/// The variables that are still defined or used, with their types, then
+/// every block.
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut used = vec![false; self.vars.len()];
+        for block in &self.blocks {
+            for inst in &block.insts {
+                for var in inst.def().into_iter().chain(inst.uses()) {
+                    used[var.0 as usize] = true;
+                }
+            }
+            if let Terminator::Branch {
+                cond: Operand::Var(var),
+                ..
+            } = block.term
+            {
+                used[var.0 as usize] = true;
+            }
+        }
+
+        for i in (0..self.vars.len()).filter(|i| used[*i]) {
+            let var = Var(i as u32);
+            writeln!(f, "{} {}", self.var(var).ty, self.var_name(var))?;
+        }
+        for i in 0..self.blocks.len() {
+            write!(f, "\n{}", self.block_text(BlockId(i)))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,696 @@
+//! Optimizations over a function in SSA form, and the pass manager that
+//! picks them for an optimization level.
+//!
+//! Every pass keeps what the program emits and where it stops with a
+//! runtime error: an instruction that can fail, a division by a variable or
+//! a cast from a string, is never removed or moved, only folded when its
+//! operands are constants it succeeds on.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::interp::{self, Value};
+use crate::syntax::{BinOp, UnOp};
+use crate::types::Type;
+
+use super::ssa::{self, DomTree};
+use super::{BlockId, Function, Inst, Operand, Terminator, Var};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Pass {
+    /// Folds instructions on constants and branches on them
+    ConstProp,
+    /// Replaces copies, and phis that pick one value, with the value
+    CopyProp,
+    /// Common subexpression elimination over the dominator tree
+    Cse,
+    /// Hoists loop invariant instructions out of `while` bodies
+    Licm,
+    /// Dead code elimination
+    Dce,
+}
+
+impl Pass {
+    /// Every pass, in the order the pass manager runs them.
+    pub const ALL: [Pass; 5] = [
+        Pass::ConstProp,
+        Pass::CopyProp,
+        Pass::Cse,
+        Pass::Licm,
+        Pass::Dce,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::ConstProp => "const-prop",
+            Pass::CopyProp => "copy-prop",
+            Pass::Cse => "cse",
+            Pass::Licm => "licm",
+            Pass::Dce => "dce",
+        }
+    }
+
+    /// Runs the pass over `func`, which has to be in SSA form. Returns
+    /// whether anything changed.
+    pub fn run(self, func: &mut Function) -> bool {
+        match self {
+            Pass::ConstProp => const_prop(func),
+            Pass::CopyProp => copy_prop(func),
+            Pass::Cse => cse(func),
+            Pass::Licm => licm(func),
+            Pass::Dce => dce(func),
+        }
+    }
+}
+
+impl fmt::Display for Pass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Pass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Pass::ALL
+            .iter()
+            .copied()
+            .find(|p| p.name() == s)
+            .ok_or_else(|| format!("unknown pass {:?}", s))
+    }
+}
+
+/// The passes to run over a lowered function.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PassManager {
+    /// In the order of `Pass::ALL`
+    passes: Vec<Pass>,
+}
+
+impl PassManager {
+    /// The passes for `-O<level>`: none at 0, propagation and dead code
+    /// elimination at 1, and everything from 2.
+    pub fn new(level: u8) -> Self {
+        let passes = match level {
+            0 => vec![],
+            1 => vec![Pass::ConstProp, Pass::CopyProp, Pass::Dce],
+            _ => Pass::ALL.to_vec(),
+        };
+        Self { passes }
+    }
+
+    pub fn passes(&self) -> &[Pass] {
+        &self.passes
+    }
+
+    pub fn enable(&mut self, pass: Pass) {
+        if !self.passes.contains(&pass) {
+            self.passes.push(pass);
+            self.passes
+                .sort_by_key(|p| Pass::ALL.iter().position(|a| a == p));
+        }
+    }
+
+    pub fn disable(&mut self, pass: Pass) {
+        self.passes.retain(|p| *p != pass);
+    }
+
+    pub fn run(&self, func: &mut Function) {
+        self.run_with(func, |_, _, _| {});
+    }
+
+    /// Puts `func` in SSA form and runs the passes, if there are any.
+    /// `dump` gets the name of each step with the function before and
+    /// after it.
+    pub fn run_with<F>(&self, func: &mut Function, mut dump: F)
+    where
+        F: FnMut(&str, &Function, &Function),
+    {
+        if self.passes.is_empty() {
+            return;
+        }
+
+        let before = func.clone();
+        ssa::construct(func);
+        dump("ssa", &before, func);
+
+        for pass in &self.passes {
+            let before = func.clone();
+            pass.run(func);
+            dump(pass.name(), &before, func);
+        }
+    }
+}
+
+/// Whether two operands always hold the same value. Unlike `==`, this
+/// tells `0.0` from `-0.0`, which print differently.
+fn same(a: &Operand, b: &Operand) -> bool {
+    match (a, b) {
+        (Operand::Const(Value::Float(a)), Operand::Const(Value::Float(b))) => {
+            a.to_bits() == b.to_bits()
+        }
+        (a, b) => a == b,
+    }
+}
+
+/// Follows `subst` from `operand` to the value it stands for.
+fn resolve(subst: &[Option<Operand>], operand: &mut Operand) {
+    while let Operand::Var(var) = operand {
+        match subst.get(var.0 as usize).and_then(Option::as_ref) {
+            Some(to) => *operand = to.clone(),
+            None => break,
+        }
+    }
+}
+
+/// Replaces every use of a variable `subst` has a value for.
+fn substitute(func: &mut Function, subst: &[Option<Operand>]) {
+    for block in &mut func.blocks {
+        for inst in &mut block.insts {
+            for operand in inst.operands_mut() {
+                resolve(subst, operand);
+            }
+        }
+        if let Some(cond) = block.term.cond_mut() {
+            resolve(subst, cond);
+        }
+    }
+}
+
+/// The one operand a phi picks, besides itself.
+fn phi_value(dst: Var, args: &[(BlockId, Operand)]) -> Option<&Operand> {
+    let mut values = args
+        .iter()
+        .map(|(_, o)| o)
+        .filter(|o| **o != Operand::Var(dst));
+    let first = values.next()?;
+    values.all(|o| same(o, first)).then_some(first)
+}
+
+/// Whether removing or moving the instruction could change what the
+/// program does: it emits, or it can stop the program with an error.
+fn has_effect(func: &Function, inst: &Inst) -> bool {
+    match inst {
+        Inst::Emit(_) | Inst::Symtable(_) => true,
+        Inst::Binary {
+            op: BinOp::Div,
+            lhs,
+            rhs,
+            ..
+        }
+        | Inst::Binary {
+            op: BinOp::Mod,
+            lhs,
+            rhs,
+            ..
+        } => func.ty(lhs) == Type::Int && !matches!(rhs, Operand::Const(Value::Int(r)) if *r != 0),
+        Inst::Cast { dst, src, .. } => {
+            func.ty(src) == Type::String && func.var(*dst).ty != Type::String
+        }
+        _ => false,
+    }
+}
+
+/// The value of an instruction whose operands are constants.
+fn fold(func: &Function, inst: &Inst) -> Option<Value> {
+    use Operand::Const;
+
+    Some(match inst {
+        Inst::Copy { src: Const(c), .. } => c.clone(),
+        Inst::Unary {
+            op, src: Const(c), ..
+        } => interp::unary(*op, c.clone()),
+        Inst::Binary {
+            dst,
+            op,
+            lhs: Const(l),
+            rhs: Const(r),
+            span,
+        } => interp::binary(*op, l.clone(), r.clone(), func.var(*dst).ty, *span).ok()?,
+        Inst::Cast {
+            dst,
+            src: Const(c),
+            span,
+        } => interp::convert(c.clone(), func.var(*dst).ty, *span).ok()?,
+        Inst::Phi { dst, args } => match phi_value(*dst, args)? {
+            Const(c) => c.clone(),
+            Operand::Var(_) => return None,
+        },
+        _ => return None,
+    })
+}
+
+fn const_prop(func: &mut Function) -> bool {
+    let mut changed = false;
+
+    loop {
+        let mut subst = vec![None; func.vars.len()];
+        let mut folded = false;
+
+        for b in 0..func.blocks.len() {
+            let insts = std::mem::take(&mut func.blocks[b].insts);
+            let mut kept = Vec::with_capacity(insts.len());
+
+            for mut inst in insts {
+                for operand in inst.operands_mut() {
+                    resolve(&subst, operand);
+                }
+                match (inst.def(), fold(func, &inst)) {
+                    (Some(dst), Some(value)) => {
+                        subst[dst.0 as usize] = Some(Operand::Const(value));
+                        folded = true;
+                    }
+                    _ => kept.push(inst),
+                }
+            }
+            func.blocks[b].insts = kept;
+        }
+        substitute(func, &subst);
+
+        for b in 0..func.blocks.len() {
+            if let Terminator::Branch {
+                cond: Operand::Const(Value::Bool(cond)),
+                then,
+                otherwise,
+            } = func.blocks[b].term
+            {
+                let (taken, dropped) = if cond {
+                    (then, otherwise)
+                } else {
+                    (otherwise, then)
+                };
+                func.blocks[b].term = Terminator::Jump(taken);
+                if dropped != taken {
+                    for inst in &mut func.blocks[dropped.0].insts {
+                        if let Inst::Phi { args, .. } = inst {
+                            args.retain(|(from, _)| *from != BlockId(b));
+                        }
+                    }
+                }
+                folded = true;
+            }
+        }
+        func.remove_unreachable();
+
+        if !folded {
+            return changed;
+        }
+        changed = true;
+    }
+}
+
+fn copy_prop(func: &mut Function) -> bool {
+    let mut changed = false;
+
+    loop {
+        let mut subst = vec![None; func.vars.len()];
+        let mut copied = false;
+
+        for block in &mut func.blocks {
+            block.insts.retain(|inst| {
+                let value = match inst {
+                    Inst::Copy { src, .. } => src,
+                    Inst::Phi { dst, args } => match phi_value(*dst, args) {
+                        Some(value) => value,
+                        None => return true,
+                    },
+                    _ => return true,
+                };
+                let dst = inst.def().expect("copies and phis define a variable");
+                subst[dst.0 as usize] = Some(value.clone());
+                copied = true;
+                false
+            });
+        }
+        substitute(func, &subst);
+
+        if !copied {
+            return changed;
+        }
+        changed = true;
+    }
+}
+
+fn dce(func: &mut Function) -> bool {
+    let mut live = vec![false; func.vars.len()];
+    let mut defs: Vec<Option<&Inst>> = vec![None; func.vars.len()];
+    let mut worklist = Vec::new();
+
+    for block in &func.blocks {
+        for inst in &block.insts {
+            if let Some(dst) = inst.def() {
+                defs[dst.0 as usize] = Some(inst);
+            }
+            if has_effect(func, inst) {
+                worklist.extend(inst.uses());
+            }
+        }
+        if let Terminator::Branch {
+            cond: Operand::Var(var),
+            ..
+        } = block.term
+        {
+            worklist.push(var);
+        }
+    }
+
+    while let Some(var) = worklist.pop() {
+        if std::mem::replace(&mut live[var.0 as usize], true) {
+            continue;
+        }
+        if let Some(inst) = defs[var.0 as usize] {
+            worklist.extend(inst.uses());
+        }
+    }
+
+    let mut changed = false;
+    for b in 0..func.blocks.len() {
+        let insts = std::mem::take(&mut func.blocks[b].insts);
+        let before = insts.len();
+        let kept: Vec<Inst> = insts
+            .into_iter()
+            .filter(|inst| match inst.def() {
+                Some(dst) => live[dst.0 as usize] || has_effect(func, inst),
+                None => true,
+            })
+            .collect();
+        changed |= kept.len() != before;
+        func.blocks[b].insts = kept;
+    }
+    changed
+}
+
+/// What an instruction computes, for finding the same computation again.
+#[derive(Debug, Clone)]
+enum Expr {
+    Unary(UnOp, Operand),
+    Binary(BinOp, Operand, Operand, Type),
+    Cast(Operand, Type),
+}
+
+impl Expr {
+    fn of(func: &Function, inst: &Inst) -> Option<Expr> {
+        Some(match inst {
+            Inst::Unary { op, src, .. } => Expr::Unary(*op, src.clone()),
+            Inst::Binary {
+                dst, op, lhs, rhs, ..
+            } => Expr::Binary(*op, lhs.clone(), rhs.clone(), func.var(*dst).ty),
+            Inst::Cast { dst, src, .. } => Expr::Cast(src.clone(), func.var(*dst).ty),
+            _ => return None,
+        })
+    }
+
+    fn same(&self, other: &Expr) -> bool {
+        match (self, other) {
+            (Expr::Unary(a, x), Expr::Unary(b, y)) => a == b && same(x, y),
+            (Expr::Binary(a, xl, xr, xt), Expr::Binary(b, yl, yr, yt)) => {
+                a == b && same(xl, yl) && same(xr, yr) && xt == yt
+            }
+            (Expr::Cast(x, xt), Expr::Cast(y, yt)) => same(x, y) && xt == yt,
+            _ => false,
+        }
+    }
+}
+
+enum Visit {
+    Enter(BlockId),
+    /// Forgets what the block made available
+    Exit(usize),
+}
+
+/// An instruction is replaced by an earlier one that computes the same
+/// thing in a dominating block. If the earlier one stops the program with
+/// an error, the later one is never reached.
+fn cse(func: &mut Function) -> bool {
+    let tree = DomTree::new(func);
+    let mut subst = vec![None; func.vars.len()];
+    let mut available: Vec<(Expr, Var)> = Vec::new();
+    let mut visits = vec![Visit::Enter(Function::ENTRY)];
+    let mut changed = false;
+
+    while let Some(visit) = visits.pop() {
+        let block = match visit {
+            Visit::Enter(block) => block,
+            Visit::Exit(len) => {
+                available.truncate(len);
+                continue;
+            }
+        };
+        visits.push(Visit::Exit(available.len()));
+        visits.extend(tree.children(block).iter().rev().map(|b| Visit::Enter(*b)));
+
+        let insts = std::mem::take(&mut func.blocks[block.0].insts);
+        let mut kept = Vec::with_capacity(insts.len());
+        for mut inst in insts {
+            for operand in inst.operands_mut() {
+                resolve(&subst, operand);
+            }
+            if let (Some(dst), Some(expr)) = (inst.def(), Expr::of(func, &inst)) {
+                match available.iter().find(|(e, _)| e.same(&expr)) {
+                    Some((_, var)) => {
+                        subst[dst.0 as usize] = Some(Operand::Var(*var));
+                        changed = true;
+                        continue;
+                    }
+                    None => available.push((expr, dst)),
+                }
+            }
+            kept.push(inst);
+        }
+        func.blocks[block.0].insts = kept;
+    }
+
+    substitute(func, &subst);
+    changed
+}
+
+/// A loop is the blocks that reach a back edge to its header without
+/// going through the header. Instructions are hoisted to the one block
+/// that jumps to the header from outside the loop, which is where `lower`
+/// puts the jump into every `while`.
+fn licm(func: &mut Function) -> bool {
+    let tree = DomTree::new(func);
+    let preds = func.predecessors();
+
+    let mut loops = Vec::new();
+    for (header, preds_of) in preds.iter().enumerate() {
+        let header = BlockId(header);
+        let mut stack: Vec<BlockId> = preds_of
+            .iter()
+            .copied()
+            .filter(|p| tree.dominates(header, *p))
+            .collect();
+        if stack.is_empty() {
+            continue;
+        }
+
+        let mut body = vec![header];
+        while let Some(b) = stack.pop() {
+            if !body.contains(&b) {
+                body.push(b);
+                stack.extend(preds[b.0].iter().copied());
+            }
+        }
+        loops.push((header, body));
+    }
+
+    // Inner loops first, so what they hoist can move further out
+    loops.sort_by_key(|(_, body)| body.len());
+
+    let mut changed = false;
+    for (header, body) in loops {
+        let outside: Vec<BlockId> = preds[header.0]
+            .iter()
+            .copied()
+            .filter(|p| !body.contains(p))
+            .collect();
+        match outside[..] {
+            [pre] if func.block(pre).term == Terminator::Jump(header) => {
+                changed |= hoist(func, &body, pre);
+            }
+            _ => {}
+        }
+    }
+    changed
+}
+
+fn hoist(func: &mut Function, body: &[BlockId], pre: BlockId) -> bool {
+    let mut in_loop = vec![false; func.vars.len()];
+    for b in body {
+        for dst in func.block(*b).insts.iter().filter_map(Inst::def) {
+            in_loop[dst.0 as usize] = true;
+        }
+    }
+
+    let mut changed = false;
+    loop {
+        let mut hoisted = Vec::new();
+
+        for b in body {
+            let insts = std::mem::take(&mut func.blocks[b.0].insts);
+            let mut kept = Vec::with_capacity(insts.len());
+            for inst in insts {
+                let invariant = !inst.is_phi()
+                    && inst.def().is_some()
+                    && !has_effect(func, &inst)
+                    && inst.uses().iter().all(|v| !in_loop[v.0 as usize]);
+
+                if invariant {
+                    let dst = inst.def().unwrap();
+                    in_loop[dst.0 as usize] = false;
+                    hoisted.push(inst);
+                } else {
+                    kept.push(inst);
+                }
+            }
+            func.blocks[b.0].insts = kept;
+        }
+
+        if hoisted.is_empty() {
+            return changed;
+        }
+        func.blocks[pre.0].insts.extend(hoisted);
+        changed = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::ir::{lower, run};
+    use crate::parser::Parser;
+    use crate::semantics::typeck;
+    use crate::syntax;
+    use crate::typed::Program;
+
+    fn check(src: &str) -> Program {
+        let tree = Parser::new().parse_str(src).unwrap();
+        typeck::check(&syntax::Program::from_cst(&tree).unwrap()).unwrap()
+    }
+
+    fn optimize(src: &str, passes: &[Pass]) -> Function {
+        let mut manager = PassManager::new(0);
+        passes.iter().for_each(|p| manager.enable(*p));
+        let mut func = lower(&check(src));
+        manager.run(&mut func);
+        func
+    }
+
+    /// What the program emits, and the error it stops with.
+    fn output(result: crate::error::Result<()>, out: Vec<u8>) -> (String, Option<String>) {
+        (
+            String::from_utf8(out).unwrap(),
+            result.err().map(|e: Error| e.to_string()),
+        )
+    }
+
+    #[test]
+    fn levels_keep_the_output() {
+        let programs = [
+            "int n = 10, a, b = 1, i;\n\
+             while (i < n) { int t = a + b; a = b; b = t; i = i + 1; }\n\
+             const int c = 3;\nc = a = b = 7;\n\
+             if (a == 7) { int a = 1; emit a b c; emit symtable; } else emit a a a;\n\
+             float f = a / 2.0;\nstring s = \"12\";\nemit f int(s) ~a;\n",
+            "int i, j, k = 4, sum;\n\
+             while (i < 3) { j = 0; while (j < k * 2) { sum = sum + k * 2 + i; j = j + 1; } \
+             i = i + 1; }\nemit sum i j;\nemit symtable;\n",
+            "float z = 0.0, m = -1.0;\nfloat w = z * m;\nint x = 3;\n\
+             if (x < 2) { emit z w x; } else emit w z x;\n",
+            "int z, x = 5;\nint y = x - x;\nemit x y z;\nint q = x / z;\nemit q q q;\n",
+            "string s = \"abc\";\nint i;\nwhile (i < 2) { i = i + 1; float f = float(s); }\n",
+            "int a = 0, z = 5;\nif (a < 7) int z = 1;\nwhile (a < 2) { a = a + 1; if (a > 1) int z = a; }\n\
+             if (z == 5) { } else int z;\nemit z a 0;\nemit symtable;\n",
+        ];
+
+        for src in programs.iter() {
+            let program = check(src);
+            let mut out = Vec::new();
+            let expected = output(interp::run(&program, &mut out), out);
+
+            for level in 0..3 {
+                let mut func = lower(&program);
+                PassManager::new(level).run(&mut func);
+                let mut out = Vec::new();
+                assert_eq!(
+                    output(run(&func, &mut out), out),
+                    expected,
+                    "-O{} of\n{}\n{}",
+                    level,
+                    src,
+                    func
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn const_prop() {
+        let func = optimize(
+            "int a = 2 * 3, b;\nif (a > 5) { b = a + 1; } else b = 0;\nemit a b a;\n",
+            &[Pass::ConstProp, Pass::Dce],
+        );
+
+        assert_eq!(func.blocks.len(), 3);
+        assert_eq!(
+            func.block_text(BlockId(2)),
+            "b2:\n    emit 6, 7, 6\n    return\n"
+        );
+    }
+
+    #[test]
+    fn failing_instructions_stay() {
+        let func = optimize(
+            "int z, x = 1 / z;\nfloat f = float(\"x\");\n",
+            &[Pass::ConstProp, Pass::Dce],
+        );
+
+        assert_eq!(
+            func.block_text(Function::ENTRY),
+            "b0:\n    %1 = 1 / 0\n    %3 = float(\"x\")\n    return\n"
+        );
+    }
+
+    #[test]
+    fn cse_and_licm() {
+        let func = optimize(
+            "int i, n = 3, k;\nwhile (i < n) { k = n * 2 + n * 2; i = i + 1; }\nemit i k k;\n",
+            &[Pass::CopyProp, Pass::Cse, Pass::Licm, Pass::Dce],
+        );
+
+        assert_eq!(
+            func.block_text(Function::ENTRY),
+            "b0:\n    %4 = 3 * 2\n    %6 = %4 + %4\n    jump b1\n"
+        );
+        assert_eq!(
+            func.block_text(BlockId(2)),
+            "b2:\n    %7 = i.1 + 1\n    jump b1\n"
+        );
+    }
+
+    #[test]
+    fn manager() {
+        assert!(PassManager::new(0).passes().is_empty());
+        assert_eq!(
+            PassManager::new(1).passes(),
+            &[Pass::ConstProp, Pass::CopyProp, Pass::Dce]
+        );
+
+        let mut manager = PassManager::new(2);
+        manager.disable(Pass::Cse);
+        manager.disable(Pass::ConstProp);
+        manager.enable(Pass::ConstProp);
+        assert_eq!(
+            manager.passes(),
+            &[Pass::ConstProp, Pass::CopyProp, Pass::Licm, Pass::Dce]
+        );
+        assert_eq!("licm".parse(), Ok(Pass::Licm));
+
+        let mut steps = Vec::new();
+        let mut func = lower(&check("int a = 1;\nemit a a a;\n"));
+        manager.run_with(&mut func, |name, _, _| steps.push(name.to_string()));
+        assert_eq!(steps, ["ssa", "const-prop", "copy-prop", "licm", "dce"]);
+    }
+}
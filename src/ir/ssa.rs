@@ -0,0 +1,349 @@
+//! Puts a `Function` in SSA form, where every variable is defined by one
+//! instruction.
+//!
+//! This is the construction of Cytron et al.: phis for a variable go on the
+//! dominance frontier of the blocks that define it, then a walk down the
+//! dominator tree gives every definition a new variable and points every
+//! use at the one that reaches it. Only variables read in some block before
+//! that block defines them get phis, so temporaries never do. A variable
+//! read where no definition reaches it reads its zero value, as `exec` and
+//! the interpreter start every variable out.
+
+use petgraph::algo::dominators::simple_fast;
+
+use crate::interp::Value;
+
+use super::{BlockId, Function, Inst, Operand, Terminator, Var};
+
+/// The dominator tree of the blocks of a function.
+#[derive(Debug, Clone)]
+pub struct DomTree {
+    /// `None` for the entry
+    idom: Vec<Option<BlockId>>,
+    children: Vec<Vec<BlockId>>,
+}
+
+impl DomTree {
+    /// Every block of `func` has to be reachable, see
+    /// `Function::remove_unreachable`.
+    pub fn new(func: &Function) -> Self {
+        let cfg = func.cfg();
+        let dominators = simple_fast(&cfg, Function::ENTRY.node());
+
+        let mut idom = vec![None; func.blocks.len()];
+        let mut children = vec![Vec::new(); func.blocks.len()];
+        for (i, idom) in idom.iter_mut().enumerate() {
+            if let Some(parent) = dominators.immediate_dominator(BlockId(i).node()) {
+                let parent = BlockId(parent.index());
+                *idom = Some(parent);
+                children[parent.0].push(BlockId(i));
+            }
+        }
+
+        Self { idom, children }
+    }
+
+    pub fn idom(&self, block: BlockId) -> Option<BlockId> {
+        self.idom[block.0]
+    }
+
+    /// The blocks `block` immediately dominates.
+    pub fn children(&self, block: BlockId) -> &[BlockId] {
+        &self.children[block.0]
+    }
+
+    /// Whether every path from the entry to `b` goes through `a`.
+    pub fn dominates(&self, a: BlockId, b: BlockId) -> bool {
+        let mut block = Some(b);
+        while let Some(b) = block {
+            if a == b {
+                return true;
+            }
+            block = self.idom(b);
+        }
+        false
+    }
+
+    /// The blocks in an order where each comes after its dominators.
+    pub fn preorder(&self) -> Vec<BlockId> {
+        let mut order = Vec::new();
+        let mut stack = vec![Function::ENTRY];
+        while let Some(b) = stack.pop() {
+            order.push(b);
+            stack.extend(self.children(b).iter().rev());
+        }
+        order
+    }
+
+    /// The dominance frontier of every block: the blocks it does not
+    /// strictly dominate but dominates a predecessor of.
+    pub fn frontiers(&self, func: &Function) -> Vec<Vec<BlockId>> {
+        let mut frontiers = vec![Vec::new(); func.blocks.len()];
+        for (b, preds) in func.predecessors().into_iter().enumerate() {
+            if preds.len() < 2 {
+                continue;
+            }
+            let b = BlockId(b);
+            for pred in preds {
+                let mut runner = pred;
+                while Some(runner) != self.idom(b) {
+                    if !frontiers[runner.0].contains(&b) {
+                        frontiers[runner.0].push(b);
+                    }
+                    runner = self.idom(runner).expect("the entry dominates every block");
+                }
+            }
+        }
+        frontiers
+    }
+}
+
+/// Puts `func` in SSA form, dropping unreachable blocks first.
+pub fn construct(func: &mut Function) {
+    func.remove_unreachable();
+    let tree = DomTree::new(func);
+
+    let vars = func.vars.len();
+    let mut global = vec![false; vars];
+    let mut defsites: Vec<Vec<BlockId>> = vec![Vec::new(); vars];
+
+    for (i, block) in func.blocks.iter().enumerate() {
+        let mut defined = vec![false; vars];
+
+        for inst in &block.insts {
+            for var in inst.uses() {
+                global[var.0 as usize] |= !defined[var.0 as usize];
+            }
+            if let Some(def) = inst.def() {
+                defined[def.0 as usize] = true;
+                if !defsites[def.0 as usize].contains(&BlockId(i)) {
+                    defsites[def.0 as usize].push(BlockId(i));
+                }
+            }
+        }
+        if let Terminator::Branch {
+            cond: Operand::Var(var),
+            ..
+        } = block.term
+        {
+            global[var.0 as usize] |= !defined[var.0 as usize];
+        }
+    }
+
+    place_phis(func, &tree, &global, defsites);
+    rename(func, &tree);
+}
+
+fn place_phis(func: &mut Function, tree: &DomTree, global: &[bool], defsites: Vec<Vec<BlockId>>) {
+    let frontiers = tree.frontiers(func);
+    let preds = func.predecessors();
+
+    for (var, mut worklist) in defsites.into_iter().enumerate() {
+        if !global[var] {
+            continue;
+        }
+        let var = Var(var as u32);
+        let mut has_phi = vec![false; func.blocks.len()];
+        let mut defines: Vec<bool> = (0..func.blocks.len())
+            .map(|b| worklist.contains(&BlockId(b)))
+            .collect();
+
+        while let Some(block) = worklist.pop() {
+            for &frontier in &frontiers[block.0] {
+                if has_phi[frontier.0] {
+                    continue;
+                }
+                has_phi[frontier.0] = true;
+
+                // Each operand names the variable until `rename` reaches
+                // the predecessor
+                let args = preds[frontier.0]
+                    .iter()
+                    .map(|p| (*p, Operand::Var(var)))
+                    .collect();
+                func.blocks[frontier.0]
+                    .insts
+                    .insert(0, Inst::Phi { dst: var, args });
+
+                if !std::mem::replace(&mut defines[frontier.0], true) {
+                    worklist.push(frontier);
+                }
+            }
+        }
+    }
+}
+
+fn def_mut(inst: &mut Inst) -> Option<&mut Var> {
+    match inst {
+        Inst::Copy { dst, .. }
+        | Inst::Unary { dst, .. }
+        | Inst::Binary { dst, .. }
+        | Inst::Cast { dst, .. }
+        | Inst::Phi { dst, .. } => Some(dst),
+        Inst::Emit(_) | Inst::Symtable(_) => None,
+    }
+}
+
+enum Visit {
+    Enter(BlockId),
+    /// Pops the definitions a block pushed
+    Exit(Vec<Var>),
+}
+
+struct Renamer {
+    /// The definition that reaches the current point, for each variable
+    /// from before renaming
+    stacks: Vec<Vec<Var>>,
+    /// Whether the variable has been given to a definition
+    taken: Vec<bool>,
+}
+
+impl Renamer {
+    fn current(&self, func: &Function, var: Var) -> Operand {
+        match self.stacks[var.0 as usize].last() {
+            Some(v) => Operand::Var(*v),
+            None => Operand::Const(Value::zero(func.var(var).ty)),
+        }
+    }
+
+    fn read(&self, func: &Function, operand: &mut Operand) {
+        if let Operand::Var(var) = operand {
+            *operand = self.current(func, *var);
+        }
+    }
+
+    /// A variable for a new definition of `var`. The first keeps `var`.
+    fn define(&mut self, func: &mut Function, var: Var) -> Var {
+        let new = if std::mem::replace(&mut self.taken[var.0 as usize], true) {
+            func.vars.push(func.var(var).clone());
+            Var(func.vars.len() as u32 - 1)
+        } else {
+            var
+        };
+        self.stacks[var.0 as usize].push(new);
+        new
+    }
+}
+
+fn rename(func: &mut Function, tree: &DomTree) {
+    let mut renamer = Renamer {
+        stacks: vec![Vec::new(); func.vars.len()],
+        taken: vec![false; func.vars.len()],
+    };
+    let mut visits = vec![Visit::Enter(Function::ENTRY)];
+
+    while let Some(visit) = visits.pop() {
+        let block = match visit {
+            Visit::Enter(block) => block,
+            Visit::Exit(defined) => {
+                for var in defined {
+                    renamer.stacks[var.0 as usize].pop();
+                }
+                continue;
+            }
+        };
+
+        let mut insts = std::mem::take(&mut func.blocks[block.0].insts);
+        let mut defined = Vec::new();
+        for inst in &mut insts {
+            if !inst.is_phi() {
+                for operand in inst.operands_mut() {
+                    renamer.read(func, operand);
+                }
+            }
+            if let Some(dst) = def_mut(inst) {
+                defined.push(*dst);
+                *dst = renamer.define(func, *dst);
+            }
+        }
+        func.blocks[block.0].insts = insts;
+
+        let mut term = func.blocks[block.0].term.clone();
+        if let Some(cond) = term.cond_mut() {
+            renamer.read(func, cond);
+        }
+        func.blocks[block.0].term = term;
+
+        let mut succs = func.block(block).term.successors();
+        succs.dedup();
+        for succ in succs {
+            let mut insts = std::mem::take(&mut func.blocks[succ.0].insts);
+            for inst in insts.iter_mut().take_while(|i| i.is_phi()) {
+                if let Inst::Phi { args, .. } = inst {
+                    for (_, arg) in args.iter_mut().filter(|(from, _)| *from == block) {
+                        renamer.read(func, arg);
+                    }
+                }
+            }
+            func.blocks[succ.0].insts = insts;
+        }
+
+        visits.push(Visit::Exit(defined));
+        visits.extend(tree.children(block).iter().rev().map(|b| Visit::Enter(*b)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interp;
+    use crate::ir::{lower, run};
+    use crate::parser::Parser;
+    use crate::semantics::typeck;
+    use crate::syntax;
+    use crate::typed::Program;
+
+    fn check(src: &str) -> Program {
+        let tree = Parser::new().parse_str(src).unwrap();
+        typeck::check(&syntax::Program::from_cst(&tree).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn loop_header_phi() {
+        let mut func = lower(&check("int i;\nwhile (i < 3) i = i + 1;\nemit i i i;\n"));
+        construct(&mut func);
+
+        assert_eq!(
+            func.block_text(BlockId(1)),
+            "b1:\n    i.1 = phi [b0: i, b2: i.2]\n    %1 = i.1 < 3\n    branch %1 b2 b3\n"
+        );
+        assert_eq!(
+            func.block_text(BlockId(3)),
+            "b3:\n    emit i.1, i.1, i.1\n    return\n"
+        );
+
+        let tree = DomTree::new(&func);
+        assert_eq!(tree.frontiers(&func)[2], vec![BlockId(1)]);
+        assert!(tree.dominates(BlockId(1), BlockId(3)));
+        assert!(!tree.dominates(BlockId(2), BlockId(3)));
+    }
+
+    #[test]
+    fn single_definitions() {
+        let program = check(
+            "int n = 10, a, b = 1, i;\n\
+             while (i < n) { int t = a + b; a = b; b = t; i = i + 1; }\n\
+             if (a > 50) { a = 1; } else { a = 2; }\n\
+             if (b > 50) b = 3;\n\
+             emit a b i;\nemit symtable;\n",
+        );
+        let mut func = lower(&program);
+        construct(&mut func);
+
+        let mut defs: Vec<Var> = func
+            .blocks
+            .iter()
+            .flat_map(|b| b.insts.iter().filter_map(Inst::def))
+            .collect();
+        let count = defs.len();
+        defs.sort();
+        defs.dedup();
+        assert_eq!(defs.len(), count);
+
+        let mut expected = Vec::new();
+        interp::run(&program, &mut expected).unwrap();
+        let mut out = Vec::new();
+        run(&func, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out), String::from_utf8(expected));
+    }
+}
@@ -1,8 +1,17 @@
-#![feature(with_options)]
-
 pub mod ast;
+pub mod bytecode;
+pub mod codegen;
+pub mod diagnostics;
+pub mod error;
 pub mod hazards;
+pub mod interp;
+pub mod ir;
+pub mod lint;
 pub mod parser;
 pub mod semantics;
+pub mod span;
 pub mod symbol_table;
+pub mod syntax;
+pub mod typed;
+pub mod types;
 pub mod visitor;
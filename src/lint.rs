@@ -0,0 +1,290 @@
+//! Lint levels. Every hazard but a syntax error is a lint named by its id,
+//! `unused` for UNUSED, whose level says whether it is reported and whether
+//! it fails the check:
+//!
+//! - `allow` drops it,
+//! - `warn` reports it as a WARN,
+//! - `deny` reports it as an ERROR,
+//! - `forbid` is `deny` that overrides every other level set for the lint,
+//!   before or after it.
+//!
+//! Warnings are `warn` and errors `deny` unless set otherwise. The
+//! `warnings` group sets the level of every lint that would be `warn`, so
+//! `-D warnings` fails on any warning.
+//!
+//! Levels come from the `[lints]` table of a `zobos.toml` first, then from
+//! the command line:
+//!
+//! ```toml
+//! [lints]
+//! unused = "deny"
+//! revar = "allow"
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::hazards::HazardType;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+    Forbid,
+}
+
+impl Level {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Allow => "allow",
+            Level::Warn => "warn",
+            Level::Deny => "deny",
+            Level::Forbid => "forbid",
+        }
+    }
+
+    /// Whether a hazard at this level fails the check.
+    pub fn is_error(self) -> bool {
+        self >= Level::Deny
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(Level::Allow),
+            "warn" => Ok(Level::Warn),
+            "deny" => Ok(Level::Deny),
+            "forbid" => Ok(Level::Forbid),
+            _ => Err(format!("unknown lint level {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What a level is set for: one kind of hazard or the `warnings` group.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Lint {
+    Warnings,
+    Hazard(HazardType),
+}
+
+/// Lint names are the hazard ids in lower case.
+impl FromStr for Lint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "warnings" {
+            return Ok(Lint::Warnings);
+        }
+
+        HazardType::ALL
+            .iter()
+            .filter(|h| **h != HazardType::Syntax)
+            .find(|h| h.display_id().eq_ignore_ascii_case(s))
+            .map(|h| Lint::Hazard(*h))
+            .ok_or_else(|| format!("unknown lint `{}`", s))
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Lint::Warnings => f.write_str("warnings"),
+            Lint::Hazard(h) => f.write_str(&h.display_id().to_ascii_lowercase()),
+        }
+    }
+}
+
+/// The level of every lint, see the module docs.
+#[derive(Debug, Clone, Default)]
+pub struct Lints {
+    levels: HashMap<Lint, Level>,
+}
+
+impl Lints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the level of `lint`, unless an earlier setting forbade it,
+    /// itself or through the `warnings` group. Forbidding the group also
+    /// overrides the levels set earlier for the lints it covers.
+    pub fn set(&mut self, lint: Lint, level: Level) -> Result<(), String> {
+        let current = match lint {
+            Lint::Warnings => self.levels.get(&lint).copied(),
+            Lint::Hazard(h) => Some(self.level(&h)),
+        };
+
+        match current {
+            Some(Level::Forbid) if level != Level::Forbid => Err(format!(
+                "`{}` is forbidden and cannot be set to `{}`",
+                lint, level
+            )),
+            _ => {
+                if lint == Lint::Warnings && level == Level::Forbid {
+                    self.levels.retain(|l, _| match l {
+                        Lint::Hazard(h) => h.default_level() != Level::Warn,
+                        Lint::Warnings => true,
+                    });
+                }
+                self.levels.insert(lint, level);
+                Ok(())
+            }
+        }
+    }
+
+    /// The level hazards of `hazard_type` are reported at. Syntax errors
+    /// are not lints and are always denied.
+    pub fn level(&self, hazard_type: &HazardType) -> Level {
+        if *hazard_type == HazardType::Syntax {
+            return Level::Deny;
+        }
+
+        match self.levels.get(&Lint::Hazard(*hazard_type)) {
+            Some(level) => *level,
+            None => match hazard_type.default_level() {
+                Level::Warn => self
+                    .levels
+                    .get(&Lint::Warnings)
+                    .copied()
+                    .unwrap_or(Level::Warn),
+                level => level,
+            },
+        }
+    }
+
+    /// Reads the `[lints]` table of a `zobos.toml`, a `lint = "level"` line
+    /// for each lint. Other tables are left for other tools.
+    pub fn read_config(&mut self, text: &str) -> Result<(), String> {
+        let mut in_lints = false;
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let error = |message: String| format!("line {}: {}", i + 1, message);
+
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('[') {
+                in_lints = line == "[lints]";
+                continue;
+            }
+            if !in_lints {
+                continue;
+            }
+
+            let (name, level) = line
+                .split_once('=')
+                .ok_or_else(|| error(format!("expected `lint = \"level\"`, found {:?}", line)))?;
+            let level = level.trim();
+            let level = level
+                .strip_prefix('"')
+                .and_then(|l| l.strip_suffix('"'))
+                .ok_or_else(|| error(format!("the level {} is not a string", level)))?;
+
+            let lint = name.trim().parse().map_err(error)?;
+            let level = level.parse().map_err(error)?;
+            self.set(lint, level).map_err(error)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hazards::{ErrorId, WarnId};
+
+    const UNUSED: HazardType = HazardType::Warn(WarnId::Unused);
+    const REVAR: HazardType = HazardType::Warn(WarnId::RedeclareVar);
+    const NOVAR: HazardType = HazardType::ErrorT(ErrorId::NoVar);
+
+    #[test]
+    fn names() {
+        assert_eq!("unused".parse(), Ok(Lint::Hazard(UNUSED)));
+        assert_eq!(
+            "MAYBEUNINIT".parse::<Lint>().unwrap().to_string(),
+            "maybeuninit"
+        );
+        assert_eq!("warnings".parse(), Ok(Lint::Warnings));
+        assert!("syntax".parse::<Lint>().is_err());
+        assert!("unusd".parse::<Lint>().is_err());
+    }
+
+    #[test]
+    fn levels() {
+        let mut lints = Lints::new();
+        assert_eq!(lints.level(&UNUSED), Level::Warn);
+        assert_eq!(lints.level(&NOVAR), Level::Deny);
+
+        lints.set(Lint::Warnings, Level::Deny).unwrap();
+        lints.set(Lint::Hazard(REVAR), Level::Allow).unwrap();
+        lints.set(Lint::Hazard(NOVAR), Level::Warn).unwrap();
+        assert_eq!(lints.level(&UNUSED), Level::Deny);
+        assert_eq!(lints.level(&REVAR), Level::Allow);
+        // The group only covers lints that would be warnings
+        assert_eq!(lints.level(&NOVAR), Level::Warn);
+        assert_eq!(lints.level(&HazardType::Syntax), Level::Deny);
+
+        lints.set(Lint::Hazard(UNUSED), Level::Forbid).unwrap();
+        assert!(lints.set(Lint::Hazard(UNUSED), Level::Allow).is_err());
+        assert_eq!(lints.level(&UNUSED), Level::Forbid);
+
+        let mut lints = Lints::new();
+        lints.set(Lint::Warnings, Level::Forbid).unwrap();
+        assert_eq!(
+            lints.set(Lint::Hazard(REVAR), Level::Warn),
+            Err("`revar` is forbidden and cannot be set to `warn`".to_string())
+        );
+        assert!(lints.set(Lint::Hazard(NOVAR), Level::Allow).is_ok());
+    }
+
+    #[test]
+    fn forbid_overrides_earlier_levels() {
+        let mut lints = Lints::new();
+        lints.set(Lint::Hazard(UNUSED), Level::Allow).unwrap();
+        lints.set(Lint::Hazard(NOVAR), Level::Allow).unwrap();
+        lints.set(Lint::Warnings, Level::Forbid).unwrap();
+
+        assert_eq!(lints.level(&UNUSED), Level::Forbid);
+        assert_eq!(lints.level(&REVAR), Level::Forbid);
+        // Not a warning, so not in the group
+        assert_eq!(lints.level(&NOVAR), Level::Allow);
+
+        lints.set(Lint::Hazard(REVAR), Level::Warn).unwrap_err();
+        lints.set(Lint::Hazard(UNUSED), Level::Forbid).unwrap();
+        assert_eq!(lints.level(&UNUSED), Level::Forbid);
+    }
+
+    #[test]
+    fn config() {
+        let mut lints = Lints::new();
+        lints
+            .read_config(
+                "[package]\nname = \"hw\"\n\n[lints]\nunused = \"deny\" # CI\nrevar = \"allow\"\n",
+            )
+            .unwrap();
+        assert_eq!(lints.level(&UNUSED), Level::Deny);
+        assert_eq!(lints.level(&REVAR), Level::Allow);
+
+        let err = Lints::new()
+            .read_config("[lints]\nunused = deny\n")
+            .unwrap_err();
+        assert_eq!(err, "line 2: the level deny is not a string");
+        let err = Lints::new()
+            .read_config("[lints]\nunused = \"loud\"\n")
+            .unwrap_err();
+        assert_eq!(err, "line 2: unknown lint level \"loud\"");
+    }
+}
@@ -0,0 +1,69 @@
+// The SLR(1) table and rules of `zlang.cfg`, generated and compiled in by
+// `build.rs`.
+
+use std::collections::BTreeMap;
+
+use super::production::Production;
+use super::symbol::{NonTerminal, Symbol};
+use super::Action;
+
+pub enum RawSymbol {
+    Terminal(&'static str),
+    NonTerminal(&'static str),
+    Lambda,
+}
+
+include!(concat!(env!("OUT_DIR"), "/tables.rs"));
+
+fn symbols() -> Vec<Symbol> {
+    SYMBOLS
+        .iter()
+        .map(|s| match s {
+            RawSymbol::Terminal(t) => Symbol::from_terminal(t.to_string()),
+            RawSymbol::NonTerminal(nt) => Symbol::from_non_terminal(nt.to_string()),
+            RawSymbol::Lambda => Symbol::Lambda,
+        })
+        .collect()
+}
+
+pub fn items() -> Vec<(NonTerminal, Production)> {
+    let symbols = symbols();
+
+    RULES
+        .iter()
+        .map(|(lhs, rhs)| {
+            let lhs = symbols[*lhs].non_terminal().unwrap().clone();
+            let production = rhs.iter().map(|s| symbols[*s].clone()).collect();
+
+            (lhs, Production(production))
+        })
+        .collect()
+}
+
+pub fn table() -> Vec<BTreeMap<Symbol, Action>> {
+    let symbols = symbols();
+
+    TABLE
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|(symbol, action)| (symbols[*symbol].clone(), *action))
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::grammar::Grammar;
+    use super::super::table::slr_table;
+    use super::*;
+
+    #[test]
+    fn embedded_matches_grammar() {
+        let grammar = Grammar::from_file("zlang.cfg").unwrap();
+
+        assert_eq!(items(), grammar.rules);
+        assert_eq!(table(), slr_table(&grammar));
+    }
+}
@@ -0,0 +1,447 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use super::production::Production;
+use super::symbol::{NonTerminal, Symbol};
+use crate::error::{Error, Result};
+
+macro_rules! bad_grammar {
+    ($($arg:tt)*) => {
+        Error::BadGrammar(format!($($arg)*))
+    };
+}
+
+fn parse_symbol(input: &str) -> Result<Symbol> {
+    Symbol::from_parse(input).map_err(|e| Error::BadGrammar(e.to_string()))
+}
+
+/// A context free grammar in the `zlang.cfg` format:
+///
+/// ```text
+/// # comment
+/// A -> b C | lambda
+///    | d
+/// ```
+///
+/// Rules are numbered in the order they appear, alternatives included,
+/// which is the same numbering `zlang-rules.lis` uses. The first rule is
+/// the start rule and must end with `$`.
+///
+/// Operator precedence is declared yacc style with `%left`, `%right` and
+/// `%nonassoc` lines, where later lines bind tighter. A rule takes the
+/// precedence of its last terminal unless it ends with `%prec terminal`.
+#[derive(Debug, Clone)]
+pub struct Grammar {
+    pub rules: Vec<(NonTerminal, Production)>,
+    /// Every symbol in the order it first appears in the grammar.
+    symbols: Vec<Symbol>,
+    precedence: BTreeMap<Symbol, (usize, Assoc)>,
+    /// Rules whose precedence was set with `%prec`.
+    rule_precedence: BTreeMap<usize, Symbol>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Assoc {
+    Left,
+    Right,
+    NonAssoc,
+}
+
+impl Grammar {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut rules = Vec::new();
+        let mut lhs: Option<NonTerminal> = None;
+        let mut precedence = BTreeMap::new();
+        let mut rule_precedence = BTreeMap::new();
+
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('%') {
+                let mut split = line.split_whitespace();
+                let assoc = match split.next().unwrap() {
+                    "%left" => Assoc::Left,
+                    "%right" => Assoc::Right,
+                    "%nonassoc" => Assoc::NonAssoc,
+                    d => {
+                        return Err(bad_grammar!(
+                            "line {}: unknown directive {}",
+                            line_no + 1,
+                            d
+                        ))
+                    }
+                };
+                let level = precedence.len() + 1;
+
+                for terminal in split {
+                    match parse_symbol(terminal)? {
+                        t @ Symbol::Terminal(_) => precedence.insert(t, (level, assoc)),
+                        _ => {
+                            return Err(bad_grammar!(
+                                "line {}: {:?} is not a terminal",
+                                line_no + 1,
+                                terminal
+                            ))
+                        }
+                    };
+                }
+                continue;
+            }
+
+            let alternatives = if let Some(rest) = line.strip_prefix('|') {
+                if lhs.is_none() {
+                    return Err(bad_grammar!("line {}: `|` without a rule", line_no + 1));
+                }
+                rest
+            } else {
+                let mut split = line.splitn(2, "->");
+                let name = split.next().unwrap().trim();
+                let rest = split
+                    .next()
+                    .ok_or_else(|| bad_grammar!("line {}: expected `->`", line_no + 1))?;
+
+                match parse_symbol(name)? {
+                    Symbol::NonTerminal(nt) if nt.non_terminal() == name => lhs = Some(nt),
+                    _ => {
+                        return Err(bad_grammar!(
+                            "line {}: {:?} is not a non-terminal",
+                            line_no + 1,
+                            name
+                        ))
+                    }
+                }
+                rest
+            };
+
+            for alternative in alternatives.split('|') {
+                let mut words: Vec<&str> = alternative.split_whitespace().collect();
+
+                if words.len() >= 2 && words[words.len() - 2] == "%prec" {
+                    let terminal = parse_symbol(words.pop().unwrap())?;
+                    words.pop();
+                    rule_precedence.insert(rules.len(), terminal);
+                }
+
+                let symbols = words
+                    .into_iter()
+                    .map(parse_symbol)
+                    .collect::<Result<Vec<Symbol>>>()?;
+
+                if symbols.is_empty() {
+                    return Err(bad_grammar!("line {}: empty alternative", line_no + 1));
+                }
+
+                if symbols.len() > 1 && symbols.contains(&Symbol::Lambda) {
+                    return Err(bad_grammar!(
+                        "line {}: lambda must stand alone",
+                        line_no + 1
+                    ));
+                }
+
+                rules.push((lhs.clone().unwrap(), Production(symbols)));
+            }
+        }
+
+        let mut grammar = Self::new(rules)?;
+        grammar.precedence = precedence;
+        grammar.rule_precedence = rule_precedence;
+
+        Ok(grammar)
+    }
+
+    pub fn new(rules: Vec<(NonTerminal, Production)>) -> Result<Self> {
+        let (_, start) = rules
+            .first()
+            .ok_or_else(|| bad_grammar!("grammar has no rules"))?;
+
+        if start.symbols().last() != Some(&end_of_input()) {
+            return Err(bad_grammar!("the start rule must end with `$`"));
+        }
+
+        let mut symbols = Vec::new();
+        for (lhs, production) in rules.iter() {
+            let lhs = Symbol::from_non_terminal(lhs.clone());
+            for symbol in std::iter::once(&lhs).chain(production.symbols()) {
+                if !symbol.is_lambda() && !symbols.contains(symbol) {
+                    symbols.push(symbol.clone());
+                }
+            }
+        }
+
+        let grammar = Self {
+            rules,
+            symbols,
+            precedence: BTreeMap::new(),
+            rule_precedence: BTreeMap::new(),
+        };
+
+        for symbol in grammar.symbols.iter() {
+            if let Symbol::NonTerminal(nt) = symbol {
+                if grammar.rules_for(nt).next().is_none() {
+                    return Err(bad_grammar!("non-terminal {:?} has no rules", nt));
+                }
+            }
+        }
+
+        Ok(grammar)
+    }
+
+    pub fn start(&self) -> &NonTerminal {
+        &self.rules[0].0
+    }
+
+    /// The symbols of a rule, with `lambda` treated as the empty string.
+    pub fn rhs(&self, rule: usize) -> &[Symbol] {
+        let production = &self.rules[rule].1;
+
+        if production.only_lambda() {
+            &[]
+        } else {
+            production.symbols()
+        }
+    }
+
+    pub fn rules_for<'a>(&'a self, nt: &'a NonTerminal) -> impl Iterator<Item = usize> + 'a {
+        self.rules
+            .iter()
+            .enumerate()
+            .filter(move |(_, (lhs, _))| lhs == nt)
+            .map(|(i, _)| i)
+    }
+
+    /// Terminals sorted by name with `$` last, then non-terminals in the
+    /// order they appear. This is the column order of `zlang.lr`.
+    pub fn columns(&self) -> Vec<Symbol> {
+        let end = end_of_input();
+        let mut terminals: Vec<Symbol> = self
+            .symbols
+            .iter()
+            .filter(|s| s.is_terminal() && **s != end)
+            .cloned()
+            .collect();
+        terminals.sort();
+        terminals.push(end);
+
+        let start = Symbol::from_non_terminal(self.start().clone());
+        terminals.extend(
+            self.symbols
+                .iter()
+                .filter(|s| s.is_non_terminal() && **s != start)
+                .cloned(),
+        );
+
+        terminals
+    }
+
+    /// The declared precedence level and associativity of a terminal.
+    pub fn precedence(&self, terminal: &Symbol) -> Option<(usize, Assoc)> {
+        self.precedence.get(terminal).copied()
+    }
+
+    pub fn rule_precedence(&self, rule: usize) -> Option<(usize, Assoc)> {
+        match self.rule_precedence.get(&rule) {
+            Some(terminal) => self.precedence(terminal),
+            None => self
+                .rhs(rule)
+                .iter()
+                .rev()
+                .find(|s| s.is_terminal())
+                .and_then(|t| self.precedence(t)),
+        }
+    }
+
+    pub fn terminals(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter().filter(|s| s.is_terminal())
+    }
+
+    pub fn non_terminals(&self) -> impl Iterator<Item = &NonTerminal> {
+        self.symbols.iter().flat_map(|s| s.non_terminal().ok())
+    }
+
+    /// Non-terminals that can derive the empty string.
+    pub fn nullable(&self) -> BTreeSet<NonTerminal> {
+        let mut nullable = BTreeSet::new();
+        let mut changed = true;
+
+        while changed {
+            changed = false;
+
+            for (rule, (lhs, _)) in self.rules.iter().enumerate() {
+                if !nullable.contains(lhs)
+                    && self.rhs(rule).iter().all(|s| match s {
+                        Symbol::NonTerminal(nt) => nullable.contains(nt),
+                        _ => false,
+                    })
+                {
+                    nullable.insert(lhs.clone());
+                    changed = true;
+                }
+            }
+        }
+
+        nullable
+    }
+
+    pub fn first_sets(&self) -> BTreeMap<NonTerminal, BTreeSet<Symbol>> {
+        let nullable = self.nullable();
+        let mut first: BTreeMap<NonTerminal, BTreeSet<Symbol>> = self
+            .non_terminals()
+            .map(|nt| (nt.clone(), BTreeSet::new()))
+            .collect();
+        let mut changed = true;
+
+        while changed {
+            changed = false;
+
+            for (rule, (lhs, _)) in self.rules.iter().enumerate() {
+                let found = first_of(self.rhs(rule), &first, &nullable).0;
+                let set = first.get_mut(lhs).unwrap();
+                let before = set.len();
+                set.extend(found);
+                changed |= set.len() != before;
+            }
+        }
+
+        first
+    }
+
+    pub fn follow_sets(&self) -> BTreeMap<NonTerminal, BTreeSet<Symbol>> {
+        let nullable = self.nullable();
+        let first = self.first_sets();
+        let mut follow: BTreeMap<NonTerminal, BTreeSet<Symbol>> = self
+            .non_terminals()
+            .map(|nt| (nt.clone(), BTreeSet::new()))
+            .collect();
+        let mut changed = true;
+
+        while changed {
+            changed = false;
+
+            for (rule, (lhs, _)) in self.rules.iter().enumerate() {
+                let rhs = self.rhs(rule);
+
+                for (i, symbol) in rhs.iter().enumerate() {
+                    if let Symbol::NonTerminal(nt) = symbol {
+                        let (mut found, rest_nullable) = first_of(&rhs[i + 1..], &first, &nullable);
+
+                        if rest_nullable {
+                            found.extend(follow[lhs].iter().cloned());
+                        }
+
+                        let set = follow.get_mut(nt).unwrap();
+                        let before = set.len();
+                        set.extend(found);
+                        changed |= set.len() != before;
+                    }
+                }
+            }
+        }
+
+        follow
+    }
+}
+
+/// The first set of a string of symbols and whether the whole string is nullable.
+pub fn first_of(
+    symbols: &[Symbol],
+    first: &BTreeMap<NonTerminal, BTreeSet<Symbol>>,
+    nullable: &BTreeSet<NonTerminal>,
+) -> (BTreeSet<Symbol>, bool) {
+    let mut found = BTreeSet::new();
+
+    for symbol in symbols {
+        match symbol {
+            Symbol::NonTerminal(nt) => {
+                found.extend(first[nt].iter().cloned());
+
+                if !nullable.contains(nt) {
+                    return (found, false);
+                }
+            }
+            Symbol::Terminal(_) => {
+                found.insert(symbol.clone());
+                return (found, false);
+            }
+            Symbol::Lambda => {}
+        }
+    }
+
+    (found, true)
+}
+
+pub fn end_of_input() -> Symbol {
+    Symbol::from_terminal("$".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nt(s: &str) -> NonTerminal {
+        NonTerminal::new(s)
+    }
+
+    fn t(s: &str) -> Symbol {
+        Symbol::from_terminal(s.to_string())
+    }
+
+    #[test]
+    fn parse_alternatives_and_comments() {
+        let grammar =
+            Grammar::parse("# comment\nS -> A $\nA -> a A | lambda # trailing\n  | b\n").unwrap();
+
+        assert_eq!(grammar.rules.len(), 4);
+        assert!(grammar.rules[2].1.only_lambda());
+        assert_eq!(grammar.rules[3].0, nt("A"));
+        assert_eq!(
+            grammar.columns(),
+            vec![t("a"), t("b"), t("$"), Symbol::from_non_terminal(nt("A"))]
+        );
+    }
+
+    #[test]
+    fn first_and_follow() {
+        let grammar = Grammar::parse("S -> A B $\nA -> a | lambda\nB -> b").unwrap();
+        let first = grammar.first_sets();
+        let follow = grammar.follow_sets();
+
+        assert!(grammar.nullable().contains(&nt("A")));
+        assert_eq!(first[&nt("S")], vec![t("a"), t("b")].into_iter().collect());
+        assert_eq!(follow[&nt("A")], vec![t("b")].into_iter().collect());
+        assert_eq!(follow[&nt("B")], vec![t("$")].into_iter().collect());
+    }
+
+    #[test]
+    fn precedence_declarations() {
+        let grammar = Grammar::parse(
+            "%left plus\n%right pow\nS -> E $\nE -> E plus E | E pow E | minus E %prec pow | n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            grammar.rules[3].1,
+            Production(vec![t("minus"), Symbol::from_non_terminal(nt("E"))])
+        );
+        assert_eq!(grammar.rule_precedence(1), Some((1, Assoc::Left)));
+        assert_eq!(grammar.rule_precedence(2), Some((2, Assoc::Right)));
+        assert_eq!(grammar.rule_precedence(3), Some((2, Assoc::Right)));
+        assert_eq!(grammar.rule_precedence(4), None);
+    }
+
+    #[test]
+    fn start_rule_needs_end_marker() {
+        assert!(Grammar::parse("S -> a").is_err());
+        assert!(Grammar::parse("S -> A $").is_err());
+    }
+}
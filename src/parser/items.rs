@@ -1 +1,296 @@
+use std::collections::{BTreeMap, BTreeSet};
 
+use super::grammar::{end_of_input, first_of, Grammar};
+use super::symbol::{NonTerminal, Symbol};
+
+/// An LR(0) item: a rule with a dot somewhere in its right hand side.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Item {
+    pub rule: usize,
+    pub dot: usize,
+}
+
+impl Item {
+    pub fn new(rule: usize, dot: usize) -> Self {
+        Self { rule, dot }
+    }
+
+    /// The symbol right after the dot, `None` for a completed item.
+    pub fn next<'g>(&self, grammar: &'g Grammar) -> Option<&'g Symbol> {
+        grammar.rhs(self.rule).get(self.dot)
+    }
+
+    pub fn is_complete(&self, grammar: &Grammar) -> bool {
+        self.next(grammar).is_none()
+    }
+
+    pub fn advance(&self) -> Self {
+        Self::new(self.rule, self.dot + 1)
+    }
+}
+
+pub type ItemSet = BTreeSet<Item>;
+
+/// An LR(1) item: an LR(0) item plus the terminal that may follow it.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Lr1Item {
+    pub item: Item,
+    pub lookahead: Symbol,
+}
+
+pub type Lr1ItemSet = BTreeSet<Lr1Item>;
+
+/// The LR(0) items of an LR(1) item set.
+pub fn core(set: &Lr1ItemSet) -> ItemSet {
+    set.iter().map(|i| i.item).collect()
+}
+
+pub fn closure(grammar: &Grammar, kernel: &ItemSet) -> ItemSet {
+    let mut set = kernel.clone();
+    let mut work: Vec<Item> = kernel.iter().copied().collect();
+
+    while let Some(item) = work.pop() {
+        if let Some(Symbol::NonTerminal(nt)) = item.next(grammar) {
+            for rule in grammar.rules_for(nt) {
+                let item = Item::new(rule, 0);
+                if set.insert(item) {
+                    work.push(item);
+                }
+            }
+        }
+    }
+
+    set
+}
+
+/// The kernel reached from `set` by moving the dot over `symbol`.
+pub fn goto(grammar: &Grammar, set: &ItemSet, symbol: &Symbol) -> ItemSet {
+    set.iter()
+        .filter(|item| item.next(grammar) == Some(symbol))
+        .map(Item::advance)
+        .collect()
+}
+
+/// FIRST and nullable sets, computed once per automaton.
+struct Lookaheads {
+    first: BTreeMap<NonTerminal, BTreeSet<Symbol>>,
+    nullable: BTreeSet<NonTerminal>,
+}
+
+fn closure1(grammar: &Grammar, sets: &Lookaheads, kernel: &Lr1ItemSet) -> Lr1ItemSet {
+    let mut set = kernel.clone();
+    let mut work: Vec<Lr1Item> = kernel.iter().cloned().collect();
+
+    while let Some(Lr1Item { item, lookahead }) = work.pop() {
+        if let Some(Symbol::NonTerminal(nt)) = item.next(grammar) {
+            let rest = &grammar.rhs(item.rule)[item.dot + 1..];
+            let (mut follows, nullable) = first_of(rest, &sets.first, &sets.nullable);
+
+            if nullable {
+                follows.insert(lookahead);
+            }
+
+            for rule in grammar.rules_for(nt) {
+                for lookahead in follows.iter() {
+                    let item = Lr1Item {
+                        item: Item::new(rule, 0),
+                        lookahead: lookahead.clone(),
+                    };
+
+                    if set.insert(item.clone()) {
+                        work.push(item);
+                    }
+                }
+            }
+        }
+    }
+
+    set
+}
+
+fn goto1(grammar: &Grammar, set: &Lr1ItemSet, symbol: &Symbol) -> Lr1ItemSet {
+    set.iter()
+        .filter(|i| i.item.next(grammar) == Some(symbol))
+        .map(|i| Lr1Item {
+            item: i.item.advance(),
+            lookahead: i.lookahead.clone(),
+        })
+        .collect()
+}
+
+/// The canonical LR(1) collection, numbered the same way as [`Automaton::lr0`].
+#[derive(Debug, Clone)]
+pub struct Lr1Automaton {
+    pub states: Vec<Lr1ItemSet>,
+    pub transitions: Vec<BTreeMap<Symbol, usize>>,
+}
+
+impl Lr1Automaton {
+    pub fn new(grammar: &Grammar) -> Self {
+        let sets = Lookaheads {
+            first: grammar.first_sets(),
+            nullable: grammar.nullable(),
+        };
+        let columns = grammar.columns();
+
+        // The start rule already ends with `$`, so its lookahead is never used.
+        let start: Lr1ItemSet = grammar
+            .rules_for(grammar.start())
+            .map(|rule| Lr1Item {
+                item: Item::new(rule, 0),
+                lookahead: end_of_input(),
+            })
+            .collect();
+
+        let mut kernels: BTreeMap<Lr1ItemSet, usize> = BTreeMap::new();
+        let mut states = vec![closure1(grammar, &sets, &start)];
+        let mut transitions = Vec::new();
+        kernels.insert(start, 0);
+
+        let mut current = 0;
+        while current < states.len() {
+            let mut row = BTreeMap::new();
+
+            for symbol in columns.iter() {
+                let kernel = goto1(grammar, &states[current], symbol);
+
+                if kernel.is_empty() {
+                    continue;
+                }
+
+                let next = match kernels.get(&kernel) {
+                    Some(&state) => state,
+                    None => {
+                        states.push(closure1(grammar, &sets, &kernel));
+                        kernels.insert(kernel, states.len() - 1);
+                        states.len() - 1
+                    }
+                };
+
+                row.insert(symbol.clone(), next);
+            }
+
+            transitions.push(row);
+            current += 1;
+        }
+
+        Self {
+            states,
+            transitions,
+        }
+    }
+}
+
+/// The characteristic finite state machine of a grammar: the closed item
+/// set of every state and its transitions.
+#[derive(Debug, Clone)]
+pub struct Automaton {
+    pub states: Vec<ItemSet>,
+    pub transitions: Vec<BTreeMap<Symbol, usize>>,
+}
+
+impl Automaton {
+    /// States are numbered breadth first, following transitions in column
+    /// order, so the numbering matches the shipped `zlang.lr`.
+    pub fn lr0(grammar: &Grammar) -> Self {
+        let columns = grammar.columns();
+        let start: ItemSet = grammar
+            .rules_for(grammar.start())
+            .map(|rule| Item::new(rule, 0))
+            .collect();
+
+        let mut kernels: BTreeMap<ItemSet, usize> = BTreeMap::new();
+        let mut states = vec![closure(grammar, &start)];
+        let mut transitions = Vec::new();
+        kernels.insert(start, 0);
+
+        let mut current = 0;
+        while current < states.len() {
+            let mut row = BTreeMap::new();
+
+            for symbol in columns.iter() {
+                let kernel = goto(grammar, &states[current], symbol);
+
+                if kernel.is_empty() {
+                    continue;
+                }
+
+                let next = match kernels.get(&kernel) {
+                    Some(&state) => state,
+                    None => {
+                        states.push(closure(grammar, &kernel));
+                        kernels.insert(kernel, states.len() - 1);
+                        states.len() - 1
+                    }
+                };
+
+                row.insert(symbol.clone(), next);
+            }
+
+            transitions.push(row);
+            current += 1;
+        }
+
+        Self {
+            states,
+            transitions,
+        }
+    }
+}
+
+impl Automaton {
+    /// LALR(1) lookaheads for every completed item of the LR(0) automaton,
+    /// found by merging the canonical LR(1) states that share a core.
+    pub fn lalr_lookaheads(&self, grammar: &Grammar) -> Vec<BTreeMap<Item, BTreeSet<Symbol>>> {
+        let by_core: BTreeMap<&ItemSet, usize> = self
+            .states
+            .iter()
+            .enumerate()
+            .map(|(i, set)| (set, i))
+            .collect();
+
+        let mut lookaheads = vec![BTreeMap::new(); self.states.len()];
+
+        for set in Lr1Automaton::new(grammar).states.iter() {
+            let state = by_core[&core(set)];
+
+            for Lr1Item { item, lookahead } in set.iter() {
+                if item.is_complete(grammar) {
+                    lookaheads[state]
+                        .entry(*item)
+                        .or_insert_with(BTreeSet::new)
+                        .insert(lookahead.clone());
+                }
+            }
+        }
+
+        lookaheads
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lalr_merges_lr1_states() {
+        // The classic grammar that is LALR(1) but not SLR(1).
+        let grammar =
+            Grammar::parse("S -> A $\nA -> L eq R | R\nL -> star R | id\nR -> L").unwrap();
+        let lr0 = Automaton::lr0(&grammar);
+        let lr1 = Lr1Automaton::new(&grammar);
+
+        assert!(lr1.states.len() > lr0.states.len());
+
+        let lookaheads = lr0.lalr_lookaheads(&grammar);
+        let r_to_l = Item::new(5, 1);
+        let state = lr0.transitions[0][&Symbol::from_non_terminal("L".to_string())];
+
+        assert_eq!(
+            lookaheads[state][&r_to_l],
+            vec![Symbol::from_terminal("$".to_string())]
+                .into_iter()
+                .collect()
+        );
+    }
+}
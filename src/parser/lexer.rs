@@ -0,0 +1,397 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use super::token::Token;
+use crate::span::Span;
+
+/// A character sequence the scanner could not turn into a token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LexError {
+    pub message: String,
+    pub span: (usize, usize),
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.span.0, self.span.1, self.message)
+    }
+}
+
+impl std::error::Error for LexError {}
+
+/// Scans zlang source text into the same `Token`s that the legacy
+/// `id data row col` token files describe. Rows and columns are 1 indexed,
+/// and every token also gets its end and byte offsets.
+pub struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>,
+    row: usize,
+    col: usize,
+    /// The byte offset of `row`, `col`
+    offset: usize,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(src: &'a str) -> Self {
+        Self {
+            chars: src.chars().peekable(),
+            row: 1,
+            col: 1,
+            offset: 0,
+        }
+    }
+
+    /// Scan the whole input, stopping at the first lexical error.
+    pub fn tokenize(src: &str) -> Result<Vec<Token>, LexError> {
+        Lexer::new(src).collect()
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn peek_second(&self) -> Option<char> {
+        let mut lookahead = self.chars.clone();
+        lookahead.next();
+        lookahead.next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        self.offset += c.len_utf8();
+
+        if c == '\n' {
+            self.row += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+
+        Some(c)
+    }
+
+    fn bump_if(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn take_while(&mut self, lexeme: &mut String, pred: impl Fn(char) -> bool) {
+        while let Some(c) = self.peek() {
+            if !pred(c) {
+                break;
+            }
+
+            lexeme.push(c);
+            self.bump();
+        }
+    }
+
+    /// Skips whitespace, `// line` and `/* block */` comments.
+    fn skip_trivia(&mut self) -> Result<(), LexError> {
+        loop {
+            match (self.peek(), self.peek_second()) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.bump();
+                }
+                (Some('/'), Some('/')) => {
+                    while let Some(c) = self.bump() {
+                        if c == '\n' {
+                            break;
+                        }
+                    }
+                }
+                (Some('/'), Some('*')) => {
+                    let span = (self.row, self.col);
+                    self.bump();
+                    self.bump();
+
+                    loop {
+                        match self.bump() {
+                            Some('*') if self.bump_if('/') => break,
+                            Some(_) => {}
+                            None => return Err(error("unterminated comment", span)),
+                        }
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn number(
+        &mut self,
+        first: char,
+        span: (usize, usize),
+    ) -> Result<(&'static str, String), LexError> {
+        let mut lexeme = first.to_string();
+        self.take_while(&mut lexeme, |c| c.is_ascii_digit());
+
+        let mut id = "intval";
+
+        if first != '.' && self.peek() == Some('.') {
+            lexeme.push('.');
+            self.bump();
+            self.take_while(&mut lexeme, |c| c.is_ascii_digit());
+            id = "floatval";
+        } else if first == '.' {
+            if lexeme.len() == 1 {
+                return Err(error("expected digits after `.`", span));
+            }
+            id = "floatval";
+        }
+
+        if let Some('e') | Some('E') = self.peek() {
+            let mut exponent = String::new();
+            let mut lookahead = self.chars.clone();
+            exponent.push(lookahead.next().unwrap());
+
+            if let Some(sign @ '+') | Some(sign @ '-') = lookahead.peek().copied() {
+                exponent.push(sign);
+                lookahead.next();
+            }
+
+            // Only treat the `e` as part of the literal when digits follow it.
+            if lookahead.peek().is_some_and(|c| c.is_ascii_digit()) {
+                for _ in 0..exponent.len() {
+                    self.bump();
+                }
+                lexeme.push_str(&exponent);
+                self.take_while(&mut lexeme, |c| c.is_ascii_digit());
+                id = "floatval";
+            }
+        }
+
+        Ok((id, lexeme))
+    }
+
+    fn string(&mut self, span: (usize, usize)) -> Result<String, LexError> {
+        let mut lexeme = String::from("\"");
+
+        loop {
+            match self.bump() {
+                Some('"') => {
+                    lexeme.push('"');
+                    return Ok(lexeme);
+                }
+                Some('\\') => {
+                    lexeme.push('\\');
+                    match self.bump() {
+                        Some('\n') | None => return Err(error("unterminated string", span)),
+                        Some(c) => lexeme.push(c),
+                    }
+                }
+                Some('\n') | None => return Err(error("unterminated string", span)),
+                Some(c) => lexeme.push(c),
+            }
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>, LexError> {
+        self.skip_trivia()?;
+
+        let span = (self.row, self.col);
+        let start = self.offset;
+        let first = match self.bump() {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+
+        let (id, lexeme) = match first {
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut lexeme = c.to_string();
+                self.take_while(&mut lexeme, |c| c.is_ascii_alphanumeric() || c == '_');
+
+                (keyword(&lexeme).unwrap_or("id"), lexeme)
+            }
+            c if c.is_ascii_digit() => self.number(c, span)?,
+            '.' if self.peek().is_some_and(|c| c.is_ascii_digit()) => self.number('.', span)?,
+            '"' => ("stringval", self.string(span)?),
+            c => {
+                let (id, lexeme) = match c {
+                    '=' if self.bump_if('=') => ("eq", "=="),
+                    '=' => ("assign", "="),
+                    '<' if self.bump_if('=') => ("leq", "<="),
+                    '<' => ("lt", "<"),
+                    '>' if self.bump_if('=') => ("geq", ">="),
+                    '>' => ("gt", ">"),
+                    '+' => ("plus", "+"),
+                    '-' => ("minus", "-"),
+                    '*' => ("mult", "*"),
+                    '/' => ("div", "/"),
+                    '%' => ("mod", "%"),
+                    '!' => ("not", "!"),
+                    '~' => ("compl", "~"),
+                    ',' => ("comma", ","),
+                    ';' => ("sc", ";"),
+                    '(' => ("lparen", "("),
+                    ')' => ("rparen", ")"),
+                    '{' => ("lbrace", "{"),
+                    '}' => ("rbrace", "}"),
+                    c => return Err(error(format!("unexpected character {:?}", c), span)),
+                };
+
+                (id, lexeme.to_string())
+            }
+        };
+
+        Ok(Some(Token {
+            id: id.to_string(),
+            data: encode_data(&lexeme),
+            span: Span::new(span, (self.row, self.col)).with_bytes(start, self.offset),
+        }))
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Token, LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_token().transpose()
+    }
+}
+
+fn error(message: impl Into<String>, span: (usize, usize)) -> LexError {
+    LexError {
+        message: message.into(),
+        span,
+    }
+}
+
+fn keyword(word: &str) -> Option<&'static str> {
+    Some(match word {
+        "bool" => "bool",
+        "const" => "const",
+        "else" => "else",
+        "emit" => "emit",
+        "float" => "float",
+        "if" => "if",
+        "int" => "int",
+        "string" => "string",
+        "symtable" => "symtable",
+        "while" => "while",
+        _ => return None,
+    })
+}
+
+/// Encodes a lexeme the way token files store it: alphanumerics are kept
+/// as-is and every other character (including `x` itself) becomes `xHH`.
+pub fn encode_data(lexeme: &str) -> String {
+    let mut data = String::with_capacity(lexeme.len());
+
+    for c in lexeme.chars() {
+        if (c.is_ascii_alphanumeric() && c != 'x') || (c as u32) > 0xff {
+            data.push(c);
+        } else {
+            data.push_str(&format!("x{:02x}", c as u32));
+        }
+    }
+
+    data
+}
+
+/// Decodes the `data` of a token line back into its lexeme, undoing
+/// [`encode_data`].
+pub fn decode_data(data: &str) -> String {
+    let mut chars = data.chars();
+    let mut lexeme = String::with_capacity(data.len());
+
+    while let Some(c) = chars.next() {
+        match c {
+            'x' => {
+                let hex: String = chars.by_ref().take(2).collect();
+                match u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .and_then(std::char::from_u32)
+                {
+                    Some(c) => lexeme.push(c),
+                    None => {
+                        lexeme.push('x');
+                        lexeme.push_str(&hex);
+                    }
+                }
+            }
+            c => lexeme.push(c),
+        }
+    }
+
+    lexeme
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(src: &str) -> Vec<String> {
+        Lexer::tokenize(src)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.id)
+            .collect()
+    }
+
+    #[test]
+    fn keywords_and_operators() {
+        assert_eq!(
+            ids("const int x = 3 <= y == z;"),
+            vec!["const", "int", "id", "assign", "intval", "leq", "id", "eq", "id", "sc"]
+        );
+        assert_eq!(
+            ids("emit symtable; while (!a) { ~b % -c / +d * e; }"),
+            vec![
+                "emit", "symtable", "sc", "while", "lparen", "not", "id", "rparen", "lbrace",
+                "compl", "id", "mod", "minus", "id", "div", "plus", "id", "mult", "id", "sc",
+                "rbrace"
+            ]
+        );
+    }
+
+    #[test]
+    fn literals() {
+        let tokens = Lexer::tokenize("1 2.5 .5 3e2 \"a \\\"b\\\"\" max").unwrap();
+        let ids: Vec<&str> = tokens.iter().map(|t| t.id.as_str()).collect();
+
+        assert_eq!(
+            ids,
+            vec![
+                "intval",
+                "floatval",
+                "floatval",
+                "floatval",
+                "stringval",
+                "id"
+            ]
+        );
+        assert_eq!(tokens[1].data, "2x2e5");
+        assert_eq!(tokens[5].data, "max78");
+    }
+
+    #[test]
+    fn spans_skip_comments() {
+        let tokens = Lexer::tokenize("// header\nint a; /* a\n b */ a = 1;").unwrap();
+        let spans: Vec<(usize, usize)> = tokens.iter().map(|t| t.span.start).collect();
+
+        assert_eq!(
+            spans,
+            vec![(2, 1), (2, 5), (2, 6), (3, 7), (3, 9), (3, 11), (3, 12)]
+        );
+        assert_eq!(tokens[0].span, Span::new((2, 1), (2, 4)).with_bytes(10, 13));
+        assert_eq!(tokens[3].span, Span::new((3, 7), (3, 8)).with_bytes(28, 29));
+    }
+
+    #[test]
+    fn data_round_trips() {
+        for lexeme in ["x", "\"a\\\"b\"", "2.5e-3", "max"] {
+            assert_eq!(decode_data(&encode_data(lexeme)), lexeme);
+        }
+    }
+
+    #[test]
+    fn unexpected_character() {
+        let err = Lexer::tokenize("int a;\n  a = @;").unwrap_err();
+        assert_eq!(err.span, (2, 7));
+    }
+}
@@ -1,19 +1,29 @@
+mod embedded;
+pub mod grammar;
 pub mod items;
+pub mod lexer;
 pub mod production;
 pub mod symbol;
+pub mod table;
 pub mod token;
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::File;
 use std::io::{BufRead, BufReader};
+use std::path::Path;
 
+use self::grammar::Grammar;
 use self::production::Production;
 use self::symbol::{NonTerminal, Symbol, Terminal};
+use self::table::{Construction, Resolution};
 use self::token::{Token, TokenStream};
 use crate::ast::{AstKind, AstNode};
-use crate::hazards::{Hazard, HazardType};
+use crate::diagnostics::DiagnosticSink;
+use crate::error::{Error, Result};
+use crate::hazards::{Hazard, HazardType, WarnId};
+use crate::span::Span;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Action {
     Shift(usize),
     Reduce(usize),
@@ -21,18 +31,24 @@ pub enum Action {
 }
 
 impl Action {
-    fn parse(input: &str) -> Self {
+    fn parse(input: &str) -> Result<Self> {
         let tokens: Vec<&str> = input.split('-').collect();
-
-        match tokens.as_slice() {
-            &["r", n] => Action::Reduce(n.parse::<usize>().unwrap() - 1), // -1 because rules are 1 indexed
-            &["R", n] => Action::ReduceTerminate(n.parse::<usize>().unwrap() - 1),
-            &["sh", n] => Action::Shift(n.parse().unwrap()),
-            _ => panic!(format!("Unsupported Action: {:?}", tokens)),
-        }
+        let bad = || Error::BadTable(format!("unsupported action {:?}", input));
+
+        Ok(match tokens.as_slice() {
+            // -1 because rules are 1 indexed
+            ["r", n] => Action::Reduce(rule_number(n).ok_or_else(bad)?),
+            ["R", n] => Action::ReduceTerminate(rule_number(n).ok_or_else(bad)?),
+            ["sh", n] => Action::Shift(n.parse().map_err(|_| bad())?),
+            _ => return Err(bad()),
+        })
     }
 }
 
+fn rule_number(n: &str) -> Option<usize> {
+    n.parse::<usize>().ok()?.checked_sub(1)
+}
+
 #[derive(Debug, Clone)]
 pub struct ParseState {
     pub state: usize,
@@ -60,7 +76,7 @@ impl ParseState {
         }
     }
 
-    fn state(&self) -> usize {
+    pub fn state(&self) -> usize {
         self.state
     }
 }
@@ -69,14 +85,14 @@ impl ParseState {
 pub enum ParseInput {
     Tree(Symbol, AstNode),
     Token(Symbol, Token),
-    EOI { span: (usize, usize) },
+    EOI { span: Span },
 }
 
 impl ParseInput {
     pub fn node(self) -> AstNode {
         match self {
-            ParseInput::Tree(s, n) => n,
-            ParseInput::Token(s, t) => ast_node_from_token(&t),
+            ParseInput::Tree(_, n) => n,
+            ParseInput::Token(_, t) => ast_node_from_token(&t),
             ParseInput::EOI { span } => {
                 let mut node = AstNode::new(AstKind::EOI);
                 node.span = span;
@@ -88,7 +104,7 @@ impl ParseInput {
     pub fn token(&self) -> &Token {
         match self {
             ParseInput::Token(_, t) => t,
-            _ => panic!(format!("ParseInput is not a token: {:?}", self)),
+            _ => panic!("ParseInput is not a token: {:?}", self),
         }
     }
 
@@ -96,7 +112,7 @@ impl ParseInput {
         match self {
             ParseInput::Tree(s, _) => s.clone(),
             ParseInput::Token(s, _) => s.clone(),
-            ParseInput::EOI { .. } => Symbol::from_parse("$").unwrap(),
+            ParseInput::EOI { .. } => grammar::end_of_input(),
         }
     }
 }
@@ -106,53 +122,151 @@ pub struct Parser {
     pub items: Vec<(NonTerminal, Production)>,
     pub table: Vec<BTreeMap<Symbol, Action>>,
     pub last_valid_token: Option<Token>,
+    /// Every syntax hazard found by the last call to `parse`.
+    pub syntax_errors: Vec<Hazard>,
+    /// The variables named by the input recovery skipped over.
+    pub skipped_names: BTreeSet<String>,
     // pub last_valid_span: (usize, usize),
     // pub tokens: TokenStream,
 }
 
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Parser {
+    /// A parser for zlang using the table compiled into the crate.
     pub fn new() -> Self {
         // let tokens = TokenStream::from_file(token_src_path);
-        let items = parse_items();
-        let table = parse_table();
+        Self::from_table(embedded::items(), embedded::table())
+    }
 
+    /// A parser for a `zlang.lr` style table and its rule list.
+    pub fn from_files(rules_path: impl AsRef<Path>, table_path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::from_table(
+            parse_items(rules_path)?,
+            parse_table(table_path)?,
+        ))
+    }
+
+    /// A parser for any grammar in the `zlang.cfg` format, using the table
+    /// `construction` generates for it. Every conflict in the table is
+    /// reported to `sink` as a CONFLICT warning.
+    pub fn from_grammar(
+        grammar: &Grammar,
+        construction: Construction,
+        resolution: Resolution,
+        mut sink: impl DiagnosticSink,
+    ) -> Self {
+        let table = table::build(grammar, construction, resolution);
+        for conflict in table.conflicts.iter() {
+            sink.report(
+                Hazard::new(HazardType::Warn(WarnId::Conflict), Vec::new())
+                    .with_description(conflict.to_string()),
+            );
+        }
+
+        Self::from_table(grammar.rules.clone(), table.rows)
+    }
+
+    /// A parser for a table built some other way, e.g. with
+    /// `table::build` in LALR(1) or LR(1) mode.
+    pub fn from_table(
+        items: Vec<(NonTerminal, Production)>,
+        table: Vec<BTreeMap<Symbol, Action>>,
+    ) -> Self {
         Self {
             items,
             table,
             last_valid_token: None,
+            syntax_errors: Vec::new(),
+            skipped_names: BTreeSet::new(),
+        }
+    }
+
+    /// Parses a token or source file into a complete tree. Any syntax error
+    /// fails the parse with every hazard found, see `parse_partial` for the
+    /// recovered tree.
+    pub fn parse(&mut self, token_src_path: impl AsRef<Path>) -> Result<AstNode> {
+        let tree = self.parse_partial(token_src_path)?;
+        self.complete(tree)
+    }
+
+    /// Parses zlang source, or token lines, held in memory.
+    pub fn parse_str(&mut self, text: &str) -> Result<AstNode> {
+        let tokens: TokenStream = text.parse()?;
+        self.parse_tokens(tokens)
+    }
+
+    /// Parses tokens that were already scanned, e.g. a `TokenStream` or a
+    /// `Vec<Token>`.
+    pub fn parse_tokens(&mut self, tokens: impl IntoIterator<Item = Token>) -> Result<AstNode> {
+        let tree = self.parse_tokens_partial(tokens);
+        self.complete(tree)
+    }
+
+    /// Parses a token or source file. Syntax errors are collected in
+    /// `syntax_errors` and the parser recovers from them, so the returned tree
+    /// is missing the statements that did not parse. `None` means the input
+    /// could not be recovered at all.
+    pub fn parse_partial(&mut self, token_src_path: impl AsRef<Path>) -> Result<Option<AstNode>> {
+        let tokens = TokenStream::open(token_src_path)?;
+
+        Ok(self.parse_tokens_partial(tokens))
+    }
+
+    fn complete(&self, tree: Option<AstNode>) -> Result<AstNode> {
+        match tree {
+            Some(tree) if self.syntax_errors.is_empty() => Ok(tree),
+            _ => Err(Error::Syntax(self.syntax_errors.clone())),
         }
     }
 
-    pub fn parse(&mut self, token_src_path: impl AsRef<std::path::Path>) -> Option<AstNode> {
+    /// Like `parse_partial`, for tokens that were already scanned.
+    pub fn parse_tokens_partial(
+        &mut self,
+        tokens: impl IntoIterator<Item = Token>,
+    ) -> Option<AstNode> {
+        self.syntax_errors.clear();
+        self.skipped_names.clear();
+        self.last_valid_token = None;
+
         let mut stack: Vec<ParseState> = Vec::new();
         stack.push(ParseState::start());
 
-        let mut tokens: Vec<ParseInput> = TokenStream::from_file(token_src_path)
+        let mut tokens: Vec<ParseInput> = tokens
             .into_iter()
-            .map(|t| ParseInput::Token(Symbol::from_parse(&t.id).unwrap(), t))
+            .map(|t| ParseInput::Token(Symbol::from_terminal(t.id.clone()), t))
             .collect();
 
         tokens.reverse();
 
+        // The states before any reduction made on the current lookahead
+        let mut before_reductions: Option<Vec<usize>> = None;
+
         while let Some(token) = tokens.last().cloned().or_else(|| {
             Some({
                 let span = if let Some(t) = &self.last_valid_token {
                     t.span
                 } else {
-                    (std::usize::MAX, std::usize::MAX)
+                    Span::point(usize::MAX, usize::MAX)
                 };
 
                 ParseInput::EOI { span }
             })
         }) {
-            // println!("Stack: {:#?}", stack);
-
             // let t = Symbol::from_parse(&token.id).unwrap();
 
             if let Some(top_state) = stack.last().map(|s| s.state) {
-                let action = self.table[top_state]
-                    .get(&token.symbol())
-                    .unwrap_or_else(|| {
+                if before_reductions.is_none() && !matches!(token, ParseInput::Tree(..)) {
+                    before_reductions = Some(stack.iter().map(|s| s.state).collect());
+                }
+
+                let action = match self.table[top_state].get(&token.symbol()) {
+                    Some(action) => action,
+                    None => {
                         // Emit a syntax error if the entry does not exist.
                         let mut span = match &token {
                             ParseInput::Token(_, t) => t.span,
@@ -161,17 +275,39 @@ impl Parser {
                         };
 
                         // This was an artificially inserted ending token:
-                        if span.0 == std::usize::MAX {
+                        if span.row() == usize::MAX {
                             if let Some(t) = &self.last_valid_token {
                                 span = t.span;
                             }
                         }
 
-                        // Create and emit the hazard
-                        let hazard = Hazard::new_one_loc(HazardType::Syntax, span.0, span.1);
-                        println!("{}", hazard.show_output());
-                        std::process::exit(1)
-                    });
+                        let found = match &token {
+                            ParseInput::Token(_, t) => t.clone(),
+                            other => Token {
+                                id: other.symbol().as_str().to_string(),
+                                data: String::new(),
+                                span,
+                            },
+                        };
+
+                        // Every terminal that would have been valid here,
+                        // including after reductions that have already run
+                        let expected = match before_reductions.take() {
+                            Some(states) => self.expected(&states),
+                            None => self.expected(&[top_state]),
+                        };
+
+                        // Record the hazard and try to keep going
+                        self.syntax_errors
+                            .push(Hazard::new_syntax(found, expected, span));
+
+                        if self.recover(&mut stack, &mut tokens) {
+                            continue;
+                        } else {
+                            return None;
+                        }
+                    }
+                };
 
                 // That was a valid token, so if it is a "raw" token
                 // make it the last valid one.
@@ -179,25 +315,23 @@ impl Parser {
                     self.last_valid_token.replace(t.clone());
                 }
 
-                // println!(
-                //     "State: {}, Token: {:?}, Action: {:?}\n",
-                //     top_state, token, action
-                // );
-
                 match *action {
                     Action::Shift(to_state) => {
                         // Advance the token state:
                         // match tokens.remove
+                        if !matches!(token, ParseInput::Tree(..)) {
+                            before_reductions = None;
+                        }
                         tokens.pop();
                         stack.push(ParseState::new(to_state, token));
                     }
                     Action::Reduce(rule) => {
                         // stack.push(ParseState::Token(0, token));
-                        self.reduce(rule, top_state, &mut stack, &mut tokens)
+                        self.reduce(rule, &mut stack, &mut tokens)
                     }
                     Action::ReduceTerminate(rule) => {
                         // stack.push(ParseState::Token(0, token));
-                        self.reduce(rule, top_state, &mut stack, &mut tokens);
+                        self.reduce(rule, &mut stack, &mut tokens);
 
                         return tokens.last().cloned().map(|t| t.node());
                     }
@@ -210,18 +344,129 @@ impl Parser {
         None
     }
 
-    fn reduce(
-        &mut self,
-        rule: usize,
-        state: usize,
-        stack: &mut Vec<ParseState>,
-        input: &mut Vec<ParseInput>,
-    ) {
-        // println!("Reduce({}): Stack: {:?}", rule, stack);
+    /// Every terminal that the parser in `states` would eventually shift,
+    /// following the reductions it makes first. Under SLR a row can reduce
+    /// on a terminal that turns out to be an error, and then no longer lists
+    /// terminals that were valid before the reduction.
+    fn expected(&self, states: &[usize]) -> Vec<Terminal> {
+        let terminals: BTreeSet<&Terminal> = self
+            .table
+            .iter()
+            .flat_map(|row| row.keys())
+            .filter_map(|s| s.terminal().ok())
+            .collect();
 
-        let (non_terminal, production) = &self.items[rule];
+        terminals
+            .into_iter()
+            .filter(|t| self.shifts(states.to_vec(), t))
+            .cloned()
+            .collect()
+    }
+
+    fn shifts(&self, mut states: Vec<usize>, terminal: &Terminal) -> bool {
+        let symbol = Symbol::Terminal(terminal.clone());
+
+        while let Some(&top) = states.last() {
+            match self.table[top].get(&symbol) {
+                Some(Action::Shift(_)) | Some(Action::ReduceTerminate(_)) => return true,
+                Some(Action::Reduce(rule)) => {
+                    let (non_terminal, production) = &self.items[*rule];
+                    if !production.only_lambda() {
+                        states.truncate(states.len().saturating_sub(production.len()));
+                    }
 
-        // println!("{:?} -> {:?}\n", non_terminal, production);
+                    let goto = states.last().and_then(|&s| {
+                        self.table[s].get(&Symbol::from_non_terminal(non_terminal.clone()))
+                    });
+                    match goto {
+                        Some(Action::Shift(to_state)) => states.push(*to_state),
+                        _ => return false,
+                    }
+                }
+                None => return false,
+            }
+        }
+
+        false
+    }
+
+    /// Panic mode recovery. The input is skipped up to the next `sc` or
+    /// `rbrace`, then states are popped until one that can shift a
+    /// `STATEMENT`, as if an error production `STATEMENT -> error sc` had
+    /// matched. The broken statement is left out of the tree, the variables
+    /// it names are added to `skipped_names`.
+    fn recover(&mut self, stack: &mut Vec<ParseState>, input: &mut Vec<ParseInput>) -> bool {
+        let statement = Symbol::from_non_terminal("STATEMENT".to_string());
+        let sc = Symbol::from_terminal("sc".to_string());
+        let rbrace = Symbol::from_terminal("rbrace".to_string());
+
+        // Only stop at a closing brace if some enclosing block can take it.
+        let closes_block = stack.iter().any(|s| {
+            self.table[s.state].contains_key(&statement)
+                && self.table[s.state].contains_key(&rbrace)
+        });
+
+        if !closes_block && input.last().map(|t| t.symbol()) == Some(rbrace.clone()) {
+            // A stray closing brace is simply dropped.
+            input.pop();
+        } else {
+            while let Some(next) = input.last() {
+                let symbol = next.symbol();
+
+                if symbol == sc {
+                    input.pop();
+                    break;
+                } else if symbol == rbrace && closes_block {
+                    break;
+                }
+
+                let skipped = input.pop().unwrap();
+                self.skip(skipped);
+            }
+        }
+
+        let end = grammar::end_of_input();
+        let wanted = match input.last() {
+            Some(next) => next.symbol(),
+            None => end.clone(),
+        };
+
+        while let Some(top) = stack.last() {
+            let row = &self.table[top.state];
+
+            // A closing brace or the end of input has to land in a state
+            // that can take it, anything else gets another chance to parse.
+            let fits = if wanted == end || wanted == rbrace && closes_block {
+                row.contains_key(&wanted)
+            } else {
+                true
+            };
+
+            if row.contains_key(&statement) && fits {
+                return true;
+            }
+
+            if let Some(tree) = stack.pop().and_then(|s| s.tree) {
+                self.skip(tree);
+            }
+        }
+
+        false
+    }
+
+    fn skip(&mut self, input: ParseInput) {
+        fn names(node: &AstNode, out: &mut BTreeSet<String>) {
+            if node.kind == AstKind::Identifier {
+                out.insert(node.data.clone());
+            }
+            node.children.iter().for_each(|c| names(c, out));
+        }
+
+        names(&input.node(), &mut self.skipped_names);
+    }
+
+    fn reduce(&mut self, rule: usize, stack: &mut Vec<ParseState>, input: &mut Vec<ParseInput>) {
+        let (non_terminal, production) = &self.items[rule];
 
         let ast_kind = ast_kind_from_str(non_terminal.non_terminal());
         let mut node = AstNode::new(ast_kind);
@@ -241,6 +486,17 @@ impl Parser {
                 .map(|s| s.tree().unwrap()),
         );
 
+        // From the first child that covers any input to the last one;
+        // empty productions have no span
+        let mut spans = node
+            .children
+            .iter()
+            .map(|c| c.span)
+            .filter(|s| *s != Span::default());
+        if let Some(first) = spans.next() {
+            node.span = first.to(spans.next_back().unwrap_or(first));
+        }
+
         stack.truncate(stack.len() - production.len());
         input.push(ParseInput::Tree(
             Symbol::from_non_terminal(non_terminal.clone()),
@@ -249,74 +505,100 @@ impl Parser {
     }
 }
 
-fn parse_items() -> Vec<(NonTerminal, Production)> {
-    let file = File::open("zlang-rules.lis").unwrap();
+/// Reads the rule list that accompanies `zlang.lr`.
+pub fn parse_items(path: impl AsRef<Path>) -> Result<Vec<(NonTerminal, Production)>> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|e| Error::io(path, e))?;
     let reader = BufReader::new(file);
-    let lines = reader.lines().flatten().filter(|l| !l.is_empty());
 
     let mut items = Vec::new();
 
-    for line in lines {
+    for line in reader.lines() {
+        let line = line.map_err(|e| Error::io(path, e))?;
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let bad = || Error::BadTable(format!("malformed rule {:?}", line));
         let mut split = line.split_whitespace();
 
         let idx: usize = split
             .next()
-            .unwrap()
+            .ok_or_else(bad)?
             .trim_start_matches('(')
             .trim_end_matches(')')
             .parse()
-            .unwrap();
+            .map_err(|_| bad())?;
 
-        let non_terminal = NonTerminal::new(split.next().unwrap());
+        let non_terminal = NonTerminal::new(split.next().ok_or_else(bad)?);
 
-        let _arrow = split.next().unwrap();
+        let _arrow = split.next().ok_or_else(bad)?;
 
-        let production: Vec<Symbol> = split.map(|s| Symbol::from_parse(s).unwrap()).collect();
+        let production = split
+            .map(Symbol::from_parse)
+            .collect::<anyhow::Result<Vec<Symbol>>>()
+            .map_err(|e| Error::BadTable(e.to_string()))?;
 
-        assert_eq!(items.len(), idx - 1);
+        if idx != items.len() + 1 {
+            return Err(Error::BadTable(format!(
+                "rule {} is out of order, expected {}",
+                idx,
+                items.len() + 1
+            )));
+        }
 
         items.push((non_terminal, Production(production)));
     }
 
-    items
+    Ok(items)
 }
 
-fn parse_table() -> Vec<BTreeMap<Symbol, Action>> {
-    let file = File::open("zlang.lr").unwrap();
-    let reader = BufReader::new(file);
-    let mut lines = reader.lines().flatten().filter(|l| !l.is_empty());
+/// Reads the precomputed CSV parse table `zlang.lr`.
+pub fn parse_table(path: impl AsRef<Path>) -> Result<Vec<BTreeMap<Symbol, Action>>> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+    let mut lines = text.lines().filter(|l| !l.is_empty());
 
-    let first_line = lines.next().unwrap();
+    let first_line = lines
+        .next()
+        .ok_or_else(|| Error::BadTable("the table is empty".to_string()))?;
 
-    let associated_symbols: Vec<Symbol> = first_line
+    let associated_symbols = first_line
         .split(',')
         .skip(1)
-        .map(|s| Symbol::from_parse(s).unwrap())
-        .collect();
+        .map(Symbol::from_parse)
+        .collect::<anyhow::Result<Vec<Symbol>>>()
+        .map_err(|e| Error::BadTable(e.to_string()))?;
 
     let mut rows = Vec::new();
 
     for line in lines {
         let mut split = line.split(',');
-        let state: usize = split.next().unwrap().parse().unwrap();
+        let state: usize = split
+            .next()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| Error::BadTable(format!("malformed row {:?}", line)))?;
 
-        let row: BTreeMap<Symbol, Action> = split
-            .zip(associated_symbols.iter())
-            .flat_map(|(value, symbol)| {
-                if !value.is_empty() {
-                    Some((symbol.clone(), Action::parse(value)))
-                } else {
-                    None
-                }
-            })
-            .collect();
+        let mut row = BTreeMap::new();
+        for (value, symbol) in split.zip(associated_symbols.iter()) {
+            if !value.is_empty() {
+                row.insert(symbol.clone(), Action::parse(value)?);
+            }
+        }
 
-        assert_eq!(rows.len(), state);
+        if state != rows.len() {
+            return Err(Error::BadTable(format!(
+                "state {} is out of order, expected {}",
+                state,
+                rows.len()
+            )));
+        }
 
         rows.push(row);
     }
 
-    rows
+    Ok(rows)
 }
 
 fn ast_kind_from_str(symbol: &str) -> AstKind {
@@ -326,7 +608,6 @@ fn ast_kind_from_str(symbol: &str) -> AstKind {
         | "sc" | "symtable" | "while" => AstKind::Token,
         "eq" => AstKind::Eq,
         // "ne"
-        "float" => AstKind::TypeFloat,
         "int" => AstKind::TypeInt,
         "intval" => AstKind::Integer,
         "floatval" => AstKind::Float,
@@ -351,7 +632,7 @@ fn ast_kind_from_str(symbol: &str) -> AstKind {
         "EXPR" => AstKind::Expr,
         "BEXPR" => AstKind::BooleanExpr,
         "AEXPR" => AstKind::ArithmeticExpr,
-        "BOOLS" | "lt" | "leq" | "eq" | "geq" | "gt" => AstKind::Bools,
+        "BOOLS" | "lt" | "leq" | "geq" | "gt" => AstKind::Bools,
         "PLUS" => AstKind::Plus,
         "TIMES" => AstKind::Times,
         "SUM" => AstKind::Sum,
@@ -359,45 +640,160 @@ fn ast_kind_from_str(symbol: &str) -> AstKind {
         "VALUE" => AstKind::Value,
         "UNARY" => AstKind::Unary,
         "CAST" => AstKind::Cast,
-        e => panic!(format!("Unsupported Symbol: {:?}", e)),
+        e => panic!("Unsupported Symbol: {:?}", e),
     }
 }
 
 fn ast_node_from_token(token: &Token) -> AstNode {
-    let kind = ast_kind_from_str(&token.id);
-
-    let mut chars = token.data.chars();
-
-    let mut data = String::with_capacity(token.data.len());
-    while let Some(c) = decode_char(&mut chars) {
-        data.push(c);
-    }
-
     AstNode {
-        kind,
-        data: data,
+        kind: ast_kind_from_str(&token.id),
+        data: lexer::decode_data(&token.data),
         span: token.span,
         children: Vec::new(),
     }
 }
 
-// fn get_char(data: &str) -> char {
-//     decode_char(&mut data.chars()).unwrap()
-// }
+#[cfg(test)]
+mod tests {
+    use super::lexer::Lexer;
+    use super::*;
 
-fn decode_char(chars: &mut dyn Iterator<Item = char>) -> Option<char> {
-    Some(match chars.next()? {
-        'x' => {
-            let first = chars.next()?;
-            let second = chars.next()?;
-            let mut first = first.to_string();
-            first.push(second);
+    fn parse(src: &str) -> AstNode {
+        Parser::new().parse_str(src).unwrap().create_ast()
+    }
 
-            let value: u32 = u32::from_str_radix(&first, 16).unwrap();
-            let char = std::char::from_u32(value).unwrap();
+    #[test]
+    fn parses_statements() {
+        let ast = parse("int x = 1, y;\nwhile (x < 10) { x = x + 1; }\nemit symtable;\n");
+
+        // Three statements and the end of input
+        assert_eq!(ast.children.len(), 4);
+        assert_eq!(ast[1][0].kind, AstKind::While);
+        assert_eq!(ast[2][0].kind, AstKind::Emit);
+        assert_eq!(ast[3].kind, AstKind::EOI);
+    }
+
+    #[test]
+    fn nodes_span_their_children() {
+        let tree = Parser::new()
+            .parse_str(
+                "int x;
+while (x < 10) { x = x + 1; }
+",
+            )
+            .unwrap();
+
+        // PROGRAM -> STMTS $, STMTS -> STMTS STATEMENT
+        let program = Span::new((1, 1), (2, 30)).with_bytes(0, 36);
+        let while_ = Span::new((2, 1), (2, 30)).with_bytes(7, 36);
+        assert_eq!(tree.span, program);
+        assert_eq!(tree[0][1].span, while_);
+        // The empty STMTS has nothing to span
+        assert_eq!(tree[0][0][0].span, Span::default());
+
+        // Simplified operators span their operands, not the operator token
+        let ast = parse("x = y = 2 * x + 1;\n");
+        let assign = &ast[0][0];
+        assert_eq!(assign.span, Span::new((1, 1), (1, 18)).with_bytes(0, 17));
+        assert_eq!(assign[1].span, Span::new((1, 5), (1, 8)).with_bytes(4, 7));
+        let sum = &assign[2];
+        assert_eq!(sum.kind, AstKind::Plus);
+        assert_eq!(sum.span, Span::new((1, 9), (1, 18)).with_bytes(8, 17));
+        assert_eq!(sum[0].kind, AstKind::Times);
+        assert_eq!(sum[0].span, Span::new((1, 9), (1, 14)).with_bytes(8, 13));
+    }
 
-            char
+    #[test]
+    fn generated_table_conflicts_are_reported() {
+        let grammar = Grammar::from_file("zlang.cfg").unwrap();
+        let mut hazards = Vec::new();
+        let mut parser = Parser::from_grammar(
+            &grammar,
+            Construction::Lalr,
+            Resolution::PreferShift,
+            &mut hazards,
+        );
+
+        assert!(parser.parse_str("int x = 1 + 2;\n").is_ok());
+        // The two shift-reduce conflicts on `plus` and `minus`
+        let ids: Vec<&str> = hazards.iter().map(|h| h.hazard_type.display_id()).collect();
+        assert_eq!(ids, vec!["CONFLICT", "CONFLICT"]);
+    }
+
+    #[test]
+    fn token_lines_and_source_agree() {
+        let tokens = Lexer::tokenize("x = 4 + 2;").unwrap();
+        let lines: String = tokens
+            .iter()
+            .map(|t| format!("{} {} {} {}\n", t.id, t.data, t.span.row(), t.span.col()))
+            .collect();
+
+        let from_tokens = Parser::new().parse_tokens(tokens).unwrap();
+        let from_lines = Parser::new().parse_str(&lines).unwrap();
+        let from_source = Parser::new().parse_str("x = 4 + 2;").unwrap();
+
+        assert_eq!(from_tokens, from_source);
+        // Token lines have no byte offsets but end in the same place
+        assert_eq!(without_bytes(from_tokens), from_lines);
+    }
+
+    fn without_bytes(mut node: AstNode) -> AstNode {
+        node.span.bytes = None;
+        node.children = node.children.into_iter().map(without_bytes).collect();
+        node
+    }
+
+    #[test]
+    fn missing_semicolon_at_end_of_input() {
+        match Parser::new().parse_str("int x;\nx = 2") {
+            Err(Error::Syntax(hazards)) => {
+                assert_eq!(hazards.len(), 1);
+                assert_eq!(hazards[0].locations[0].row, 2);
+                assert_eq!(hazards[0].syntax.as_ref().unwrap().found.id, "$");
+            }
+            other => panic!("expected a syntax error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn lex_errors_are_not_syntax_errors() {
+        assert!(matches!(
+            Parser::new().parse_str("int x = 3 @ 4;"),
+            Err(Error::Lex(_))
+        ));
+    }
+
+    #[test]
+    fn recovers_from_syntax_errors() {
+        let src = "int x = 3;\nx = 4 5;\n{ x = ; x = 2; }\nx = 6;\n";
+
+        let mut parser = Parser::new();
+        let tokens = TokenStream::from_source(src).unwrap();
+        let tree = parser.parse_tokens_partial(tokens).unwrap();
+        let spans: Vec<(usize, usize)> = parser
+            .syntax_errors
+            .iter()
+            .map(|h| (h.locations[0].row, h.locations[0].col))
+            .collect();
+
+        assert_eq!(spans, vec![(2, 7), (3, 7)]);
+        assert_eq!(parser.skipped_names.iter().collect::<Vec<_>>(), vec!["x"]);
+        assert_eq!(
+            parser.syntax_errors[0].message().unwrap(),
+            "found `intval`, expected one of `div`, `eq`, `geq`, `gt`, `leq`, `lt`, `minus`, \
+             `mod`, `mult`, `plus`, `sc`"
+        );
+
+        // The declaration, the block with its one good statement, the last
+        // assignment and the end of input.
+        let ast = tree.create_ast();
+        assert_eq!(ast.children.len(), 4);
+        assert_eq!(ast[1][0].kind, AstKind::BraceStmt);
+        assert_eq!(ast[1][0].children.len(), 1);
+
+        match parser.parse_str(src) {
+            Err(Error::Syntax(hazards)) => assert_eq!(hazards.len(), 2),
+            other => panic!("expected syntax errors, got {:?}", other),
         }
-        c => c,
-    })
+    }
 }
@@ -11,11 +11,7 @@ impl Production {
     }
 
     pub fn index_of(&self, symbol: &Symbol) -> Option<usize> {
-        self.0
-            .iter()
-            .enumerate()
-            .find(|(i, s)| *s == symbol)
-            .map(|(i, s)| i)
+        self.0.iter().position(|s| s == symbol)
     }
 
     pub fn only_lambda(&self) -> bool {
@@ -29,6 +25,10 @@ impl Production {
     pub fn len(&self) -> usize {
         self.symbols().len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols().is_empty()
+    }
 }
 
 impl fmt::Display for Production {
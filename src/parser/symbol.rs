@@ -88,7 +88,7 @@ impl Symbol {
                 return Ok(Symbol::from_non_terminal(nonterminal.as_str().to_string()));
             } else if let Some(dollar) = captures.name("dollar") {
                 return Ok(Symbol::from_terminal(dollar.as_str().to_string()));
-            } else if let Some(_) = captures.name("lambda") {
+            } else if captures.name("lambda").is_some() {
                 return Ok(Symbol::Lambda);
             }
         }
@@ -113,11 +113,7 @@ impl Symbol {
     }
 
     pub fn is_terminal(&self) -> bool {
-        if let Symbol::Terminal(_) = self {
-            true
-        } else {
-            false
-        }
+        matches!(self, Symbol::Terminal(_))
     }
 
     pub fn non_terminal(&self) -> Result<&NonTerminal> {
@@ -129,11 +125,7 @@ impl Symbol {
     }
 
     pub fn is_non_terminal(&self) -> bool {
-        if let Symbol::NonTerminal(_) = self {
-            true
-        } else {
-            false
-        }
+        matches!(self, Symbol::NonTerminal(_))
     }
 
     pub fn is_lambda(&self) -> bool {
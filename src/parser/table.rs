@@ -0,0 +1,379 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::str::FromStr;
+
+use super::grammar::{Assoc, Grammar};
+use super::items::{Automaton, Item, ItemSet, Lr1Automaton};
+use super::production::Production;
+use super::symbol::{NonTerminal, Symbol};
+use super::Action;
+
+/// How the lookaheads of reduce actions are computed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Construction {
+    /// LR(0) states, reducing on the follow set of the rule.
+    Slr,
+    /// LR(0) states with the lookaheads of the merged LR(1) states.
+    Lalr,
+    /// Canonical LR(1) states.
+    Lr1,
+}
+
+impl FromStr for Construction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "slr" => Ok(Construction::Slr),
+            "lalr" => Ok(Construction::Lalr),
+            "lr1" => Ok(Construction::Lr1),
+            _ => Err(format!("unknown table kind {:?}", s)),
+        }
+    }
+}
+
+/// How conflicting table entries are settled.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    /// Shift-reduce conflicts shift, reduce-reduce conflicts use the earlier rule.
+    PreferShift,
+    /// Use the grammar's `%left`/`%right`/`%nonassoc` declarations, falling
+    /// back to `PreferShift` when either side has no precedence.
+    Precedence,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConflictKind {
+    ShiftReduce,
+    ReduceReduce,
+}
+
+/// Two or more actions competing for one table cell.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub kind: ConflictKind,
+    pub state: usize,
+    pub lookahead: Symbol,
+    /// The rules that want to reduce.
+    pub reductions: Vec<(NonTerminal, Production)>,
+    /// The rules whose dot sits before the lookahead, for shift-reduce conflicts.
+    pub shifts: Vec<(NonTerminal, Production)>,
+    /// The action left in the table, `None` if a `%nonassoc` made it an error.
+    pub chosen: Option<Action>,
+    /// Whether precedence declarations decided the conflict.
+    pub by_precedence: bool,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            ConflictKind::ShiftReduce => "shift-reduce",
+            ConflictKind::ReduceReduce => "reduce-reduce",
+        };
+
+        writeln!(
+            f,
+            "{} conflict in state {} on {}:",
+            kind,
+            self.state,
+            self.lookahead.as_str()
+        )?;
+
+        for (lhs, production) in self.shifts.iter() {
+            writeln!(f, "    shift  {:?} -> {}", lhs, production)?;
+        }
+        for (lhs, production) in self.reductions.iter() {
+            writeln!(f, "    reduce {:?} -> {}", lhs, production)?;
+        }
+
+        match self.chosen {
+            Some(action) => write!(f, "    resolved as {:?}", action),
+            None => write!(f, "    resolved as an error"),
+        }
+    }
+}
+
+/// A generated parse table and every conflict found while building it.
+#[derive(Debug, Clone)]
+pub struct ParseTable {
+    pub rows: Vec<BTreeMap<Symbol, Action>>,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// The shape every construction reduces to before filling in actions.
+struct States {
+    items: Vec<ItemSet>,
+    transitions: Vec<BTreeMap<Symbol, usize>>,
+    /// Lookaheads of each completed item, by state.
+    reductions: Vec<BTreeMap<Item, BTreeSet<Symbol>>>,
+}
+
+impl States {
+    fn new(grammar: &Grammar, construction: Construction) -> Self {
+        match construction {
+            Construction::Slr => {
+                let automaton = Automaton::lr0(grammar);
+                let follow = grammar.follow_sets();
+                let reductions = automaton
+                    .states
+                    .iter()
+                    .map(|items| {
+                        items
+                            .iter()
+                            .filter(|i| i.is_complete(grammar))
+                            .map(|i| (*i, follow[&grammar.rules[i.rule].0].clone()))
+                            .collect()
+                    })
+                    .collect();
+
+                Self {
+                    items: automaton.states,
+                    transitions: automaton.transitions,
+                    reductions,
+                }
+            }
+            Construction::Lalr => {
+                let automaton = Automaton::lr0(grammar);
+                let reductions = automaton.lalr_lookaheads(grammar);
+
+                Self {
+                    items: automaton.states,
+                    transitions: automaton.transitions,
+                    reductions,
+                }
+            }
+            Construction::Lr1 => {
+                let automaton = Lr1Automaton::new(grammar);
+                let mut reductions = Vec::with_capacity(automaton.states.len());
+                let mut items = Vec::with_capacity(automaton.states.len());
+
+                for set in automaton.states.iter() {
+                    let mut lookaheads: BTreeMap<Item, BTreeSet<Symbol>> = BTreeMap::new();
+
+                    for i in set.iter().filter(|i| i.item.is_complete(grammar)) {
+                        lookaheads
+                            .entry(i.item)
+                            .or_default()
+                            .insert(i.lookahead.clone());
+                    }
+
+                    reductions.push(lookaheads);
+                    items.push(set.iter().map(|i| i.item).collect());
+                }
+
+                Self {
+                    items,
+                    transitions: automaton.transitions,
+                    reductions,
+                }
+            }
+        }
+    }
+}
+
+/// Builds a parse table in the same shape as `zlang.lr`: goto entries for
+/// non-terminals are stored as shifts, and the state that completes the
+/// start rule reduces and terminates on every column.
+pub fn build(grammar: &Grammar, construction: Construction, resolution: Resolution) -> ParseTable {
+    let states = States::new(grammar, construction);
+    let columns = grammar.columns();
+
+    let mut rows = Vec::with_capacity(states.items.len());
+    let mut conflicts = Vec::new();
+
+    for (state, items) in states.items.iter().enumerate() {
+        let mut row: BTreeMap<Symbol, Action> = states.transitions[state]
+            .iter()
+            .map(|(symbol, &to)| (symbol.clone(), Action::Shift(to)))
+            .collect();
+
+        // Every rule that wants to reduce on each lookahead.
+        let mut wanted: BTreeMap<&Symbol, Vec<usize>> = BTreeMap::new();
+
+        for (item, lookaheads) in states.reductions[state].iter() {
+            if item.rule == 0 {
+                for symbol in columns.iter() {
+                    row.insert(symbol.clone(), Action::ReduceTerminate(0));
+                }
+                continue;
+            }
+
+            for symbol in lookaheads.iter() {
+                wanted.entry(symbol).or_default().push(item.rule);
+            }
+        }
+
+        for (symbol, mut rules) in wanted {
+            rules.sort_unstable();
+
+            let shift = match row.get(symbol) {
+                Some(Action::Shift(to)) => Some(*to),
+                _ => None,
+            };
+
+            let mut conflict = Conflict {
+                kind: ConflictKind::ReduceReduce,
+                state,
+                lookahead: symbol.clone(),
+                reductions: rules.iter().map(|&r| grammar.rules[r].clone()).collect(),
+                shifts: Vec::new(),
+                chosen: None,
+                by_precedence: false,
+            };
+
+            let chosen = match shift {
+                None => Some(Action::Reduce(rules[0])),
+                Some(to) => {
+                    conflict.kind = ConflictKind::ShiftReduce;
+                    conflict.shifts = items
+                        .iter()
+                        .filter(|i| i.next(grammar) == Some(symbol))
+                        .map(|i| grammar.rules[i.rule].clone())
+                        .collect();
+
+                    let decided = match resolution {
+                        Resolution::Precedence if rules.len() == 1 => {
+                            precedence_decides(grammar, rules[0], symbol, to)
+                        }
+                        _ => None,
+                    };
+
+                    conflict.by_precedence = decided.is_some();
+                    decided.unwrap_or(Some(Action::Shift(to)))
+                }
+            };
+
+            match chosen {
+                Some(action) => row.insert(symbol.clone(), action),
+                None => row.remove(symbol),
+            };
+
+            if shift.is_some() || rules.len() > 1 {
+                conflict.chosen = chosen;
+                conflicts.push(conflict);
+            }
+        }
+
+        rows.push(row);
+    }
+
+    ParseTable { rows, conflicts }
+}
+
+/// Settles a shift-reduce conflict between `rule` and shifting `lookahead`.
+/// The outer `None` means there was not enough precedence information, the
+/// inner one that the cell becomes a syntax error.
+fn precedence_decides(
+    grammar: &Grammar,
+    rule: usize,
+    lookahead: &Symbol,
+    to: usize,
+) -> Option<Option<Action>> {
+    let (rule_level, _) = grammar.rule_precedence(rule)?;
+    let (token_level, assoc) = grammar.precedence(lookahead)?;
+
+    Some(if rule_level > token_level {
+        Some(Action::Reduce(rule))
+    } else if rule_level < token_level {
+        Some(Action::Shift(to))
+    } else {
+        match assoc {
+            Assoc::Left => Some(Action::Reduce(rule)),
+            Assoc::Right => Some(Action::Shift(to)),
+            Assoc::NonAssoc => None,
+        }
+    })
+}
+
+/// The SLR(1) table the course tools produce, settling conflicts in favor of shifts.
+pub fn slr_table(grammar: &Grammar) -> Vec<BTreeMap<Symbol, Action>> {
+    build(grammar, Construction::Slr, Resolution::PreferShift).rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{parse_items, parse_table};
+    use super::*;
+
+    fn zlang() -> Grammar {
+        Grammar::from_file("zlang.cfg").unwrap()
+    }
+
+    #[test]
+    fn generated_table_matches_shipped() {
+        let grammar = zlang();
+
+        assert_eq!(grammar.rules, parse_items("zlang-rules.lis").unwrap());
+        assert_eq!(slr_table(&grammar), parse_table("zlang.lr").unwrap());
+    }
+
+    #[test]
+    fn zlang_has_the_two_documented_conflicts() {
+        // `emit id AEXPR AEXPR`: after the first AEXPR a plus or minus can
+        // either continue the sum or start the second AEXPR as a UNARY.
+        let grammar = zlang();
+        let aexpr = (
+            NonTerminal::new("AEXPR"),
+            Production(vec![Symbol::from_non_terminal("SUM".to_string())]),
+        );
+
+        for construction in [Construction::Slr, Construction::Lalr, Construction::Lr1].iter() {
+            let table = build(&grammar, *construction, Resolution::PreferShift);
+            let lookaheads: Vec<&str> = table
+                .conflicts
+                .iter()
+                .map(|c| c.lookahead.as_str())
+                .collect();
+
+            assert_eq!(lookaheads, vec!["minus", "plus"]);
+            for conflict in table.conflicts.iter() {
+                assert_eq!(conflict.kind, ConflictKind::ShiftReduce);
+                assert_eq!(conflict.reductions, vec![aexpr.clone()]);
+                assert!(matches!(conflict.chosen, Some(Action::Shift(_))));
+            }
+        }
+    }
+
+    #[test]
+    fn reduce_reduce_conflicts_are_reported() {
+        let grammar = Grammar::parse("S -> A $ | B $\nA -> x\nB -> x").unwrap();
+        let table = build(&grammar, Construction::Lr1, Resolution::PreferShift);
+
+        assert_eq!(table.conflicts.len(), 1);
+        assert_eq!(table.conflicts[0].kind, ConflictKind::ReduceReduce);
+        assert_eq!(table.conflicts[0].reductions.len(), 2);
+        assert_eq!(table.conflicts[0].chosen, Some(Action::Reduce(2)));
+    }
+
+    #[test]
+    fn precedence_resolves_ambiguous_expressions() {
+        let grammar = Grammar::parse(
+            "%nonassoc lt\n%left plus\n%left mult\nS -> E $\nE -> E plus E | E mult E | E lt E | n",
+        )
+        .unwrap();
+        let table = build(&grammar, Construction::Lalr, Resolution::Precedence);
+
+        assert!(table.conflicts.iter().all(|c| c.by_precedence));
+
+        let plus = Symbol::from_terminal("plus".to_string());
+        let mult = Symbol::from_terminal("mult".to_string());
+        let lt = Symbol::from_terminal("lt".to_string());
+        let find = |rule: usize, lookahead: &Symbol| {
+            table
+                .conflicts
+                .iter()
+                .find(|c| c.lookahead == *lookahead && c.reductions[0] == grammar.rules[rule])
+                .unwrap()
+                .chosen
+        };
+
+        // E plus E . mult: multiplication binds tighter, so shift
+        assert!(matches!(find(1, &mult), Some(Action::Shift(_))));
+        // E mult E . plus: reduce the product first
+        assert_eq!(find(2, &plus), Some(Action::Reduce(2)));
+        // E plus E . plus: left associative
+        assert_eq!(find(1, &plus), Some(Action::Reduce(1)));
+        // E lt E . lt: non-associative, a syntax error
+        assert_eq!(find(3, &lt), None);
+    }
+}
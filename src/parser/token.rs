@@ -1,17 +1,22 @@
 use std::fmt::Debug;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Lines};
+use std::io::{BufRead, BufReader};
 use std::path::Path;
+use std::str::FromStr;
+
+use super::lexer::{decode_data, Lexer};
+use crate::error::{Error, Result};
+use crate::span::Span;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Token {
     pub id: String,
     pub data: String,
-    pub span: (usize, usize),
+    pub span: Span,
 }
 
 pub struct TokenStream {
-    lines: Box<dyn Iterator<Item = String>>,
+    tokens: Box<dyn Iterator<Item = Token>>,
 }
 
 impl Debug for TokenStream {
@@ -21,37 +26,128 @@ impl Debug for TokenStream {
 }
 
 impl TokenStream {
-    pub fn from_file(path: impl AsRef<Path>) -> Self {
-        let file = File::open(path).unwrap_or_else(|e| std::process::exit(1));
-        let reader = BufReader::new(file);
-        let lines = reader.lines().flatten().filter(|l| !l.is_empty());
-        let lines = Box::new(lines);
+    /// Opens either a zlang source file or a pre-lexed token file. Files
+    /// ending in `.z` are always scanned, anything else is sniffed like
+    /// [`TokenStream::from_str`].
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+
+        if is_source(path, &text) {
+            Self::from_source(&text)
+        } else {
+            Self::from_reader(text.as_bytes())
+        }
+    }
+
+    /// Reads a token file. Every line is checked up front, so a malformed
+    /// one is reported before any parsing starts.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|e| Error::io(path, e))?;
+
+        read_token_lines(BufReader::new(file), path)
+    }
+
+    /// Reads `id data row col` token lines from any reader, e.g. stdin or
+    /// an in-memory `Cursor`.
+    pub fn from_reader(reader: impl BufRead) -> Result<Self> {
+        read_token_lines(reader, Path::new("<input>"))
+    }
+
+    /// Scans zlang source text.
+    pub fn from_source(src: &str) -> Result<Self> {
+        let tokens = Lexer::tokenize(src)?;
+
+        Ok(Self::from_tokens(tokens))
+    }
 
-        Self { lines }
+    /// Wraps tokens that were produced some other way.
+    pub fn from_tokens<I>(tokens: I) -> Self
+    where
+        I: IntoIterator<Item = Token>,
+        I::IntoIter: 'static,
+    {
+        Self {
+            tokens: Box::new(tokens.into_iter()),
+        }
     }
 
     fn next(&mut self) -> Option<Token> {
-        let next_line = self.lines.next()?;
+        self.tokens.next()
+    }
+}
+
+/// Scans `text` as zlang source unless every line looks like an
+/// `id data row col` token line.
+impl FromStr for TokenStream {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        if is_token_file(text) {
+            Self::from_reader(text.as_bytes())
+        } else {
+            Self::from_source(text)
+        }
+    }
+}
 
-        let mut split = next_line.split_whitespace();
+fn read_token_lines(reader: impl BufRead, path: &Path) -> Result<TokenStream> {
+    let mut tokens = Vec::new();
 
-        let id = split.next()?.to_string();
-        let data = split.next()?.to_string();
-        let span = (
-            split
-                .next()
-                .unwrap_or_else(|| std::process::exit(42))
-                .parse()
-                .unwrap(),
-            split
-                .next()
-                .unwrap_or_else(|| std::process::exit(42))
-                .parse()
-                .unwrap(),
-        );
+    for (line_no, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| Error::io(path, e))?;
 
-        Some(Token { id, data, span })
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match parse_token_line(&line) {
+            Some(token) => tokens.push(token),
+            None => {
+                return Err(Error::MalformedToken {
+                    line: line_no + 1,
+                    text: line,
+                })
+            }
+        }
     }
+
+    Ok(TokenStream::from_tokens(tokens))
+}
+
+fn parse_token_line(line: &str) -> Option<Token> {
+    let mut split = line.split_whitespace();
+
+    let id = split.next()?.to_string();
+    let data = split.next()?.to_string();
+    let (row, col) = (split.next()?.parse().ok()?, split.next()?.parse().ok()?);
+    // Token lines only say where a token starts
+    let len = decode_data(&data).chars().count();
+    let span = Span::new((row, col), (row, col + len));
+
+    Some(Token { id, data, span })
+}
+
+/// Whether the file `path`, holding `text`, is read as zlang source rather
+/// than token lines by [`TokenStream::open`].
+pub fn is_source(path: &Path, text: &str) -> bool {
+    path.extension().is_some_and(|e| e == "z") || !is_token_file(text)
+}
+
+fn is_token_file(text: &str) -> bool {
+    text.lines().filter(|l| !l.trim().is_empty()).all(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+
+        match fields.as_slice() {
+            [id, _, row, col] => {
+                id.chars().all(|c| c.is_ascii_lowercase() || c == '$')
+                    && row.parse::<usize>().is_ok()
+                    && col.parse::<usize>().is_ok()
+            }
+            _ => false,
+        }
+    })
 }
 
 impl Iterator for TokenStream {
@@ -61,3 +157,62 @@ impl Iterator for TokenStream {
         self.next()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Token lines have no byte offsets but end in the same place
+    fn without_bytes(tokens: Vec<Token>) -> Vec<Token> {
+        tokens
+            .into_iter()
+            .map(|mut t| {
+                t.span.bytes = None;
+                t
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sniff_token_files() {
+        assert!(is_token_file("int int 1 1\nid x78 1 5\n\nsc x3b 1 6\n"));
+        assert!(!is_token_file("int x;\nx = 1;\n"));
+        assert!(!is_token_file("emit x 1 2;\n"));
+        assert!(is_source(Path::new("a.z"), "int int 1 1\n"));
+        assert!(!is_source(Path::new("a.tok"), "int int 1 1\n"));
+    }
+
+    #[test]
+    fn source_matches_token_lines() {
+        let lexed: Vec<Token> = TokenStream::from_source("int x;").unwrap().collect();
+        let legacy: Vec<Token> = ["int int 1 1", "id x78 1 5", "sc x3b 1 6"]
+            .iter()
+            .flat_map(|l| parse_token_line(l))
+            .collect();
+
+        assert_eq!(without_bytes(lexed), legacy);
+    }
+
+    #[test]
+    fn strings_and_readers() {
+        let lines = "int int 1 1\nid x78 1 5\nsc x3b 1 6\n";
+        let from_reader: Vec<Token> = TokenStream::from_reader(std::io::Cursor::new(lines))
+            .unwrap()
+            .collect();
+        let sniffed: Vec<Token> = lines.parse::<TokenStream>().unwrap().collect();
+        let source: Vec<Token> = "int x;".parse::<TokenStream>().unwrap().collect();
+
+        assert_eq!(from_reader, sniffed);
+        assert_eq!(from_reader, without_bytes(source));
+    }
+
+    #[test]
+    fn malformed_token_lines_are_errors() {
+        let lines = std::io::Cursor::new("int int 1 1\nid x78 1\n");
+
+        match TokenStream::from_reader(lines) {
+            Err(Error::MalformedToken { line, .. }) => assert_eq!(line, 2),
+            other => panic!("expected a malformed token error, got {:?}", other),
+        }
+    }
+}
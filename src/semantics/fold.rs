@@ -0,0 +1,240 @@
+//! Constant folding. Every subexpression of literals and `const` variables
+//! is evaluated with the interpreter's own operators, so promotion, casts
+//! and wrapping come out the same as at run time, and replaced with a
+//! literal when zlang has one for its value. Booleans and non-finite floats
+//! have none and keep their expression.
+//!
+//! A division or modulo by zero is left in place to fail at run time, and
+//! reported as a DIVZERO warning.
+
+use crate::diagnostics::DiagnosticSink;
+use crate::hazards::{Hazard, HazardType, WarnId};
+use crate::interp::{self, Value};
+use crate::syntax::Literal;
+use crate::typed::{Decl, Declarator, Emit, Expr, ExprKind, Program, Stmt, Var, VarId};
+
+/// Where `expr` finds the values of `const` variables.
+pub trait Consts {
+    /// The value of `var`, if it is a `const` whose value is known.
+    fn value(&self, var: VarId) -> Option<Value>;
+}
+
+/// Folds the constant subexpressions of `expr`, returning its value if the
+/// whole expression is constant. Divisions by zero are reported to `sink`.
+pub fn expr<C: Consts, D: DiagnosticSink>(
+    consts: &C,
+    expr: &mut Expr,
+    sink: &mut D,
+) -> Option<Value> {
+    let (ty, span) = (expr.ty, expr.span);
+
+    let value = match &mut expr.kind {
+        ExprKind::Literal(literal) => return Some(Value::from(&*literal)),
+        ExprKind::Var(_, var) => consts.value(*var)?,
+        ExprKind::Unary { op, operand } => interp::unary(*op, self::expr(consts, operand, sink)?),
+        ExprKind::Binary { op, lhs, rhs } => {
+            // Fold both sides to find every division by zero
+            let lhs = self::expr(consts, lhs, sink);
+            let rhs = self::expr(consts, rhs, sink);
+
+            match interp::binary(*op, lhs?, rhs?, ty, span) {
+                Ok(value) => value,
+                Err(_) => {
+                    let id = HazardType::Warn(WarnId::DivZero);
+                    sink.report(Hazard::at(id, span));
+                    return None;
+                }
+            }
+        }
+        // A string that does not parse is left to fail at run time
+        ExprKind::Cast(inner) => {
+            interp::convert(self::expr(consts, inner, sink)?, ty, span).ok()?
+        }
+    };
+
+    if let Some(literal) = literal(&value) {
+        expr.kind = ExprKind::Literal(literal);
+    }
+    Some(value)
+}
+
+fn literal(value: &Value) -> Option<Literal> {
+    match value {
+        Value::Int(i) => Some(Literal::Int(*i)),
+        Value::Float(f) if f.is_finite() => Some(Literal::Float(*f)),
+        Value::String(s) => Some(Literal::String(s.clone())),
+        Value::Float(_) | Value::Bool(_) => None,
+    }
+}
+
+/// Folds every expression of `program`, returning the DIVZERO warnings.
+pub fn program(program: &mut Program) -> Vec<Hazard> {
+    let mut folder = Folder {
+        vars: &program.vars,
+        values: vec![None; program.vars.len()],
+        hazards: Vec::new(),
+    };
+    folder.stmts(&mut program.stmts);
+    folder.hazards
+}
+
+/// Variables are tracked by the `VarId` the type checker gave them, so a
+/// declaration that is the body of an `if` or a `while` is a variable of
+/// its own and cannot change a `const` of the enclosing block.
+struct Folder<'p> {
+    vars: &'p [Var],
+    /// Indexed by `VarId`, only known for a `const`
+    values: Vec<Option<Value>>,
+    hazards: Vec<Hazard>,
+}
+
+impl Consts for Folder<'_> {
+    fn value(&self, var: VarId) -> Option<Value> {
+        self.values[var.0].clone()
+    }
+}
+
+impl Folder<'_> {
+    fn expr(&mut self, e: &mut Expr) -> Option<Value> {
+        let mut hazards = Vec::new();
+        let value = expr(self, e, &mut hazards);
+        self.hazards.extend(hazards);
+        value
+    }
+
+    fn stmts(&mut self, stmts: &mut [Stmt]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &mut Stmt) {
+        match stmt {
+            Stmt::Decl(decl) => self.decl(decl),
+            Stmt::Assign(assign) => {
+                // Stores to a `const` are dropped
+                self.expr(&mut assign.value);
+            }
+            Stmt::If(if_) => {
+                self.expr(&mut if_.cond);
+                self.stmt(&mut if_.then);
+            }
+            Stmt::IfElse(if_else) => {
+                self.expr(&mut if_else.cond);
+                self.stmts(&mut if_else.then.stmts);
+                self.stmt(&mut if_else.otherwise);
+            }
+            Stmt::While(while_) => {
+                self.expr(&mut while_.cond);
+                self.stmt(&mut while_.body);
+            }
+            Stmt::Emit(Emit::Value { var, first, second }) => {
+                self.expr(var);
+                self.expr(first);
+                self.expr(second);
+            }
+            Stmt::Emit(Emit::Symtable { .. }) => {}
+            Stmt::Block(block) => self.stmts(&mut block.stmts),
+        }
+    }
+
+    /// A redeclaration in the same block without an initializer changes
+    /// nothing, with one it stores to the first variable like in the
+    /// interpreter.
+    fn decl(&mut self, decl: &mut Decl) {
+        for declarator in &mut decl.vars {
+            match declarator {
+                Declarator::Ident(ident, var) => {
+                    if self.vars[var.0].declared_by(ident) {
+                        self.store(*var, Some(Value::zero(decl.ty.ty)));
+                    }
+                }
+                Declarator::Init(assign) => {
+                    let value = self.expr(&mut assign.value);
+                    for target in &assign.targets {
+                        self.store(target.var, value.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    fn store(&mut self, var: VarId, value: Option<Value>) {
+        let ty = self.vars[var.0].ty;
+        self.values[var.0] = value.filter(|_| ty.constant).and_then(|v| v.cast(ty.ty));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::semantics::typeck;
+    use crate::syntax;
+
+    fn fold_str(src: &str) -> (Program, Vec<String>) {
+        let tree = Parser::new().parse_str(src).unwrap();
+        let mut program = typeck::check(&syntax::Program::from_cst(&tree).unwrap()).unwrap();
+        let hazards = self::program(&mut program);
+        (program, hazards.iter().map(|h| h.show_output()).collect())
+    }
+
+    /// The initializer of the `n`th statement, a declaration.
+    fn init(program: &Program, n: usize) -> &ExprKind {
+        match &program.stmts[n] {
+            Stmt::Decl(Decl { vars, .. }) => match &vars[0] {
+                Declarator::Init(assign) => &assign.value.kind,
+                v => panic!("expected an initializer, found {:?}", v),
+            },
+            s => panic!("expected a declaration, found {:?}", s),
+        }
+    }
+
+    #[test]
+    fn folds_literals_and_consts() {
+        let (program, hazards) = fold_str(
+            "int x = 3 * 4 + 1;\nconst float pi = 3;\nfloat tau = pi * 2;\n\
+             const int c;\nint y = c - 7 / 2;\nint z = x + 1;\nfloat f = float(x);\nint t = int(\" 2\") + int(2.9);\n",
+        );
+
+        assert!(hazards.is_empty());
+        assert_eq!(init(&program, 0), &ExprKind::Literal(Literal::Int(13)));
+        assert_eq!(init(&program, 2), &ExprKind::Literal(Literal::Float(6.0)));
+        assert_eq!(init(&program, 4), &ExprKind::Literal(Literal::Int(-3)));
+        // `x` is not a `const`
+        assert!(matches!(init(&program, 5), ExprKind::Binary { .. }));
+        assert!(matches!(init(&program, 6), ExprKind::Cast(_)));
+        assert_eq!(init(&program, 7), &ExprKind::Literal(Literal::Int(4)));
+    }
+
+    #[test]
+    fn division_by_zero() {
+        let (program, hazards) = fold_str(
+            "const int zero = 0;\nint x = 1 % zero;\nfloat f = 1.0 / 0;\nint y = (2 / 0) * (3 / 0);\n",
+        );
+
+        assert_eq!(
+            hazards,
+            vec![
+                "OUTPUT :WARN: 2 11 :DIVZERO:",
+                "OUTPUT :WARN: 4 12 :DIVZERO:",
+                "OUTPUT :WARN: 4 22 :DIVZERO:",
+            ]
+        );
+        assert!(matches!(init(&program, 1), ExprKind::Binary { .. }));
+        // Infinity has no literal
+        assert!(matches!(init(&program, 2), ExprKind::Binary { .. }));
+    }
+
+    #[test]
+    fn redeclarations() {
+        let (program, _) = fold_str(
+            "const int c = 1;\nint i;\nwhile (i < 3) const int c = i;\nint a = c;\n\
+             const int d = 1;\nconst int d = 2;\nint b = d;\n",
+        );
+
+        // The body of the `while` has a `c` of its own
+        assert_eq!(init(&program, 3), &ExprKind::Literal(Literal::Int(1)));
+        assert_eq!(init(&program, 6), &ExprKind::Literal(Literal::Int(2)));
+    }
+}
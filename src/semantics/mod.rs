@@ -1,2 +1,4 @@
-pub mod conv;
+pub mod fold;
 pub mod novar;
+pub mod typeck;
+pub mod uninit;
@@ -0,0 +1,448 @@
+//! The type checker. It resolves every variable to its declaration, types
+//! every expression with the rules in `types` and returns the program as a
+//! `typed::Program`, with every variable numbered by a `VarId`. Only errors
+//! stop a program from being typed, the warnings are left to `SymbolVisitor`.
+
+use crate::hazards::{ErrorId, Hazard, HazardType};
+use crate::syntax::{self, Expr, Ident, Span};
+use crate::typed::{self, ExprKind, VarId};
+use crate::types::{self, QualType, Type};
+
+/// Where `check_expr` looks up the variables an expression reads.
+pub trait Scope {
+    /// The variable `ident` names and its type.
+    fn lookup(&mut self, ident: &Ident) -> Option<(VarId, QualType)>;
+}
+
+/// Types `expr`. Fails with a NOVAR hazard for every undeclared variable
+/// and an EXPR hazard for every operator applied to the wrong types.
+pub fn check_expr<S: Scope>(scope: &mut S, expr: &Expr) -> Result<typed::Expr, Vec<Hazard>> {
+    let span = expr.span();
+    let typed = |kind, ty| typed::Expr { kind, ty, span };
+
+    match expr {
+        Expr::Literal { value, .. } => Ok(typed(ExprKind::Literal(value.clone()), value.ty())),
+        Expr::Var(ident) => match scope.lookup(ident) {
+            Some((var, q)) => Ok(typed(ExprKind::Var(ident.clone(), var), q.ty)),
+            None => Err(vec![no_var(ident)]),
+        },
+        Expr::Unary { op, operand, .. } => {
+            let operand = check_expr(scope, operand)?;
+
+            match types::unary(*op, operand.ty) {
+                Some(ty) => Ok(typed(
+                    ExprKind::Unary {
+                        op: *op,
+                        operand: Box::new(operand),
+                    },
+                    ty,
+                )),
+                None => Err(vec![hazard(ErrorId::Expr, span).with_description(format!(
+                    "`{}` cannot be applied to a `{}`",
+                    op, operand.ty
+                ))]),
+            }
+        }
+        Expr::Cast { to, expr, .. } => {
+            let expr = check_expr(scope, expr)?;
+            let ty = types::cast(*to, expr.ty);
+
+            Ok(typed(ExprKind::Cast(Box::new(expr)), ty))
+        }
+        Expr::Binary { op, lhs, rhs, .. } => {
+            // Report the errors of both sides
+            let (lhs, rhs) = match (check_expr(scope, lhs), check_expr(scope, rhs)) {
+                (Ok(lhs), Ok(rhs)) => (lhs, rhs),
+                (lhs, rhs) => {
+                    let mut errs = lhs.err().unwrap_or_default();
+                    errs.extend(rhs.err().unwrap_or_default());
+                    return Err(errs);
+                }
+            };
+
+            let (lhs_ty, rhs_ty) = (lhs.ty, rhs.ty);
+            match types::binary(*op, lhs_ty, rhs_ty) {
+                Some(ty) => Ok(typed(
+                    ExprKind::Binary {
+                        op: *op,
+                        lhs: Box::new(lhs),
+                        rhs: Box::new(rhs),
+                    },
+                    ty,
+                )),
+                None => Err(vec![hazard(ErrorId::Expr, span).with_description(format!(
+                    "`{}` cannot be applied to a `{}` and a `{}`",
+                    op, lhs_ty, rhs_ty
+                ))]),
+            }
+        }
+    }
+}
+
+/// Type checks `program`, returning every error if it is not well typed.
+pub fn check(program: &syntax::Program) -> Result<typed::Program, Vec<Hazard>> {
+    let mut checker = TypeChecker {
+        scopes: vec![Vec::new()],
+        vars: Vec::new(),
+        hazards: Vec::new(),
+    };
+    let stmts = checker.stmts(&program.stmts);
+
+    if checker.hazards.is_empty() {
+        Ok(typed::Program {
+            stmts,
+            vars: checker.vars,
+        })
+    } else {
+        Err(checker.hazards)
+    }
+}
+
+fn hazard(id: ErrorId, span: Span) -> Hazard {
+    Hazard::at(HazardType::ErrorT(id), span)
+}
+
+/// NOVAR for a read of or a store to `ident`.
+pub(crate) fn no_var(ident: &Ident) -> Hazard {
+    hazard(ErrorId::NoVar, ident.span)
+        .with_description(format!("variable `{}` is not declared", ident.name))
+}
+
+/// CONV for storing a `value` in the variable `name`, of type `var`.
+pub(crate) fn conversion(name: &str, var: Type, value: Type, span: Span) -> Hazard {
+    hazard(ErrorId::Conversion, span).with_description(format!(
+        "cannot store a `{}` in `{}`, a `{}`",
+        value, name, var
+    ))
+}
+
+/// CONV for a condition of type `ty`.
+pub(crate) fn not_bool(ty: Type, span: Span) -> Hazard {
+    hazard(ErrorId::Conversion, span)
+        .with_description(format!("condition is a `{}`, not a `bool`", ty))
+}
+
+struct TypeChecker {
+    /// The variables of every open block in declaration order, the
+    /// innermost block last
+    scopes: Vec<Vec<VarId>>,
+    /// Indexed by `VarId`
+    vars: Vec<typed::Var>,
+    hazards: Vec<Hazard>,
+}
+
+impl Scope for TypeChecker {
+    fn lookup(&mut self, ident: &Ident) -> Option<(VarId, QualType)> {
+        self.scopes
+            .iter()
+            .rev()
+            .flat_map(|s| s.iter())
+            .find(|v| self.vars[v.0].name == ident.name)
+            .map(|&v| (v, self.vars[v.0].ty))
+    }
+}
+
+impl TypeChecker {
+    /// The variable `ident` declares. A redeclaration keeps the first
+    /// declaration, like the symbol table.
+    fn declare(&mut self, ident: &Ident, ty: QualType) -> (VarId, QualType) {
+        let vars = &self.vars;
+        let scope = self.scopes.last_mut().unwrap();
+
+        if let Some(&var) = scope.iter().find(|v| vars[v.0].name == ident.name) {
+            return (var, vars[var.0].ty);
+        }
+
+        let var = VarId(self.vars.len());
+        self.vars.push(typed::Var {
+            name: ident.name.clone(),
+            ty,
+            depth: self.scopes.len() - 1,
+            span: ident.span,
+        });
+        self.scopes.last_mut().unwrap().push(var);
+        (var, ty)
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Option<typed::Expr> {
+        check_expr(self, expr)
+            .map_err(|e| self.hazards.extend(e))
+            .ok()
+    }
+
+    fn convert(&mut self, target: &syntax::Target, var: QualType, value: &typed::Expr) {
+        if !types::can_convert(var.ty, value.ty) {
+            let name = &target.ident.name;
+            self.hazards
+                .push(conversion(name, var.ty, value.ty, target.span));
+        }
+    }
+
+    fn stmts(&mut self, stmts: &[syntax::Stmt]) -> Vec<typed::Stmt> {
+        stmts.iter().filter_map(|s| self.stmt(s)).collect()
+    }
+
+    fn stmt(&mut self, stmt: &syntax::Stmt) -> Option<typed::Stmt> {
+        Some(match stmt {
+            syntax::Stmt::Decl(decl) => typed::Stmt::Decl(self.decl(decl)?),
+            syntax::Stmt::Assign(assign) => typed::Stmt::Assign(self.assign(assign)?),
+            syntax::Stmt::If(if_) => {
+                let cond = self.predicate(&if_.cond);
+                let then = self.body(&if_.then);
+
+                typed::Stmt::If(typed::If {
+                    cond: cond?,
+                    then: Box::new(then?),
+                })
+            }
+            syntax::Stmt::IfElse(if_else) => {
+                let cond = self.predicate(&if_else.cond);
+                let then = self.block(&if_else.then);
+                let otherwise = self.body(&if_else.otherwise);
+
+                typed::Stmt::IfElse(typed::IfElse {
+                    cond: cond?,
+                    then,
+                    otherwise: Box::new(otherwise?),
+                })
+            }
+            syntax::Stmt::While(while_) => {
+                let cond = self.predicate(&while_.cond);
+                let body = self.body(&while_.body);
+
+                typed::Stmt::While(typed::While {
+                    cond: cond?,
+                    body: Box::new(body?),
+                })
+            }
+            syntax::Stmt::Emit(syntax::Emit::Value {
+                ident,
+                first,
+                second,
+            }) => {
+                let var = self.expr(&Expr::Var(ident.clone()));
+                let first = self.expr(first);
+                let second = self.expr(second);
+
+                typed::Stmt::Emit(typed::Emit::Value {
+                    var: Box::new(var?),
+                    first: Box::new(first?),
+                    second: Box::new(second?),
+                })
+            }
+            syntax::Stmt::Emit(syntax::Emit::Symtable { span }) => {
+                typed::Stmt::Emit(typed::Emit::Symtable {
+                    span: *span,
+                    vars: self.scopes.concat(),
+                })
+            }
+            syntax::Stmt::Block(block) => typed::Stmt::Block(self.block(block)),
+        })
+    }
+
+    /// The body of an `if`, `else` or `while`. A declaration on its own is
+    /// in a scope of its own, so it is not visible after the statement.
+    fn body(&mut self, stmt: &syntax::Stmt) -> Option<typed::Stmt> {
+        if let syntax::Stmt::Decl(_) = stmt {
+            self.scopes.push(Vec::new());
+            let stmt = self.stmt(stmt);
+            self.scopes.pop();
+            stmt
+        } else {
+            self.stmt(stmt)
+        }
+    }
+
+    fn block(&mut self, block: &syntax::Block) -> typed::Block {
+        self.scopes.push(Vec::new());
+        let stmts = self.stmts(&block.stmts);
+        self.scopes.pop();
+
+        typed::Block {
+            stmts,
+            span: block.span,
+        }
+    }
+
+    /// Conditions have to be `bool`.
+    fn predicate(&mut self, cond: &Expr) -> Option<typed::Expr> {
+        let cond = self.expr(cond)?;
+        if cond.ty != Type::Bool {
+            self.hazards.push(not_bool(cond.ty, cond.span));
+        }
+        Some(cond)
+    }
+
+    fn decl(&mut self, decl: &syntax::Decl) -> Option<typed::Decl> {
+        let ty = QualType::new(decl.ty, decl.constant);
+        let mut vars = Vec::new();
+
+        for var in &decl.vars {
+            match var {
+                syntax::Declarator::Ident(ident) => {
+                    let (var, _) = self.declare(ident, ty);
+                    vars.push(Some(typed::Declarator::Ident(ident.clone(), var)));
+                }
+                syntax::Declarator::Init(assign) => {
+                    // The value is checked before its targets are in scope
+                    let value = self.expr(&assign.value);
+                    let declared: Vec<_> = assign
+                        .targets
+                        .iter()
+                        .map(|t| self.declare(&t.ident, ty))
+                        .collect();
+
+                    vars.push(value.map(|value| {
+                        let targets = assign
+                            .targets
+                            .iter()
+                            .zip(declared)
+                            .map(|(t, (var, var_ty))| {
+                                self.convert(t, ty, &value);
+                                typed::Target {
+                                    ident: t.ident.clone(),
+                                    var,
+                                    ty: var_ty,
+                                    span: t.span,
+                                }
+                            })
+                            .collect();
+
+                        typed::Declarator::Init(typed::Assign { targets, value })
+                    }));
+                }
+            }
+        }
+
+        Some(typed::Decl {
+            ty,
+            span: decl.span,
+            vars: vars.into_iter().collect::<Option<_>>()?,
+        })
+    }
+
+    fn assign(&mut self, assign: &syntax::Assign) -> Option<typed::Assign> {
+        let value = self.expr(&assign.value);
+        let mut targets = Vec::new();
+
+        for target in &assign.targets {
+            match self.lookup(&target.ident) {
+                Some((var, ty)) => {
+                    if let Some(value) = &value {
+                        self.convert(target, ty, value);
+                    }
+                    targets.push(typed::Target {
+                        ident: target.ident.clone(),
+                        var,
+                        ty,
+                        span: target.span,
+                    });
+                }
+                None => self.hazards.push(no_var(&target.ident)),
+            }
+        }
+
+        Some(typed::Assign {
+            targets,
+            value: value?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn check_str(src: &str) -> Result<typed::Program, Vec<Hazard>> {
+        check(&syntax::Program::from_cst(&Parser::new().parse_str(src).unwrap()).unwrap())
+    }
+
+    fn outputs(hazards: Vec<Hazard>) -> Vec<String> {
+        hazards.iter().map(|h| h.show_output()).collect()
+    }
+
+    #[test]
+    fn annotates_expressions() {
+        let program = check_str("int i = 1;\nfloat f = i + 2.5;\n").unwrap();
+
+        let typed::Stmt::Decl(decl) = &program.stmts[1] else {
+            panic!("expected a declaration");
+        };
+        let typed::Declarator::Init(assign) = &decl.vars[0] else {
+            panic!("expected an initializer");
+        };
+        assert_eq!(assign.value.ty, Type::Float);
+        match &assign.value.kind {
+            ExprKind::Binary { lhs, rhs, .. } => {
+                assert_eq!(lhs.ty, Type::Int);
+                assert_eq!(rhs.ty, Type::Float);
+            }
+            k => panic!("expected a binary expression, found {:?}", k),
+        }
+        assert_eq!(assign.targets[0].ty, QualType::new(Type::Float, false));
+    }
+
+    #[test]
+    fn reports_every_error() {
+        let hazards = check_str(
+            "int i = 1.5;\n{ string s; }\ns = \"a\";\nwhile (i + \"a\" < 2) i = 1;\nif (1 < 2) emit i i 1;\n",
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            outputs(hazards),
+            vec![
+                "OUTPUT :ERROR: 1 7 :CONV:",
+                "OUTPUT :ERROR: 3 1 :NOVAR:",
+                "OUTPUT :ERROR: 4 10 :EXPR:",
+            ]
+        );
+    }
+
+    #[test]
+    fn resolves_variables() {
+        let program =
+            check_str("int a = 1;\n{ float a = a; emit symtable; }\nint a = 2;\na = a;\n").unwrap();
+
+        let names: Vec<_> = program
+            .vars
+            .iter()
+            .map(|v| (v.name.as_str(), v.depth, v.ty.ty))
+            .collect();
+        assert_eq!(names, vec![("a", 0, Type::Int), ("a", 1, Type::Float)]);
+
+        let typed::Stmt::Block(block) = &program.stmts[1] else {
+            panic!("expected a block");
+        };
+        let typed::Stmt::Decl(decl) = &block.stmts[0] else {
+            panic!("expected a declaration");
+        };
+        let typed::Declarator::Init(assign) = &decl.vars[0] else {
+            panic!("expected an initializer");
+        };
+        // The initializer reads the outer `a`
+        assert!(matches!(assign.value.kind, ExprKind::Var(_, VarId(0))));
+        assert_eq!(assign.targets[0].var, VarId(1));
+        let typed::Stmt::Emit(typed::Emit::Symtable { vars, .. }) = &block.stmts[1] else {
+            panic!("expected an `emit symtable`");
+        };
+        assert_eq!(vars, &vec![VarId(0), VarId(1)]);
+
+        // A redeclaration is the first variable
+        let typed::Stmt::Decl(decl) = &program.stmts[2] else {
+            panic!("expected a declaration");
+        };
+        assert_eq!(decl.vars[0].vars()[0].1, VarId(0));
+        assert!(!program.var(VarId(0)).declared_by(decl.vars[0].vars()[0].0));
+    }
+
+    #[test]
+    fn unbraced_declarations_are_scoped() {
+        let hazards = check_str("int a = 0;\nif (a > 7) int z = 1;\nemit z 0 0;\n").unwrap_err();
+        assert_eq!(outputs(hazards), vec!["OUTPUT :ERROR: 3 6 :NOVAR:"]);
+
+        let hazards = check_str("int a = 0;\nwhile (a < 7) int z = a;\na = z;\n").unwrap_err();
+        assert_eq!(outputs(hazards), vec!["OUTPUT :ERROR: 3 5 :NOVAR:"]);
+    }
+}
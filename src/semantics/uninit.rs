@@ -0,0 +1,277 @@
+//! Definite assignment. Every read of a variable is given the state of the
+//! variable on the paths that reach it: assigned on all of them, on some of
+//! them, or on none. `SymbolVisitor` reports the reads assigned on none as
+//! UNINIT and the ones assigned on some as MAYBEUNINIT.
+//!
+//! The analysis follows the structured control flow: an `if` joins the
+//! state after its body with the state before it, an `if`/`else` joins its
+//! two branches, and a `while` is walked twice, the second time from the
+//! state before the loop joined with the state after one iteration, which
+//! is already the fixed point. Scoping is the symbol table's: a declaration
+//! that is the body of an `if` or a `while` is in a scope of its own.
+
+use std::collections::HashMap;
+
+use crate::syntax::{
+    Assign, Block, Decl, Declarator, Ident, If, IfElse, Program, Span, Stmt, While,
+};
+use crate::visitor::{self, Visitor};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum State {
+    Unassigned,
+    /// Assigned on some paths but not all
+    Maybe,
+    Assigned,
+}
+
+impl State {
+    fn join(self, other: State) -> State {
+        if self == other {
+            self
+        } else {
+            State::Maybe
+        }
+    }
+}
+
+/// The state of the variable at every read, found by `analyze`.
+#[derive(Debug, Clone, Default)]
+pub struct Reads {
+    reads: HashMap<Span, State>,
+}
+
+impl Reads {
+    /// The state of the variable read by the identifier at `span`. Reads
+    /// of undeclared variables count as assigned.
+    pub fn state(&self, span: Span) -> State {
+        self.reads.get(&span).copied().unwrap_or(State::Assigned)
+    }
+}
+
+/// Finds the state of the variable at every read in `program`.
+pub fn analyze(program: &Program) -> Reads {
+    let mut analysis = Analysis {
+        scopes: vec![Vec::new()],
+        reads: Reads::default(),
+    };
+    analysis.visit_program(program);
+    analysis.reads
+}
+
+#[derive(Debug, Clone)]
+struct Var {
+    name: String,
+    constant: bool,
+    state: State,
+}
+
+type Scopes = Vec<Vec<Var>>;
+
+struct Analysis {
+    /// The innermost block last
+    scopes: Scopes,
+    reads: Reads,
+}
+
+impl Analysis {
+    fn lookup(&mut self, ident: &Ident) -> Option<&mut Var> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|s| s.iter_mut().find(|v| v.name == ident.name))
+    }
+
+    /// A redeclaration in the same block is a store to the first variable.
+    fn declare(&mut self, ident: &Ident, constant: bool, state: State) {
+        let scope = self.scopes.last_mut().unwrap();
+
+        match scope.iter_mut().find(|v| v.name == ident.name) {
+            Some(var) => {
+                if state == State::Assigned {
+                    var.state = state;
+                }
+            }
+            None => scope.push(Var {
+                name: ident.name.clone(),
+                constant,
+                state,
+            }),
+        }
+    }
+
+    /// Joins the current state with `other`, the state on another path to
+    /// the same point. A variable only one of them declared keeps its
+    /// state, it only exists on that path.
+    fn join(&mut self, other: Scopes) {
+        for (scope, other) in self.scopes.iter_mut().zip(other) {
+            let common = scope.len().min(other.len());
+            for (var, other) in scope.iter_mut().zip(&other) {
+                var.state = var.state.join(other.state);
+            }
+            scope.extend(other.into_iter().skip(common));
+        }
+    }
+
+    /// The body of an `if`, `else` or `while`. A declaration on its own is
+    /// in a scope of its own.
+    fn visit_body(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Decl(decl) => {
+                self.scopes.push(Vec::new());
+                self.visit_decl(decl);
+                self.scopes.pop();
+            }
+            stmt => self.visit_stmt(stmt),
+        }
+    }
+}
+
+impl Visitor for Analysis {
+    fn visit_block(&mut self, block: &Block) {
+        self.scopes.push(Vec::new());
+        visitor::walk_block(self, block);
+        self.scopes.pop();
+    }
+
+    fn visit_decl(&mut self, decl: &Decl) {
+        for var in &decl.vars {
+            match var {
+                Declarator::Ident(ident) => self.declare(ident, decl.constant, State::Unassigned),
+                Declarator::Init(assign) => {
+                    self.visit_expr(&assign.value);
+                    for target in &assign.targets {
+                        self.declare(&target.ident, decl.constant, State::Assigned);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Stores to a `const` are dropped.
+    fn visit_assign(&mut self, assign: &Assign) {
+        self.visit_expr(&assign.value);
+
+        for target in &assign.targets {
+            if let Some(var) = self.lookup(&target.ident) {
+                if !var.constant {
+                    var.state = State::Assigned;
+                }
+            }
+        }
+    }
+
+    fn visit_if(&mut self, if_: &If) {
+        self.visit_expr(&if_.cond);
+        let before = self.scopes.clone();
+        self.visit_body(&if_.then);
+        self.join(before);
+    }
+
+    fn visit_if_else(&mut self, if_else: &IfElse) {
+        self.visit_expr(&if_else.cond);
+        let before = self.scopes.clone();
+        self.visit_block(&if_else.then);
+        let then = std::mem::replace(&mut self.scopes, before);
+        self.visit_body(&if_else.otherwise);
+        self.join(then);
+    }
+
+    fn visit_while(&mut self, while_: &While) {
+        let before = self.scopes.clone();
+        for _ in 0..2 {
+            self.visit_expr(&while_.cond);
+            self.visit_body(&while_.body);
+            self.join(before.clone());
+        }
+    }
+
+    fn visit_var(&mut self, ident: &Ident) {
+        if let Some(state) = self.lookup(ident).map(|v| v.state) {
+            self.reads.reads.insert(ident.span, state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    /// The reads that are not definitely assigned, in source order.
+    fn uninit(src: &str) -> Vec<((usize, usize), State)> {
+        let tree = Parser::new().parse_str(src).unwrap();
+        let reads = analyze(&Program::from_cst(&tree).unwrap());
+
+        let mut uninit: Vec<_> = reads
+            .reads
+            .into_iter()
+            .filter(|(_, s)| *s != State::Assigned)
+            .map(|(span, s)| (span.start, s))
+            .collect();
+        uninit.sort_by_key(|(start, _)| *start);
+        uninit
+    }
+
+    #[test]
+    fn straight_line() {
+        assert_eq!(
+            uninit("int a, b = 1;\nemit a b b;\na = b;\nemit a b b;\n"),
+            vec![((2, 6), State::Unassigned)]
+        );
+        // Stores to a `const` are dropped
+        assert_eq!(
+            uninit("const int c;\nc = 1;\nemit c c c;\n"),
+            vec![
+                ((3, 6), State::Unassigned),
+                ((3, 8), State::Unassigned),
+                ((3, 10), State::Unassigned)
+            ]
+        );
+    }
+
+    #[test]
+    fn branches() {
+        assert_eq!(
+            uninit(
+                "int a, b, c;\nif (1 < 2) a = 1;\n\
+                 if (1 < 2) { b = 1; c = 1; } else b = 2;\nemit a b c;\n"
+            ),
+            vec![((4, 6), State::Maybe), ((4, 10), State::Maybe)]
+        );
+    }
+
+    #[test]
+    fn loops() {
+        assert_eq!(
+            uninit(
+                "int i, x, y;\ny = 0;\n\
+                 while (i < 3) { emit x x y; x = 1; i = i + 1; }\nemit x i i;\n"
+            ),
+            vec![
+                ((3, 8), State::Maybe),
+                ((3, 22), State::Maybe),
+                ((3, 24), State::Maybe),
+                ((3, 40), State::Maybe),
+                ((4, 6), State::Maybe),
+                ((4, 8), State::Maybe),
+                ((4, 10), State::Maybe),
+            ]
+        );
+    }
+
+    #[test]
+    fn scopes() {
+        assert_eq!(
+            uninit(
+                "int x;\n{ int x = 1; emit x x x; }\n\
+                 if (1 < 2) { x = 1; } else { int x; x = 2; }\nemit x x x;\n"
+            ),
+            vec![
+                ((4, 6), State::Maybe),
+                ((4, 8), State::Maybe),
+                ((4, 10), State::Maybe)
+            ]
+        );
+    }
+}
@@ -0,0 +1,80 @@
+//! Where tokens, nodes and hazards are in the source.
+
+use std::fmt;
+
+/// A range of the source. Rows and columns are 1 indexed and `end` is the
+/// position just past the last character, so an empty span has `start ==
+/// end`. Token files only give where each token starts, their spans end
+/// after the lexeme on the same row and have no byte offsets.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Span {
+    /// The row and column of the first character
+    pub start: (usize, usize),
+    /// The row and column just past the last character
+    pub end: (usize, usize),
+    /// The byte offsets of `start` and `end`, when the source was scanned
+    pub bytes: Option<(usize, usize)>,
+}
+
+impl Span {
+    pub fn new(start: (usize, usize), end: (usize, usize)) -> Self {
+        Self {
+            start,
+            end,
+            bytes: None,
+        }
+    }
+
+    /// An empty span at `row`, `col`.
+    pub fn point(row: usize, col: usize) -> Self {
+        Self::new((row, col), (row, col))
+    }
+
+    pub fn with_bytes(mut self, start: usize, end: usize) -> Self {
+        self.bytes = Some((start, end));
+        self
+    }
+
+    pub fn row(&self) -> usize {
+        self.start.0
+    }
+
+    pub fn col(&self) -> usize {
+        self.start.1
+    }
+
+    /// From the start of `self` to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+            bytes: match (self.bytes, other.bytes) {
+                (Some((start, _)), Some((_, end))) => Some((start, end)),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// `row:col` of the start, like the course tools report positions.
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.start.0, self.start.1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn join() {
+        let a = Span::new((1, 5), (1, 6)).with_bytes(4, 5);
+        let b = Span::new((2, 1), (2, 4)).with_bytes(7, 10);
+
+        assert_eq!(a.to(b), Span::new((1, 5), (2, 4)).with_bytes(4, 10));
+        assert_eq!(a.to(Span::point(2, 4)), Span::new((1, 5), (2, 4)));
+        assert_eq!(b.to_string(), "2:1");
+        assert!(a < b);
+    }
+}
@@ -1,7 +1,18 @@
-use crate::ast::{AstKind, AstNode};
-use crate::hazards::{ErrorId, Hazard, HazardType, Location, WarnId};
+use crate::diagnostics::{DiagnosticSink, Diagnostics};
+use crate::error::Error;
+use crate::hazards::{Hazard, HazardType, WarnId};
+use crate::interp::Value;
+use crate::semantics::fold::{self, Consts};
+use crate::semantics::typeck::{self, Scope};
+use crate::semantics::uninit::{self, Reads, State};
+use crate::syntax::{
+    Assign, Block, Decl, Declarator, Emit, Expr, Ident, If, IfElse, Program, Span, Stmt, While,
+};
+use crate::typed::VarId;
+use crate::types::{self, QualType, Type};
+use crate::visitor::{self, Visitor};
 use std::cell::Cell;
-use std::fs::File;
+use std::fs::OpenOptions;
 use std::io::prelude::*;
 use std::path::PathBuf;
 
@@ -12,66 +23,72 @@ pub struct SymbolTable {
 }
 
 impl SymbolTable {
+    /// Returns the REVAR warning if `ident` is already declared in `scope`.
     pub fn push_symbol(
         &mut self,
         scope: usize,
-        ty: String,
+        ty: QualType,
         ident: String,
-        span: (usize, usize),
-        const_: bool,
-    ) {
-        if !self.check_for_redeclare(&ident, scope, span) {
-            let symbol = Symbol::new(scope, ty, ident, span, const_);
+        span: Span,
+    ) -> Option<Hazard> {
+        let redeclare = self.check_for_redeclare(&ident, scope, span);
+        if redeclare.is_none() {
+            let mut symbol = Symbol::new(scope, ty, ident, span);
+            // A `const` declared without a value keeps its zero value
+            if ty.constant {
+                symbol.value = Some(Value::zero(ty.ty));
+            }
             self.symbols.push(symbol);
         }
+        redeclare
     }
 
+    /// `value` is the folded initializer, if it is constant. Returns the
+    /// REVAR warning if `ident` is already declared in `scope`.
     pub fn push_symbol_init(
         &mut self,
         scope: usize,
-        ty: String,
+        ty: QualType,
         ident: String,
-        span: (usize, usize),
-        const_: bool,
-    ) {
-        if !self.check_for_redeclare(&ident, scope, span) {
-            let symbol = Symbol::new(scope, ty, ident, span, const_);
-            symbol.initialized.set(true);
+        span: Span,
+        value: Option<Value>,
+    ) -> Option<Hazard> {
+        let redeclare = self.check_for_redeclare(&ident, scope, span);
+        if redeclare.is_some() {
+            // The first declaration takes the new value
+            if let Some(symbol) = self
+                .symbols
+                .iter_mut()
+                .rev()
+                .find(|s| s.ident == ident && s.scope == scope)
+            {
+                if symbol.ty.constant {
+                    symbol.value = value.and_then(|v| v.cast(symbol.ty.ty));
+                }
+            }
+        } else {
+            let mut symbol = Symbol::new(scope, ty, ident, span);
+            symbol.value = value.filter(|_| ty.constant).and_then(|v| v.cast(ty.ty));
             self.symbols.push(symbol);
         }
+        redeclare
     }
 
-    /// will emit an error if is redeclare, and not add it to the scope
-    pub fn check_for_redeclare(&self, ident: &str, scope: usize, span: (usize, usize)) -> bool {
-        let current_valid_symbols = self.symbols_in_valid_scope(scope);
-        let is_redeclare = current_valid_symbols
-            .iter()
-            .any(|s| s.ident == ident && s.scope == scope);
-        if is_redeclare {
-            let redeclare_warn =
-                Hazard::new_one_loc(HazardType::Warn(WarnId::RedeclareVar), span.0, span.1);
-            println!("{}", redeclare_warn.show_output());
-        }
-        println!(
-            "chkcing if ident: {}, scope: {} is redeclare: {}",
-            ident, scope, is_redeclare
-        );
-        // gitprintln!("is it? ")
-        is_redeclare
-    }
-
-    /// tells you if a ident is in valid scope and has been initialized. This
-    /// should be run before we use a variable in an expr
-    pub fn has_been_initialized(&self, ident: String, current_scope: usize, span: (usize, usize)) {
-        if self
-            .symbols_in_valid_scope(current_scope)
-            .iter()
-            .filter(|s| s.initialized.get())
-            .any(|s| s.ident == ident)
-        {
-            let h = Hazard::new_one_loc(HazardType::Warn(WarnId::Uninit), span.0, span.1);
-            println!("{}", h.show_output());
-        }
+    /// The REVAR warning at `span` if `ident` is already declared in
+    /// `scope`, pointing back at the first declaration.
+    pub fn check_for_redeclare(&self, ident: &str, scope: usize, span: Span) -> Option<Hazard> {
+        let first = self
+            .symbols_in_valid_scope(scope)
+            .into_iter()
+            .find(|s| s.ident == ident && s.scope == scope)?;
+
+        let warn = Hazard::at(HazardType::Warn(WarnId::RedeclareVar), span)
+            .with_description(format!(
+                "variable `{}` is already declared in this block",
+                ident
+            ))
+            .with_label(first.span, "first declared here");
+        Some(warn)
     }
 
     pub fn symbols_in_valid_scope(&self, current_scope: usize) -> Vec<&Symbol> {
@@ -104,16 +121,17 @@ impl SymbolTable {
         self.symbols.retain(|s| s.scope < scope);
     }
 
-    pub fn write_to_file(&self, path: &PathBuf) {
+    /// Appends the table to `path`, so every `emit symtable` adds a dump.
+    pub fn write_to_file(&self, path: &PathBuf) -> crate::error::Result<()> {
         let out = self.output();
-        let mut file = File::with_options()
-            .write(true)
+        let mut file = OpenOptions::new()
             .append(true)
             .create(true)
             .open(path)
-            .unwrap_or_else(|e| std::process::exit(1));
+            .map_err(|e| Error::io(path, e))?;
 
-        file.write_all(out.as_bytes()).unwrap();
+        file.write_all(out.as_bytes())
+            .map_err(|e| Error::io(path, e))
     }
 
     pub fn output(&self) -> String {
@@ -127,66 +145,99 @@ impl SymbolTable {
     }
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Clone)]
 pub struct Symbol {
     pub scope: usize,
-    pub const_: bool,
-    pub ty: String, // ty = type. Should probably not be a string, but don't have types yet
+    pub ty: QualType,
     pub ident: String, // identifier
-    pub span: (usize, usize),
+    pub span: Span,
     pub used: Cell<bool>,
-    pub initialized: Cell<bool>,
+    /// The value of a `const` whose initializer folded to a constant
+    pub value: Option<Value>,
 }
 
 impl Symbol {
-    pub fn new(
-        scope: usize,
-        ty: String,
-        ident: String,
-        span: (usize, usize),
-        const_: bool,
-    ) -> Self {
+    pub fn new(scope: usize, ty: QualType, ident: String, span: Span) -> Self {
         Self {
             scope,
-            const_,
             ty,
             ident,
             span,
             used: Cell::new(false),
-            initialized: Cell::new(false),
+            value: None,
         }
     }
+
+    /// `scope,type,ident`, then the value of a `const` that has one.
     pub fn output(&self) -> String {
         let mut out = String::new();
         out.push_str(&self.scope.to_string());
         out.push(',');
-        if self.const_ {
+        if self.ty.constant {
             out.push_str("const");
         }
-        out.push_str(&self.ty.to_string());
+        out.push_str(self.ty.ty.name());
         out.push(',');
         out.push_str(&self.ident.to_string());
+        if let (true, Some(value)) = (self.ty.constant, &self.value) {
+            out.push(',');
+            out.push_str(&value.to_string());
+        }
         out.clone().to_owned()
     }
 }
 
+/// Checks a program, reporting every hazard to `sink`.
 #[derive(Debug, Default)]
-pub struct SymbolVisitor {
+pub struct SymbolVisitor<S = Diagnostics> {
     table: SymbolTable,
     scope: usize,
-    pub errored: bool,
     output_path: std::path::PathBuf,
+    /// Whether each read is initialized, from `uninit::analyze`
+    reads: Reads,
+    /// The first failed `emit symtable` write, returned by `program`.
+    io_error: Option<Error>,
+    sink: S,
 }
 
 impl SymbolVisitor {
+    /// A visitor that collects the hazards, see `into_hazards`.
     pub fn new(output_path: std::path::PathBuf) -> Self {
-        let mut new = Self::default();
-        new.output_path = output_path;
-        new
+        Self::with_sink(output_path, Diagnostics::new())
+    }
+
+    /// Every hazard found, in the order they were found.
+    pub fn into_hazards(self) -> Vec<Hazard> {
+        self.sink.into_vec()
+    }
+}
+
+impl<S: DiagnosticSink> SymbolVisitor<S> {
+    pub fn with_sink(output_path: std::path::PathBuf, sink: S) -> Self {
+        Self {
+            table: SymbolTable::default(),
+            scope: 0,
+            output_path,
+            reads: Reads::default(),
+            io_error: None,
+            sink,
+        }
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
     }
 
-    pub fn write_table_to_file(&self, path: &PathBuf) {
-        self.table.write_to_file(path);
+    pub fn into_sink(self) -> S {
+        self.sink
+    }
+
+    fn report(&mut self, hazard: Hazard) {
+        self.sink.report(hazard);
+    }
+
+    pub fn write_table_to_file(&self, path: &PathBuf) -> crate::error::Result<()> {
+        self.table.write_to_file(path)
     }
 
     pub fn current_symbols(&self) -> Vec<&Symbol> {
@@ -195,26 +246,10 @@ impl SymbolVisitor {
 
     pub fn exists(&self, ident: &str) -> bool {
         (0..=self.scope)
-            .map(|s| self.table.symbols_in_valid_scope(s).into_iter())
-            .flatten()
+            .flat_map(|s| self.table.symbols_in_valid_scope(s).into_iter())
             .any(|s| s.ident == ident)
     }
 
-    pub fn initialized(&self, ident: &str) -> bool {
-        for symbols in (0..=self.scope)
-            .rev()
-            .map(|s| self.table.symbols_in_valid_scope(s))
-        {
-            for symbol in symbols {
-                if symbol.ident == ident {
-                    return symbol.initialized.get();
-                }
-            }
-        }
-
-        false
-    }
-
     pub fn set_used(&self, ident: &str) {
         for symbols in (0..=self.scope)
             .rev()
@@ -228,639 +263,380 @@ impl SymbolVisitor {
         }
     }
 
-    pub fn report_unused(&self, scope: usize) {
-        self.table
+    pub fn report_unused(&mut self, scope: usize) {
+        let unused: Vec<Hazard> = self
+            .table
             .symbols
             .iter()
             .filter(|s| !s.used.get() && s.scope == scope)
-            .for_each(|s| {
-                let hazard =
-                    Hazard::new_one_loc(HazardType::Warn(WarnId::Unused), s.span.0, s.span.1);
-                println!("{}", hazard.show_output());
+            .map(|s| {
+                Hazard::at(HazardType::Warn(WarnId::Unused), s.span)
+                    .with_description(format!("variable `{}` is never used", s.ident))
             })
+            .collect();
+
+        unused.into_iter().for_each(|h| self.report(h));
     }
 
-    pub fn program(&mut self, program: &AstNode) {
-        // println!("Program: {}", program.kind.to_string());
-        for c in &program.children {
-            if c.kind == AstKind::Statement {
-                self.stmt(c);
-            }
-        }
+    /// Runs `f` in a new scope, reporting its unused variables at the end.
+    fn scoped(&mut self, f: impl FnOnce(&mut Self)) {
+        self.scope += 1;
+
+        f(self);
+
+        self.report_unused(self.scope);
+        self.table.clean_table(self.scope);
+        self.scope -= 1;
     }
 
-    /// expected_type is what the expr should evaluate too. Returns a type
-    pub fn get_expr_type(&mut self, expr: &AstNode) -> Result<String, Vec<Hazard>> {
-        // println!("Expr: {}", expr.kind.to_string());
-        // println!(
-        //     "Valid Scope: {:?}\n",
-        //     self.table.symbols_in_valid_scope(self.scope)
-        // );
-
-        // a < (b + c)
-
-        if expr.children.is_empty() {
-            match expr.kind {
-                AstKind::Integer => return Ok("int".to_string()),
-                AstKind::Float => return Ok("float".to_string()),
-                AstKind::String => return Ok("string".to_string()),
-                AstKind::Identifier => {
-                    if let Some(ident) = self
-                        .table
-                        .symbols_in_valid_scope(self.scope)
-                        .iter()
-                        .find(|s| s.ident == expr.data)
-                    {
-                        ident.used.set(true);
-
-                        if ident.initialized.get() {
-                            return Ok(ident.ty.clone());
-                        } else {
-                            let warn = Hazard::new_one_loc(
-                                HazardType::Warn(WarnId::Uninit),
-                                expr.span.0,
-                                expr.span.1,
-                            );
-
-                            println!("{}", warn.show_output());
-
-                            return Ok(ident.ty.clone());
-                        }
-                    } else {
-                        // Identifier doesn't even exist:
-                        let h = Hazard::new_one_loc(
-                            HazardType::ErrorT(ErrorId::NoVar),
-                            expr.span.0,
-                            expr.span.1,
-                        );
-                        self.errored = true;
-                        return Err(vec![h]);
-                    }
-                }
-                e => panic!(format!(
-                    "invalid kind for bottom node in expr tree: {:?}",
-                    e
-                )),
-            }
+    /// The body of an `if`, `else` or `while`. A declaration on its own is
+    /// in a scope of its own, so it is not visible after the statement.
+    fn visit_body(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Decl(decl) => self.scoped(|v| v.visit_decl(decl)),
+            stmt => self.visit_stmt(stmt),
         }
+    }
 
-        if expr.children.len() == 1 {
-            match expr.data.as_str() {
-                "+" | "-" => {
-                    let child_kind = &self.get_expr_type(&expr[0])?;
-                    if is_numeric(child_kind) {
-                        return Ok(child_kind.clone());
-                    } else {
-                        self.errored = true;
-                        return Err(vec![Hazard::new_one_loc(
-                            HazardType::ErrorT(ErrorId::Expr),
-                            expr.span.0,
-                            expr.span.1,
-                        )]);
-                    }
-                }
-                "~" => {
-                    // bitwise complement
-                    let child_kind = &self.get_expr_type(&expr[0])?;
-                    if child_kind == "int" {
-                        return Ok("bool".to_string());
-                    } else {
-                        self.errored = true;
-                        return Err(vec![Hazard::new_one_loc(
-                            HazardType::ErrorT(ErrorId::Expr),
-                            expr.span.0,
-                            expr.span.1,
-                        )]);
-                    }
-                }
-                "!" => {
-                    let child_kind = &self.get_expr_type(&expr[0])?;
-                    if child_kind == "bool" {
-                        return Ok("bool".to_string());
-                    } else {
-                        self.errored = true;
-                        return Err(vec![Hazard::new_one_loc(
-                            HazardType::ErrorT(ErrorId::Expr),
-                            expr.span.0,
-                            expr.span.1,
-                        )]);
-                    }
-                }
-                _ => panic!("Invalid string of expr_data when dealing with one child in expr"),
-            }
+    /// Checks a whole program, ending with the unused variables of the top
+    /// level. Hazards go to the sink as they are found, the result only
+    /// fails if an `emit symtable` could not be written.
+    pub fn program(&mut self, program: &Program) -> crate::error::Result<()> {
+        self.reads = uninit::analyze(program);
+        self.visit_program(program);
+        self.report_unused(0);
+
+        match self.io_error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
         }
+    }
+
+    /// The type of `expr`, reading its variables marks them used.
+    pub fn get_expr_type(&mut self, expr: &Expr) -> Result<Type, Vec<Hazard>> {
+        self.eval(expr).map(|(ty, _)| ty)
+    }
 
-        let mut errs = Vec::new();
+    /// The type of `expr` and its value if it folds to a constant. A
+    /// division by zero found folding it is reported to the sink.
+    pub fn eval(&mut self, expr: &Expr) -> Result<(Type, Option<Value>), Vec<Hazard>> {
+        let mut typed = typeck::check_expr(self, expr)?;
 
-        if let AstKind::Cast = expr.kind {
-            let cast_type = expr[0].data.clone();
-            let rhs = self.get_expr_type(&expr[1]);
+        let mut hazards = Vec::new();
+        let value = fold::expr(self, &mut typed, &mut hazards);
+        hazards.into_iter().for_each(|h| self.report(h));
 
-            match rhs {
-                Ok(_) => return Ok(cast_type),
-                Err(e) => return Err(e),
-            }
-        }
+        Ok((typed.ty, value))
+    }
 
-        // TODO if Expr has no children then we return what?
-        let lhs = self.get_expr_type(&expr[0]).map_err(|e| errs.extend(e));
-        let rhs = self.get_expr_type(&expr[1]).map_err(|e| errs.extend(e));
-        if !errs.is_empty() {
-            return Err(errs);
-        }
-        let lhs = lhs.unwrap();
-        let rhs = rhs.unwrap();
-        // assert_eq!(AstKind::Expr, expr.kind);
-        let op = expr.data.as_str();
-        println!("Op: {}, Kind: {}", op, expr.kind);
-        match expr.kind {
-            AstKind::Plus => {
-                if lhs == rhs && (lhs == "float" || lhs == "int") {
-                    Ok(lhs)
-                } else if (lhs == "float" || lhs == "int") && (rhs == "float" || rhs == "int") {
-                    Ok("float".to_string())
-                } else {
-                    self.errored = true;
-                    Err(vec![Hazard::new_one_loc(
-                        HazardType::ErrorT(ErrorId::Expr),
-                        expr.span.0,
-                        expr.span.1,
-                    )])
+    /// The condition of an `if` or `while` has to be a `bool`.
+    fn predicate(&mut self, predicate: &Expr) {
+        match self.get_expr_type(predicate) {
+            Err(e) => e.into_iter().for_each(|h| self.report(h)),
+            Ok(p) => {
+                if p != Type::Bool {
+                    self.report(typeck::not_bool(p, predicate.span()));
                 }
             }
-            AstKind::Bools | AstKind::Eq => {
-                let op = expr.data.as_str();
-                if is_numeric(&lhs) && is_numeric(&rhs) {
-                    Ok("bool".to_string())
-                } else if lhs == rhs
-                    // || (lhs.contains("bool") && rhs.contains("int")
-                        // || rhs.contains("bool") && lhs.contains("int"))
-                        && (op == "==" || op == "!=")
+        }
+    }
+
+    fn handle_comma(&mut self, decl: &Decl, var: &Declarator) -> Result<(), Vec<Hazard>> {
+        let ty = QualType::new(decl.ty, decl.constant);
+
+        match var {
+            // It is a single identifier
+            Declarator::Ident(ident) => {
+                if let Some(h) =
+                    self.table
+                        .push_symbol(self.scope, ty, ident.name.clone(), ident.span)
                 {
-                    Ok("bool".to_string())
-                } else {
-                    self.errored = true;
-                    Err(vec![Hazard::new_one_loc(
-                        HazardType::ErrorT(ErrorId::Expr),
-                        expr.span.0,
-                        expr.span.1,
-                    )])
+                    self.report(h);
                 }
             }
-            AstKind::Times => {
-                if op == "%" {
-                    if lhs == rhs && lhs == "int" {
-                        Ok(lhs)
-                    } else {
-                        self.errored = true;
-                        Err(vec![Hazard::new_one_loc(
-                            HazardType::ErrorT(ErrorId::Expr),
-                            expr.span.0,
-                            expr.span.1,
-                        )])
+            Declarator::Init(assign) => {
+                let mut errors = Vec::new();
+                let (expr_ty, value) = match self.eval(&assign.value) {
+                    Ok((ty, value)) => (Ok(ty), value),
+                    Err(e) => (Err(e), None),
+                };
+                for target in &assign.targets {
+                    let ident = &target.ident;
+
+                    if let Some(h) = self.table.push_symbol_init(
+                        self.scope,
+                        ty,
+                        ident.name.clone(),
+                        ident.span,
+                        value.clone(),
+                    ) {
+                        self.report(h);
                     }
-                } else if lhs == rhs && (lhs == "float" || lhs == "int") {
-                    Ok(lhs)
-                } else if (lhs == "float" || lhs == "int") && (rhs == "float" || rhs == "int") {
-                    Ok("float".to_string())
-                } else {
-                    self.errored = true;
-                    Err(vec![Hazard::new_one_loc(
-                        HazardType::ErrorT(ErrorId::Expr),
-                        expr.span.0,
-                        expr.span.1,
-                    )])
+
+                    if let Ok(expr_ty) = expr_ty {
+                        if !types::can_convert(ty.ty, expr_ty) {
+                            let h = typeck::conversion(&ident.name, ty.ty, expr_ty, target.span);
+                            errors.push(h);
+                        }
+                    }
+                }
+                if let Err(e) = expr_ty {
+                    errors.extend(e);
+                }
+
+                if !errors.is_empty() {
+                    return Err(errors);
                 }
             }
-            e => panic!(format!(
-                "Bad astkind where there should be expr: {:?}",
-                expr
-            )),
         }
-    }
 
-    // fn compare_result(lhs: &str, rhs: &str) -> String {
-
-    //     if lhs == "float" && rhs == "int" {
-    //         return "float".to_string()
-    //     }
-
-    // }
+        Ok(())
+    }
+}
 
-    fn handle_emit(&mut self, emit: &AstNode) {
-        // This is a specific identifier:
-        if emit.children.len() == 3 {
-            for child in &emit.children {
-                if let Err(e) = self.get_expr_type(child) {
-                    e.iter().for_each(|h| println!("{}", h.show_output()));
-                }
-            }
-        // let ident = &emit[0];
+/// Variables are looked up in the scopes visible from the current block.
+/// Reading one marks it used and warns if it is not initialized on every
+/// path to the read. The `VarId` of a symbol is its index in the table,
+/// which holds while `eval` types and folds an expression.
+impl<S: DiagnosticSink> Scope for SymbolVisitor<S> {
+    fn lookup(&mut self, ident: &Ident) -> Option<(VarId, QualType)> {
+        let (id, symbol) = self
+            .table
+            .symbols
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, s)| s.scope <= self.scope && s.ident == ident.name)?;
 
-        // let symbol = self.table.get_symbol(&ident.data, self.scope);
+        symbol.used.set(true);
+        let (ty, declared) = (symbol.ty, symbol.span);
 
-        // if let Some(symbol) = symbol {
-        //     symbol.used.set(true);
-        // } else {
-        //     // ERROR: NOVAR
-        // }
-        } else if emit.children.len() == 1 {
-            self.table.write_to_file(&self.output_path);
+        let warn = match self.reads.state(ident.span) {
+            State::Unassigned => Some((
+                WarnId::Uninit,
+                "used before initialization",
+                "declared here without a value",
+            )),
+            State::Maybe => Some((
+                WarnId::MaybeUninit,
+                "may be used before initialization",
+                "declared here",
+            )),
+            State::Assigned => None,
+        };
+        if let Some((id, description, label)) = warn {
+            let warn = Hazard::at(HazardType::Warn(id), ident.span)
+                .with_description(format!("variable `{}` {}", ident.name, description))
+                .with_label(declared, label);
+            self.report(warn);
         }
-    }
 
-    fn stmt(&mut self, stmt: &AstNode) {
-        // println!("Stmt: {}", stmt.kind.to_string());
+        Some((VarId(id), ty))
+    }
+}
 
-        assert_eq!(AstKind::Statement, stmt.kind);
+/// Only the values of `const` variables are known.
+impl<S: DiagnosticSink> Consts for SymbolVisitor<S> {
+    fn value(&self, var: VarId) -> Option<Value> {
+        let symbol = &self.table.symbols[var.0];
+        symbol.value.clone().filter(|_| symbol.ty.constant)
+    }
+}
 
-        match stmt[0].kind {
-            AstKind::DecList => {
-                self.decl_list(&stmt[0]);
-            }
-            AstKind::Assign | AstKind::Eq => {
-                self.assign_stmt(&stmt[0]);
-            }
-            AstKind::Emit => {
-                self.handle_emit(&stmt[0]);
-            }
-            AstKind::If => {
-                self.if_stmt_stmt(&stmt[0]);
-            }
-            AstKind::IfElse => {
-                self.if_else_stmt(&stmt[0]);
-            }
-            AstKind::While => {
-                self.while_stmt(&stmt[0]);
+/// Only statements are visited, expressions are typed by `get_expr_type`.
+impl<S: DiagnosticSink> Visitor for SymbolVisitor<S> {
+    fn visit_emit(&mut self, emit: &Emit) {
+        match emit {
+            Emit::Value {
+                ident,
+                first,
+                second,
+            } => {
+                let var = Expr::Var(ident.clone());
+                for expr in [&var, first, second].iter() {
+                    if let Err(e) = self.get_expr_type(expr) {
+                        e.into_iter().for_each(|h| self.report(h));
+                    }
+                }
             }
-            AstKind::BraceStmt => {
-                self.brace_stmt(&stmt[0]);
+            Emit::Symtable { .. } => {
+                if let Err(e) = self.table.write_to_file(&self.output_path) {
+                    self.io_error.get_or_insert(e);
+                }
             }
-            s => panic!(format!(
-                "Unsupported Stmt Child: {}: {:?}",
-                s.to_string(),
-                stmt[0]
-            )),
         }
     }
 
     // Pushing and popping scopes and stuff:
-    fn brace_stmt(&mut self, brace: &AstNode) {
-        self.scope += 1;
-
-        for child in &brace.children {
-            self.stmt(child); // call stmt on all of the brace child children
-        }
-
-        self.report_unused(self.scope);
-        self.table.clean_table(self.scope);
-        self.scope -= 1;
-        // TODO: Clean table of all symbols with scope = self.scope + 1
+    fn visit_block(&mut self, block: &Block) {
+        self.scoped(|v| visitor::walk_block(v, block));
     }
 
-    fn assign_stmt(&mut self, assign: &AstNode) {
-        let rhs_type = self.get_expr_type(&assign.children.last().unwrap()).clone();
+    fn visit_assign(&mut self, assign: &Assign) {
+        let rhs_type = self.get_expr_type(&assign.value);
         // Get the identifier and its type
 
-        for child in 0..assign.children.len() - 1 {
-            let equal = &assign[child];
-            let ident = &assign[child][0].data;
-            let symbol = self.table.get_symbol(ident, self.scope);
+        for (i, target) in assign.targets.iter().enumerate() {
+            let ident = &target.ident;
+            let symbol = self.table.get_symbol(&ident.name, self.scope);
 
             match symbol {
                 Some(symbol) => {
-                    let lhs_ty = &symbol.ty;
-
-                    if symbol.const_ {
-                        let h = Hazard::new_one_loc(
-                            HazardType::Warn(WarnId::Const),
-                            assign[child][0].span.0,
-                            assign[child][0].span.1,
-                        );
+                    let (lhs_ty, declared) = (symbol.ty, symbol.span);
 
-                        println!("{}", h.show_output());
+                    // In `x = y = 1` the value of `y` is used
+                    if i > 0 {
+                        symbol.used.set(true);
                     }
 
-                    if child > 0 {
-                        symbol.used.set(true);
+                    if lhs_ty.constant {
+                        let h = Hazard::at(HazardType::Warn(WarnId::Const), ident.span)
+                            .with_description(format!(
+                                "`{}` is const, the store to it is ignored",
+                                ident.name
+                            ))
+                            .with_label(declared, "declared const here");
+
+                        self.report(h);
                     }
 
                     if let Ok(ref rhs_ty) = rhs_type {
-                        if !is_valid_conversion(&lhs_ty, rhs_ty) {
-                            let h = Hazard::new_one_loc(
-                                HazardType::ErrorT(ErrorId::Conversion),
-                                assign[child].span.0,
-                                assign[child].span.1,
-                            );
-
-                            symbol.initialized.set(true);
-
-                            println!("{}", h.show_output());
-                            self.errored = true;
-                        } else {
-                            if !symbol.const_ {
-                                symbol.initialized.set(true);
-                            }
+                        if !types::can_convert(lhs_ty.ty, *rhs_ty) {
+                            let h =
+                                typeck::conversion(&ident.name, lhs_ty.ty, *rhs_ty, target.span);
+
+                            self.report(h);
                         }
                     }
                 }
                 None => {
-                    let h = Hazard::new_one_loc(
-                        HazardType::ErrorT(ErrorId::NoVar),
-                        assign[child][0].span.0,
-                        assign[child][0].span.1,
-                    );
-
-                    self.errored = true;
-                    println!("{}", h.show_output());
+                    self.report(typeck::no_var(ident));
                 }
             }
         }
 
-        if let Err(ref e) = rhs_type {
-            e.iter().for_each(|h| println!("{}", h.show_output()));
+        if let Err(e) = rhs_type {
+            e.into_iter().for_each(|h| self.report(h));
         }
     }
 
-    fn if_stmt_stmt(&mut self, if_: &AstNode) {
-        let predicate = &if_[0];
-
-        let predicate_type = self.get_expr_type(predicate);
-
-        if let Err(ref e) = predicate_type {}
-
-        match predicate_type {
-            Err(e) => {
-                self.errored = true;
-                e.iter().for_each(|h| println!("{}", h.show_output()));
-            }
-            Ok(p) => {
-                if !p.contains("bool") {
-                    let h = Hazard::new_one_loc(
-                        HazardType::ErrorT(ErrorId::Conversion),
-                        predicate.span.0,
-                        predicate.span.1,
-                    );
-
-                    println!("{}", h.show_output());
-                    self.errored = true;
-                }
-            }
-        }
-
-        if if_[1][0].kind == AstKind::BraceStmt {
-            let brace_stmt = &if_[1][0];
-            self.brace_stmt(brace_stmt);
-        } else {
-            let stmt = &if_[1];
-            self.stmt(stmt);
-        }
+    fn visit_if(&mut self, if_: &If) {
+        self.predicate(&if_.cond);
+        self.visit_body(&if_.then);
     }
 
-    fn if_else_stmt(&mut self, if_else: &AstNode) {
-        assert_eq!(if_else.children.len(), 3); // first is predicate, second is true, third is false
-        let predicate = &if_else[0];
-
-        let predicate_type = self.get_expr_type(predicate);
-
-        if let Err(ref e) = predicate_type {}
-
-        match predicate_type {
-            Err(e) => {
-                self.errored = true;
-                e.iter().for_each(|h| println!("{}", h.show_output()));
-            }
-            Ok(p) => {
-                if !p.contains("bool") {
-                    let h = Hazard::new_one_loc(
-                        HazardType::ErrorT(ErrorId::Conversion),
-                        predicate.span.0,
-                        predicate.span.1,
-                    );
-
-                    println!("{}", h.show_output());
-                    self.errored = true;
-                }
-            }
-        }
-
-        if if_else[1].kind == AstKind::BraceStmt {
-            let brace_stmt = &if_else[1];
-            self.brace_stmt(brace_stmt);
-        } else {
-            let stmt = &if_else[1];
-            self.stmt(stmt);
-        }
-        // probably have something like above but for now I am going to assume #2 is something like
-        // Statement -> BraceStmt -> Statement
-        if if_else[2][0].kind == AstKind::BraceStmt {
-            let false_brace = &if_else[2][0];
-            self.brace_stmt(&false_brace);
-        } else if if_else[2][0].kind == AstKind::IfElse {
-            self.if_else_stmt(&if_else[2][0]);
-        }
-
-        // let true_brace_stmt = &if_else[1][0];
-        // let false_brace_stmt = &if_else[2][0];
-
-        // self.brace_stmt(false_brace_stmt);
+    fn visit_if_else(&mut self, if_else: &IfElse) {
+        self.predicate(&if_else.cond);
+        self.visit_block(&if_else.then);
+        self.visit_body(&if_else.otherwise);
     }
 
-    fn while_stmt(&mut self, while_: &AstNode) {
-        // println!("While: {}", while_.kind.to_string());
-
-        // println!("{:#?}", self.table);
-
-        let predicate = &while_[0];
-
-        let predicate_type = self.get_expr_type(predicate);
-
-        if let Err(ref e) = predicate_type {}
-
-        match predicate_type {
-            Err(e) => {
-                self.errored = true;
-                e.iter().for_each(|h| println!("{}", h.show_output()));
-            }
-            Ok(p) => {
-                if !p.contains("bool") {
-                    let h = Hazard::new_one_loc(
-                        HazardType::ErrorT(ErrorId::Conversion),
-                        predicate.span.0,
-                        predicate.span.1,
-                    );
-
-                    println!("{}", h.show_output());
-                    self.errored = true;
-                }
-            }
-        }
-
-        let brace_stmt = &while_[1][0];
-
-        self.brace_stmt(brace_stmt);
+    fn visit_while(&mut self, while_: &While) {
+        self.predicate(&while_.cond);
+        self.visit_body(&while_.body);
     }
 
-    fn decl_list(&mut self, stmt: &AstNode) {
-        // println!("DeclList: {}", stmt.kind.to_string());
-        // At this point the lhs child is a type and
-        // some identifier
-        // The rhs is either an id or ASSIGN
-        // Get the type of the LHS, check for CONV with lhs == rhs
-
-        let lhs = &stmt.children[0];
-
-        // We have nice flattened tree:
-        // let comma_list = &stmt[1].children;
-
-        let ty = lhs;
-
-        for decl_list_child in stmt.children.iter().skip(1) {
-            for comma in &decl_list_child.children {
-                // println!("Comma: {:?}", comma);
-                if let Err(h) = self.handle_comma(ty, comma) {
-                    h.iter().for_each(|h| println!("{}", h.show_output()));
-                }
-                // println!("{}\n", self.table.output());
+    fn visit_decl(&mut self, decl: &Decl) {
+        for var in &decl.vars {
+            if let Err(h) = self.handle_comma(decl, var) {
+                h.into_iter().for_each(|h| self.report(h));
             }
         }
     }
-    fn handle_comma(&mut self, ty: &AstNode, comma: &AstNode) -> Result<(), Vec<Hazard>> {
-        // println!("DeclId: {}", comma.kind.to_string());
-        // println!("{:?}", comma);
-        // Either an identifier or an assign list
-        // let child = &comma[0];        // Either an identifier or an assign list
-        // let child = &comma[0];
-
-        // println!("{}\n", self.table.output());
-
-        let (is_const, string_ty) = get_decl_type(ty);
-
-        // There is a single identifier
-        match comma.children.as_slice() {
-            // It is a single identifier
-            [] => self.table.push_symbol(
-                self.scope,
-                string_ty,
-                comma.data.clone(),
-                comma.span,
-                is_const,
-            ),
-            // [ident] => self.table.push_symbol(
-            //     self.scope,
-            //     string_ty,
-            //     ident.data.clone(),
-            //     ident.span,
-            //     is_const,
-            // ),
-            [equals @ .., expr] => {
-                let mut errors = Vec::new();
-                let expr_ty = self.get_expr_type(expr);
-                for equal in equals {
-                    let ident = &equal[0];
-
-                    self.table.push_symbol_init(
-                        self.scope,
-                        string_ty.clone(),
-                        ident.data.clone(),
-                        ident.span,
-                        is_const,
-                    );
-
-                    if let Ok(ref expr_ty) = expr_ty {
-                        if !is_valid_conversion(&string_ty, &expr_ty) {
-                            // println!("NOT VALID: {} <- {}", string_ty, expr_ty);
-                            let h = Hazard::new_one_loc(
-                                HazardType::ErrorT(ErrorId::Conversion),
-                                equal.span.0, // TODO have to point this to assign node? Liam needs to change assignment node to keep span
-                                equal.span.1,
-                            );
-                            self.errored = true;
-                            errors.push(h);
-                        }
-                    }
-
-                    // TODO: Determine expr type and check it
-                    // with string_ty
-                }
-                if let Err(e) = expr_ty {
-                    errors.extend(e.into_iter());
-                }
-
-                if !errors.is_empty() {
-                    return Err(errors);
-                }
-            }
-            [] => panic!("There must be at least one child"),
-        }
+}
 
-        // println!("After Comma: {:?}", self.table);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
 
-        Ok(())
+    /// A table path of its own for every test and test run.
+    fn table_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("zobos-{}-{}.sym", name, std::process::id()))
     }
-}
 
-pub fn is_numeric(ty: &str) -> bool {
-    ty == "float" || ty == "int"
-}
+    #[test]
+    fn symbol_table_output() {
+        let s1 = Symbol::new(
+            0,
+            QualType::new(Type::Int, false),
+            "x".to_string(),
+            Span::point(1, 5),
+        );
+        let mut s2 = Symbol::new(
+            1,
+            QualType::new(Type::Float, true),
+            "pi".to_string(),
+            Span::point(2, 7),
+        );
+        s2.value = Some(Value::Float(3.0));
+
+        assert_eq!(s1.output(), "0,int,x");
+        let table = SymbolTable {
+            symbols: vec![s1, s2],
+            valid_scopes: vec![],
+        };
+        assert_eq!(table.output(), "0,int,x\n1,constfloat,pi,3.0\n");
+    }
 
-pub fn get_decl_type(ty: &AstNode) -> (bool, String) {
-    let is_const = ty.data.contains("const");
-    let string_ty = ty.data.split(' ').rev().next().unwrap().clone().to_string();
+    #[test]
+    fn hazards_in_order() {
+        let src = "int x;\nint x;\nemit y 1 2;\nx = 1 / 0;\n{ int z; }\n";
+        let tree = Parser::new().parse_str(src).unwrap();
+
+        let path = table_path("hazards");
+        let mut sv = SymbolVisitor::new(path.clone());
+        sv.program(&Program::from_cst(&tree).unwrap()).unwrap();
+        assert!(sv.sink().has_errors());
+        // Only `emit symtable` writes the table
+        assert!(!path.exists());
+
+        let output: Vec<_> = sv.into_hazards().iter().map(Hazard::show_output).collect();
+        assert_eq!(
+            output,
+            vec![
+                "OUTPUT :WARN: 2 5 :REVAR:",
+                "OUTPUT :ERROR: 3 6 :NOVAR:",
+                "OUTPUT :WARN: 4 7 :DIVZERO:",
+                "OUTPUT :WARN: 5 7 :UNUSED:",
+                "OUTPUT :WARN: 1 5 :UNUSED:",
+            ]
+        );
+    }
 
-    (is_const, string_ty)
-}
+    #[test]
+    fn unbraced_declarations_are_scoped() {
+        let src = "int a = 0;\nif (a > 7) int z = 1;\nemit z 0 0;\n";
+        let tree = Parser::new().parse_str(src).unwrap();
+
+        let path = table_path("scoped");
+        let mut sv = SymbolVisitor::new(path.clone());
+        sv.program(&Program::from_cst(&tree).unwrap()).unwrap();
+        assert!(sv.sink().has_errors());
+        assert!(!path.exists());
+
+        let output: Vec<_> = sv.into_hazards().iter().map(Hazard::show_output).collect();
+        assert_eq!(
+            output,
+            vec!["OUTPUT :WARN: 2 16 :UNUSED:", "OUTPUT :ERROR: 3 6 :NOVAR:"]
+        );
+    }
 
-pub fn is_valid_conversion(var_type: &str, val_type: &str) -> bool {
-    match val_type {
-        "string" => match var_type {
-            "int" => false,
-            "float" => false,
-            "bool" => false,
-            _ => true,
-        },
-        "float" => match var_type {
-            "int" => false,
-            "bool" => false,
-            "string" => false,
-            _ => true,
-        },
-        "bool" => match var_type {
-            "float" => false,
-            "string" => false,
-            _ => true,
-        },
-        "int" => match var_type {
-            // Check table with keith
-            "bool" => false,
-            "string" => false,
-            _ => true,
-        },
-        _ => panic!("unknown lhs passed into valid conversion"),
+    #[test]
+    fn folded_consts() {
+        let path = table_path("folded");
+        let src = "const int c = 3 * 4 + 1;\nconst float f = c / 2;\nconst bool b = c > 2;\n\
+                   int x = c;\nconst int d = x;\nemit symtable;\n";
+        let tree = Parser::new().parse_str(src).unwrap();
+
+        let mut sv = SymbolVisitor::new(path.clone());
+        sv.program(&Program::from_cst(&tree).unwrap()).unwrap();
+
+        let table = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            table,
+            "0,constint,c,13\n0,constfloat,f,6.0\n0,constbool,b,true\n0,int,x\n0,constint,d\n"
+        );
     }
 }
-
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-
-//     #[test]
-//     fn symbol_table_output() {
-//         let s1 = Symbol::new(0, String::from("test_type"), String::from("test_ident"));
-//         let s2 = Symbol::new(
-//             0,
-//             String::from("test_type_var_2"),
-//             String::from("test_ident_var_2"),
-//         );
-//         assert_eq!(s1.output(), "0,test_type,test_ident");
-//         let syms = vec![s1, s2];
-//         let table = SymbolTable { symbols: syms };
-//         let output = table.output();
-//         assert_eq!(
-//             output,
-//             r#"0,test_type,test_ident
-// 0,test_type_var_2,test_ident_var_2
-// "#
-//         );
-//     }
-// }
@@ -0,0 +1,728 @@
+//! The typed abstract syntax tree of a zlang program, built from the
+//! concrete parse tree the parser returns. Parentheses, separators and the
+//! left recursive list rules are gone, and every construct has its own
+//! variant, so analyses never index children by position.
+
+use std::fmt;
+
+use crate::ast::{AstKind, AstNode};
+use crate::error::{Error, Result};
+use crate::hazards::{Hazard, HazardType};
+pub use crate::types::Type;
+
+pub use crate::span::Span;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub stmts: Vec<Stmt>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    Decl(Decl),
+    Assign(Assign),
+    If(If),
+    IfElse(IfElse),
+    While(While),
+    Emit(Emit),
+    Block(Block),
+}
+
+/// `const int x, y = 2;`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decl {
+    pub ty: Type,
+    pub constant: bool,
+    /// The type keyword
+    pub span: Span,
+    pub vars: Vec<Declarator>,
+}
+
+/// One comma separated entry of a declaration.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Declarator {
+    /// `int x;`
+    Ident(Ident),
+    /// `int x = 1;` Every target of a chained `int x = y = 1;` is declared.
+    Init(Assign),
+}
+
+impl Declarator {
+    /// The identifiers this declares.
+    pub fn idents(&self) -> Vec<&Ident> {
+        match self {
+            Declarator::Ident(ident) => vec![ident],
+            Declarator::Init(assign) => assign.targets.iter().map(|t| &t.ident).collect(),
+        }
+    }
+}
+
+/// `x = y = value`, with the targets in source order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assign {
+    pub targets: Vec<Target>,
+    pub value: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub ident: Ident,
+    /// The `=` after the identifier
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct If {
+    pub cond: Expr,
+    pub then: Box<Stmt>,
+}
+
+/// The grammar only allows a block before `else`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IfElse {
+    pub cond: Expr,
+    pub then: Block,
+    pub otherwise: Box<Stmt>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct While {
+    pub cond: Expr,
+    pub body: Box<Stmt>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Emit {
+    /// `emit id AEXPR AEXPR`
+    Value {
+        ident: Ident,
+        first: Expr,
+        second: Expr,
+    },
+    /// `emit symtable`
+    Symtable { span: Span },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub stmts: Vec<Stmt>,
+    /// The opening brace
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Int(i64),
+    Float(f64),
+    /// The string without its quotes, escapes resolved.
+    String(String),
+}
+
+impl Literal {
+    pub fn ty(&self) -> Type {
+        match self {
+            Literal::Int(_) => Type::Int,
+            Literal::Float(_) => Type::Float,
+            Literal::String(_) => Type::String,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum UnOp {
+    /// `+x`
+    Plus,
+    /// `-x`
+    Neg,
+    /// `!x`
+    Not,
+    /// `~x`
+    Compl,
+}
+
+impl UnOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            UnOp::Plus => "+",
+            UnOp::Neg => "-",
+            UnOp::Not => "!",
+            UnOp::Compl => "~",
+        }
+    }
+}
+
+impl fmt::Display for UnOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.symbol())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Lt,
+    Leq,
+    Eq,
+    Geq,
+    Gt,
+}
+
+impl BinOp {
+    pub fn from_symbol(symbol: &str) -> Option<Self> {
+        Some(match symbol {
+            "+" => BinOp::Add,
+            "-" => BinOp::Sub,
+            "*" => BinOp::Mul,
+            "/" => BinOp::Div,
+            "%" => BinOp::Mod,
+            "<" => BinOp::Lt,
+            "<=" => BinOp::Leq,
+            "==" => BinOp::Eq,
+            ">=" => BinOp::Geq,
+            ">" => BinOp::Gt,
+            _ => return None,
+        })
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Mod => "%",
+            BinOp::Lt => "<",
+            BinOp::Leq => "<=",
+            BinOp::Eq => "==",
+            BinOp::Geq => ">=",
+            BinOp::Gt => ">",
+        }
+    }
+
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            BinOp::Lt | BinOp::Leq | BinOp::Eq | BinOp::Geq | BinOp::Gt
+        )
+    }
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.symbol())
+    }
+}
+
+/// Operators carry the span of their operator token, casts the span of the
+/// type keyword.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal {
+        value: Literal,
+        span: Span,
+    },
+    Var(Ident),
+    Unary {
+        op: UnOp,
+        operand: Box<Expr>,
+        span: Span,
+    },
+    Binary {
+        op: BinOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+        span: Span,
+    },
+    Cast {
+        to: Type,
+        expr: Box<Expr>,
+        span: Span,
+    },
+}
+
+impl Expr {
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Literal { span, .. }
+            | Expr::Unary { span, .. }
+            | Expr::Binary { span, .. }
+            | Expr::Cast { span, .. } => *span,
+            Expr::Var(ident) => ident.span,
+        }
+    }
+}
+
+impl Program {
+    /// Builds the AST from a zlang parse tree, as returned by
+    /// `Parser::parse`. Fails with a syntax hazard for every number literal
+    /// that has no value, and panics if the tree does not come from the
+    /// zlang grammar.
+    pub fn from_cst(tree: &AstNode) -> Result<Self> {
+        assert_eq!(AstKind::Program, tree.kind);
+
+        let hazards = literal_errors(tree);
+        if !hazards.is_empty() {
+            return Err(Error::Syntax(hazards));
+        }
+
+        Ok(Self {
+            stmts: stmts(&tree[0]),
+        })
+    }
+}
+
+fn stmts(node: &AstNode) -> Vec<Stmt> {
+    assert_eq!(AstKind::Stmts, node.kind);
+
+    // STMTS -> STMTS STATEMENT | lambda, flattened without recursion
+    let mut list = Vec::new();
+    let mut node = node;
+    while let [rest, statement] = node.children.as_slice() {
+        list.push(stmt(statement));
+        node = rest;
+    }
+
+    list.reverse();
+    list
+}
+
+fn stmt(node: &AstNode) -> Stmt {
+    assert_eq!(AstKind::Statement, node.kind);
+    let child = &node[0];
+
+    match child.kind {
+        AstKind::BraceStmt => Stmt::Block(block(child)),
+        AstKind::DecList => Stmt::Decl(decl(child)),
+        AstKind::Assign => Stmt::Assign(assign(child)),
+        AstKind::If => Stmt::If(If {
+            cond: expr(&child[2]),
+            then: Box::new(stmt(&child[4])),
+        }),
+        AstKind::IfElse => Stmt::IfElse(IfElse {
+            cond: expr(&child[2]),
+            then: block(&child[4]),
+            otherwise: Box::new(stmt(&child[6])),
+        }),
+        AstKind::While => Stmt::While(While {
+            cond: expr(&child[2]),
+            body: Box::new(stmt(&child[4])),
+        }),
+        AstKind::Emit => Stmt::Emit(emit(child)),
+        k => panic!("Unsupported Stmt Child: {}", k),
+    }
+}
+
+fn block(node: &AstNode) -> Block {
+    assert_eq!(AstKind::BraceStmt, node.kind);
+
+    Block {
+        stmts: stmts(&node[1]),
+        span: node[0].span,
+    }
+}
+
+fn decl(node: &AstNode) -> Decl {
+    // DECLLIST -> DECLTYPE DECLIDS
+    let (constant, ty) = match node[0].children.as_slice() {
+        [ty] => (false, ty),
+        [_const, ty] => (true, ty),
+        _ => panic!("Bad DeclType"),
+    };
+
+    // DECLIDS -> DECLIDS comma DECLID | DECLID
+    let mut vars = Vec::new();
+    let mut ids = &node[1];
+    loop {
+        let (rest, decl_id) = match ids.children.as_slice() {
+            [decl_id] => (None, decl_id),
+            [rest, _comma, decl_id] => (Some(rest), decl_id),
+            _ => panic!("Bad DeclIds"),
+        };
+
+        let child = &decl_id[0];
+        vars.push(match child.kind {
+            AstKind::Assign => Declarator::Init(assign(child)),
+            _ => Declarator::Ident(ident(child)),
+        });
+
+        match rest {
+            Some(rest) => ids = rest,
+            None => break,
+        }
+    }
+    vars.reverse();
+
+    Decl {
+        ty: type_name(&ty.data),
+        constant,
+        span: node[0][0].span,
+        vars,
+    }
+}
+
+fn assign(node: &AstNode) -> Assign {
+    // ASSIGN -> id assign ASSIGN | id assign EXPR
+    let mut targets = Vec::new();
+    let mut node = node;
+    loop {
+        targets.push(Target {
+            ident: ident(&node[0]),
+            span: node[1].span,
+        });
+
+        if node[2].kind == AstKind::Assign {
+            node = &node[2];
+        } else {
+            return Assign {
+                targets,
+                value: expr(&node[2]),
+            };
+        }
+    }
+}
+
+fn emit(node: &AstNode) -> Emit {
+    match node.children.as_slice() {
+        [_emit, id, first, second] => Emit::Value {
+            ident: ident(id),
+            first: expr(first),
+            second: expr(second),
+        },
+        [emit, _symtable] => Emit::Symtable { span: emit.span },
+        _ => panic!("Bad Emit"),
+    }
+}
+
+fn ident(node: &AstNode) -> Ident {
+    assert_eq!(AstKind::Identifier, node.kind);
+
+    Ident {
+        name: node.data.clone(),
+        span: node.span,
+    }
+}
+
+fn type_name(name: &str) -> Type {
+    Type::from_name(name).unwrap_or_else(|| panic!("Unknown type: {}", name))
+}
+
+fn binary(op: &AstNode, lhs: &AstNode, rhs: &AstNode) -> Expr {
+    Expr::Binary {
+        op: BinOp::from_symbol(&op.data).unwrap_or_else(|| panic!("Unknown operator: {}", op.data)),
+        lhs: Box::new(expr(lhs)),
+        rhs: Box::new(expr(rhs)),
+        span: op.span,
+    }
+}
+
+fn expr(node: &AstNode) -> Expr {
+    match (node.kind, node.children.as_slice()) {
+        // EXPR -> AEXPR | BEXPR, AEXPR -> SUM, SUM -> PRODUCT, PRODUCT -> VALUE
+        (AstKind::Expr, [child])
+        | (AstKind::ArithmeticExpr, [child])
+        | (AstKind::Sum, [child])
+        | (AstKind::Product, [child]) => expr(child),
+        // BEXPR -> AEXPR BOOLS AEXPR
+        (AstKind::BooleanExpr, [lhs, op, rhs]) => binary(&op[0], lhs, rhs),
+        // SUM -> SUM PLUS PRODUCT, PRODUCT -> PRODUCT TIMES VALUE
+        (AstKind::Sum, [lhs, op, rhs]) | (AstKind::Product, [lhs, op, rhs]) => {
+            binary(&op[0], lhs, rhs)
+        }
+        (AstKind::Value, [child]) => value(child),
+        // VALUE -> lparen AEXPR rparen | lparen BEXPR rparen
+        (AstKind::Value, [_lparen, inner, _rparen]) => expr(inner),
+        (k, _) => panic!("Bad Expr: {}", k),
+    }
+}
+
+fn value(node: &AstNode) -> Expr {
+    let literal = |value| Expr::Literal {
+        value,
+        span: node.span,
+    };
+
+    match node.kind {
+        AstKind::Integer | AstKind::Float => {
+            literal(number(node).expect("literals are checked before conversion"))
+        }
+        AstKind::String => literal(Literal::String(unquote(&node.data))),
+        AstKind::Identifier => Expr::Var(ident(node)),
+        AstKind::Unary => {
+            // UNARY -> PLUS VALUE | not VALUE | compl VALUE
+            let op_token = match node[0].kind {
+                AstKind::Plus => &node[0][0],
+                _ => &node[0],
+            };
+            let op = match op_token.data.as_str() {
+                "+" => UnOp::Plus,
+                "-" => UnOp::Neg,
+                "!" => UnOp::Not,
+                "~" => UnOp::Compl,
+                o => panic!("Unknown unary operator: {}", o),
+            };
+
+            Expr::Unary {
+                op,
+                operand: Box::new(expr(&node[1])),
+                span: op_token.span,
+            }
+        }
+        // CAST -> type lparen AEXPR rparen
+        AstKind::Cast => Expr::Cast {
+            to: type_name(&node[0].data),
+            expr: Box::new(expr(&node[2])),
+            span: node[0].span,
+        },
+        k => panic!("Bad Value: {}", k),
+    }
+}
+
+/// The value of an `intval` or `floatval` token, or why it has none.
+fn number(node: &AstNode) -> std::result::Result<Literal, String> {
+    let lexeme = &node.data;
+
+    match node.kind {
+        AstKind::Integer => match lexeme.parse() {
+            Ok(i) => Ok(Literal::Int(i)),
+            Err(_) if !lexeme.is_empty() && lexeme.bytes().all(|b| b.is_ascii_digit()) => Err(
+                format!("integer literal `{}` is too large for an int", lexeme),
+            ),
+            Err(_) => Err(format!("`{}` is not an integer literal", lexeme)),
+        },
+        AstKind::Float => match lexeme.parse::<f64>() {
+            Ok(f) if f.is_finite() => Ok(Literal::Float(f)),
+            Ok(_) => Err(format!(
+                "float literal `{}` is too large for a float",
+                lexeme
+            )),
+            Err(_) => Err(format!("`{}` is not a float literal", lexeme)),
+        },
+        k => unreachable!("{} is not a number", k),
+    }
+}
+
+/// A syntax hazard for every number literal in `tree` without a value, in
+/// source order.
+fn literal_errors(tree: &AstNode) -> Vec<Hazard> {
+    let mut hazards = Vec::new();
+    // The statement list is left recursive, so walk without recursion
+    let mut nodes = vec![tree];
+
+    while let Some(node) = nodes.pop() {
+        if let AstKind::Integer | AstKind::Float = node.kind {
+            if let Err(message) = number(node) {
+                hazards.push(Hazard::at(HazardType::Syntax, node.span).with_description(message));
+            }
+        }
+        nodes.extend(node.children.iter().rev());
+    }
+    hazards
+}
+
+/// Strips the quotes from a `stringval` lexeme and resolves its escapes.
+fn unquote(lexeme: &str) -> String {
+    let inner = lexeme
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(lexeme);
+
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn program(src: &str) -> Program {
+        Program::from_cst(&Parser::new().parse_str(src).unwrap()).unwrap()
+    }
+
+    /// The span of the one character token at `col` of the first line.
+    fn at(col: usize) -> Span {
+        Span::new((1, col), (1, col + 1)).with_bytes(col - 1, col)
+    }
+
+    fn var(name: &str, span: Span) -> Expr {
+        Expr::Var(Ident {
+            name: name.to_string(),
+            span,
+        })
+    }
+
+    #[test]
+    fn precedence_and_parentheses() {
+        let program = program("x = a + b * (c - d);");
+
+        let value = match &program.stmts[0] {
+            Stmt::Assign(assign) => &assign.value,
+            s => panic!("expected an assignment, got {:?}", s),
+        };
+
+        assert_eq!(
+            *value,
+            Expr::Binary {
+                op: BinOp::Add,
+                lhs: Box::new(var("a", at(5))),
+                rhs: Box::new(Expr::Binary {
+                    op: BinOp::Mul,
+                    lhs: Box::new(var("b", at(9))),
+                    rhs: Box::new(Expr::Binary {
+                        op: BinOp::Sub,
+                        lhs: Box::new(var("c", at(14))),
+                        rhs: Box::new(var("d", at(18))),
+                        span: at(16),
+                    }),
+                    span: at(11),
+                }),
+                span: at(7),
+            }
+        );
+    }
+
+    #[test]
+    fn declarations() {
+        let program = program("const float a, b = c = 2.5;\nstring s = \"a \\\"q\\\"\";");
+
+        let decl = match &program.stmts[0] {
+            Stmt::Decl(decl) => decl,
+            s => panic!("expected a declaration, got {:?}", s),
+        };
+        assert_eq!(decl.ty, Type::Float);
+        assert!(decl.constant);
+
+        let names: Vec<Vec<&str>> = decl
+            .vars
+            .iter()
+            .map(|v| v.idents().iter().map(|i| i.name.as_str()).collect())
+            .collect();
+        assert_eq!(names, vec![vec!["a"], vec!["b", "c"]]);
+
+        match &program.stmts[1] {
+            Stmt::Decl(Decl { vars, .. }) => match &vars[0] {
+                Declarator::Init(Assign {
+                    value: Expr::Literal { value, .. },
+                    ..
+                }) => assert_eq!(*value, Literal::String("a \"q\"".to_string())),
+                v => panic!("expected a string initializer, got {:?}", v),
+            },
+            s => panic!("expected a declaration, got {:?}", s),
+        }
+    }
+
+    #[test]
+    fn control_flow_and_unary() {
+        let program = program(
+            "while (x < 3) { if (!b == 1) { emit symtable; } else emit x 1 2; }\nx = int(~2.0);",
+        );
+        assert_eq!(program.stmts.len(), 2);
+
+        let body = match &program.stmts[0] {
+            Stmt::While(While { cond, body }) => {
+                assert!(matches!(cond, Expr::Binary { op: BinOp::Lt, .. }));
+                body
+            }
+            s => panic!("expected a while loop, got {:?}", s),
+        };
+
+        match &**body {
+            Stmt::Block(Block { stmts, .. }) => match &stmts[0] {
+                Stmt::IfElse(IfElse {
+                    cond,
+                    then,
+                    otherwise,
+                }) => {
+                    match cond {
+                        Expr::Binary {
+                            op: BinOp::Eq, lhs, ..
+                        } => assert!(matches!(**lhs, Expr::Unary { op: UnOp::Not, .. })),
+                        c => panic!("expected a comparison, got {:?}", c),
+                    }
+                    assert!(matches!(then.stmts[0], Stmt::Emit(Emit::Symtable { .. })));
+                    assert!(matches!(**otherwise, Stmt::Emit(Emit::Value { .. })));
+                }
+                s => panic!("expected an if/else, got {:?}", s),
+            },
+            s => panic!("expected a block, got {:?}", s),
+        }
+
+        match &program.stmts[1] {
+            Stmt::Assign(Assign {
+                value: Expr::Cast { to, expr, .. },
+                ..
+            }) => {
+                assert_eq!(*to, Type::Int);
+                assert!(matches!(
+                    **expr,
+                    Expr::Unary {
+                        op: UnOp::Compl,
+                        ..
+                    }
+                ));
+            }
+            s => panic!("expected a cast, got {:?}", s),
+        }
+    }
+
+    #[test]
+    fn literals_without_a_value() {
+        let errors = |src: &str| match Program::from_cst(&Parser::new().parse_str(src).unwrap()) {
+            Err(Error::Syntax(hazards)) => hazards
+                .iter()
+                .map(|h| (h.show_output(), h.message().unwrap()))
+                .collect::<Vec<_>>(),
+            r => panic!("expected syntax errors, got {:?}", r),
+        };
+
+        assert_eq!(
+            errors(
+                "int i = 9223372036854775807;\nint j = 9223372036854775808;\nfloat f = 1e999;\n"
+            ),
+            vec![
+                (
+                    "OUTPUT :SYNTAX: 2 9 :SYNTAX:".to_string(),
+                    "integer literal `9223372036854775808` is too large for an int".to_string()
+                ),
+                (
+                    "OUTPUT :SYNTAX: 3 11 :SYNTAX:".to_string(),
+                    "float literal `1e999` is too large for a float".to_string()
+                ),
+            ]
+        );
+        // Token files can hold any lexeme
+        assert_eq!(
+            errors(
+                "float float 1 1\nid f 1 7\nassign x3d 1 9\nfloatval 1x2e2x2e3 1 11\nsc x3b 1 16\n"
+            ),
+            vec![(
+                "OUTPUT :SYNTAX: 1 11 :SYNTAX:".to_string(),
+                "`1.2.3` is not a float literal".to_string()
+            )]
+        );
+    }
+}
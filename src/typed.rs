@@ -0,0 +1,168 @@
+//! The AST after type checking. It has the shape of the one in `syntax`,
+//! but every expression carries its resolved type and every variable the
+//! type it was declared with. Later passes can rely on the program being
+//! well typed: all variables are declared and every operator, conversion
+//! and condition is valid.
+//!
+//! Every use of a variable is resolved to a `VarId`, an index into
+//! `Program::vars`, so later passes never have to look a name up in scopes
+//! of their own. A redeclaration in the same block names the variable it
+//! redeclares, and a shadowing declaration in an inner block a new one.
+
+use crate::syntax::{BinOp, Ident, Literal, Span, UnOp};
+use crate::types::{QualType, Type};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub stmts: Vec<Stmt>,
+    /// Indexed by `VarId`
+    pub vars: Vec<Var>,
+}
+
+impl Program {
+    pub fn var(&self, id: VarId) -> &Var {
+        &self.vars[id.0]
+    }
+}
+
+/// A variable, numbered in the order the type checker declares them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct VarId(pub usize);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Var {
+    pub name: String,
+    /// The type of the first declaration, a redeclaration keeps it
+    pub ty: QualType,
+    /// How many scopes the declaration is nested in, 0 at the top level
+    pub depth: usize,
+    /// The identifier of the first declaration
+    pub span: Span,
+}
+
+impl Var {
+    /// Whether `ident` is the declaration that introduces the variable,
+    /// rather than a redeclaration of it in the same block.
+    pub fn declared_by(&self, ident: &Ident) -> bool {
+        self.span == ident.span
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    Decl(Decl),
+    Assign(Assign),
+    If(If),
+    IfElse(IfElse),
+    While(While),
+    Emit(Emit),
+    Block(Block),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decl {
+    pub ty: QualType,
+    /// The type keyword
+    pub span: Span,
+    pub vars: Vec<Declarator>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Declarator {
+    Ident(Ident, VarId),
+    Init(Assign),
+}
+
+impl Declarator {
+    /// The declared identifiers and the variables they name.
+    pub fn vars(&self) -> Vec<(&Ident, VarId)> {
+        match self {
+            Declarator::Ident(ident, var) => vec![(ident, *var)],
+            Declarator::Init(assign) => assign.targets.iter().map(|t| (&t.ident, t.var)).collect(),
+        }
+    }
+}
+
+/// `x = y = value`, with the targets in source order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assign {
+    pub targets: Vec<Target>,
+    pub value: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub ident: Ident,
+    pub var: VarId,
+    pub ty: QualType,
+    /// The `=` after the identifier
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct If {
+    pub cond: Expr,
+    pub then: Box<Stmt>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IfElse {
+    pub cond: Expr,
+    pub then: Block,
+    pub otherwise: Box<Stmt>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct While {
+    pub cond: Expr,
+    pub body: Box<Stmt>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Emit {
+    /// `emit x a b`, where `x` is read like any other variable
+    Value {
+        var: Box<Expr>,
+        first: Box<Expr>,
+        second: Box<Expr>,
+    },
+    Symtable {
+        span: Span,
+        /// The live variables, the outermost scope first and each scope in
+        /// declaration order
+        vars: Vec<VarId>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub stmts: Vec<Stmt>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub ty: Type,
+    /// The same span as the `syntax::Expr` it was checked from
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Literal(Literal),
+    Var(Ident, VarId),
+    Unary {
+        op: UnOp,
+        operand: Box<Expr>,
+    },
+    /// The operands keep their own types, so `1 + 2.0` is a `float` with an
+    /// `int` on the left.
+    Binary {
+        op: BinOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+    /// The target type is the type of the expression.
+    Cast(Box<Expr>),
+}
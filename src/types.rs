@@ -0,0 +1,168 @@
+//! zlang types and the rules relating them. Which values may be stored in
+//! which variables, and what every operator accepts and returns, is spelled
+//! out in the tables below; the checkers only look things up here.
+
+use std::fmt;
+
+use crate::syntax::{BinOp, UnOp};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Type {
+    Bool,
+    Int,
+    Float,
+    String,
+}
+
+impl Type {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bool" => Some(Type::Bool),
+            "int" => Some(Type::Int),
+            "float" => Some(Type::Float),
+            "string" => Some(Type::String),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Type::Bool => "bool",
+            Type::Int => "int",
+            Type::Float => "float",
+            Type::String => "string",
+        }
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, Type::Int | Type::Float)
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The type of a variable, which may be declared `const`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct QualType {
+    pub ty: Type,
+    pub constant: bool,
+}
+
+impl QualType {
+    pub fn new(ty: Type, constant: bool) -> Self {
+        Self { ty, constant }
+    }
+}
+
+impl fmt::Display for QualType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.constant {
+            write!(f, "const ")?;
+        }
+        write!(f, "{}", self.ty)
+    }
+}
+
+/// `(variable, value)`: a value of the second type may be stored in a
+/// variable of the first.
+const CONVERSIONS: &[(Type, Type)] = &[
+    (Type::Bool, Type::Bool),
+    (Type::Int, Type::Int),
+    (Type::Int, Type::Bool),
+    (Type::Float, Type::Float),
+    (Type::Float, Type::Int),
+    (Type::String, Type::String),
+];
+
+const ARITHMETIC: &[BinOp] = &[BinOp::Add, BinOp::Sub, BinOp::Mul, BinOp::Div];
+const COMPARISON: &[BinOp] = &[BinOp::Lt, BinOp::Leq, BinOp::Eq, BinOp::Geq, BinOp::Gt];
+
+/// `(operators, lhs, rhs, result)`
+const BINARY: &[(&[BinOp], Type, Type, Type)] = &[
+    (ARITHMETIC, Type::Int, Type::Int, Type::Int),
+    (ARITHMETIC, Type::Float, Type::Float, Type::Float),
+    (ARITHMETIC, Type::Int, Type::Float, Type::Float),
+    (ARITHMETIC, Type::Float, Type::Int, Type::Float),
+    (&[BinOp::Mod], Type::Int, Type::Int, Type::Int),
+    (COMPARISON, Type::Int, Type::Int, Type::Bool),
+    (COMPARISON, Type::Float, Type::Float, Type::Bool),
+    (COMPARISON, Type::Int, Type::Float, Type::Bool),
+    (COMPARISON, Type::Float, Type::Int, Type::Bool),
+    (&[BinOp::Eq], Type::Bool, Type::Bool, Type::Bool),
+    (&[BinOp::Eq], Type::String, Type::String, Type::Bool),
+];
+
+/// `(operators, operand, result)`
+const UNARY: &[(&[UnOp], Type, Type)] = &[
+    (&[UnOp::Plus, UnOp::Neg], Type::Int, Type::Int),
+    (&[UnOp::Plus, UnOp::Neg], Type::Float, Type::Float),
+    (&[UnOp::Compl], Type::Int, Type::Bool),
+    (&[UnOp::Not], Type::Bool, Type::Bool),
+];
+
+/// Whether a `value` may be assigned to a variable of type `var`.
+pub fn can_convert(var: Type, value: Type) -> bool {
+    CONVERSIONS.contains(&(var, value))
+}
+
+/// The type of `lhs op rhs`, `None` if the operator does not apply.
+pub fn binary(op: BinOp, lhs: Type, rhs: Type) -> Option<Type> {
+    BINARY
+        .iter()
+        .find(|(ops, l, r, _)| ops.contains(&op) && *l == lhs && *r == rhs)
+        .map(|row| row.3)
+}
+
+/// The type of `op operand`, `None` if the operator does not apply.
+pub fn unary(op: UnOp, operand: Type) -> Option<Type> {
+    UNARY
+        .iter()
+        .find(|(ops, t, _)| ops.contains(&op) && *t == operand)
+        .map(|row| row.2)
+}
+
+/// Casts are explicit, so any type may be cast to any other.
+pub fn cast(to: Type, _from: Type) -> Type {
+    to
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions() {
+        assert!(can_convert(Type::Int, Type::Int));
+        assert!(can_convert(Type::Float, Type::Int));
+        assert!(can_convert(Type::Int, Type::Bool));
+        assert!(!can_convert(Type::Int, Type::Float));
+        assert!(!can_convert(Type::Bool, Type::Int));
+        assert!(!can_convert(Type::String, Type::Bool));
+        assert!(!can_convert(Type::Int, Type::String));
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(binary(BinOp::Add, Type::Int, Type::Int), Some(Type::Int));
+        assert_eq!(
+            binary(BinOp::Div, Type::Int, Type::Float),
+            Some(Type::Float)
+        );
+        assert_eq!(binary(BinOp::Mod, Type::Float, Type::Int), None);
+        assert_eq!(binary(BinOp::Lt, Type::Int, Type::Float), Some(Type::Bool));
+        assert_eq!(
+            binary(BinOp::Eq, Type::String, Type::String),
+            Some(Type::Bool)
+        );
+        assert_eq!(binary(BinOp::Lt, Type::String, Type::String), None);
+        assert_eq!(binary(BinOp::Add, Type::String, Type::String), None);
+
+        assert_eq!(unary(UnOp::Neg, Type::Float), Some(Type::Float));
+        assert_eq!(unary(UnOp::Not, Type::Int), None);
+        assert_eq!(unary(UnOp::Compl, Type::Int), Some(Type::Bool));
+    }
+}
@@ -1,199 +1,643 @@
-// use crate::ast::{AstKind, AstNode};
-
-// pub trait Visitor
-// where
-//     Self: Sized,
-//     Self::Output: Default,
-// {
-//     type Output;
-
-//     fn visit_program(&mut self, program: &AstNode) -> Self::Output {
-//         assert_eq!(AstKind::Program, program.kind);
-
-//         walk_program(self, program)
-//     }
-
-//     fn visit_stmt(&mut self, stmt: &AstNode) -> Self::Output {
-//         assert_eq!(AstKind::Statement, stmt.kind);
-
-//         walk_stmt(self, stmt)
-//     }
-
-//     fn visit_emit(&mut self, emit: &AstNode) -> Self::Output {
-//         // walk_id(emit[0]);
-//         // walk_int(emit[1]);
-//         // walk_int(emit[2]);
-
-//         Self::Output::default()
-//     }
-
-//     fn visit_decl_list(&mut self, decl_list: &AstNode) -> Self::Output {}
-
-//     // fn visit_assign(&mut self, assign: &AstNode) -> Self::Output {}
-//     // fn visit_if(&mut self, if_: &AstNode) -> Self::Output {}
-//     // fn visit_if_else(&mut self, if_else: &AstNode) -> Self::Output {}
-//     // fn visit_while(&mut self, while_: &AstNode) -> Self::Output {}
-//     // fn visit_brace_stmt(&mut self, brace_stmt: &AstNode) -> Self::Output {}
-
-//     // fn visit_stmts(&mut self, stmts: &AstNode) {
-//     //     assert_eq!(AstKind::Stmts, stmts.kind);
-
-//     //     if !stmts.children.is_empty() {
-//     //         self.visit_stmts(&stmts[0]);
-//     //         self.visit_stmt(&stmts[1]);
-//     //     }
-//     // }
-
-//     // fn visit_stmt(&mut self, stmt: &AstNode) {
-//     //     assert_eq!(AstKind::Statement, stmt.kind);
-
-//     //     match stmt[0].kind {
-//     //         AstKind::DecList => {
-//     //             self.visit_decl_list(&stmt[0]);
-//     //         }
-//     //         AstKind::Assign => {}
-//     //         AstKind::Emit => {}
-//     //         AstKind::If => {}
-//     //         AstKind::IfElse => {}
-//     //         AstKind::While => {}
-//     //         AstKind::BraceStmt => {}
-//     //         _ => panic!("Unsupported Stmt Child"),
-//     //     }
-//     // }
-
-//     // // DeclList
-//     // fn visit_decl_list(&mut self, decl_list: &AstNode) {
-//     //     self.visit_decl(&decl_list[0], &decl_list[1]);
-//     // }
-
-//     // // DeclType DeclIds
-//     // fn visit_decl(&mut self, decl_type: &AstNode, decl_ids: &AstNode) {
-//     //     // ...
-//     // }
-
-//     // fn visit_eoi(&mut self, node: &AstNode) {}
-// }
-
-// pub fn walk_program<V: Visitor>(visitor: &mut V, program: &AstNode) -> V::Output {
-//     for i in 0..program.children.len() - 1 {
-//         visitor.visit_stmt(&program[i]);
-//     }
-
-//     visitor.visit_stmt(&program[program.children.len() - 1])
-// }
-
-// pub fn walk_stmt<V: Visitor>(visitor: &mut V, stmt: &AstNode) -> V::Output {
-//     let child = &stmt[0];
-
-//     match child.kind {
-//         AstKind::Emit => visitor.visit_emit(child),
-//         AstKind::DecList => visitor.visit_decl_list(child),
-//         AstKind::Assign => visitor.visit_assign(child),
-//         AstKind::If => visitor.visit_if(child),
-//         AstKind::IfElse => visitor.visit_if_else(child),
-//         AstKind::While => visitor.visit_while(child),
-//         AstKind::BraceStmt => visitor.visit_brace_stmt(child),
-//         _ => panic!("Unsupported Statement Child"),
-//     }
-// }
-
-// // match child.kind {
-// //     AstKind::Emit => walk_emit(self, child),
-// //     AstKind::DecList => walk_decl_list(self, child),
-// //     AstKind::Assign => walk_assign(self, child),
-// //     AstKind::If => walk_if(self, child),
-// //     AstKind::IfElse => walk_if_else(self, child),
-// //     AstKind::While => walk_while(self, child),
-// //     AstKind::BraceStmt => walk_brace_stmt(self, child),
-// // }
-
-// // pub trait Visitor
-// // where
-// //     Self: Sized,
-// // {
-// //     fn visit_program(&mut self, program: &AstNode) {
-// //         assert_eq!(AstKind::Program, program.kind);
-
-// //         self.visit_stmts(&program[0]);
-// //         self.visit_eoi(&program[1]);
-// //     }
-
-// //     fn visit_stmts(&mut self, stmts: &AstNode) {
-// //         assert_eq!(AstKind::Stmts, stmts.kind);
-
-// //         if !stmts.children.is_empty() {
-// //             self.visit_stmts(&stmts[0]);
-// //             self.visit_stmt(&stmts[1]);
-// //         }
-// //     }
-
-// //     fn visit_stmt(&mut self, stmt: &AstNode) {
-// //         assert_eq!(AstKind::Statement, stmt.kind);
-
-// //         match stmt[0].kind {
-// //             AstKind::DecList => {
-// //                 self.visit_decl_list(&stmt[0]);
-// //             }
-// //             AstKind::Assign => {}
-// //             AstKind::Emit => {}
-// //             AstKind::If => {}
-// //             AstKind::IfElse => {}
-// //             AstKind::While => {}
-// //             AstKind::BraceStmt => {}
-// //             _ => panic!("Unsupported Stmt Child"),
-// //         }
-// //     }
-
-// //     // DeclList
-// //     fn visit_decl_list(&mut self, decl_list: &AstNode) {
-// //         self.visit_decl(&decl_list[0], &decl_list[1]);
-// //     }
-
-// //     // DeclType DeclIds
-// //     fn visit_decl(&mut self, decl_type: &AstNode, decl_ids: &AstNode) {
-// //         // ...
-// //     }
-
-// //     fn visit_eoi(&mut self, node: &AstNode) {}
-// // }
-
-// // pub struct DeclCounter;
-
-// // impl Visitor<usize> for DeclCounter {
-// //     fn visit_decl_list(&mut self, decl_list: &AstNode) {
-// //         Visitor::visit_decl(self, &decl_list[0], &decl_list[1]);
-// //     }
-// // }
-
-// // #[derive(Debug, Copy, Clone, PartialEq)]
-// // pub enum AstKind {
-// //     Token,
-// //     EOI,
-// //     Stmts,
-// //     Statement,
-// //     BraceStmt,
-// //     DecList,
-// //     Assign,
-// //     If,
-// //     IfElse,
-// //     While,
-// //     Emit,
-// //     DeclType,
-// //     DeclId,
-// //     DeclIds,
-// //     Expr,
-// //     BooleanExpr,
-// //     ArithmeticExpr,
-// //     Bools,
-// //     Plus,
-// //     Times,
-// //     Sum,
-// //     Product,
-// //     Value,
-// //     Unary,
-// //     Cast,
-// //     Program,
-// //     String,
-// //     Identifier,
-// //     TypeString,
-// // }
+//! Traversals over the typed AST in `syntax`.
+//!
+//! Every `visit_*` method defaults to the matching `walk_*` function, which
+//! visits the children of the node. An implementation overrides the nodes it
+//! cares about and calls the `walk_*` function itself when it still wants
+//! the children visited. Children are walked in evaluation order, so the
+//! value of an assignment comes before its targets.
+//!
+//! `Visitor` borrows the tree, `VisitorMut` edits it in place and `Fold`
+//! consumes it and builds a new one.
+
+use crate::syntax::{
+    Assign, BinOp, Block, Decl, Declarator, Emit, Expr, Ident, If, IfElse, Literal, Program, Span,
+    Stmt, Target, Type, UnOp, While,
+};
+
+pub trait Visitor: Sized {
+    fn visit_program(&mut self, program: &Program) {
+        walk_program(self, program)
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        walk_stmt(self, stmt)
+    }
+
+    fn visit_decl(&mut self, decl: &Decl) {
+        walk_decl(self, decl)
+    }
+
+    fn visit_declarator(&mut self, declarator: &Declarator) {
+        walk_declarator(self, declarator)
+    }
+
+    fn visit_assign(&mut self, assign: &Assign) {
+        walk_assign(self, assign)
+    }
+
+    fn visit_target(&mut self, target: &Target) {
+        walk_target(self, target)
+    }
+
+    fn visit_if(&mut self, if_: &If) {
+        walk_if(self, if_)
+    }
+
+    fn visit_if_else(&mut self, if_else: &IfElse) {
+        walk_if_else(self, if_else)
+    }
+
+    fn visit_while(&mut self, while_: &While) {
+        walk_while(self, while_)
+    }
+
+    fn visit_emit(&mut self, emit: &Emit) {
+        walk_emit(self, emit)
+    }
+
+    fn visit_block(&mut self, block: &Block) {
+        walk_block(self, block)
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr)
+    }
+
+    fn visit_literal(&mut self, _literal: &Literal, _span: Span) {}
+
+    /// An identifier read in an expression or named by `emit`.
+    fn visit_var(&mut self, ident: &Ident) {
+        self.visit_ident(ident)
+    }
+
+    fn visit_unary(&mut self, _op: UnOp, operand: &Expr, _span: Span) {
+        self.visit_expr(operand)
+    }
+
+    fn visit_binary(&mut self, _op: BinOp, lhs: &Expr, rhs: &Expr, _span: Span) {
+        self.visit_expr(lhs);
+        self.visit_expr(rhs);
+    }
+
+    fn visit_cast(&mut self, _to: Type, expr: &Expr, _span: Span) {
+        self.visit_expr(expr)
+    }
+
+    /// Every identifier, declared, assigned or read.
+    fn visit_ident(&mut self, _ident: &Ident) {}
+}
+
+pub fn walk_program<V: Visitor>(visitor: &mut V, program: &Program) {
+    for stmt in &program.stmts {
+        visitor.visit_stmt(stmt);
+    }
+}
+
+pub fn walk_stmt<V: Visitor>(visitor: &mut V, stmt: &Stmt) {
+    match stmt {
+        Stmt::Decl(decl) => visitor.visit_decl(decl),
+        Stmt::Assign(assign) => visitor.visit_assign(assign),
+        Stmt::If(if_) => visitor.visit_if(if_),
+        Stmt::IfElse(if_else) => visitor.visit_if_else(if_else),
+        Stmt::While(while_) => visitor.visit_while(while_),
+        Stmt::Emit(emit) => visitor.visit_emit(emit),
+        Stmt::Block(block) => visitor.visit_block(block),
+    }
+}
+
+pub fn walk_decl<V: Visitor>(visitor: &mut V, decl: &Decl) {
+    for var in &decl.vars {
+        visitor.visit_declarator(var);
+    }
+}
+
+pub fn walk_declarator<V: Visitor>(visitor: &mut V, declarator: &Declarator) {
+    match declarator {
+        Declarator::Ident(ident) => visitor.visit_ident(ident),
+        Declarator::Init(assign) => visitor.visit_assign(assign),
+    }
+}
+
+pub fn walk_assign<V: Visitor>(visitor: &mut V, assign: &Assign) {
+    visitor.visit_expr(&assign.value);
+
+    for target in &assign.targets {
+        visitor.visit_target(target);
+    }
+}
+
+pub fn walk_target<V: Visitor>(visitor: &mut V, target: &Target) {
+    visitor.visit_ident(&target.ident)
+}
+
+pub fn walk_if<V: Visitor>(visitor: &mut V, if_: &If) {
+    visitor.visit_expr(&if_.cond);
+    visitor.visit_stmt(&if_.then);
+}
+
+pub fn walk_if_else<V: Visitor>(visitor: &mut V, if_else: &IfElse) {
+    visitor.visit_expr(&if_else.cond);
+    visitor.visit_block(&if_else.then);
+    visitor.visit_stmt(&if_else.otherwise);
+}
+
+pub fn walk_while<V: Visitor>(visitor: &mut V, while_: &While) {
+    visitor.visit_expr(&while_.cond);
+    visitor.visit_stmt(&while_.body);
+}
+
+pub fn walk_emit<V: Visitor>(visitor: &mut V, emit: &Emit) {
+    if let Emit::Value {
+        ident,
+        first,
+        second,
+    } = emit
+    {
+        visitor.visit_var(ident);
+        visitor.visit_expr(first);
+        visitor.visit_expr(second);
+    }
+}
+
+pub fn walk_block<V: Visitor>(visitor: &mut V, block: &Block) {
+    for stmt in &block.stmts {
+        visitor.visit_stmt(stmt);
+    }
+}
+
+pub fn walk_expr<V: Visitor>(visitor: &mut V, expr: &Expr) {
+    match expr {
+        Expr::Literal { value, span } => visitor.visit_literal(value, *span),
+        Expr::Var(ident) => visitor.visit_var(ident),
+        Expr::Unary { op, operand, span } => visitor.visit_unary(*op, operand, *span),
+        Expr::Binary { op, lhs, rhs, span } => visitor.visit_binary(*op, lhs, rhs, *span),
+        Expr::Cast { to, expr, span } => visitor.visit_cast(*to, expr, *span),
+    }
+}
+
+/// Like `Visitor`, with mutable access to every node. Operators and spans
+/// are passed by reference so they can be rewritten too.
+pub trait VisitorMut: Sized {
+    fn visit_program_mut(&mut self, program: &mut Program) {
+        walk_program_mut(self, program)
+    }
+
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt)
+    }
+
+    fn visit_decl_mut(&mut self, decl: &mut Decl) {
+        walk_decl_mut(self, decl)
+    }
+
+    fn visit_declarator_mut(&mut self, declarator: &mut Declarator) {
+        walk_declarator_mut(self, declarator)
+    }
+
+    fn visit_assign_mut(&mut self, assign: &mut Assign) {
+        walk_assign_mut(self, assign)
+    }
+
+    fn visit_target_mut(&mut self, target: &mut Target) {
+        walk_target_mut(self, target)
+    }
+
+    fn visit_if_mut(&mut self, if_: &mut If) {
+        walk_if_mut(self, if_)
+    }
+
+    fn visit_if_else_mut(&mut self, if_else: &mut IfElse) {
+        walk_if_else_mut(self, if_else)
+    }
+
+    fn visit_while_mut(&mut self, while_: &mut While) {
+        walk_while_mut(self, while_)
+    }
+
+    fn visit_emit_mut(&mut self, emit: &mut Emit) {
+        walk_emit_mut(self, emit)
+    }
+
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        walk_block_mut(self, block)
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr)
+    }
+
+    fn visit_literal_mut(&mut self, _literal: &mut Literal, _span: &mut Span) {}
+
+    fn visit_var_mut(&mut self, ident: &mut Ident) {
+        self.visit_ident_mut(ident)
+    }
+
+    fn visit_unary_mut(&mut self, _op: &mut UnOp, operand: &mut Expr, _span: &mut Span) {
+        self.visit_expr_mut(operand)
+    }
+
+    fn visit_binary_mut(
+        &mut self,
+        _op: &mut BinOp,
+        lhs: &mut Expr,
+        rhs: &mut Expr,
+        _span: &mut Span,
+    ) {
+        self.visit_expr_mut(lhs);
+        self.visit_expr_mut(rhs);
+    }
+
+    fn visit_cast_mut(&mut self, _to: &mut Type, expr: &mut Expr, _span: &mut Span) {
+        self.visit_expr_mut(expr)
+    }
+
+    fn visit_ident_mut(&mut self, _ident: &mut Ident) {}
+}
+
+pub fn walk_program_mut<V: VisitorMut>(visitor: &mut V, program: &mut Program) {
+    for stmt in &mut program.stmts {
+        visitor.visit_stmt_mut(stmt);
+    }
+}
+
+pub fn walk_stmt_mut<V: VisitorMut>(visitor: &mut V, stmt: &mut Stmt) {
+    match stmt {
+        Stmt::Decl(decl) => visitor.visit_decl_mut(decl),
+        Stmt::Assign(assign) => visitor.visit_assign_mut(assign),
+        Stmt::If(if_) => visitor.visit_if_mut(if_),
+        Stmt::IfElse(if_else) => visitor.visit_if_else_mut(if_else),
+        Stmt::While(while_) => visitor.visit_while_mut(while_),
+        Stmt::Emit(emit) => visitor.visit_emit_mut(emit),
+        Stmt::Block(block) => visitor.visit_block_mut(block),
+    }
+}
+
+pub fn walk_decl_mut<V: VisitorMut>(visitor: &mut V, decl: &mut Decl) {
+    for var in &mut decl.vars {
+        visitor.visit_declarator_mut(var);
+    }
+}
+
+pub fn walk_declarator_mut<V: VisitorMut>(visitor: &mut V, declarator: &mut Declarator) {
+    match declarator {
+        Declarator::Ident(ident) => visitor.visit_ident_mut(ident),
+        Declarator::Init(assign) => visitor.visit_assign_mut(assign),
+    }
+}
+
+pub fn walk_assign_mut<V: VisitorMut>(visitor: &mut V, assign: &mut Assign) {
+    visitor.visit_expr_mut(&mut assign.value);
+
+    for target in &mut assign.targets {
+        visitor.visit_target_mut(target);
+    }
+}
+
+pub fn walk_target_mut<V: VisitorMut>(visitor: &mut V, target: &mut Target) {
+    visitor.visit_ident_mut(&mut target.ident)
+}
+
+pub fn walk_if_mut<V: VisitorMut>(visitor: &mut V, if_: &mut If) {
+    visitor.visit_expr_mut(&mut if_.cond);
+    visitor.visit_stmt_mut(&mut if_.then);
+}
+
+pub fn walk_if_else_mut<V: VisitorMut>(visitor: &mut V, if_else: &mut IfElse) {
+    visitor.visit_expr_mut(&mut if_else.cond);
+    visitor.visit_block_mut(&mut if_else.then);
+    visitor.visit_stmt_mut(&mut if_else.otherwise);
+}
+
+pub fn walk_while_mut<V: VisitorMut>(visitor: &mut V, while_: &mut While) {
+    visitor.visit_expr_mut(&mut while_.cond);
+    visitor.visit_stmt_mut(&mut while_.body);
+}
+
+pub fn walk_emit_mut<V: VisitorMut>(visitor: &mut V, emit: &mut Emit) {
+    if let Emit::Value {
+        ident,
+        first,
+        second,
+    } = emit
+    {
+        visitor.visit_var_mut(ident);
+        visitor.visit_expr_mut(first);
+        visitor.visit_expr_mut(second);
+    }
+}
+
+pub fn walk_block_mut<V: VisitorMut>(visitor: &mut V, block: &mut Block) {
+    for stmt in &mut block.stmts {
+        visitor.visit_stmt_mut(stmt);
+    }
+}
+
+pub fn walk_expr_mut<V: VisitorMut>(visitor: &mut V, expr: &mut Expr) {
+    match expr {
+        Expr::Literal { value, span } => visitor.visit_literal_mut(value, span),
+        Expr::Var(ident) => visitor.visit_var_mut(ident),
+        Expr::Unary { op, operand, span } => visitor.visit_unary_mut(op, operand, span),
+        Expr::Binary { op, lhs, rhs, span } => visitor.visit_binary_mut(op, lhs, rhs, span),
+        Expr::Cast { to, expr, span } => visitor.visit_cast_mut(to, expr, span),
+    }
+}
+
+/// Rebuilds the tree bottom up. Each `fold_*` method defaults to the free
+/// function of the same name, which folds the children and reassembles the
+/// node, so a `Fold` can replace a node with a different kind of node, e.g.
+/// a constant expression with its value.
+pub trait Fold: Sized {
+    fn fold_program(&mut self, program: Program) -> Program {
+        fold_program(self, program)
+    }
+
+    fn fold_stmt(&mut self, stmt: Stmt) -> Stmt {
+        fold_stmt(self, stmt)
+    }
+
+    fn fold_decl(&mut self, decl: Decl) -> Decl {
+        fold_decl(self, decl)
+    }
+
+    fn fold_declarator(&mut self, declarator: Declarator) -> Declarator {
+        fold_declarator(self, declarator)
+    }
+
+    fn fold_assign(&mut self, assign: Assign) -> Assign {
+        fold_assign(self, assign)
+    }
+
+    fn fold_target(&mut self, target: Target) -> Target {
+        fold_target(self, target)
+    }
+
+    fn fold_if(&mut self, if_: If) -> If {
+        fold_if(self, if_)
+    }
+
+    fn fold_if_else(&mut self, if_else: IfElse) -> IfElse {
+        fold_if_else(self, if_else)
+    }
+
+    fn fold_while(&mut self, while_: While) -> While {
+        fold_while(self, while_)
+    }
+
+    fn fold_emit(&mut self, emit: Emit) -> Emit {
+        fold_emit(self, emit)
+    }
+
+    fn fold_block(&mut self, block: Block) -> Block {
+        fold_block(self, block)
+    }
+
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        fold_expr(self, expr)
+    }
+
+    fn fold_literal(&mut self, value: Literal, span: Span) -> Expr {
+        Expr::Literal { value, span }
+    }
+
+    fn fold_var(&mut self, ident: Ident) -> Expr {
+        Expr::Var(self.fold_ident(ident))
+    }
+
+    fn fold_unary(&mut self, op: UnOp, operand: Expr, span: Span) -> Expr {
+        Expr::Unary {
+            op,
+            operand: Box::new(self.fold_expr(operand)),
+            span,
+        }
+    }
+
+    fn fold_binary(&mut self, op: BinOp, lhs: Expr, rhs: Expr, span: Span) -> Expr {
+        Expr::Binary {
+            op,
+            lhs: Box::new(self.fold_expr(lhs)),
+            rhs: Box::new(self.fold_expr(rhs)),
+            span,
+        }
+    }
+
+    fn fold_cast(&mut self, to: Type, expr: Expr, span: Span) -> Expr {
+        Expr::Cast {
+            to,
+            expr: Box::new(self.fold_expr(expr)),
+            span,
+        }
+    }
+
+    fn fold_ident(&mut self, ident: Ident) -> Ident {
+        ident
+    }
+}
+
+pub fn fold_program<F: Fold>(folder: &mut F, program: Program) -> Program {
+    Program {
+        stmts: program
+            .stmts
+            .into_iter()
+            .map(|s| folder.fold_stmt(s))
+            .collect(),
+    }
+}
+
+pub fn fold_stmt<F: Fold>(folder: &mut F, stmt: Stmt) -> Stmt {
+    match stmt {
+        Stmt::Decl(decl) => Stmt::Decl(folder.fold_decl(decl)),
+        Stmt::Assign(assign) => Stmt::Assign(folder.fold_assign(assign)),
+        Stmt::If(if_) => Stmt::If(folder.fold_if(if_)),
+        Stmt::IfElse(if_else) => Stmt::IfElse(folder.fold_if_else(if_else)),
+        Stmt::While(while_) => Stmt::While(folder.fold_while(while_)),
+        Stmt::Emit(emit) => Stmt::Emit(folder.fold_emit(emit)),
+        Stmt::Block(block) => Stmt::Block(folder.fold_block(block)),
+    }
+}
+
+pub fn fold_decl<F: Fold>(folder: &mut F, decl: Decl) -> Decl {
+    Decl {
+        vars: decl
+            .vars
+            .into_iter()
+            .map(|v| folder.fold_declarator(v))
+            .collect(),
+        ..decl
+    }
+}
+
+pub fn fold_declarator<F: Fold>(folder: &mut F, declarator: Declarator) -> Declarator {
+    match declarator {
+        Declarator::Ident(ident) => Declarator::Ident(folder.fold_ident(ident)),
+        Declarator::Init(assign) => Declarator::Init(folder.fold_assign(assign)),
+    }
+}
+
+pub fn fold_assign<F: Fold>(folder: &mut F, assign: Assign) -> Assign {
+    let value = folder.fold_expr(assign.value);
+    let targets = assign
+        .targets
+        .into_iter()
+        .map(|t| folder.fold_target(t))
+        .collect();
+
+    Assign { targets, value }
+}
+
+pub fn fold_target<F: Fold>(folder: &mut F, target: Target) -> Target {
+    Target {
+        ident: folder.fold_ident(target.ident),
+        span: target.span,
+    }
+}
+
+pub fn fold_if<F: Fold>(folder: &mut F, if_: If) -> If {
+    If {
+        cond: folder.fold_expr(if_.cond),
+        then: Box::new(folder.fold_stmt(*if_.then)),
+    }
+}
+
+pub fn fold_if_else<F: Fold>(folder: &mut F, if_else: IfElse) -> IfElse {
+    IfElse {
+        cond: folder.fold_expr(if_else.cond),
+        then: folder.fold_block(if_else.then),
+        otherwise: Box::new(folder.fold_stmt(*if_else.otherwise)),
+    }
+}
+
+pub fn fold_while<F: Fold>(folder: &mut F, while_: While) -> While {
+    While {
+        cond: folder.fold_expr(while_.cond),
+        body: Box::new(folder.fold_stmt(*while_.body)),
+    }
+}
+
+pub fn fold_emit<F: Fold>(folder: &mut F, emit: Emit) -> Emit {
+    match emit {
+        Emit::Value {
+            ident,
+            first,
+            second,
+        } => Emit::Value {
+            ident: folder.fold_ident(ident),
+            first: folder.fold_expr(first),
+            second: folder.fold_expr(second),
+        },
+        symtable => symtable,
+    }
+}
+
+pub fn fold_block<F: Fold>(folder: &mut F, block: Block) -> Block {
+    Block {
+        stmts: block
+            .stmts
+            .into_iter()
+            .map(|s| folder.fold_stmt(s))
+            .collect(),
+        span: block.span,
+    }
+}
+
+pub fn fold_expr<F: Fold>(folder: &mut F, expr: Expr) -> Expr {
+    match expr {
+        Expr::Literal { value, span } => folder.fold_literal(value, span),
+        Expr::Var(ident) => folder.fold_var(ident),
+        Expr::Unary { op, operand, span } => folder.fold_unary(op, *operand, span),
+        Expr::Binary { op, lhs, rhs, span } => folder.fold_binary(op, *lhs, *rhs, span),
+        Expr::Cast { to, expr, span } => folder.fold_cast(to, *expr, span),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn program(src: &str) -> Program {
+        Program::from_cst(&Parser::new().parse_str(src).unwrap()).unwrap()
+    }
+
+    /// Records every identifier in visiting order.
+    #[derive(Default)]
+    struct Idents(Vec<String>);
+
+    impl Visitor for Idents {
+        fn visit_ident(&mut self, ident: &Ident) {
+            self.0.push(ident.name.clone());
+        }
+    }
+
+    #[test]
+    fn visits_in_evaluation_order() {
+        let program = program("int a, b = c = d + 1;\nwhile (a < b) { emit a e 2; }");
+        let mut idents = Idents::default();
+        idents.visit_program(&program);
+
+        assert_eq!(idents.0, vec!["a", "d", "b", "c", "a", "b", "a", "e"]);
+    }
+
+    struct Rename;
+
+    impl VisitorMut for Rename {
+        fn visit_ident_mut(&mut self, ident: &mut Ident) {
+            ident.name.make_ascii_uppercase();
+        }
+    }
+
+    #[test]
+    fn visitor_mut_rewrites_in_place() {
+        let mut program = program("x = y + int(z);");
+        Rename.visit_program_mut(&mut program);
+
+        let mut idents = Idents::default();
+        idents.visit_program(&program);
+        assert_eq!(idents.0, vec!["Y", "Z", "X"]);
+    }
+
+    /// Replaces `e * 1` with `e`.
+    struct DropTimesOne;
+
+    impl Fold for DropTimesOne {
+        fn fold_binary(&mut self, op: BinOp, lhs: Expr, rhs: Expr, span: Span) -> Expr {
+            let lhs = self.fold_expr(lhs);
+            let rhs = self.fold_expr(rhs);
+
+            match (op, &rhs) {
+                (
+                    BinOp::Mul,
+                    Expr::Literal {
+                        value: Literal::Int(1),
+                        ..
+                    },
+                ) => lhs,
+                _ => Expr::Binary {
+                    op,
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(rhs),
+                    span,
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn fold_replaces_nodes() {
+        let folded = DropTimesOne.fold_program(program("{ x = y * 1 * 1 + 2; }"));
+
+        let Stmt::Block(block) = &folded.stmts[0] else {
+            panic!("expected a block");
+        };
+        let Stmt::Assign(assign) = &block.stmts[0] else {
+            panic!("expected an assignment");
+        };
+        match &assign.value {
+            Expr::Binary {
+                op: BinOp::Add,
+                lhs,
+                ..
+            } => assert!(matches!(&**lhs, Expr::Var(y) if y.name == "y")),
+            e => panic!("expected y + 2, found {:?}", e),
+        }
+    }
+}